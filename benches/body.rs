@@ -0,0 +1,136 @@
+#![feature(test)]
+#![deny(warnings)]
+
+extern crate futures;
+extern crate hyper;
+extern crate pretty_env_logger;
+extern crate test;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+
+use futures::{future, Future};
+use futures::sync::oneshot;
+
+use hyper::header::ContentLength;
+use hyper::server::{self, Service};
+
+// Counts the bytes allocated by the whole process, the server thread
+// included.
+struct Counting;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const ASSET_LEN: usize = 1_000_000;
+
+// Serves a 1MB asset with the body made by `body`, and reports the bytes
+// allocated per response.
+fn bench_asset<F>(b: &mut test::Bencher, name: &str, body: F)
+where
+    F: Fn() -> hyper::Body + Send + Sync + 'static,
+{
+    let _ = pretty_env_logger::try_init();
+    let body = Arc::new(body);
+    let (_until_tx, until_rx) = oneshot::channel();
+    let addr = {
+        let (addr_tx, addr_rx) = mpsc::channel();
+        ::std::thread::spawn(move || {
+            let addr = "127.0.0.1:0".parse().unwrap();
+            let srv = hyper::server::Http::new()
+                .bind(&addr, move || Ok(Asset(body.clone())))
+                .unwrap();
+            addr_tx.send(srv.local_addr().unwrap()).unwrap();
+            srv.run_until(until_rx.map_err(|_| ())).unwrap();
+        });
+        addr_rx.recv().unwrap()
+    };
+
+    let mut tcp = TcpStream::connect(addr).unwrap();
+    let mut buf = vec![0; 64 * 1024];
+    let mut get = || {
+        tcp.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut body = 0;
+        let mut head = Vec::new();
+        while body < ASSET_LEN {
+            let n = tcp.read(&mut buf).unwrap();
+            assert!(n > 0);
+            if head.ends_with(b"\r\n\r\n") {
+                body += n;
+            } else {
+                head.extend_from_slice(&buf[..n]);
+                if let Some(end) = head.windows(4).position(|w| w == b"\r\n\r\n") {
+                    body += head.len() - end - 4;
+                    head.truncate(end + 4);
+                }
+            }
+        }
+        assert_eq!(body, ASSET_LEN);
+    };
+
+    // warm up the buffers of the connection
+    get();
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    let mut responses = 0;
+    b.bytes = ASSET_LEN as u64;
+    b.iter(|| {
+        get();
+        responses += 1;
+    });
+    let allocated = ALLOCATED.load(Ordering::Relaxed) - before;
+    eprintln!("{}: {} bytes allocated per response", name, allocated / responses.max(1));
+}
+
+#[bench]
+fn asset_from_vec(b: &mut test::Bencher) {
+    let asset = vec![b'x'; ASSET_LEN];
+    bench_asset(b, "asset_from_vec", move || hyper::Body::from(asset.clone()))
+}
+
+#[bench]
+fn asset_from_shared(b: &mut test::Bencher) {
+    let asset: Arc<[u8]> = Arc::from(vec![b'x'; ASSET_LEN]);
+    bench_asset(b, "asset_from_shared", move || hyper::Body::from_shared(asset.clone()))
+}
+
+#[bench]
+fn asset_from_static(b: &mut test::Bencher) {
+    static ASSET: [u8; ASSET_LEN] = [b'x'; ASSET_LEN];
+    bench_asset(b, "asset_from_static", || hyper::Body::from_static(&ASSET))
+}
+
+struct Asset<F>(Arc<F>);
+
+impl<F> Service for Asset<F>
+where
+    F: Fn() -> hyper::Body,
+{
+    type Request = server::Request;
+    type Response = server::Response;
+    type Error = hyper::Error;
+    type Future = future::FutureResult<Self::Response, hyper::Error>;
+    fn call(&self, _req: Self::Request) -> Self::Future {
+        future::ok(
+            server::Response::new()
+                .with_header(ContentLength(ASSET_LEN as u64))
+                .with_body((self.0)())
+        )
+    }
+}
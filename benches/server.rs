@@ -81,11 +81,22 @@ fn throughput_fixedsize_many_chunks(b: &mut test::Bencher) {
     })
 }
 
+// A static 1KB response, its head and body written with a single `write`.
+#[bench]
+fn throughput_fixedsize_1kb_payload(b: &mut test::Bencher) {
+    bench_server!(b, ContentLength(1_024), || body(&[b'x'; 1_024]))
+}
+
 #[bench]
 fn throughput_chunked_small_payload(b: &mut test::Bencher) {
     bench_server!(b, TransferEncoding::chunked(), || body(b"Hello, World!"))
 }
 
+#[bench]
+fn throughput_chunked_1kb_payload(b: &mut test::Bencher) {
+    bench_server!(b, TransferEncoding::chunked(), || body(&[b'x'; 1_024]))
+}
+
 #[bench]
 fn throughput_chunked_large_payload(b: &mut test::Bencher) {
     bench_server!(b, TransferEncoding::chunked(), ||  body(&[b'x'; 1_000_000]))
@@ -99,6 +110,55 @@ fn throughput_chunked_many_chunks(b: &mut test::Bencher) {
     })
 }
 
+// Opens `CONNS` connections at once, so that they wait in the listen backlog
+// together, then makes one request on each.
+fn bench_accept(b: &mut test::Bencher, burst: usize) {
+    const CONNS: usize = 100;
+    let _ = pretty_env_logger::try_init();
+    let (_until_tx, until_rx) = oneshot::channel();
+    let addr = {
+        let (addr_tx, addr_rx) = mpsc::channel();
+        ::std::thread::spawn(move || {
+            let addr = "127.0.0.1:0".parse().unwrap();
+            let srv = hyper::server::Http::new()
+                .accept_burst(burst)
+                .bind(&addr, || Ok(BenchPayload {
+                    header: ContentLength(13),
+                    body: || body(b"Hello, World!"),
+                }))
+                .unwrap();
+            let addr = srv.local_addr().unwrap();
+            addr_tx.send(addr).unwrap();
+            srv.run_until(until_rx.map_err(|_| ())).unwrap();
+        });
+
+        addr_rx.recv().unwrap()
+    };
+
+    let mut buf = Vec::new();
+    b.iter(|| {
+        let tcps = (0..CONNS)
+            .map(|_| TcpStream::connect(addr).unwrap())
+            .collect::<Vec<_>>();
+        for mut tcp in tcps {
+            tcp.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+            buf.clear();
+            tcp.read_to_end(&mut buf).unwrap();
+            assert!(buf.ends_with(b"Hello, World!"));
+        }
+    });
+}
+
+#[bench]
+fn accept_many_connections(b: &mut test::Bencher) {
+    bench_accept(b, 1)
+}
+
+#[bench]
+fn accept_many_connections_burst(b: &mut test::Bencher) {
+    bench_accept(b, 64)
+}
+
 #[bench]
 fn raw_tcp_throughput_small_payload(b: &mut test::Bencher) {
     let (tx, rx) = mpsc::channel();
@@ -13,14 +13,22 @@ use self::Error::{
     Method,
     Uri,
     Version,
+    Http2Preface,
     Header,
     Status,
     Timeout,
+    HeaderTimeout,
+    BodyTimeout,
+    WriteTimeout,
     Upgrade,
     Closed,
     Cancel,
     Io,
+    User,
     TooLarge,
+    UriTooLong,
+    BodyTooLarge,
+    Body,
     Incomplete,
     Utf8
 };
@@ -37,16 +45,37 @@ pub enum Error {
     Uri(UriError),
     /// An invalid `HttpVersion`, such as `HTP/1.1`
     Version,
+    /// The HTTP/2 connection preface was received by a server speaking only
+    /// HTTP/1, such as from a client assuming HTTP/2 without negotiating it.
+    Http2Preface,
     /// An invalid `Header`.
     Header,
     /// A message head is too large to be reasonable.
     TooLarge,
+    /// The URI of a request exceeded the configured maximum length.
+    UriTooLong,
+    /// A message body exceeded the configured maximum size.
+    BodyTooLarge,
+    /// A message body couldn't be decoded, such as an invalid chunk.
+    ///
+    /// This is the error of a `Body` stream for a peer that sent a
+    /// malformed body, or closed the connection before its end.
+    Body(BodyError),
     /// A message reached EOF, but is not complete.
     Incomplete,
     /// An invalid `Status`, such as `1337 ELITE`.
     Status,
     /// A timeout occurred waiting for an IO event.
     Timeout,
+    /// A message head was not completely received before the configured
+    /// read timeout elapsed.
+    HeaderTimeout,
+    /// No bytes of a message body were received before the configured body
+    /// read timeout elapsed.
+    BodyTimeout,
+    /// No bytes of a message could be written before the configured write
+    /// timeout elapsed.
+    WriteTimeout,
     /// A protocol upgrade was encountered, but not yet supported in hyper.
     Upgrade,
     /// A pending item was dropped before ever being processed.
@@ -57,12 +86,65 @@ pub enum Error {
     Io(IoError),
     /// Parsing a field as string failed
     Utf8(Utf8Error),
+    /// An error returned by the `Service` of a server connection.
+    User(Box<Error>),
 
     #[doc(hidden)]
     __Nonexhaustive(Void)
 }
 
 impl Error {
+    /// The kind of this error, telling what caused it.
+    ///
+    /// This allows, for instance, logging errors caused by misbehaving
+    /// clients at a lower level than those caused by the server itself.
+    pub fn kind(&self) -> ErrorKind {
+        match *self {
+            Method |
+            Uri(_) |
+            Version |
+            Http2Preface |
+            Header |
+            TooLarge |
+            UriTooLong |
+            BodyTooLarge |
+            Body(_) |
+            Incomplete |
+            Status |
+            Upgrade |
+            Utf8(_) => ErrorKind::Parse,
+            Timeout |
+            HeaderTimeout |
+            BodyTimeout |
+            WriteTimeout => ErrorKind::Timeout,
+            Cancel(_) |
+            Closed => ErrorKind::Canceled,
+            Io(_) => ErrorKind::Io,
+            User(_) => ErrorKind::User,
+            Error::__Nonexhaustive(..) => unreachable!(),
+        }
+    }
+
+    pub(crate) fn new_user(err: Error) -> Error {
+        User(Box::new(err))
+    }
+
+    // The error of reading a message body: its decoding error if the body
+    // was malformed, or its timeout, else one of the connection.
+    pub(crate) fn new_body_read(err: IoError) -> Error {
+        let body = err.get_ref()
+            .and_then(|e| e.downcast_ref::<Error>())
+            .and_then(|e| match *e {
+                Body(kind) => Some(Body(kind)),
+                BodyTimeout => Some(BodyTimeout),
+                _ => None,
+            });
+        match body {
+            Some(e) => e,
+            None => Io(err),
+        }
+    }
+
     pub(crate) fn new_canceled<E: Into<Box<StdError + Send + Sync>>>(cause: Option<E>) -> Error {
         Error::Cancel(Canceled {
             cause: cause.map(Into::into),
@@ -70,6 +152,65 @@ impl Error {
     }
 }
 
+/// The kind of an `Error`, see `Error::kind`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// Reading or writing the connection failed, for instance because the
+    /// peer reset it.
+    Io,
+    /// A message received from the peer was invalid.
+    Parse,
+    /// The `Service` of the connection returned an error.
+    User,
+    /// A pending operation was canceled, or the connection was closed.
+    Canceled,
+    /// A configured timeout elapsed.
+    Timeout,
+
+    #[doc(hidden)]
+    __Nonexhaustive(Void)
+}
+
+/// How a message body couldn't be decoded, see `Error::Body`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BodyError {
+    /// A chunk size line was invalid, or the data of a chunk wasn't
+    /// followed by a CRLF right after its size.
+    InvalidChunkSize,
+    /// The connection closed before all of the `Content-Length`, or of the
+    /// data of a chunk, was received.
+    IncompleteBody,
+    /// The last chunk wasn't followed by the end of the trailer section.
+    ///
+    /// Trailer fields aren't supported in a received body, so a body with
+    /// any also fails with this error.
+    InvalidTrailer,
+    /// The connection closed in the middle of the framing of a chunked
+    /// body, such as a chunk size line, before the last chunk.
+    UnexpectedEof,
+
+    #[doc(hidden)]
+    __Nonexhaustive(Void)
+}
+
+impl BodyError {
+    fn description(&self) -> &str {
+        match *self {
+            BodyError::InvalidChunkSize => "invalid chunk size",
+            BodyError::IncompleteBody => "end of file before message length reached",
+            BodyError::InvalidTrailer => "invalid trailer section after last chunk",
+            BodyError::UnexpectedEof => "unexpected end of file in chunked body",
+            BodyError::__Nonexhaustive(..) => unreachable!(),
+        }
+    }
+}
+
+impl fmt::Display for BodyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.description())
+    }
+}
+
 /// A pending item was dropped before ever being processed.
 ///
 /// For example, a `Request` could be queued in the `Client`, *just*
@@ -94,6 +235,7 @@ impl fmt::Display for Canceled {
 }
 
 #[doc(hidden)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub struct Void(());
 
 impl fmt::Debug for Void {
@@ -106,8 +248,10 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Uri(ref e) => fmt::Display::fmt(e, f),
+            Body(ref e) => fmt::Display::fmt(e, f),
             Io(ref e) => fmt::Display::fmt(e, f),
             Utf8(ref e) => fmt::Display::fmt(e, f),
+            User(ref e) => fmt::Display::fmt(e, f),
             ref e => f.write_str(e.description()),
         }
     }
@@ -118,17 +262,25 @@ impl StdError for Error {
         match *self {
             Method => "invalid Method specified",
             Version => "invalid HTTP version specified",
+            Http2Preface => "received the HTTP/2 connection preface, but only HTTP/1 is supported",
             Header => "invalid Header provided",
             TooLarge => "message head is too large",
+            UriTooLong => "request URI is too long",
+            BodyTooLarge => "message body is too large",
+            Body(ref e) => e.description(),
             Status => "invalid Status provided",
             Incomplete => "message is incomplete",
             Timeout => "timeout",
+            HeaderTimeout => "timed out reading message head",
+            BodyTimeout => "timed out reading message body",
+            WriteTimeout => "timed out writing message",
             Upgrade => "unsupported protocol upgrade",
             Closed => "connection is closed",
             Cancel(ref e) => e.description(),
             Uri(ref e) => e.description(),
             Io(ref e) => e.description(),
             Utf8(ref e) => e.description(),
+            User(ref e) => e.description(),
             Error::__Nonexhaustive(..) =>  unreachable!(),
         }
     }
@@ -138,6 +290,7 @@ impl StdError for Error {
             Io(ref error) => Some(error),
             Uri(ref error) => Some(error),
             Utf8(ref error) => Some(error),
+            User(ref error) => Some(&**error),
             Cancel(ref e) => e.cause.as_ref().map(|e| &**e as &StdError),
             Error::__Nonexhaustive(..) =>  unreachable!(),
             _ => None,
@@ -193,7 +346,7 @@ mod tests {
     use std::error::Error as StdError;
     use std::io;
     use httparse;
-    use super::Error;
+    use super::{BodyError, Error, ErrorKind};
     use super::Error::*;
 
     #[test]
@@ -242,4 +395,21 @@ mod tests {
         from!(httparse::Error::TooManyHeaders => TooLarge);
         from!(httparse::Error::Version => Version);
     }
+
+    #[test]
+    fn test_kind() {
+        assert_eq!(Header.kind(), ErrorKind::Parse);
+        assert_eq!(Http2Preface.kind(), ErrorKind::Parse);
+        assert_eq!(Incomplete.kind(), ErrorKind::Parse);
+        assert_eq!(Body(BodyError::IncompleteBody).kind(), ErrorKind::Parse);
+        assert_eq!(HeaderTimeout.kind(), ErrorKind::Timeout);
+        assert_eq!(BodyTimeout.kind(), ErrorKind::Timeout);
+        assert_eq!(Closed.kind(), ErrorKind::Canceled);
+        assert_eq!(Io(io::Error::new(io::ErrorKind::ConnectionReset, "reset")).kind(), ErrorKind::Io);
+
+        let e = Error::new_user(Io(io::Error::new(io::ErrorKind::Other, "other")));
+        assert_eq!(e.kind(), ErrorKind::User);
+        assert_eq!(e.to_string(), "other");
+        assert_eq!(e.cause().unwrap().to_string(), "other");
+    }
 }
@@ -993,7 +993,7 @@ mod tests {
                                   rel=\"previous\"; rev=next; title=\"previous chapter\"\
                                   \r\n\r\n".to_vec());
 
-        let (mut res, _) = ServerTransaction::parse(&mut raw).unwrap().unwrap();
+        let (mut res, _) = ServerTransaction::parse(&mut raw, 100).unwrap().unwrap();
 
         let link = res.headers.remove::<Link>().unwrap();
 
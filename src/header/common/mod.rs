@@ -59,6 +59,7 @@ pub use self::server::Server;
 pub use self::set_cookie::SetCookie;
 pub use self::strict_transport_security::StrictTransportSecurity;
 pub use self::te::Te;
+pub use self::trailer::Trailer;
 pub use self::transfer_encoding::TransferEncoding;
 pub use self::upgrade::{Upgrade, Protocol, ProtocolName};
 pub use self::user_agent::UserAgent;
@@ -495,6 +496,7 @@ mod server;
 mod set_cookie;
 mod strict_transport_security;
 mod te;
+mod trailer;
 mod transfer_encoding;
 mod upgrade;
 mod user_agent;
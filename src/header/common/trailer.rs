@@ -0,0 +1,46 @@
+use unicase::Ascii;
+
+header! {
+    /// `Trailer` header, defined in
+    /// [RFC7230](http://tools.ietf.org/html/rfc7230#section-4.4)
+    ///
+    /// The `Trailer` header field lists the header fields a sender is going
+    /// to send in the trailer section of a chunked message, after its body.
+    ///
+    /// # ABNF
+    ///
+    /// ```text
+    /// Trailer = 1#field-name
+    /// ```
+    ///
+    /// # Example values
+    /// * `Content-MD5`
+    /// * `Grpc-Status, Grpc-Message`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate hyper;
+    /// # extern crate unicase;
+    /// # fn main() {
+    /// // extern crate unicase;
+    ///
+    /// use hyper::header::{Headers, Trailer};
+    /// use unicase::Ascii;
+    ///
+    /// let mut headers = Headers::new();
+    /// headers.set(
+    ///     Trailer(vec![
+    ///         Ascii::new("grpc-status".to_owned()),
+    ///         Ascii::new("grpc-message".to_owned())
+    ///     ])
+    /// );
+    /// # }
+    /// ```
+    (Trailer, "Trailer") => (Ascii<String>)+
+
+    test_trailer {
+        test_header!(test1, vec![b"Content-MD5"]);
+        test_header!(test2, vec![b"Grpc-Status, Grpc-Message"]);
+    }
+}
@@ -26,6 +26,8 @@ extern crate http;
 extern crate httparse;
 extern crate iovec;
 extern crate language_tags;
+#[cfg(unix)]
+extern crate libc;
 #[macro_use] extern crate log;
 pub extern crate mime;
 extern crate net2;
@@ -37,6 +39,8 @@ extern crate tokio_core as tokio;
 #[cfg(feature = "tokio-proto")]
 extern crate tokio_proto;
 extern crate tokio_service;
+#[cfg(all(unix, feature = "unix"))]
+extern crate tokio_uds;
 extern crate unicase;
 extern crate want;
 
@@ -45,9 +49,9 @@ extern crate test;
 
 pub use uri::Uri;
 pub use client::Client;
-pub use error::{Result, Error};
+pub use error::{Result, Error, ErrorKind, BodyError};
 pub use header::Headers;
-pub use proto::{Body, Chunk};
+pub use proto::{Body, Chunk, ConcatLimited};
 pub use proto::request::Request;
 pub use proto::response::Response;
 pub use method::Method::{self, Get, Head, Post, Put, Delete};
@@ -1,8 +1,12 @@
 use std::fmt;
+#[cfg(feature = "tokio-proto")]
+use std::io;
+use std::sync::{Arc, Mutex};
 
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use futures::{Async, AsyncSink, Future, Poll, Sink, StartSend, Stream};
 use futures::sync::{mpsc, oneshot};
+use tokio_io::AsyncRead;
 #[cfg(feature = "tokio-proto")]
 use tokio_proto;
 use std::borrow::Cow;
@@ -27,6 +31,9 @@ pub struct Body {
     /// a brand new connection, since the pool didn't know about the idle
     /// connection yet.
     delayed_eof: Option<DelayEof>,
+    /// Allow the server to be told when this `Body` is first polled, which
+    /// is when a deferred `100 Continue` is sent.
+    on_first_poll: Option<oneshot::Sender<()>>,
 }
 
 #[derive(Debug)]
@@ -38,9 +45,20 @@ enum Kind {
         rx: mpsc::Receiver<Result<Chunk, ::Error>>,
     },
     Once(Option<Chunk>),
+    Reader(ReaderBody),
     Empty,
 }
 
+// The chunks read from an `AsyncRead`, see `Body::from_reader`. The mutex
+// only keeps `Body` `Sync`, it is never locked.
+struct ReaderBody(Mutex<Box<Stream<Item=Chunk, Error=::Error> + Send>>);
+
+struct ReadChunks<R> {
+    reader: R,
+    chunk_size: usize,
+    buf: Option<BytesMut>,
+}
+
 type DelayEofUntil = oneshot::Receiver<Never>;
 
 enum DelayEof {
@@ -52,6 +70,17 @@ enum DelayEof {
     Eof(DelayEofUntil),
 }
 
+/// A future concatenating the chunks of a `Body`, up to a maximum size.
+///
+/// Created with `Body::concat_limited`.
+#[must_use = "futures do nothing unless polled"]
+#[derive(Debug)]
+pub struct ConcatLimited {
+    body: Body,
+    buf: BytesMut,
+    max: usize,
+}
+
 //pub(crate)
 #[derive(Debug)]
 pub struct ChunkSender {
@@ -90,10 +119,72 @@ impl Body {
         }
     }
 
+    /// Return a body of the bytes of `slice`, without copying them.
+    ///
+    /// This is the same as `Body::from(slice)`, named to make the absence of
+    /// a copy explicit, such as for a static asset compiled into the binary.
+    pub fn from_static(slice: &'static [u8]) -> Body {
+        Body::from(Chunk::from(slice))
+    }
+
+    /// Return a body of the bytes of `mem`, without copying them.
+    ///
+    /// The memory is written to the connection as is, and dropped once the
+    /// body was written, so a large asset can be cached once and sent in
+    /// the responses of many connections without allocating for each of
+    /// them.
+    pub fn from_shared(mem: Arc<[u8]>) -> Body {
+        Body::from(Chunk::from(mem))
+    }
+
+    /// Return a body stream reading `reader`, such as a file, until EOF, in
+    /// chunks of up to `chunk_size` bytes.
+    ///
+    /// The reader is only read when the body is polled, so when sending a
+    /// response to a slow client, it isn't read faster than the body is
+    /// written, see `Http::body_write_buffer_limit`. An error reading it
+    /// fails the body with `Error::Io`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_size` is 0.
+    ///
+    /// # Deprecated servers
+    ///
+    /// The server of the deprecated `Http::bind_connection` can't read such
+    /// a body, and fails it.
+    pub fn from_reader<R>(reader: R, chunk_size: usize) -> Body
+        where R: AsyncRead + Send + 'static,
+    {
+        assert!(chunk_size > 0, "chunk_size must be more than 0");
+        let chunks = ReadChunks {
+            reader: reader,
+            chunk_size: chunk_size,
+            buf: None,
+        };
+        Body::new(Kind::Reader(ReaderBody(Mutex::new(Box::new(chunks)))))
+    }
+
+    /// Concatenate all the chunks of this body into a single `Chunk`, of at
+    /// most `max` bytes.
+    ///
+    /// Unlike `Stream::concat2`, the future fails with `Error::BodyTooLarge`
+    /// as soon as a chunk would take the body past `max`, without buffering
+    /// that chunk or polling the rest of the body. This bounds the memory
+    /// a client can make a service use by sending a large body.
+    pub fn concat_limited(self, max: usize) -> ConcatLimited {
+        ConcatLimited {
+            body: self,
+            buf: BytesMut::new(),
+            max: max,
+        }
+    }
+
     fn new(kind: Kind) -> Body {
         Body {
             kind: kind,
             delayed_eof: None,
+            on_first_poll: None,
         }
     }
 
@@ -101,6 +192,16 @@ impl Body {
         self.delayed_eof = Some(DelayEof::NotEof(fut));
     }
 
+    pub(crate) fn on_first_poll(&mut self, tx: oneshot::Sender<()>) {
+        self.on_first_poll = Some(tx);
+    }
+
+    fn notify_first_poll(&mut self) {
+        if let Some(tx) = self.on_first_poll.take() {
+            let _ = tx.send(());
+        }
+    }
+
     fn poll_eof(&mut self) -> Poll<Option<Chunk>, ::Error> {
         match self.delayed_eof.take() {
             Some(DelayEof::NotEof(mut delay)) => {
@@ -150,6 +251,7 @@ impl Body {
                 Async::NotReady => Ok(Async::NotReady),
             },
             Kind::Once(ref mut val) => Ok(Async::Ready(val.take())),
+            Kind::Reader(ref mut reader) => reader.0.get_mut().unwrap().poll(),
             Kind::Empty => Ok(Async::Ready(None)),
         }
     }
@@ -168,6 +270,7 @@ impl Stream for Body {
 
     #[inline]
     fn poll(&mut self) -> Poll<Option<Chunk>, ::Error> {
+        self.notify_first_poll();
         self.poll_eof()
     }
 }
@@ -180,6 +283,48 @@ impl fmt::Debug for Body {
     }
 }
 
+impl fmt::Debug for ReaderBody {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("Reader")
+    }
+}
+
+impl<R: AsyncRead> Stream for ReadChunks<R> {
+    type Item = Chunk;
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Option<Chunk>, ::Error> {
+        let chunk_size = self.chunk_size;
+        let n = {
+            let buf = self.buf.get_or_insert_with(|| BytesMut::from(vec![0; chunk_size]));
+            try_ready!(self.reader.poll_read(&mut buf[..]))
+        };
+        if n == 0 {
+            self.buf = None;
+            return Ok(Async::Ready(None));
+        }
+        let mut buf = self.buf.take().expect("buffer was just read into");
+        buf.truncate(n);
+        Ok(Async::Ready(Some(Chunk::from(buf.freeze()))))
+    }
+}
+
+impl Future for ConcatLimited {
+    type Item = Chunk;
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Chunk, ::Error> {
+        while let Some(chunk) = try_ready!(self.body.poll()) {
+            if chunk.len() > self.max - self.buf.len() {
+                debug!("body exceeds the limit of {} bytes", self.max);
+                return Err(::Error::BodyTooLarge);
+            }
+            self.buf.extend_from_slice(&chunk);
+        }
+        Ok(Async::Ready(self.buf.take().freeze().into()))
+    }
+}
+
 //pub(crate)
 pub fn channel() -> (ChunkSender, Body) {
     let (tx, rx) = mpsc::channel(0);
@@ -226,7 +371,9 @@ impl ChunkSender {
 
 feat_server_proto! {
     impl From<Body> for tokio_proto::streaming::Body<Chunk, ::Error> {
-        fn from(b: Body) -> tokio_proto::streaming::Body<Chunk, ::Error> {
+        fn from(mut b: Body) -> tokio_proto::streaming::Body<Chunk, ::Error> {
+            // the converted body can't tell when it is polled
+            b.notify_first_poll();
             match b.kind {
                 Kind::Tokio(b) => b,
                 Kind::Chan { close_tx, rx } => {
@@ -236,6 +383,15 @@ feat_server_proto! {
                     rx.into()
                 },
                 Kind::Once(Some(chunk)) => TokioBody::from(chunk),
+                Kind::Reader(_) => {
+                    // reading needs polling the reader, which this body can't
+                    // do for lack of a task of its own
+                    debug!("a body from a reader can't be sent by a tokio-proto server");
+                    let (mut tx, body) = TokioBody::pair();
+                    let err = io::Error::new(io::ErrorKind::Other, "unsupported reader body");
+                    let _ = tx.try_send(Err(::Error::Io(err)));
+                    body
+                },
                 Kind::Once(None) |
                 Kind::Empty => TokioBody::empty(),
             }
@@ -352,3 +508,64 @@ fn test_body_stream_concat() {
     assert_eq!(total.as_ref(), b"hello world");
 
 }
+
+#[test]
+fn test_body_concat_limited() {
+    use futures::{Sink, Future};
+    let (tx, body) = Body::pair();
+
+    ::std::thread::spawn(move || {
+        let tx = tx.send(Ok("hello ".into())).wait().unwrap();
+        tx.send(Ok("world".into())).wait().unwrap();
+    });
+
+    let total = body.concat_limited(11).wait().unwrap();
+    assert_eq!(total.as_ref(), b"hello world");
+}
+
+#[test]
+fn test_body_concat_limited_too_large() {
+    use futures::{Sink, Future};
+    let (tx, body) = Body::pair();
+
+    ::std::thread::spawn(move || {
+        let tx = tx.send(Ok("hello ".into())).wait().unwrap();
+        let _ = tx.send(Ok("world".into())).wait();
+    });
+
+    match body.concat_limited(10).wait() {
+        Err(::Error::BodyTooLarge) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn test_body_from_shared() {
+    use futures::Stream;
+
+    let mem: Arc<[u8]> = Arc::from(&b"hello world"[..]);
+    let body = Body::from_shared(mem.clone());
+    assert_eq!(Arc::strong_count(&mem), 2);
+    let chunks = body.collect().wait().unwrap();
+    assert_eq!(chunks.len(), 1);
+    // the chunk points at the shared memory, nothing was copied
+    assert_eq!(chunks[0].as_ptr(), mem.as_ptr());
+    assert_eq!(&chunks[0][..], b"hello world");
+    drop(chunks);
+    assert_eq!(Arc::strong_count(&mem), 1);
+
+    let mut chunk = Chunk::from(mem.clone());
+    chunk.extend(b"!".iter().cloned());
+    assert_eq!(&chunk[..], b"hello world!");
+    assert_eq!(&mem[..], b"hello world");
+}
+
+#[test]
+fn test_body_from_reader() {
+    use futures::Stream;
+
+    let body = Body::from_reader(&b"hello world"[..], 4);
+    let chunks = body.collect().wait().unwrap();
+    let chunks = chunks.iter().map(|chunk| chunk.as_ref()).collect::<Vec<_>>();
+    assert_eq!(chunks, vec![&b"hell"[..], b"o wo", b"rld"]);
+}
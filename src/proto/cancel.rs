@@ -0,0 +1,130 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use futures::{Async, Future, Poll};
+use futures::task::{self, Task};
+
+/// A token cancelled once the connection of a `Request` goes away.
+///
+/// Every `Request` read from a connection carries a clone of the same
+/// token, see `Request::cancel_token`. It is cancelled when the client
+/// closes its side of the connection while a response is still pending,
+/// including while the body of the response is being written, when a
+/// graceful shutdown of the connection starts, or when the connection is
+/// closed for any other reason.
+///
+/// A service that is slow to respond can check `is_cancelled`, or select
+/// on `cancelled`, to stop computing a response nobody will receive. A
+/// streaming body, such as of server-sent events or a long-poll, can do
+/// the same to stop producing chunks once the client went away.
+#[derive(Clone)]
+pub struct CancelToken {
+    inner: Arc<Inner>,
+}
+
+/// A future resolving once its `CancelToken` is cancelled.
+///
+/// Created with `CancelToken::cancelled`.
+#[must_use = "futures do nothing unless polled"]
+pub struct Cancelled {
+    token: CancelToken,
+}
+
+struct Inner {
+    cancelled: AtomicBool,
+    tasks: Mutex<Vec<Task>>,
+}
+
+impl CancelToken {
+    pub(crate) fn new() -> CancelToken {
+        CancelToken {
+            inner: Arc::new(Inner {
+                cancelled: AtomicBool::new(false),
+                tasks: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// Whether this token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// A future resolving once this token is cancelled.
+    pub fn cancelled(&self) -> Cancelled {
+        Cancelled {
+            token: self.clone(),
+        }
+    }
+
+    pub(crate) fn cancel(&self) {
+        if !self.inner.cancelled.swap(true, Ordering::SeqCst) {
+            trace!("cancel token cancelled");
+            let tasks = ::std::mem::replace(&mut *self.inner.tasks.lock().unwrap(), Vec::new());
+            for task in tasks {
+                task.notify();
+            }
+        }
+    }
+}
+
+impl fmt::Debug for CancelToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CancelToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+impl Future for Cancelled {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        if self.token.is_cancelled() {
+            return Ok(Async::Ready(()));
+        }
+        let mut tasks = self.token.inner.tasks.lock().unwrap();
+        // checked again while locked, since `cancel` takes the tasks
+        // only after marking the token
+        if self.token.is_cancelled() {
+            return Ok(Async::Ready(()));
+        }
+        if !tasks.iter().any(|task| task.will_notify_current()) {
+            tasks.push(task::current());
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+impl fmt::Debug for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Cancelled")
+            .field("token", &self.token)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::Future;
+    use futures::future;
+
+    use super::CancelToken;
+
+    #[test]
+    fn cancel_wakes_cancelled_future() {
+        let token = CancelToken::new();
+        let mut cancelled = token.clone().cancelled();
+
+        future::lazy(|| {
+            assert!(cancelled.poll().unwrap().is_not_ready());
+            token.cancel();
+            assert!(cancelled.poll().unwrap().is_ready());
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+
+        assert!(token.is_cancelled());
+    }
+}
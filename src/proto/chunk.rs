@@ -1,5 +1,6 @@
 use std::fmt;
 //use std::mem;
+use std::sync::Arc;
 
 use bytes::Bytes;
 
@@ -8,6 +9,9 @@ pub struct Chunk(Inner);
 
 enum Inner {
     Shared(Bytes),
+    // Memory shared with the application, such as a cached static asset,
+    // written without being copied.
+    Arc(Arc<[u8]>),
 }
 
 impl From<Vec<u8>> for Chunk {
@@ -38,6 +42,15 @@ impl From<&'static str> for Chunk {
     }
 }
 
+/// The memory is shared, not copied: the chunk keeps a reference to it
+/// until it was written.
+impl From<Arc<[u8]>> for Chunk {
+    #[inline]
+    fn from(mem: Arc<[u8]>) -> Chunk {
+        Chunk(Inner::Arc(mem))
+    }
+}
+
 impl From<Bytes> for Chunk {
     #[inline]
     fn from(mem: Bytes) -> Chunk {
@@ -45,11 +58,13 @@ impl From<Bytes> for Chunk {
     }
 }
 
+/// A chunk of shared memory, see `From<Arc<[u8]>>`, is copied.
 impl From<Chunk> for Bytes {
     #[inline]
     fn from(chunk: Chunk) -> Bytes {
         match chunk.0 {
             Inner::Shared(bytes) => bytes,
+            Inner::Arc(mem) => Bytes::from(&mem[..]),
         }
     }
 }
@@ -68,6 +83,7 @@ impl AsRef<[u8]> for Chunk {
     fn as_ref(&self) -> &[u8] {
         match self.0 {
             Inner::Shared(ref slice) => slice,
+            Inner::Arc(ref mem) => mem,
         }
     }
 }
@@ -92,17 +108,18 @@ impl IntoIterator for Chunk {
 
     #[inline]
     fn into_iter(self) -> Self::IntoIter {
-        match self.0 {
-            Inner::Shared(bytes) => bytes.into_iter(),
-        }
+        Bytes::from(self).into_iter()
     }
 }
 
 impl Extend<u8> for Chunk {
     #[inline]
     fn extend<T>(&mut self, iter: T) where T: IntoIterator<Item=u8> {
-        match self.0 {
-            Inner::Shared(ref mut bytes) => bytes.extend(iter)
-        }
+        let copied = match self.0 {
+            Inner::Shared(ref mut bytes) => return bytes.extend(iter),
+            Inner::Arc(ref mem) => Bytes::from(&mem[..]),
+        };
+        self.0 = Inner::Shared(copied);
+        self.extend(iter)
     }
 }
@@ -0,0 +1,305 @@
+use std::error::Error as StdError;
+#[allow(unused, deprecated)]
+use std::ascii::AsciiExt;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::{self, FromStr};
+
+use header::Headers;
+
+/// A range of IP addresses, such as `10.0.0.0/8` or `2001:db8::/32`.
+///
+/// See `Http::trust_forwarded_for`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct IpNet {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpNet {
+    /// Create the range of the addresses starting with the first
+    /// `prefix_len` bits of `addr`. The other bits of `addr` are cleared.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `prefix_len` is larger than 32 for an IPv4 address, or than
+    /// 128 for an IPv6 address.
+    pub fn new(addr: IpAddr, prefix_len: u8) -> IpNet {
+        assert!(prefix_len <= max_prefix_len(&addr), "prefix length {} is too long for {}", prefix_len, addr);
+        IpNet {
+            addr: network(addr, prefix_len),
+            prefix_len: prefix_len,
+        }
+    }
+
+    /// The first address of the range.
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    /// The number of leading bits shared by the addresses of the range.
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    /// Whether `ip` is in this range.
+    ///
+    /// An IPv4-mapped IPv6 address, such as a client of a listener bound to
+    /// `[::]`, is compared as the IPv4 address it maps.
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        let ip = canonical(*ip);
+        max_prefix_len(&ip) == max_prefix_len(&self.addr) && network(ip, self.prefix_len) == self.addr
+    }
+}
+
+impl fmt::Display for IpNet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+impl FromStr for IpNet {
+    type Err = IpNetError;
+
+    /// Parses `addr/prefix_len`, or a single address as a range of one.
+    fn from_str(s: &str) -> Result<IpNet, IpNetError> {
+        let (addr, prefix_len) = match s.find('/') {
+            Some(i) => (&s[..i], Some(&s[i + 1..])),
+            None => (s, None),
+        };
+        let addr = addr.parse::<IpAddr>().map_err(|_| IpNetError(()))?;
+        let prefix_len = match prefix_len {
+            Some(len) => len.parse::<u8>().map_err(|_| IpNetError(()))?,
+            None => max_prefix_len(&addr),
+        };
+        if prefix_len > max_prefix_len(&addr) {
+            return Err(IpNetError(()));
+        }
+        Ok(IpNet::new(addr, prefix_len))
+    }
+}
+
+/// An error parsing an `IpNet`.
+#[derive(Clone, Debug)]
+pub struct IpNetError(());
+
+impl fmt::Display for IpNetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.description())
+    }
+}
+
+impl StdError for IpNetError {
+    fn description(&self) -> &str {
+        "invalid IP address range"
+    }
+}
+
+fn max_prefix_len(addr: &IpAddr) -> u8 {
+    match *addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+// `addr` with the bits after the first `len` cleared.
+fn network(addr: IpAddr, len: u8) -> IpAddr {
+    match addr {
+        IpAddr::V4(v4) => {
+            let mask = if len == 0 { 0 } else { !0u32 << (32 - len as u32) };
+            IpAddr::V4(Ipv4Addr::from(u32::from(v4) & mask))
+        },
+        IpAddr::V6(v6) => {
+            let mut octets = v6.octets();
+            for (i, octet) in octets.iter_mut().enumerate() {
+                let kept = (len as usize).saturating_sub(i * 8);
+                if kept < 8 {
+                    *octet &= !(0xffu8 >> kept);
+                }
+            }
+            IpAddr::V6(Ipv6Addr::from(octets))
+        },
+    }
+}
+
+fn canonical(ip: IpAddr) -> IpAddr {
+    if let IpAddr::V6(v6) = ip {
+        let s = v6.segments();
+        if s[..5] == [0, 0, 0, 0, 0] && s[5] == 0xffff {
+            return IpAddr::V4(Ipv4Addr::new((s[6] >> 8) as u8, s[6] as u8, (s[7] >> 8) as u8, s[7] as u8));
+        }
+    }
+    ip
+}
+
+/// The address of the client of a request received from `peer`, according
+/// to its `Forwarded` or `X-Forwarded-For` header.
+///
+/// The header is only used when `peer` is in one of the `trusted` ranges.
+/// Its addresses are then walked from the right, skipping the trusted
+/// proxies that appended them, and the first address that isn't trusted is
+/// the client. An address without a port, as in `X-Forwarded-For`, gets
+/// the port 0. A malformed header is ignored, leaving `peer`.
+pub(crate) fn client_addr(peer: SocketAddr, headers: &Headers, trusted: &[IpNet]) -> SocketAddr {
+    let is_trusted = |ip: &IpAddr| trusted.iter().any(|net| net.contains(ip));
+    if !is_trusted(&peer.ip()) {
+        return peer;
+    }
+    let nodes = if let Some(raw) = headers.get_raw("Forwarded") {
+        raw.iter().map(parse_forwarded).collect::<Option<Vec<_>>>()
+    } else if let Some(raw) = headers.get_raw("X-Forwarded-For") {
+        raw.iter().map(parse_x_forwarded_for).collect::<Option<Vec<_>>>()
+    } else {
+        return peer;
+    };
+    let nodes = match nodes {
+        Some(nodes) => nodes,
+        None => {
+            debug!("ignoring malformed forwarded header from {}", peer);
+            return peer;
+        }
+    };
+    let mut client = peer;
+    for &node in nodes.iter().flat_map(|line| line.iter()).rev() {
+        client = node;
+        if !is_trusted(&node.ip()) {
+            break;
+        }
+    }
+    client
+}
+
+// The `for` addresses of a line of `Forwarded`, see RFC 7239.
+fn parse_forwarded(line: &[u8]) -> Option<Vec<SocketAddr>> {
+    let line = match str::from_utf8(line) {
+        Ok(line) => line,
+        Err(_) => return None,
+    };
+    line.split(',').map(|element| {
+        element.split(';').filter_map(|pair| {
+            let pair = pair.trim();
+            match pair.find('=') {
+                Some(eq) if pair[..eq].eq_ignore_ascii_case("for") => Some(pair[eq + 1..].trim_matches('"')),
+                _ => None,
+            }
+        }).next().and_then(parse_node)
+    }).collect()
+}
+
+fn parse_x_forwarded_for(line: &[u8]) -> Option<Vec<SocketAddr>> {
+    let line = match str::from_utf8(line) {
+        Ok(line) => line,
+        Err(_) => return None,
+    };
+    line.split(',').map(|node| parse_node(node.trim())).collect()
+}
+
+// An address, with an optional port: `192.0.2.1`, `192.0.2.1:80`,
+// `[2001:db8::1]:80`, or a bare `2001:db8::1`. The identifiers `unknown`
+// and `_hidden` of `Forwarded` aren't addresses.
+fn parse_node(node: &str) -> Option<SocketAddr> {
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr);
+    }
+    let ip = if node.starts_with('[') && node.ends_with(']') {
+        &node[1..node.len() - 1]
+    } else {
+        node
+    };
+    ip.parse::<IpAddr>().ok().map(|ip| SocketAddr::new(ip, 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use header::Headers;
+    use super::{client_addr, IpNet};
+
+    fn net(s: &str) -> IpNet {
+        s.parse().unwrap()
+    }
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_ip_net_contains() {
+        let v4 = net("10.1.0.0/16");
+        assert!(v4.contains(&"10.1.200.3".parse().unwrap()));
+        assert!(!v4.contains(&"10.2.0.1".parse().unwrap()));
+        assert!(v4.contains(&"::ffff:10.1.0.1".parse().unwrap()));
+        assert!(!v4.contains(&"2001:db8::1".parse().unwrap()));
+
+        let v6 = net("2001:db8::/32");
+        assert!(v6.contains(&"2001:db8:1::1".parse().unwrap()));
+        assert!(!v6.contains(&"2001:db9::1".parse().unwrap()));
+
+        let v6 = net("2001:db8:ab00::/41");
+        assert!(v6.contains(&"2001:db8:ab7f::1".parse().unwrap()));
+        assert!(!v6.contains(&"2001:db8:ab80::1".parse().unwrap()));
+
+        assert!(net("0.0.0.0/0").contains(&"192.0.2.1".parse().unwrap()));
+        assert!(net("192.0.2.1").contains(&"192.0.2.1".parse().unwrap()));
+        assert!(!net("192.0.2.1").contains(&"192.0.2.2".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_ip_net_parse_invalid() {
+        assert!("10.0.0.0/33".parse::<IpNet>().is_err());
+        assert!("::/129".parse::<IpNet>().is_err());
+        assert!("10.0.0/8".parse::<IpNet>().is_err());
+        assert!("10.0.0.0/".parse::<IpNet>().is_err());
+        assert_eq!(net("10.0.0.0/8").to_string(), "10.0.0.0/8");
+    }
+
+    #[test]
+    fn test_ip_net_clears_host_bits() {
+        assert_eq!(net("10.1.2.3/8").to_string(), "10.0.0.0/8");
+        assert_eq!(net("10.1.2.3/0").to_string(), "0.0.0.0/0");
+        assert_eq!(net("2001:db8:abcd::1/36").to_string(), "2001:db8:a000::/36");
+        assert_eq!(net("2001:db8::1").to_string(), "2001:db8::1/128");
+    }
+
+    #[test]
+    fn test_client_addr_x_forwarded_for() {
+        let trusted = [net("10.0.0.0/8")];
+        let peer = addr("10.0.0.1:4000");
+        let mut headers = Headers::new();
+        headers.set_raw("X-Forwarded-For", "198.51.100.1, 203.0.113.7, 10.0.0.2");
+        // the proxy 10.0.0.2 is skipped, a client can spoof the leftmost
+        assert_eq!(client_addr(peer, &headers, &trusted), addr("203.0.113.7:0"));
+
+        assert_eq!(client_addr(addr("192.0.2.1:4000"), &headers, &trusted), addr("192.0.2.1:4000"));
+
+        headers.set_raw("X-Forwarded-For", vec![b"203.0.113.7".to_vec(), b"10.0.0.2".to_vec()]);
+        assert_eq!(client_addr(peer, &headers, &trusted), addr("203.0.113.7:0"));
+
+        headers.set_raw("X-Forwarded-For", "10.0.0.3, 10.0.0.2");
+        assert_eq!(client_addr(peer, &headers, &trusted), addr("10.0.0.3:0"));
+
+        headers.set_raw("X-Forwarded-For", "203.0.113.7, garbage");
+        assert_eq!(client_addr(peer, &headers, &trusted), peer);
+
+        assert_eq!(client_addr(peer, &Headers::new(), &trusted), peer);
+    }
+
+    #[test]
+    fn test_client_addr_forwarded() {
+        let trusted = [net("10.0.0.0/8")];
+        let peer = addr("10.0.0.1:4000");
+        let mut headers = Headers::new();
+        headers.set_raw("Forwarded", "for=192.0.2.60;proto=http;by=203.0.113.43, For=\"[2001:db8:cafe::17]:4711\"");
+        headers.set_raw("X-Forwarded-For", "198.51.100.1");
+        assert_eq!(client_addr(peer, &headers, &trusted), addr("[2001:db8:cafe::17]:4711"));
+
+        headers.set_raw("Forwarded", "for=192.0.2.43:80, for=10.0.0.2");
+        assert_eq!(client_addr(peer, &headers, &trusted), addr("192.0.2.43:80"));
+
+        headers.set_raw("Forwarded", "for=unknown");
+        assert_eq!(client_addr(peer, &headers, &trusted), peer);
+        headers.set_raw("Forwarded", "proto=https");
+        assert_eq!(client_addr(peer, &headers, &trusted), peer);
+    }
+}
@@ -1,21 +1,30 @@
+use std::cmp;
 use std::fmt;
-use std::io::{self};
+use std::io::{self, Write};
 use std::marker::PhantomData;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
 use bytes::Bytes;
-use futures::{Async, AsyncSink, Poll, StartSend};
+use futures::{Async, AsyncSink, Future, Poll, StartSend};
 #[cfg(feature = "tokio-proto")]
 use futures::{Sink, Stream};
 #[cfg(feature = "tokio-proto")]
 use futures::task::Task;
+use tokio::reactor::{Remote, Timeout};
 use tokio_io::{AsyncRead, AsyncWrite};
 #[cfg(feature = "tokio-proto")]
 use tokio_proto::streaming::pipeline::{Frame, Transport};
 
-use proto::{Chunk, Decode, Http1Transaction, MessageHead};
+use proto::{Chunk, Decode, ExchangeInfo, Http1Transaction, MessageHead, TimingInfo, WriteDrained};
+use proto::response::{self, Response};
 use super::io::{Cursor, Buffered};
 use super::{EncodedBuf, Encoder, Decoder};
+use header::{ContentLength, ContentType, Date, Headers, Server, TransferEncoding};
+use mime;
 use method::Method;
+use status::StatusCode;
 use version::HttpVersion;
 
 
@@ -29,6 +38,7 @@ use version::HttpVersion;
 pub struct Conn<I, B, T> {
     io: Buffered<I, EncodedBuf<Cursor<B>>>,
     state: State,
+    timeouts: Timeouts,
     _marker: PhantomData<T>
 }
 
@@ -52,32 +62,235 @@ where I: AsyncRead + AsyncWrite,
         Conn {
             io: Buffered::new(io),
             state: State {
+                auto_date: true,
+                auto_drain_body: false,
+                connect_tunnel: false,
+                close_announced: false,
+                continue_withheld: false,
+                defer_continue: false,
                 error: None,
                 #[cfg(feature = "tokio-proto")]
                 is_tokio_proto: false,
                 keep_alive: KA::Busy,
+                max_body_size: None,
+                max_drain_bytes: None,
+                max_pipeline_depth: None,
+                event_stream: false,
+                max_requests: None,
+                body_read: 0,
+                body_drained: 0,
+                bytes_written: 0,
+                date_clock: None,
+                head_read: 0,
+                http10_keep_alive: true,
+                idle_timed_out: false,
+                messages_read: 0,
                 method: None,
                 notify_read: false,
+                read_probed: false,
+                on_exchange_complete: None,
+                on_write_drained: None,
+                on_parse_error: None,
+                internal_error_response: None,
+                pending_continue: false,
                 #[cfg(feature = "tokio-proto")]
                 read_task: None,
                 reading: Reading::Init,
+                reject_conflicting_framing: false,
+                remote_addr: None,
+                require_host: false,
+                send_408_on_timeout: false,
+                server_header: None,
+                timing: None,
+                unflushed_messages: 0,
+                upgraded: false,
                 writing: Writing::Init,
                 // We assume a modern world where the remote speaks HTTP/1.1.
                 // If they tell us otherwise, we'll downgrade in `read_head`.
                 version: Version::Http11,
             },
+            timeouts: Timeouts {
+                remote: None,
+                header_read: None,
+                header_read_timer: None,
+                min_head_rate: None,
+                head_rate_timer: None,
+                head_rate_mark: 0,
+                body_read: None,
+                body_read_timer: None,
+                keep_alive: None,
+                keep_alive_timer: None,
+                write: None,
+                write_timer: None,
+                coalesce: None,
+                coalesce_timer: None,
+                coalesce_elapsed: false,
+                max_age: None,
+                max_age_timer: None,
+            },
             _marker: PhantomData,
         }
     }
 
+    pub fn set_timer(&mut self, remote: Remote) {
+        self.timeouts.remote = Some(remote);
+    }
+
+    pub fn set_header_read_timeout(&mut self, dur: Duration) {
+        self.timeouts.header_read = Some(dur);
+    }
+
+    pub fn set_min_head_read_rate(&mut self, bytes: usize, per: Duration) {
+        self.timeouts.min_head_rate = Some((bytes, per));
+    }
+
+    pub fn set_body_read_timeout(&mut self, dur: Duration) {
+        self.timeouts.body_read = Some(dur);
+    }
+
+    pub fn set_keep_alive_timeout(&mut self, dur: Duration) {
+        self.timeouts.keep_alive = Some(dur);
+    }
+
+    /// Waits at most `dur` for a request head, shortening longer keep-alive
+    /// and header read timeouts.
+    pub fn limit_head_wait(&mut self, dur: Duration) {
+        let shortest = |current: Option<Duration>| Some(current.map_or(dur, |current| cmp::min(current, dur)));
+        self.timeouts.keep_alive = shortest(self.timeouts.keep_alive);
+        self.timeouts.header_read = shortest(self.timeouts.header_read);
+    }
+
+    pub fn set_write_timeout(&mut self, dur: Duration) {
+        self.timeouts.write = Some(dur);
+    }
+
+    pub fn set_write_coalesce(&mut self, max_delay: Duration, max_bytes: usize) {
+        // tiny chunks are copied together, rather than queued one by one
+        self.io.set_write_strategy_flatten();
+        self.timeouts.coalesce = Some((max_delay, max_bytes));
+    }
+
+    pub fn set_max_age(&mut self, dur: Duration) {
+        self.timeouts.max_age = Some(dur);
+    }
+
+    pub fn set_http10_keep_alive(&mut self, enabled: bool) {
+        self.state.http10_keep_alive = enabled;
+    }
+
+    pub fn set_defer_continue(&mut self, enabled: bool) {
+        self.state.defer_continue = enabled;
+    }
+
     pub fn set_flush_pipeline(&mut self, enabled: bool) {
         self.io.set_flush_pipeline(enabled);
     }
 
+    pub fn set_max_pipeline_depth(&mut self, max: Option<usize>) {
+        self.state.max_pipeline_depth = max;
+    }
+
+    pub fn set_max_requests(&mut self, max: Option<u64>) {
+        self.state.max_requests = max;
+    }
+
+    pub fn set_max_body_size(&mut self, max: Option<u64>) {
+        self.state.max_body_size = max;
+    }
+
+    pub fn set_auto_drain_body(&mut self, enabled: bool) {
+        self.state.auto_drain_body = enabled;
+    }
+
+    pub fn set_max_drain_bytes(&mut self, max: Option<u64>) {
+        self.state.max_drain_bytes = max;
+    }
+
+    pub fn set_reject_conflicting_framing(&mut self, enabled: bool) {
+        self.state.reject_conflicting_framing = enabled;
+    }
+
+    pub fn set_require_host(&mut self, enabled: bool) {
+        self.state.require_host = enabled;
+    }
+
+    pub fn set_send_408_on_timeout(&mut self, enabled: bool) {
+        self.state.send_408_on_timeout = enabled;
+    }
+
     pub fn set_max_buf_size(&mut self, max: usize) {
         self.io.set_max_buf_size(max);
     }
 
+    pub fn set_read_buf_init(&mut self, size: usize) {
+        self.io.set_read_buf_init(size);
+    }
+
+    pub fn set_write_buf_limit(&mut self, limit: Option<usize>) {
+        self.io.set_write_buf_limit(limit);
+    }
+
+    pub fn set_auto_date(&mut self, enabled: bool) {
+        self.state.auto_date = enabled;
+    }
+
+    pub fn set_connect_tunnel(&mut self, enabled: bool) {
+        self.state.connect_tunnel = enabled;
+    }
+
+    pub fn set_date_clock(&mut self, clock: Arc<Fn() -> SystemTime + Send + Sync>) {
+        self.state.date_clock = Some(clock);
+    }
+
+    pub fn set_server_header(&mut self, server: Option<Server>) {
+        self.state.server_header = server;
+    }
+
+    pub fn set_on_exchange_complete(&mut self, f: Arc<Fn(ExchangeInfo) + Send + Sync>) {
+        self.state.on_exchange_complete = Some(f);
+    }
+
+    pub fn set_on_write_drained(&mut self, f: Arc<Fn(WriteDrained) + Send + Sync>) {
+        self.state.on_write_drained = Some(f);
+    }
+
+    pub fn set_on_parse_error(&mut self, f: Arc<Fn(&::Error, Option<SocketAddr>) -> Option<Response<Chunk>> + Send + Sync>) {
+        self.state.on_parse_error = Some(f);
+    }
+
+    pub fn set_internal_error_response(&mut self, f: Arc<Fn(&::Error) -> Response<Chunk> + Send + Sync>) {
+        self.state.internal_error_response = Some(f);
+    }
+
+    pub fn set_remote_addr(&mut self, addr: Option<SocketAddr>) {
+        self.state.remote_addr = addr;
+    }
+
+    pub fn set_read_buf(&mut self, buf: &[u8]) {
+        self.io.set_read_buf(buf);
+    }
+
+    pub fn set_on_exchange_timing(&mut self, f: Arc<Fn(TimingInfo) + Send + Sync>) {
+        self.state.timing = Some(Timing {
+            callback: f,
+            head_received: None,
+            service_called: None,
+            unflushed: Vec::new(),
+        });
+    }
+
+    pub fn set_max_headers(&mut self, max: usize) {
+        self.io.set_max_headers(max);
+    }
+
+    pub fn set_max_head_size(&mut self, max: Option<usize>) {
+        self.io.set_max_head_size(max);
+    }
+
+    pub fn set_max_uri_len(&mut self, max: Option<usize>) {
+        self.io.set_max_uri_len(max);
+    }
+
     pub fn set_write_strategy_flatten(&mut self) {
         self.io.set_write_strategy_flatten();
     }
@@ -86,6 +299,14 @@ where I: AsyncRead + AsyncWrite,
         self.io.into_inner()
     }
 
+    pub fn bytes_read(&self) -> u64 {
+        self.io.bytes_read()
+    }
+
+    pub fn bytes_written(&self) -> u64 {
+        self.io.bytes_written()
+    }
+
     #[cfg(feature = "tokio-proto")]
     fn poll_incoming(&mut self) -> Poll<Option<Frame<MessageHead<T::Incoming>, Chunk, ::Error>>, io::Error> {
         trace!("Conn::poll_incoming()");
@@ -159,6 +380,23 @@ where I: AsyncRead + AsyncWrite,
         self.state.is_write_closed()
     }
 
+    /// Whether the last message written switched this connection away
+    /// from HTTP.
+    pub fn is_upgraded(&self) -> bool {
+        self.state.upgraded
+    }
+
+    /// Whether this connection was closed by the keep-alive timeout,
+    /// while idle between messages.
+    pub fn is_idle_timed_out(&self) -> bool {
+        self.state.idle_timed_out
+    }
+
+    /// The number of message heads read so far.
+    pub fn messages_read(&self) -> u64 {
+        self.state.messages_read
+    }
+
     pub fn can_read_head(&self) -> bool {
         match self.state.reading {
             //Reading::Init => true,
@@ -194,16 +432,41 @@ where I: AsyncRead + AsyncWrite,
 
         loop {
             let (version, head) = match self.io.parse::<T>() {
-                Ok(Async::Ready(head)) => (head.version, head),
-                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready((head, len))) => {
+                    self.timeouts.disarm_head();
+                    self.state.head_read = len as u64;
+                    if let Some(ref mut timing) = self.state.timing {
+                        timing.head_received = Some(Instant::now());
+                        timing.service_called = None;
+                    }
+                    (head.version, head)
+                },
+                Ok(Async::NotReady) => {
+                    if self.poll_head_timeouts()? {
+                        return Ok(Async::Ready(None));
+                    }
+                    return Ok(Async::NotReady);
+                },
                 Err(e) => {
+                    self.timeouts.disarm_head();
                     // If we are currently waiting on a message, then an empty
                     // message should be reported as an error. If not, it is just
                     // the connection closing gracefully.
                     let must_error = self.should_error_on_eof();
                     self.state.close_read();
                     self.io.consume_leading_lines();
-                    let was_mid_parse = !self.io.read_buf().is_empty();
+                    let e = match e {
+                        ::Error::Version if T::should_read_first() && is_h2_preface(self.io.read_buf()) => {
+                            ::Error::Http2Preface
+                        },
+                        e => e,
+                    };
+                    // A complete head that was larger than allowed has
+                    // already been taken out of the read buffer.
+                    let was_mid_parse = match e {
+                        ::Error::TooLarge => true,
+                        _ => !self.io.read_buf().is_empty(),
+                    };
                     return if was_mid_parse || must_error {
                         debug!("parse error ({}) with {} bytes", e, self.io.read_buf().len());
                         self.on_parse_error(e)
@@ -225,6 +488,28 @@ where I: AsyncRead + AsyncWrite,
                 }
             };
 
+            // A message framed by both could be read differently by a proxy
+            // in front, which is how requests are smuggled past it.
+            if self.state.reject_conflicting_framing
+                && head.headers.has::<TransferEncoding>()
+                && head.headers.has::<ContentLength>() {
+                debug!("message with both Transfer-Encoding and Content-Length");
+                self.state.close_read();
+                return self.on_parse_error(::Error::Header)
+                    .map(|()| Async::NotReady);
+            }
+
+            // A request without exactly one Host could be routed, or cached,
+            // differently by a proxy in front.
+            if self.state.require_host
+                && head.version == HttpVersion::Http11
+                && head.headers.get_raw("Host").map_or(0, |raw| raw.len()) != 1 {
+                debug!("HTTP/1.1 request without exactly one Host header");
+                self.state.close_read();
+                return self.on_parse_error(::Error::Header)
+                    .map(|()| Async::NotReady);
+            }
+
             let decoder = match T::decoder(&head, &mut self.state.method) {
                 Ok(Decode::Normal(d)) => {
                     d
@@ -249,23 +534,43 @@ where I: AsyncRead + AsyncWrite,
 
             debug!("incoming body is {}", decoder);
 
-            self.state.busy();
-            if head.expecting_continue() {
-                let msg = b"HTTP/1.1 100 Continue\r\n\r\n";
-                self.io.write_buf_mut().extend_from_slice(msg);
+            if !decoder.is_eof() {
+                if let (Some(max), Some(&ContentLength(len))) = (self.state.max_body_size, head.headers.get()) {
+                    if len > max {
+                        debug!("content-length ({}) exceeds max body size ({})", len, max);
+                        return self.on_body_too_large()
+                            .map(|()| Async::NotReady);
+                    }
+                }
             }
-            let wants_keep_alive = head.should_keep_alive();
+
+            self.state.busy();
+            self.state.body_read = 0;
+            let wants_keep_alive = match self.state.version {
+                Version::Http10 => self.state.http10_keep_alive && head.should_keep_alive(),
+                Version::Http11 => head.should_keep_alive(),
+            };
             self.state.keep_alive &= wants_keep_alive;
             let (body, reading) = if decoder.is_eof() {
                 (false, Reading::KeepAlive)
             } else {
                 (true, Reading::Body(decoder))
             };
+            if head.expecting_continue() {
+                if self.state.defer_continue {
+                    // sent by `send_continue`, once the body is wanted
+                    self.state.pending_continue = body;
+                    self.state.continue_withheld = body;
+                } else {
+                    self.write_continue();
+                }
+            }
             if let Reading::Closed = self.state.reading {
                 // actually want an `if not let ...`
             } else {
                 self.state.reading = reading;
             }
+            self.state.messages_read += 1;
             if !body {
                 self.try_keep_alive();
             }
@@ -273,6 +578,65 @@ where I: AsyncRead + AsyncWrite,
         }
     }
 
+    // While waiting for a message head, an idle keep-alive connection is
+    // closed once the keep-alive timeout elapses without any bytes arriving.
+    // As soon as a head has started (immediately on a fresh connection), the
+    // header read timer takes over, and errors if the head doesn't complete.
+    // So does the minimum read rate, sampling the bytes buffered at the end of
+    // every one of its windows.
+    //
+    // Returns `true` if the connection was closed for being idle.
+    fn poll_head_timeouts(&mut self) -> ::Result<bool> {
+        if self.state.is_idle() && self.io.read_buf().is_empty() {
+            let timeouts = &mut self.timeouts;
+            if poll_timer(&mut timeouts.keep_alive_timer, timeouts.keep_alive, timeouts.remote.as_ref())? {
+                debug!("keep-alive timeout elapsed, closing idle connection");
+                self.state.idle_timed_out = true;
+                self.state.close();
+                return Ok(true);
+            }
+        } else {
+            let timeouts = &mut self.timeouts;
+            timeouts.keep_alive_timer = None;
+            if poll_timer(&mut timeouts.header_read_timer, timeouts.header_read, timeouts.remote.as_ref())? {
+                debug!("header read timeout elapsed with {} bytes", self.io.read_buf().len());
+                return self.on_head_timeout();
+            }
+            if let Some((bytes, per)) = timeouts.min_head_rate {
+                let buffered = self.io.read_buf().len();
+                if timeouts.head_rate_timer.is_none() {
+                    // the buffered bytes are the start of this head
+                    timeouts.head_rate_mark = 0;
+                }
+                if poll_timer(&mut timeouts.head_rate_timer, Some(per), timeouts.remote.as_ref())? {
+                    let read = buffered.saturating_sub(timeouts.head_rate_mark);
+                    if read < bytes {
+                        debug!("read {} bytes of head in {:?}, below the minimum of {}", read, per, bytes);
+                        return self.on_head_timeout();
+                    }
+                    timeouts.head_rate_mark = buffered;
+                    // arm the next window
+                    poll_timer(&mut timeouts.head_rate_timer, Some(per), timeouts.remote.as_ref())?;
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    // A head timed out. With `send_408_on_timeout`, a client that sent at
+    // least its request line is answered like a parse error, with a `408`,
+    // before the connection closes.
+    fn on_head_timeout(&mut self) -> ::Result<bool> {
+        self.timeouts.disarm_head();
+        if self.state.send_408_on_timeout && has_request_line(self.io.read_buf()) {
+            self.state.close_read();
+            self.on_parse_error(::Error::HeaderTimeout)?;
+            return Ok(false);
+        }
+        self.state.close();
+        Err(::Error::HeaderTimeout)
+    }
+
     pub fn read_body(&mut self) -> Poll<Option<Chunk>, io::Error> {
         debug_assert!(self.can_read_body());
 
@@ -283,6 +647,18 @@ where I: AsyncRead + AsyncWrite,
                 match decoder.decode(&mut self.io) {
                     Ok(Async::Ready(slice)) => {
                         let (reading, chunk) = if !slice.is_empty() {
+                            self.state.body_read += slice.len() as u64;
+                            if let Some(max) = self.state.max_body_size {
+                                if self.state.body_read > max {
+                                    debug!("incoming body exceeds max body size ({})", max);
+                                    // a response that was already started is
+                                    // left to finish before the connection closes
+                                    let _ = self.on_body_too_large();
+                                    return Err(io::Error::new(io::ErrorKind::InvalidData, ::Error::BodyTooLarge));
+                                }
+                            }
+                            // any progress resets the body read timeout
+                            self.timeouts.body_read_timer = None;
                             return Ok(Async::Ready(Some(Chunk::from(slice))));
                         } else if decoder.is_eof() {
                             debug!("incoming body completed");
@@ -296,7 +672,7 @@ where I: AsyncRead + AsyncWrite,
                         };
                         (reading, Ok(Async::Ready(chunk)))
                     },
-                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Ok(Async::NotReady) => return self.poll_body_timeout(),
                     Err(e) => {
                         trace!("decode stream error: {}", e);
                         (Reading::Closed, Err(e))
@@ -306,11 +682,25 @@ where I: AsyncRead + AsyncWrite,
             _ => unreachable!("read_body invalid state: {:?}", self.state.reading),
         };
 
+        self.timeouts.body_read_timer = None;
         self.state.reading = reading;
         self.try_keep_alive();
         ret
     }
 
+    // While the rest of a body is awaited, reading stops once the body read
+    // timeout elapses without any bytes of it arriving. The service can still
+    // respond, before the connection closes.
+    fn poll_body_timeout(&mut self) -> Poll<Option<Chunk>, io::Error> {
+        let timeouts = &mut self.timeouts;
+        if poll_timer(&mut timeouts.body_read_timer, timeouts.body_read, timeouts.remote.as_ref())? {
+            debug!("body read timeout elapsed after {} bytes", self.state.body_read);
+            self.state.close_read();
+            return Err(io::Error::new(io::ErrorKind::TimedOut, ::Error::BodyTimeout));
+        }
+        Ok(Async::NotReady)
+    }
+
     pub fn read_keep_alive(&mut self) -> Result<(), ::Error> {
         debug_assert!(!self.can_read_head() && !self.can_read_body());
 
@@ -324,6 +714,51 @@ where I: AsyncRead + AsyncWrite,
         Ok(())
     }
 
+    /// While a server waits on its response, check whether the client
+    /// closed its side of the connection.
+    ///
+    /// Returns `true` if reading was closed because of it.
+    pub fn poll_read_closed(&mut self) -> bool {
+        if !T::should_read_first() {
+            return false;
+        }
+        match self.state.reading {
+            Reading::KeepAlive => (),
+            _ => return false,
+        }
+        // any bytes already buffered are a pipelined message, and reading
+        // more would only grow the buffer
+        if !self.is_mid_message() || !self.io.read_buf().is_empty() {
+            return false;
+        }
+        match self.io.read_from_io() {
+            Ok(Async::Ready(0)) => {
+                trace!("poll_read_closed; read eof mid message");
+                self.state.close_read();
+                true
+            },
+            Ok(Async::Ready(_)) => false,
+            Ok(Async::NotReady) => {
+                self.state.read_probed = true;
+                false
+            },
+            Err(e) => {
+                debug!("poll_read_closed; read error mid message: {}", e);
+                self.state.close_read();
+                true
+            }
+        }
+    }
+
+    // A server connection that hasn't received any byte of its first
+    // request is as idle as one waiting between requests, once closing.
+    fn is_awaiting_first_message(&self) -> bool {
+        T::should_read_first() &&
+            self.state.messages_read == 0 &&
+            !self.is_mid_message() &&
+            self.io.read_buf().is_empty()
+    }
+
     fn is_mid_message(&self) -> bool {
         match (&self.state.reading, &self.state.writing) {
             (&Reading::Init, &Writing::Init) => false,
@@ -458,6 +893,9 @@ where I: AsyncRead + AsyncWrite,
                     Ok(Async::Ready(_)) => (),
                     Ok(Async::NotReady) => {
                         trace!("maybe_notify; read_from_io blocked");
+                        // read_head arms the keep-alive timer, so it needs
+                        // to run once even though nothing can be read yet.
+                        self.state.notify_read = self.timeouts.keep_alive.is_some();
                         return
                     },
                     Err(e) => {
@@ -479,6 +917,11 @@ where I: AsyncRead + AsyncWrite,
                 }
             }
             }
+        } else if wants_read && self.state.read_probed {
+            // `poll_read_closed` left reading blocked while the message was
+            // in flight, but read_head still has to arm the keep-alive timer.
+            self.state.read_probed = false;
+            self.state.notify_read = self.timeouts.keep_alive.is_some();
         }
     }
 
@@ -487,6 +930,24 @@ where I: AsyncRead + AsyncWrite,
         self.maybe_notify();
     }
 
+    /// Records that the message just read was handed to the dispatcher.
+    pub fn on_dispatch(&mut self) {
+        if let Some(ref mut timing) = self.state.timing {
+            timing.service_called = Some(Instant::now());
+        }
+    }
+
+    // The outgoing message was completely buffered, and is reported to the
+    // timing callback once it is flushed.
+    fn on_message_written(&mut self) {
+        self.state.unflushed_messages += 1;
+        if let Some(ref mut timing) = self.state.timing {
+            if let (Some(head), Some(called)) = (timing.head_received.take(), timing.service_called.take()) {
+                timing.unflushed.push((head, called));
+            }
+        }
+    }
+
     pub fn can_write_head(&self) -> bool {
         if !T::should_read_first() {
             match self.state.reading {
@@ -513,18 +974,93 @@ where I: AsyncRead + AsyncWrite,
         self.io.can_buffer()
     }
 
+    /// Whether a `100 Continue` is waiting on `send_continue`.
+    pub fn is_continue_pending(&self) -> bool {
+        self.state.pending_continue
+    }
+
+    /// Send a `100 Continue` that was deferred when reading the head.
+    ///
+    /// Nothing is sent if the final response has already been started.
+    pub fn send_continue(&mut self) {
+        if self.state.pending_continue {
+            self.state.pending_continue = false;
+            if let Writing::Init = self.state.writing {
+                self.state.continue_withheld = false;
+                self.write_continue();
+            }
+        }
+    }
+
+    fn write_continue(&mut self) {
+        let msg = b"HTTP/1.1 100 Continue\r\n\r\n";
+        self.io.write_buf_mut().extend_from_slice(msg);
+    }
+
+    /// Write an informational (1xx) response ahead of the final one.
+    ///
+    /// This doesn't change the state of the connection, which still waits
+    /// for the final response head. Nothing is written to an HTTP/1.0 peer,
+    /// or once the final response has been started.
+    pub fn write_informational(&mut self, head: MessageHead<StatusCode>) {
+        debug_assert!(head.subject.is_informational());
+        match (&self.state.writing, self.state.version) {
+            (&Writing::Init, Version::Http11) => (),
+            _ => {
+                debug!("not sending informational response ({})", head.subject);
+                return;
+            }
+        }
+        if head.subject == StatusCode::Continue {
+            self.state.pending_continue = false;
+            self.state.continue_withheld = false;
+        }
+        trace!("writing informational response ({})", head.subject);
+        // `103 Early Hints` is only an `Unregistered` status, whose reason
+        // phrase is filled in here
+        let reason = match head.subject.canonical_reason() {
+            Some(reason) => reason,
+            None if head.subject.as_u16() == 103 => "Early Hints",
+            None => "",
+        };
+        let buf = self.io.write_buf_mut();
+        let _ = write!(buf, "HTTP/1.1 {} {}\r\n{}\r\n", head.subject.as_u16(), reason, head.headers);
+    }
+
     pub fn write_head(&mut self, mut head: MessageHead<T::Outgoing>, body: bool) {
         debug_assert!(self.can_write_head());
 
+        // a final response means the client gets no `100 Continue`
+        self.state.pending_continue = false;
+
         if !T::should_read_first() {
             self.state.busy();
         }
 
         self.enforce_version(&mut head);
 
+        if let Some(ref clock) = self.state.date_clock {
+            if self.state.auto_date && !head.headers.has::<Date>() {
+                head.headers.set(Date(clock().into()));
+            }
+        }
+        if let Some(ref server) = self.state.server_header {
+            if !head.headers.has::<Server>() {
+                head.headers.set(server.clone());
+            }
+        }
+
+        self.state.event_stream = is_event_stream(&head.headers);
+
         let buf = self.io.write_buf_mut();
-        self.state.writing = match T::encode(head, body, &mut self.state.method, buf) {
+        let start = buf.len();
+        let encoded = T::encode(head, body, self.state.auto_date, self.state.connect_tunnel, &mut self.state.method, buf);
+        self.state.bytes_written = (buf.len() - start) as u64;
+        let mut written = false;
+        self.state.writing = match encoded {
             Ok(encoder) => {
+                self.state.upgraded = encoder.is_last();
+                written = encoder.is_eof();
                 if !encoder.is_eof() {
                     Writing::Body(encoder)
                 } else if encoder.is_last() {
@@ -538,6 +1074,36 @@ where I: AsyncRead + AsyncWrite,
                 Writing::Closed
             }
         };
+        if written {
+            self.on_message_written();
+        }
+    }
+
+    /// Ends the body of the message being written with the `trailers`.
+    ///
+    /// A trailer section is only written for a chunked body, which is then
+    /// ended with it, instead of with `write_body(None)`.
+    pub fn write_trailers(&mut self, trailers: Headers) -> StartSend<Option<B>, io::Error> {
+        let last = match self.state.writing {
+            Writing::Body(ref encoder) if encoder.is_chunked() && trailers.len() > 0 => encoder.is_last(),
+            _ => {
+                if trailers.len() > 0 {
+                    debug!("dropping trailers of a body that isn't chunked");
+                }
+                return self.write_body(None);
+            }
+        };
+        {
+            let buf = self.io.write_buf_mut();
+            let _ = write!(buf, "0\r\n{}\r\n", trailers);
+        }
+        self.state.writing = if last {
+            Writing::Closed
+        } else {
+            Writing::KeepAlive
+        };
+        self.on_message_written();
+        Ok(AsyncSink::Ready)
     }
 
     // If we know the remote speaks an older version, we try to fix up any messages
@@ -545,6 +1111,16 @@ where I: AsyncRead + AsyncWrite,
     fn enforce_version(&mut self, head: &mut MessageHead<T::Outgoing>) {
         use header::Connection;
 
+        if let Some(max) = self.state.max_requests {
+            if self.state.messages_read >= max && self.state.wants_keep_alive() {
+                debug!("max requests ({}) served, closing connection", max);
+                self.state.close_announced = true;
+                self.state.disable_keep_alive();
+            }
+        }
+
+        // a `Connection: close` set on the message always wins, whatever
+        // the keep-alive setting of the connection
         let wants_keep_alive = if self.state.wants_keep_alive() {
             let ka = head.should_keep_alive();
             self.state.keep_alive &= ka;
@@ -568,6 +1144,10 @@ where I: AsyncRead + AsyncWrite,
                 // the user's headers be.
             }
         }
+
+        if self.state.close_announced && !head.headers.has::<Connection>() {
+            head.headers.set(Connection::close());
+        }
     }
 
     pub fn write_body(&mut self, chunk: Option<B>) -> StartSend<Option<B>, io::Error> {
@@ -584,6 +1164,7 @@ where I: AsyncRead + AsyncWrite,
             }
         }
 
+        let mut written = false;
         let state = match self.state.writing {
             Writing::Body(ref mut encoder) => {
                 if let Some(chunk) = chunk {
@@ -591,10 +1172,12 @@ where I: AsyncRead + AsyncWrite,
                         return Ok(AsyncSink::Ready);
                     }
 
+                    self.state.bytes_written += chunk.as_ref().len() as u64;
                     let encoded = encoder.encode(Cursor::new(chunk));
                     self.io.buffer(encoded);
 
                     if encoder.is_eof() {
+                        written = true;
                         if encoder.is_last() {
                             Writing::Closed
                         } else {
@@ -610,6 +1193,7 @@ where I: AsyncRead + AsyncWrite,
                             if let Some(end) = end {
                                 self.io.buffer(end);
                             }
+                            written = true;
                             if encoder.is_last() {
                                 Writing::Closed
                             } else {
@@ -624,6 +1208,9 @@ where I: AsyncRead + AsyncWrite,
         };
 
         self.state.writing = state;
+        if written {
+            self.on_message_written();
+        }
         Ok(AsyncSink::Ready)
     }
 
@@ -631,10 +1218,26 @@ where I: AsyncRead + AsyncWrite,
     // to write a response before closing the connection.
     //
     // - Client: there is nothing we can do
-    // - Server: if Response hasn't been written yet, we can send a 4xx response
+    // - Server: if Response hasn't been written yet, we can send a 4xx response,
+    //   or the one returned by `on_parse_error`
     fn on_parse_error(&mut self, err: ::Error) -> ::Result<()> {
+        let custom = match err {
+            ::Error::BodyTooLarge => None,
+            _ => match self.state.on_parse_error {
+                Some(ref f) => f(&err, self.state.remote_addr),
+                None => None,
+            },
+        };
         match self.state.writing {
             Writing::Init => {
+                if let Some(res) = custom {
+                    let (head, body) = response::split(res);
+                    if let Some(head) = T::on_error_response(head) {
+                        self.write_error_response(head, body);
+                        self.state.error = Some(err);
+                        return Ok(());
+                    }
+                }
                 if let Some(msg) = T::on_error(&err) {
                     self.write_head(msg, false);
                     self.state.error = Some(err);
@@ -648,13 +1251,172 @@ where I: AsyncRead + AsyncWrite,
         Err(err)
     }
 
+    /// Writes the response of `Http::internal_error_response` in place of
+    /// the one a service failed to produce, or else returns the error back.
+    pub fn write_service_error(&mut self, err: ::Error) -> ::Result<()> {
+        let res = match self.state.internal_error_response {
+            Some(ref f) if self.can_write_head() => f(&err),
+            _ => return Err(err),
+        };
+        debug!("service error: {}", err);
+        let (head, body) = response::split(res);
+        match T::on_error_response(head) {
+            Some(head) => {
+                self.write_error_response(head, body);
+                Ok(())
+            },
+            None => Err(err),
+        }
+    }
+
+    /// Writes the response of `Http::overload_response` in place of calling
+    /// the service, closing the connection afterwards.
+    pub fn write_overload_response(&mut self, head: MessageHead<T::Outgoing>, body: Option<Chunk>) {
+        self.state.close_announced = true;
+        self.state.disable_keep_alive();
+        self.write_error_response(head, body);
+    }
+
+    // Writes a complete error response, buffering its body right after the
+    // head.
+    fn write_error_response(&mut self, mut head: MessageHead<T::Outgoing>, body: Option<Chunk>) {
+        let body = match body {
+            Some(ref body) if !body.is_empty() => body,
+            _ => return self.write_head(head, false),
+        };
+        head.headers.set(ContentLength(body.len() as u64));
+        self.write_head(head, true);
+        let last = match self.state.writing {
+            Writing::Body(ref encoder) => encoder.is_last(),
+            _ => return,
+        };
+        self.io.write_buf_mut().extend_from_slice(body);
+        self.state.bytes_written += body.len() as u64;
+        self.state.writing = if last {
+            Writing::Closed
+        } else {
+            Writing::KeepAlive
+        };
+        self.on_message_written();
+    }
+
+    // The body of the incoming message is larger than `max_body_size`. Stop
+    // reading, and reply with a `413 Payload Too Large` if nothing was
+    // written for this message yet, closing the connection afterwards.
+    fn on_body_too_large(&mut self) -> ::Result<()> {
+        self.state.close_read();
+        self.state.close_announced = true;
+        self.on_parse_error(::Error::BodyTooLarge)
+    }
+
     pub fn flush(&mut self) -> Poll<(), io::Error> {
-        try_ready!(self.io.flush());
+        self.flush_inner(false)
+    }
+
+    /// Flush the write buffer, even if `flush_pipeline` would wait on more
+    /// buffered messages.
+    pub fn flush_all(&mut self) -> Poll<(), io::Error> {
+        self.flush_inner(true)
+    }
+
+    fn flush_inner(&mut self, all: bool) -> Poll<(), io::Error> {
+        // with `flush_pipeline`, stop buffering responses to pipelined
+        // messages once there are too many, so that no more messages are
+        // read until the peer has taken these
+        let pipeline_full = self.state.max_pipeline_depth
+            .map_or(false, |max| self.state.unflushed_messages >= max);
+        let written = self.io.bytes_written();
+        let was_empty = self.io.is_write_empty();
+        let flushed = if all {
+            self.io.flush_all()
+        } else if pipeline_full {
+            trace!("max_pipeline_depth reached, flushing {} messages", self.state.unflushed_messages);
+            self.io.flush_all()
+        } else if self.state.event_stream {
+            // neither pipelined responses nor coalescing hold back events
+            self.io.flush_all()
+        } else if self.poll_coalesce()? {
+            return Ok(Async::Ready(()));
+        } else {
+            self.io.flush()
+        };
+        if flushed.is_ok() && self.io.bytes_written() != written {
+            // any progress resets the write timeout
+            self.timeouts.write_timer = None;
+        }
+        if let Ok(Async::NotReady) = flushed {
+            let timeouts = &mut self.timeouts;
+            if poll_timer(&mut timeouts.write_timer, timeouts.write, timeouts.remote.as_ref())? {
+                debug!("write timeout elapsed, closing connection");
+                self.state.close();
+                self.state.error = Some(::Error::WriteTimeout);
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "write timeout elapsed"));
+            }
+        }
+        try_ready!(flushed);
+        self.timeouts.write_timer = None;
+        if self.io.is_write_empty() {
+            self.timeouts.coalesce_timer = None;
+            self.timeouts.coalesce_elapsed = false;
+            self.state.unflushed_messages = 0;
+            if !was_empty {
+                if let Some(ref f) = self.state.on_write_drained {
+                    f(WriteDrained {
+                        remote_addr: self.state.remote_addr,
+                        bytes_written: self.io.bytes_written(),
+                        _inner: (),
+                    });
+                }
+            }
+        }
+        if let Some(ref mut timing) = self.state.timing {
+            // with `flush_pipeline`, flushing may leave bytes buffered
+            if !timing.unflushed.is_empty() && self.io.is_write_empty() {
+                let flushed = Instant::now();
+                for (head, called) in timing.unflushed.drain(..) {
+                    (timing.callback)(TimingInfo {
+                        head_received: head,
+                        service_called: called,
+                        response_flushed: flushed,
+                        _inner: (),
+                    });
+                }
+            }
+        }
         self.try_keep_alive();
         trace!("flushed {:?}", self.state);
         Ok(Async::Ready(()))
     }
 
+    // With `write_coalesce`, the bytes of a body being written are held back
+    // until there are `max_bytes` of them, or `max_delay` has elapsed since
+    // the first one was buffered. The end of a body is flushed right away.
+    //
+    // Returns whether the buffered bytes are being held.
+    fn poll_coalesce(&mut self) -> io::Result<bool> {
+        let (delay, max_bytes) = match self.timeouts.coalesce {
+            Some(coalesce) => coalesce,
+            None => return Ok(false),
+        };
+        let holding = self.can_write_body()
+            && !self.timeouts.coalesce_elapsed
+            && !self.io.is_write_empty()
+            && self.io.write_buf_len() < max_bytes
+            && self.io.can_buffer();
+        if !holding {
+            return Ok(false);
+        }
+        let timeouts = &mut self.timeouts;
+        if poll_timer(&mut timeouts.coalesce_timer, Some(delay), timeouts.remote.as_ref())? {
+            trace!("write coalescing delay elapsed with {} bytes", self.io.write_buf_len());
+            // until the buffer has been emptied
+            timeouts.coalesce_elapsed = true;
+            return Ok(false);
+        }
+        // without a reactor, there's no timer to flush later
+        Ok(timeouts.coalesce_timer.is_some())
+    }
+
     pub fn shutdown(&mut self) -> Poll<(), io::Error> {
         match self.io.io_mut().shutdown() {
             Ok(Async::NotReady) => Ok(Async::NotReady),
@@ -673,6 +1435,40 @@ where I: AsyncRead + AsyncWrite,
         self.state.close_read();
     }
 
+    /// The receiver of the incoming body went away before its end.
+    ///
+    /// If `auto_drain_body` is set, the rest of the body is then read and
+    /// discarded by `poll_drain_body`, so the connection can be kept alive.
+    /// Otherwise, or if the peer is still waiting on a `100 Continue`, reading
+    /// stops and the connection closes.
+    pub fn discard_body(&mut self) {
+        if self.state.auto_drain_body && !self.state.continue_withheld {
+            trace!("body receiver dropped before eof, draining");
+            self.state.body_drained = 0;
+        } else {
+            trace!("body receiver dropped before eof, closing");
+            self.close_read();
+        }
+    }
+
+    pub fn poll_drain_body(&mut self) -> Poll<(), io::Error> {
+        while self.can_read_body() {
+            match try_ready!(self.read_body()) {
+                Some(chunk) => {
+                    self.state.body_drained += chunk.len() as u64;
+                    if let Some(max) = self.state.max_drain_bytes {
+                        if self.state.body_drained > max {
+                            debug!("discarded body exceeds max drain bytes ({}), closing", max);
+                            self.close_read();
+                        }
+                    }
+                },
+                None => (),
+            }
+        }
+        Ok(Async::Ready(()))
+    }
+
     pub fn close_write(&mut self) {
         self.state.close_write();
     }
@@ -685,6 +1481,33 @@ where I: AsyncRead + AsyncWrite,
         }
     }
 
+    /// Closes the connection like `graceful_close` once its maximum age has
+    /// elapsed, whether it is idle or not. The timer is armed on the first
+    /// call.
+    pub fn poll_max_age(&mut self) -> ::Result<()> {
+        let elapsed = {
+            let timeouts = &mut self.timeouts;
+            poll_timer(&mut timeouts.max_age_timer, timeouts.max_age, timeouts.remote.as_ref())?
+        };
+        if elapsed {
+            debug!("max connection age elapsed, closing after the in-flight response");
+            self.timeouts.max_age = None;
+            self.graceful_close();
+        }
+        Ok(())
+    }
+
+    /// Like `disable_keep_alive`, but an in-flight response will also tell
+    /// the peer with a `Connection: close` header.
+    pub fn graceful_close(&mut self) {
+        self.state.close_announced = true;
+        if self.is_awaiting_first_message() {
+            self.state.close_read();
+        } else {
+            self.disable_keep_alive();
+        }
+    }
+
     pub fn take_error(&mut self) -> ::Result<()> {
         if let Some(err) = self.state.error.take() {
             Err(err)
@@ -789,6 +1612,17 @@ where I: AsyncRead + AsyncWrite + 'static,
       T: Http1Transaction + 'static,
       T::Outgoing: fmt::Debug {}
 
+impl<I, B: AsRef<[u8]>, T> Conn<I, B, T> {
+    pub fn io(&self) -> &I {
+        self.io.io()
+    }
+
+    /// The bytes buffered to be written, that haven't been flushed yet.
+    pub fn pending_write_bytes(&self) -> usize {
+        self.io.write_buf_len()
+    }
+}
+
 impl<I, B: AsRef<[u8]>, T> fmt::Debug for Conn<I, B, T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("Conn")
@@ -799,19 +1633,144 @@ impl<I, B: AsRef<[u8]>, T> fmt::Debug for Conn<I, B, T> {
 }
 
 struct State {
+    auto_date: bool,
+    auto_drain_body: bool,
+    // Whether a successful response to a CONNECT makes a tunnel of the
+    // connection.
+    connect_tunnel: bool,
+    close_announced: bool,
+    continue_withheld: bool,
+    defer_continue: bool,
     error: Option<::Error>,
     #[cfg(feature = "tokio-proto")]
     is_tokio_proto: bool,
     keep_alive: KA,
+    max_body_size: Option<u64>,
+    max_drain_bytes: Option<u64>,
+    max_pipeline_depth: Option<usize>,
+    // Whether the message being written is a `text/event-stream`, each
+    // chunk of which is flushed right away.
+    event_stream: bool,
+    // Once this many messages were read, the connection closes after
+    // writing the next one.
+    max_requests: Option<u64>,
+    body_read: u64,
+    body_drained: u64,
+    bytes_written: u64,
+    date_clock: Option<Arc<Fn() -> SystemTime + Send + Sync>>,
+    head_read: u64,
+    http10_keep_alive: bool,
+    idle_timed_out: bool,
+    messages_read: u64,
     method: Option<Method>,
     notify_read: bool,
+    read_probed: bool,
+    on_exchange_complete: Option<Arc<Fn(ExchangeInfo) + Send + Sync>>,
+    on_write_drained: Option<Arc<Fn(WriteDrained) + Send + Sync>>,
+    on_parse_error: Option<Arc<Fn(&::Error, Option<SocketAddr>) -> Option<Response<Chunk>> + Send + Sync>>,
+    internal_error_response: Option<Arc<Fn(&::Error) -> Response<Chunk> + Send + Sync>>,
+    pending_continue: bool,
     #[cfg(feature = "tokio-proto")]
     read_task: Option<Task>,
     reading: Reading,
+    reject_conflicting_framing: bool,
+    remote_addr: Option<SocketAddr>,
+    require_host: bool,
+    send_408_on_timeout: bool,
+    server_header: Option<Server>,
+    timing: Option<Timing>,
+    unflushed_messages: usize,
+    upgraded: bool,
     writing: Writing,
     version: Version,
 }
 
+// Timestamps of the exchanges of a connection, only kept once a callback
+// is set with `set_on_exchange_timing`.
+struct Timing {
+    callback: Arc<Fn(TimingInfo) + Send + Sync>,
+    head_received: Option<Instant>,
+    service_called: Option<Instant>,
+    // Messages that were completely buffered, waiting to be flushed.
+    unflushed: Vec<(Instant, Instant)>,
+}
+
+// Timers are created through a `Remote`, so that a `Conn` can still be
+// sent to the thread of the reactor that drives it.
+struct Timeouts {
+    remote: Option<Remote>,
+    header_read: Option<Duration>,
+    header_read_timer: Option<Timeout>,
+    // The bytes of head to read per window, and the buffered length at the
+    // start of the current window.
+    min_head_rate: Option<(usize, Duration)>,
+    head_rate_timer: Option<Timeout>,
+    head_rate_mark: usize,
+    body_read: Option<Duration>,
+    body_read_timer: Option<Timeout>,
+    keep_alive: Option<Duration>,
+    keep_alive_timer: Option<Timeout>,
+    write: Option<Duration>,
+    write_timer: Option<Timeout>,
+    // The delay and size bounds of `set_write_coalesce`, and whether the
+    // delay elapsed for the bytes currently buffered.
+    coalesce: Option<(Duration, usize)>,
+    coalesce_timer: Option<Timeout>,
+    coalesce_elapsed: bool,
+    // Cleared once elapsed, so that the timer isn't armed again.
+    max_age: Option<Duration>,
+    max_age_timer: Option<Timeout>,
+}
+
+impl Timeouts {
+    fn disarm_head(&mut self) {
+        self.header_read_timer = None;
+        self.head_rate_timer = None;
+        self.keep_alive_timer = None;
+    }
+}
+
+// Whether `buf`, the start of a head, holds a complete first line, skipping
+// the empty lines allowed before it.
+fn has_request_line(buf: &[u8]) -> bool {
+    buf.iter()
+        .skip_while(|&&b| b == b'\r' || b == b'\n')
+        .any(|&b| b == b'\n')
+}
+
+// Whether `buf` starts like the connection preface of an HTTP/2 client,
+// `PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n`, which fails to parse as a request
+// at its version.
+fn is_h2_preface(buf: &[u8]) -> bool {
+    buf.starts_with(b"PRI * HTTP/2")
+}
+
+fn is_event_stream(headers: &Headers) -> bool {
+    headers.get::<ContentType>()
+        .map_or(false, |ct| ct.type_() == mime::TEXT && ct.subtype() == mime::EVENT_STREAM)
+}
+
+// Polls an optional timer, first arming it to fire after `dur` if needed.
+//
+// Returns whether the timer has elapsed, in which case it is also disarmed.
+fn poll_timer(timer: &mut Option<Timeout>, dur: Option<Duration>, remote: Option<&Remote>) -> io::Result<bool> {
+    if timer.is_none() {
+        match (dur, remote.and_then(Remote::handle)) {
+            (Some(dur), Some(handle)) => *timer = Some(Timeout::new(dur, &handle)?),
+            _ => return Ok(false),
+        }
+    }
+
+    let elapsed = match *timer {
+        Some(ref mut t) => t.poll()?.is_ready(),
+        None => false,
+    };
+    if elapsed {
+        *timer = None;
+    }
+    Ok(elapsed)
+}
+
 #[derive(Debug)]
 enum Reading {
     Init,
@@ -928,19 +1887,33 @@ impl State {
         match (&self.reading, &self.writing) {
             (&Reading::KeepAlive, &Writing::KeepAlive) => {
                 if let KA::Busy = self.keep_alive.status() {
+                    self.exchange_complete(true);
                     self.idle();
                 } else {
+                    self.exchange_complete(false);
                     self.close();
                 }
             },
             (&Reading::Closed, &Writing::KeepAlive) |
             (&Reading::KeepAlive, &Writing::Closed) => {
+                self.exchange_complete(false);
                 self.close()
             }
             _ => ()
         }
     }
 
+    fn exchange_complete(&self, keep_alive: bool) {
+        if let Some(ref f) = self.on_exchange_complete {
+            f(ExchangeInfo {
+                keep_alive: keep_alive,
+                bytes_read: self.head_read + self.body_read,
+                bytes_written: self.bytes_written,
+                _inner: (),
+            });
+        }
+    }
+
     fn disable_keep_alive(&mut self) {
         self.keep_alive.disable()
     }
@@ -954,6 +1927,10 @@ impl State {
 
     fn idle(&mut self) {
         self.method = None;
+        self.pending_continue = false;
+        self.continue_withheld = false;
+        self.head_read = 0;
+        self.body_read = 0;
         self.keep_alive.idle();
         if self.is_idle() {
             self.reading = Reading::Init;
@@ -1090,6 +2067,33 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_conn_graceful_close_before_first_message() {
+        let io = AsyncIo::new_buf(vec![], 1);
+        let mut conn = Conn::<_, proto::Chunk, ServerTransaction>::new(io);
+        conn.graceful_close();
+        assert!(conn.is_read_closed());
+
+        // unlike when keep-alive is disabled up front
+        let io = AsyncIo::new_buf(vec![], 1);
+        let mut conn = Conn::<_, proto::Chunk, ServerTransaction>::new(io);
+        conn.disable_keep_alive();
+        assert!(!conn.is_read_closed());
+
+        // a partial head is still read to the end
+        let io = AsyncIo::new_buf(vec![], 1);
+        let mut conn = Conn::<_, proto::Chunk, ServerTransaction>::new(io);
+        conn.set_read_buf(b"GET / HTTP/1.1\r\n");
+        conn.graceful_close();
+        assert!(!conn.is_read_closed());
+
+        // a client writes first
+        let io = AsyncIo::new_buf(vec![], 1);
+        let mut conn = Conn::<_, proto::Chunk, ClientTransaction>::new(io);
+        conn.graceful_close();
+        assert!(!conn.is_read_closed());
+    }
+
     #[test]
     fn test_conn_init_read_eof_idle_partial_parse() {
         let io = AsyncIo::new_buf(b"GET / HTTP/1.1".to_vec(), 100);
@@ -1208,6 +2212,43 @@ mod tests {
         }).wait();
     }
 
+    #[test]
+    fn test_conn_read_body_max_body_size() {
+        let _: Result<(), ()> = future::lazy(|| {
+            let io = AsyncIo::new_buf(b"\
+                POST / HTTP/1.1\r\n\
+                Transfer-Encoding: chunked\r\n\r\n\
+                5\r\nhello\r\n\
+                6\r\n world\r\n\
+                0\r\n\r\n\
+            ".to_vec(), 1024);
+            let mut conn = Conn::<_, proto::Chunk, ServerTransaction>::new(io);
+            conn.set_max_body_size(Some(8));
+
+            match conn.read_head() {
+                Ok(Async::Ready(Some((_, true)))) => (),
+                other => panic!("unexpected head: {:?}", other)
+            }
+
+            match conn.read_body() {
+                Ok(Async::Ready(Some(ref chunk))) if chunk.as_ref() == b"hello" => (),
+                other => panic!("unexpected body: {:?}", other)
+            }
+
+            // the second chunk goes over the limit
+            match conn.read_body() {
+                Err(ref e) if e.kind() == ::std::io::ErrorKind::InvalidData => (),
+                other => panic!("unexpected body: {:?}", other)
+            }
+            assert!(conn.is_read_closed());
+            match conn.take_error() {
+                Err(::Error::BodyTooLarge) => (),
+                other => panic!("unexpected error: {:?}", other)
+            }
+            Ok(())
+        }).wait();
+    }
+
     #[test]
     fn test_conn_closed_read() {
         let io = AsyncIo::new_buf(vec![], 0);
@@ -1360,4 +2401,53 @@ mod tests {
         assert!(conn.start_send(Frame::Body { chunk: Some(Vec::new().into()) }).unwrap().is_ready());
         conn.start_send(Frame::Body { chunk: Some(vec![b'a'].into()) }).unwrap_err();
     }
+
+    #[test]
+    fn test_conn_max_pipeline_depth() {
+        let _: Result<(), ()> = future::lazy(|| {
+            let reqs = b"GET / HTTP/1.1\r\n\r\n".iter().cloned().cycle().take(18 * 3).collect::<Vec<u8>>();
+            let io = AsyncIo::new_buf(reqs, 1024);
+            let mut conn = Conn::<_, proto::Chunk, ServerTransaction>::new(io);
+            conn.set_flush_pipeline(true);
+            conn.set_max_pipeline_depth(Some(2));
+
+            let mut writes = Vec::new();
+            for _ in 0..3 {
+                match conn.read_head() {
+                    Ok(Async::Ready(Some(_))) => (),
+                    other => panic!("unexpected read_head: {:?}", other.map(|_| ())),
+                }
+                conn.write_head(MessageHead::default(), false);
+                assert!(Conn::flush(&mut conn).unwrap().is_ready());
+                writes.push(conn.io.io_mut().num_writes());
+            }
+
+            // the first response waits on the next pipelined one, but the
+            // second is flushed right away, though the third request is
+            // already buffered
+            assert_eq!(writes, vec![0, 1, 2]);
+            Ok(())
+        }).wait();
+    }
+
+    #[test]
+    fn test_conn_write_head_and_body_in_one_write() {
+        let _: Result<(), ()> = future::lazy(|| {
+            let io = AsyncIo::new_buf(vec![], 4096);
+            let mut conn = Conn::<_, proto::Chunk, ServerTransaction>::new(io);
+            conn.state.reading = Reading::KeepAlive;
+            conn.set_auto_date(false);
+
+            let head = MessageHead::<::StatusCode>::default();
+            assert!(conn.start_send(Frame::Message { message: head, body: true }).unwrap().is_ready());
+            assert!(conn.start_send(Frame::Body { chunk: Some(vec![b'a'; 1024].into()) }).unwrap().is_ready());
+            assert!(conn.start_send(Frame::Body { chunk: None }).unwrap().is_ready());
+            assert!(conn.poll_complete().unwrap().is_ready());
+
+            // the head, the chunk with its framing, and the last chunk are
+            // all flushed in a single vectored write
+            assert_eq!(conn.io.io_mut().num_writes(), 1);
+            Ok(())
+        }).wait();
+    }
 }
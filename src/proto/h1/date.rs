@@ -10,7 +10,7 @@ pub const DATE_VALUE_LENGTH: usize = 29;
 pub(crate) fn extend(dst: &mut Vec<u8>) {
     CACHED.with(|cache| {
         let mut cache = cache.borrow_mut();
-        if !cache.interval {
+        if cache.intervals == 0 {
             cache.update_without_interval();
         }
         dst.extend_from_slice(cache.bytes());
@@ -18,9 +18,18 @@ pub(crate) fn extend(dst: &mut Vec<u8>) {
 }
 
 pub(crate) fn update_interval() {
+    CACHED.with(|cache| {
+        cache.borrow_mut().render(time::get_time());
+    })
+}
+
+// Each interval updating this thread's date is counted, so that one of them
+// stopping doesn't leave the date stale while another is still expected to
+// be running.
+pub(crate) fn interval_on() {
     CACHED.with(|cache| {
         let mut cache = cache.borrow_mut();
-        cache.interval = true;
+        cache.intervals += 1;
         cache.render(time::get_time());
     })
 }
@@ -28,7 +37,8 @@ pub(crate) fn update_interval() {
 pub(crate) fn interval_off() {
     CACHED.with(|cache| {
         let mut cache = cache.borrow_mut();
-        cache.interval = false;
+        debug_assert!(cache.intervals > 0);
+        cache.intervals -= 1;
     })
 }
 
@@ -36,14 +46,14 @@ struct CachedDate {
     bytes: [u8; DATE_VALUE_LENGTH],
     pos: usize,
     next_update: time::Timespec,
-    interval: bool,
+    intervals: usize,
 }
 
 thread_local!(static CACHED: RefCell<CachedDate> = RefCell::new(CachedDate {
     bytes: [0; DATE_VALUE_LENGTH],
     pos: 0,
     next_update: time::Timespec::new(0, 0),
-    interval: false,
+    intervals: 0,
 }));
 
 impl CachedDate {
@@ -1,4 +1,3 @@
-use std::error::Error as StdError;
 use std::fmt;
 use std::usize;
 use std::io;
@@ -6,6 +5,7 @@ use std::io;
 use futures::{Async, Poll};
 use bytes::Bytes;
 
+use error::BodyError;
 use super::io::MemRead;
 
 use self::Kind::{Length, Chunked, Eof};
@@ -97,7 +97,7 @@ impl Decoder {
                     if num > *remaining {
                         *remaining = 0;
                     } else if num == 0 {
-                        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, IncompleteBody));
+                        return Err(body_error(io::ErrorKind::UnexpectedEof, BodyError::IncompleteBody));
                     } else {
                         *remaining -= num;
                     }
@@ -157,8 +157,7 @@ macro_rules! byte (
         if !buf.is_empty() {
             buf[0]
         } else {
-            return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
-                                      "Unexpected eof during chunk size line"));
+            return Err(body_error(io::ErrorKind::UnexpectedEof, BodyError::UnexpectedEof));
         }
     })
 );
@@ -203,8 +202,7 @@ impl ChunkedState {
             b';' => return Ok(Async::Ready(ChunkedState::Extension)),
             b'\r' => return Ok(Async::Ready(ChunkedState::SizeLf)),
             _ => {
-                return Err(io::Error::new(io::ErrorKind::InvalidInput,
-                                          "Invalid chunk size line: Invalid Size"));
+                return Err(body_error(io::ErrorKind::InvalidInput, BodyError::InvalidChunkSize));
             }
         }
         Ok(Async::Ready(ChunkedState::Size))
@@ -217,8 +215,7 @@ impl ChunkedState {
             b';' => Ok(Async::Ready(ChunkedState::Extension)),
             b'\r' => Ok(Async::Ready(ChunkedState::SizeLf)),
             _ => {
-                Err(io::Error::new(io::ErrorKind::InvalidInput,
-                                   "Invalid chunk size linear white space"))
+                Err(body_error(io::ErrorKind::InvalidInput, BodyError::InvalidChunkSize))
             }
         }
     }
@@ -240,7 +237,7 @@ impl ChunkedState {
                     Ok(Async::Ready(ChunkedState::Body))
                 }
             },
-            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid chunk size LF")),
+            _ => Err(body_error(io::ErrorKind::InvalidInput, BodyError::InvalidChunkSize)),
         }
     }
 
@@ -262,7 +259,7 @@ impl ChunkedState {
 
         if count == 0 {
             *rem = 0;
-            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, IncompleteBody));
+            return Err(body_error(io::ErrorKind::UnexpectedEof, BodyError::IncompleteBody));
         }
         *buf = Some(slice);
         *rem -= count as u64;
@@ -276,43 +273,34 @@ impl ChunkedState {
     fn read_body_cr<R: MemRead>(rdr: &mut R) -> Poll<ChunkedState, io::Error> {
         match byte!(rdr) {
             b'\r' => Ok(Async::Ready(ChunkedState::BodyLf)),
-            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid chunk body CR")),
+            _ => Err(body_error(io::ErrorKind::InvalidInput, BodyError::InvalidChunkSize)),
         }
     }
     fn read_body_lf<R: MemRead>(rdr: &mut R) -> Poll<ChunkedState, io::Error> {
         match byte!(rdr) {
             b'\n' => Ok(Async::Ready(ChunkedState::Size)),
-            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid chunk body LF")),
+            _ => Err(body_error(io::ErrorKind::InvalidInput, BodyError::InvalidChunkSize)),
         }
     }
 
     fn read_end_cr<R: MemRead>(rdr: &mut R) -> Poll<ChunkedState, io::Error> {
         match byte!(rdr) {
             b'\r' => Ok(Async::Ready(ChunkedState::EndLf)),
-            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid chunk end CR")),
+            _ => Err(body_error(io::ErrorKind::InvalidInput, BodyError::InvalidTrailer)),
         }
     }
     fn read_end_lf<R: MemRead>(rdr: &mut R) -> Poll<ChunkedState, io::Error> {
         match byte!(rdr) {
             b'\n' => Ok(Async::Ready(ChunkedState::End)),
-            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid chunk end LF")),
+            _ => Err(body_error(io::ErrorKind::InvalidInput, BodyError::InvalidTrailer)),
         }
     }
 }
 
-#[derive(Debug)]
-struct IncompleteBody;
-
-impl fmt::Display for IncompleteBody {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_str(self.description())
-    }
-}
-
-impl StdError for IncompleteBody {
-    fn description(&self) -> &str {
-        "end of file before message length reached"
-    }
+// An error of the body carried through the `io::Error` of reading it, see
+// `Error::new_body_read`.
+fn body_error(kind: io::ErrorKind, err: BodyError) -> io::Error {
+    io::Error::new(kind, ::Error::Body(err))
 }
 
 #[cfg(test)]
@@ -451,6 +439,43 @@ mod tests {
         assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
     }
 
+    #[test]
+    fn test_read_chunked_body_errors() {
+        use error::BodyError;
+
+        fn decode_err(mut bytes: &[u8], decoder: Decoder) -> ::Error {
+            let mut decoder = decoder;
+            loop {
+                match decoder.decode(&mut bytes) {
+                    Ok(Async::Ready(ref buf)) if buf.is_empty() => panic!("decoded {:?}", bytes),
+                    Ok(_) => (),
+                    Err(e) => return ::Error::new_body_read(e),
+                }
+            }
+        }
+
+        macro_rules! assert_body_err {
+            ($bytes:expr, $decoder:expr, $kind:expr) => {
+                match decode_err($bytes, $decoder) {
+                    ::Error::Body(kind) => assert_eq!(kind, $kind),
+                    e => panic!("{:?}: expected {:?}, found {:?}", $bytes, $kind, e),
+                }
+            }
+        }
+
+        assert_body_err!(b"X\r\n", Decoder::chunked(), BodyError::InvalidChunkSize);
+        assert_body_err!(b"3 x\r\n", Decoder::chunked(), BodyError::InvalidChunkSize);
+        assert_body_err!(b"3\rfoo", Decoder::chunked(), BodyError::InvalidChunkSize);
+        assert_body_err!(b"3\r\nfoobar\r\n", Decoder::chunked(), BodyError::InvalidChunkSize);
+        assert_body_err!(b"9\r\nfoo bar", Decoder::chunked(), BodyError::IncompleteBody);
+        assert_body_err!(b"foo", Decoder::length(10), BodyError::IncompleteBody);
+        assert_body_err!(b"0\r\nGrpc-Status: 0\r\n\r\n", Decoder::chunked(), BodyError::InvalidTrailer);
+        assert_body_err!(b"0\r\n\rx", Decoder::chunked(), BodyError::InvalidTrailer);
+        assert_body_err!(b"3\r\nfoo\r\n3", Decoder::chunked(), BodyError::UnexpectedEof);
+        assert_body_err!(b"3\r\nfoo", Decoder::chunked(), BodyError::UnexpectedEof);
+        assert_body_err!(b"0\r\n", Decoder::chunked(), BodyError::UnexpectedEof);
+    }
+
     #[test]
     fn test_read_chunked_single_read() {
         let mut mock_buf = &b"10\r\n1234567890abcdef\r\n0\r\n"[..];
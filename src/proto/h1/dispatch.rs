@@ -1,11 +1,26 @@
+use std::borrow::Cow;
 use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 
 use bytes::Bytes;
 use futures::{Async, AsyncSink, Future, Poll, Stream};
+use futures::sync::oneshot;
+use futures::task;
+use tokio::reactor::{Remote, Timeout};
 use tokio_io::{AsyncRead, AsyncWrite};
 use tokio_service::Service;
 
-use proto::{Body, Conn, Http1Transaction, MessageHead, RequestHead, ResponseHead};
+use header::{Connection, ConnectionOption, ContentLength, Headers, TransferEncoding};
+use proto::{Body, CancelToken, Conn, Http1Transaction, Informational, IpNet, MessageHead, RequestHead, ResponseHead};
+use proto::forwarded::client_addr;
+use proto::informational::InformationalRx;
+use proto::trailers::PendingTrailers;
+use proto::request::ConnInfo;
+use proto::request_id::{LogId, RequestIds};
+use proto::request_limit::{Permit, RequestLimit};
+use server::conn::UpgradeSlot;
 use ::StatusCode;
 
 pub struct Dispatcher<D, Bs, I, B, T> {
@@ -13,7 +28,10 @@ pub struct Dispatcher<D, Bs, I, B, T> {
     dispatch: D,
     body_tx: Option<::proto::body::ChunkSender>,
     body_rx: Option<Bs>,
+    trailers: Option<PendingTrailers>,
+    continue_rx: Option<oneshot::Receiver<()>>,
     is_closing: bool,
+    fairness_limit: Option<u64>,
 }
 
 pub trait Dispatch {
@@ -24,13 +42,81 @@ pub trait Dispatch {
     fn recv_msg(&mut self, msg: ::Result<(Self::RecvItem, Option<Body>)>) -> ::Result<()>;
     fn poll_ready(&mut self) -> Poll<(), ()>;
     fn should_poll(&self) -> bool;
+    fn on_read_closed(&mut self);
+    fn poll_informational(&mut self) -> Option<MessageHead<StatusCode>>;
+    fn take_trailers(&mut self) -> Option<PendingTrailers>;
+    fn take_overload_response(&mut self) -> Option<(Self::PollItem, Option<::Chunk>)>;
+    // Whether a successful response to a CONNECT hands the connection over,
+    // ending HTTP on it.
+    fn tunnels_connect(&self) -> bool;
+    fn log_id(&self) -> LogId;
 }
 
 pub struct Server<S: Service> {
     in_flight: Option<S::Future>,
+    // A request read while `request_limit` was reached, for the service
+    // once a permit is released.
+    queued: Option<::Request>,
+    request_limit: Option<Arc<RequestLimit>>,
+    permit: Option<Permit>,
+    overload: Option<OverloadResponse>,
+    // Whether the request read gets `overload`, and whether every request
+    // does, for a connection accepted over `max_connections`.
+    overloaded: bool,
+    reject_all: bool,
+    info: Option<ConnInfo>,
+    cancel: CancelOnDrop,
+    informational_responses: bool,
+    informational: Option<InformationalRx>,
+    trailers: Option<PendingTrailers>,
+    request_timeout: Option<RequestTimeout>,
+    echo_headers: Vec<Cow<'static, str>>,
+    // The headers of the request in flight to copy onto its response.
+    echoed: Headers,
+    strip_headers: Vec<Cow<'static, str>>,
+    strip_hop_by_hop: bool,
+    trusted_proxies: Vec<IpNet>,
+    upgrades: Option<UpgradeSlot>,
+    tunnel_connect: bool,
+    request_ids: Option<RequestIds>,
     pub(crate) service: S,
 }
 
+// The deadline of `Http::request_timeout`, armed for each request.
+struct RequestTimeout {
+    dur: Duration,
+    status: StatusCode,
+    remote: Remote,
+    timer: Option<Timeout>,
+}
+
+// The response of `Http::overload_response`, its body kept as `Bytes` so
+// that every overloaded request gets a copy.
+#[derive(Clone, Debug)]
+pub struct OverloadResponse {
+    head: MessageHead<StatusCode>,
+    body: Option<Bytes>,
+}
+
+impl OverloadResponse {
+    pub fn new(res: ::Response<::Chunk>) -> OverloadResponse {
+        let (head, body) = ::proto::response::split(res);
+        OverloadResponse {
+            head: head,
+            body: body.map(Bytes::from),
+        }
+    }
+}
+
+// Cancels the token of a connection once its dispatcher goes away.
+struct CancelOnDrop(CancelToken);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
+
 pub struct Client<B> {
     callback: Option<::client::dispatch::Callback<ClientMsg<B>, ::Response>>,
     rx: ClientRx<B>,
@@ -54,14 +140,74 @@ where
             dispatch: dispatch,
             body_tx: None,
             body_rx: None,
+            trailers: None,
+            continue_rx: None,
             is_closing: false,
+            fairness_limit: None,
         }
     }
 
+    /// Limits the number of pipelined requests read in a single poll,
+    /// before yielding to the other tasks of the reactor.
+    pub fn set_pipeline_fairness_limit(&mut self, limit: Option<u64>) {
+        self.fairness_limit = limit;
+    }
+
     pub fn disable_keep_alive(&mut self) {
         self.conn.disable_keep_alive()
     }
 
+    pub fn graceful_close(&mut self) {
+        self.conn.graceful_close()
+    }
+
+    pub fn dispatch(&self) -> &D {
+        &self.dispatch
+    }
+
+    pub fn dispatch_mut(&mut self) -> &mut D {
+        &mut self.dispatch
+    }
+
+    pub fn is_upgraded(&self) -> bool {
+        self.conn.is_upgraded()
+    }
+
+    pub fn set_remote_addr(&mut self, addr: Option<SocketAddr>) {
+        self.conn.set_remote_addr(addr);
+    }
+
+    pub fn set_read_buf(&mut self, buf: &[u8]) {
+        self.conn.set_read_buf(buf);
+    }
+
+    pub fn limit_head_wait(&mut self, dur: Duration) {
+        self.conn.limit_head_wait(dur);
+    }
+
+    pub fn is_idle_timed_out(&self) -> bool {
+        self.conn.is_idle_timed_out()
+    }
+
+    pub fn messages_read(&self) -> u64 {
+        self.conn.messages_read()
+    }
+
+    pub fn bytes_read(&self) -> u64 {
+        self.conn.bytes_read()
+    }
+
+    pub fn bytes_written(&self) -> u64 {
+        self.conn.bytes_written()
+    }
+
+    /// Calls `AsyncWrite::shutdown` on the underlying IO, once
+    /// `poll_without_shutdown` is done.
+    pub fn poll_shutdown(&mut self) -> Poll<(), ::Error> {
+        try_ready!(self.conn.shutdown());
+        Ok(Async::Ready(()))
+    }
+
     pub fn into_inner(self) -> (I, Bytes, D) {
         let (io, buf) = self.conn.into_inner();
         (io, buf, self.dispatch)
@@ -92,9 +238,12 @@ where
     }
 
     fn poll_inner(&mut self, should_shutdown: bool) -> Poll<(), ::Error> {
+        self.conn.poll_max_age()?;
+        let messages_read = self.conn.messages_read();
         loop {
             self.poll_read()?;
             self.poll_write()?;
+            self.poll_continue();
             self.poll_flush()?;
 
             // This could happen if reading paused before blocking on IO,
@@ -108,9 +257,23 @@ where
             if !self.conn.wants_read_again() {
                 break;
             }
+            if let Some(limit) = self.fairness_limit {
+                if self.conn.messages_read() - messages_read >= limit {
+                    // the next pipelined request is read once the other
+                    // connections of the reactor had a turn
+                    trace!("{}pipeline fairness limit reached, yielding", self.dispatch.log_id());
+                    task::current().notify();
+                    break;
+                }
+            }
         }
 
         if self.is_done() {
+            // HTTP may be done before its bytes are written, such as when
+            // the read side closes while a response is still buffered, so
+            // all of them are flushed before the IO is shut down or handed
+            // back.
+            try_ready!(self.poll_flush_all());
             if should_shutdown {
                 try_ready!(self.conn.shutdown());
             }
@@ -137,10 +300,9 @@ where
                         },
                         Err(_canceled) => {
                             // user doesn't care about the body
-                            // so we should stop reading
-                            trace!("body receiver dropped before eof, closing");
-                            self.conn.close_read();
-                            return Ok(Async::Ready(()));
+                            // so we should drain it, or stop reading
+                            self.conn.discard_body();
+                            continue;
                         }
                     }
                     match self.conn.read_body() {
@@ -154,8 +316,7 @@ where
                                 }
                                 Err(_canceled) => {
                                     if self.conn.can_read_body() {
-                                        trace!("body receiver dropped before eof, closing");
-                                        self.conn.close_read();
+                                        self.conn.discard_body();
                                     }
                                 }
 
@@ -169,25 +330,55 @@ where
                             return Ok(Async::NotReady);
                         }
                         Err(e) => {
-                            let _ = body.start_send(Err(::Error::Io(e)));
+                            let _ = body.start_send(Err(::Error::new_body_read(e)));
                         }
                     }
                 } else {
                     // just drop, the body will close automatically
                 }
+            } else if self.conn.can_read_body() {
+                // the body receiver was dropped, discard the rest
+                match self.conn.poll_drain_body() {
+                    Ok(Async::Ready(())) => (),
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(e) => {
+                        debug!("{}error draining body: {}", self.dispatch.log_id(), e);
+                    }
+                }
             } else {
+                if self.conn.poll_read_closed() {
+                    self.dispatch.on_read_closed();
+                }
                 return self.conn.read_keep_alive().map(Async::Ready);
             }
         }
     }
 
+    fn poll_informational(&mut self) {
+        while let Some(head) = self.dispatch.poll_informational() {
+            self.conn.write_informational(head);
+        }
+    }
+
+    fn poll_continue(&mut self) {
+        if let Some(mut rx) = self.continue_rx.take() {
+            match rx.poll() {
+                Ok(Async::Ready(())) => self.conn.send_continue(),
+                Ok(Async::NotReady) => self.continue_rx = Some(rx),
+                Err(_canceled) => {
+                    trace!("{}body dropped before being polled, no 100 Continue", self.dispatch.log_id());
+                }
+            }
+        }
+    }
+
     fn poll_read_head(&mut self) -> Poll<(), ::Error> {
         // can dispatch receive, or does it still care about, an incoming message?
         match self.dispatch.poll_ready() {
             Ok(Async::Ready(())) => (),
             Ok(Async::NotReady) => unreachable!("dispatch not ready when conn is"),
             Err(()) => {
-                trace!("dispatch no longer receiving messages");
+                trace!("{}dispatch no longer receiving messages", self.dispatch.log_id());
                 self.close();
                 return Ok(Async::Ready(()));
             }
@@ -196,13 +387,19 @@ where
         match self.conn.read_head() {
             Ok(Async::Ready(Some((head, has_body)))) => {
                 let body = if has_body {
-                    let (mut tx, rx) = ::proto::body::channel();
+                    let (mut tx, mut rx) = ::proto::body::channel();
                     let _ = tx.poll_ready(); // register this task if rx is dropped
                     self.body_tx = Some(tx);
+                    if self.conn.is_continue_pending() {
+                        let (continue_tx, continue_rx) = oneshot::channel();
+                        rx.on_first_poll(continue_tx);
+                        self.continue_rx = Some(continue_rx);
+                    }
                     Some(rx)
                 } else {
                     None
                 };
+                self.conn.on_dispatch();
                 self.dispatch.recv_msg(Ok((head, body)))?;
                 Ok(Async::Ready(()))
             },
@@ -212,7 +409,7 @@ where
             }
             Ok(Async::NotReady) => Ok(Async::NotReady),
             Err(err) => {
-                debug!("read_head error: {}", err);
+                debug!("{}read_head error: {}", self.dispatch.log_id(), err);
                 self.dispatch.recv_msg(Err(err))?;
                 // if here, the dispatcher gave the user the error
                 // somewhere else. we still need to shutdown, but
@@ -227,9 +424,28 @@ where
             if self.is_closing {
                 return Ok(Async::Ready(()));
             } else if self.body_rx.is_none() && self.conn.can_write_head() && self.dispatch.should_poll() {
-                if let Some((head, body)) = try_ready!(self.dispatch.poll_msg()) {
+                self.poll_informational();
+                if let Some((head, body)) = self.dispatch.take_overload_response() {
+                    self.conn.write_overload_response(head, body);
+                    continue;
+                }
+                let msg = match self.dispatch.poll_msg() {
+                    Ok(Async::Ready(msg)) => msg,
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(err) => {
+                        // the response of `Http::internal_error_response`,
+                        // if any, takes the place of the failed one
+                        self.conn.write_service_error(err)?;
+                        continue;
+                    }
+                };
+                if let Some((head, body)) = msg {
+                    // sent while computing the final response
+                    self.poll_informational();
+                    self.conn.set_connect_tunnel(self.dispatch.tunnels_connect());
                     self.conn.write_head(head, body.is_some());
                     self.body_rx = body;
+                    self.trailers = self.dispatch.take_trailers();
                 } else {
                     self.close();
                     return Ok(Async::Ready(()));
@@ -243,7 +459,8 @@ where
                         chunk
                     },
                     Async::Ready(None) => {
-                        if self.conn.can_write_body() {
+                        // with trailers, the body is ended once they're ready
+                        if self.trailers.is_none() && self.conn.can_write_body() {
                             self.conn.write_body(None)?;
                         }
                         continue;
@@ -260,7 +477,19 @@ where
                 } else if chunk.as_ref().len() == 0 {
                     // ok
                 } else {
-                    warn!("unexpected chunk when body cannot write");
+                    warn!("{}unexpected chunk when body cannot write", self.dispatch.log_id());
+                }
+            } else if let Some(mut pending) = self.trailers.take() {
+                if self.conn.can_write_body() {
+                    let trailers = match pending.poll() {
+                        Ok(Async::Ready(trailers)) => trailers,
+                        Ok(Async::NotReady) => {
+                            self.trailers = Some(pending);
+                            return Ok(Async::NotReady);
+                        },
+                        Err(()) => Headers::new(),
+                    };
+                    assert!(self.conn.write_trailers(trailers)?.is_ready());
                 }
             } else {
                 return Ok(Async::NotReady);
@@ -269,10 +498,25 @@ where
     }
 
     fn poll_flush(&mut self) -> Poll<(), ::Error> {
-        self.conn.flush().map_err(|err| {
-            debug!("error writing: {}", err);
-            err.into()
-        })
+        let flushed = self.conn.flush();
+        self.on_flushed(flushed)
+    }
+
+    fn poll_flush_all(&mut self) -> Poll<(), ::Error> {
+        let flushed = self.conn.flush_all();
+        self.on_flushed(flushed)
+    }
+
+    fn on_flushed(&mut self, flushed: Poll<(), io::Error>) -> Poll<(), ::Error> {
+        match flushed {
+            Ok(async) => Ok(async),
+            Err(err) => {
+                debug!("{}error writing: {}", self.dispatch.log_id(), err);
+                // errors such as a write timeout are recorded by the `Conn`
+                self.conn.take_error()?;
+                Err(err.into())
+            }
+        }
     }
 
     fn close(&mut self) {
@@ -300,6 +544,16 @@ where
 }
 
 
+impl<D, Bs, I, B: AsRef<[u8]>, T> Dispatcher<D, Bs, I, B, T> {
+    pub fn io(&self) -> &I {
+        self.conn.io()
+    }
+
+    pub fn pending_write_bytes(&self) -> usize {
+        self.conn.pending_write_bytes()
+    }
+}
+
 impl<D, Bs, I, B, T> Future for Dispatcher<D, Bs, I, B, T>
 where
     D: Dispatch<PollItem=MessageHead<T::Outgoing>, PollBody=Bs, RecvItem=MessageHead<T::Incoming>>,
@@ -323,9 +577,158 @@ impl<S> Server<S> where S: Service {
     pub fn new(service: S) -> Server<S> {
         Server {
             in_flight: None,
+            queued: None,
+            request_limit: None,
+            permit: None,
+            overload: None,
+            overloaded: false,
+            reject_all: false,
+            info: None,
+            cancel: CancelOnDrop(CancelToken::new()),
+            informational_responses: false,
+            informational: None,
+            trailers: None,
+            request_timeout: None,
+            echo_headers: Vec::new(),
+            echoed: Headers::new(),
+            strip_headers: Vec::new(),
+            strip_hop_by_hop: false,
+            trusted_proxies: Vec::new(),
+            upgrades: None,
+            tunnel_connect: false,
+            request_ids: None,
             service: service,
         }
     }
+
+    /// Sets the names of the request headers copied onto every response
+    /// that doesn't have them.
+    pub fn set_echo_headers(&mut self, names: Vec<Cow<'static, str>>) {
+        self.echo_headers = names;
+    }
+
+    /// Sets the names of the headers removed from every response of the
+    /// service.
+    pub fn set_strip_headers(&mut self, names: Vec<Cow<'static, str>>) {
+        self.strip_headers = names;
+    }
+
+    /// Sets whether the hop-by-hop headers of the responses of the service
+    /// are removed.
+    pub fn set_strip_hop_by_hop(&mut self, enabled: bool) {
+        self.strip_hop_by_hop = enabled;
+    }
+
+    /// Sets the ranges of the proxies whose `Forwarded` or `X-Forwarded-For`
+    /// header overrides the remote address of a request.
+    pub fn set_trusted_proxies(&mut self, trusted: Vec<IpNet>) {
+        self.trusted_proxies = trusted;
+    }
+
+    /// Gives each request an `OnUpgrade`, whose connection is sent through
+    /// `slot` if the service takes it over.
+    pub fn set_upgrades(&mut self, slot: UpgradeSlot) {
+        self.upgrades = Some(slot);
+    }
+
+    /// Makes a tunnel of the connection with any successful response to a
+    /// CONNECT, even if no `OnUpgrade` was taken.
+    pub fn set_tunnel_connect(&mut self) {
+        self.tunnel_connect = true;
+    }
+
+    /// Gives each request a `RequestId`, also prefixing the log messages
+    /// about it.
+    pub fn set_request_ids(&mut self) {
+        self.request_ids = Some(RequestIds::new());
+    }
+
+    /// Limits how long the service may take to produce each response, after
+    /// which a response with `status` is sent instead.
+    pub fn set_request_timeout(&mut self, dur: Duration, status: StatusCode, remote: Remote) {
+        self.request_timeout = Some(RequestTimeout {
+            dur: dur,
+            status: status,
+            remote: remote,
+            timer: None,
+        });
+    }
+
+    /// Limits the requests in flight at once, over all the connections
+    /// sharing `limit`. A request read over the limit waits for one of them
+    /// to complete before the service is called.
+    pub fn set_request_limit(&mut self, limit: Arc<RequestLimit>) {
+        self.request_limit = Some(limit);
+    }
+
+    /// Sends `res` in place of calling the service for a request read while
+    /// `request_limit` is reached, instead of waiting for a permit. The
+    /// connection is closed afterwards.
+    pub fn set_overload_response(&mut self, res: OverloadResponse) {
+        self.overload = Some(res);
+    }
+
+    /// Sends the overload response to the first request, for a connection
+    /// accepted while the server was overloaded.
+    pub fn set_overloaded(&mut self) {
+        self.reject_all = true;
+    }
+
+    /// Sets the connection details injected into every received `Request`.
+    pub fn set_conn_info(&mut self, info: ConnInfo) {
+        self.info = Some(info);
+    }
+
+    /// Cancels the token injected into every received `Request`.
+    pub fn cancel(&mut self) {
+        self.cancel.0.cancel();
+    }
+
+    /// Gives each request an `Informational` sender.
+    pub fn set_informational_responses(&mut self) {
+        self.informational_responses = true;
+    }
+
+    fn has_request(&self) -> bool {
+        self.in_flight.is_some() || self.queued.is_some() || self.overloaded
+    }
+}
+
+impl<S, Bs> Server<S>
+where
+    S: Service<Request=::Request, Response=::Response<Bs>, Error=::Error>,
+    Bs: Stream<Error=::Error>,
+    Bs::Item: AsRef<[u8]>,
+{
+    fn call(&mut self, req: ::Request) -> ::Result<()> {
+        self.in_flight = Some(self.service.call(req));
+        if let Some(ref mut timeout) = self.request_timeout {
+            timeout.timer = match timeout.remote.handle() {
+                Some(handle) => Some(Timeout::new(timeout.dur, &handle)?),
+                None => None,
+            };
+        }
+        Ok(())
+    }
+
+    // Calls the service with the queued request, if a permit can be taken.
+    fn call_queued(&mut self) -> ::Result<()> {
+        let permit = match (&self.queued, &self.request_limit) {
+            (&Some(_), &Some(ref limit)) => RequestLimit::poll_acquire(limit),
+            _ => return Ok(()),
+        };
+        match permit {
+            Some(permit) => {
+                self.permit = Some(permit);
+                let req = self.queued.take().expect("queued request");
+                self.call(req)
+            },
+            None => {
+                trace!("{}max_concurrent_requests reached, request waiting", self.log_id());
+                Ok(())
+            },
+        }
+    }
 }
 
 impl<S, Bs> Dispatch for Server<S>
@@ -339,14 +742,49 @@ where
     type RecvItem = RequestHead;
 
     fn poll_msg(&mut self) -> Poll<Option<(Self::PollItem, Option<Self::PollBody>)>, ::Error> {
+        self.call_queued()?;
+        if self.queued.is_some() {
+            return Ok(Async::NotReady);
+        }
         if let Some(mut fut) = self.in_flight.take() {
-            let resp = match fut.poll()? {
+            let mut resp = match fut.poll().map_err(::Error::new_user)? {
                 Async::Ready(res) => res,
                 Async::NotReady => {
+                    let log_id = self.log_id();
+                    if let Some(ref mut timeout) = self.request_timeout {
+                        let elapsed = match timeout.timer {
+                            Some(ref mut timer) => timer.poll()?.is_ready(),
+                            None => false,
+                        };
+                        if elapsed {
+                            // dropping the future stops the work of the service
+                            debug!("{}service didn't respond within {:?}", log_id, timeout.dur);
+                            timeout.timer = None;
+                            self.permit = None;
+                            let mut resp = ::Response::<Bs>::new().with_status(timeout.status);
+                            echo(&mut self.echoed, resp.headers_mut());
+                            let (head, _) = ::proto::response::split(resp);
+                            return Ok(Async::Ready(Some((head.into(), None))));
+                        }
+                    }
                     self.in_flight = Some(fut);
                     return Ok(Async::NotReady);
                 }
             };
+            trace!("{}service responded with {}", self.log_id(), resp.status());
+            self.permit = None;
+            if let Some(ref mut timeout) = self.request_timeout {
+                timeout.timer = None;
+            }
+            echo(&mut self.echoed, resp.headers_mut());
+            for name in &self.strip_headers {
+                resp.headers_mut().remove_raw(name);
+            }
+            if self.strip_hop_by_hop {
+                strip_hop_by_hop(resp.status(), resp.headers_mut());
+            }
+            self.trailers = ::proto::response::take_trailers(&mut resp)
+                .map(|trailers| PendingTrailers::new(trailers, resp.headers()));
             let (head, body) = ::proto::response::split(resp);
             Ok(Async::Ready(Some((head.into(), body))))
         } else {
@@ -356,13 +794,64 @@ where
 
     fn recv_msg(&mut self, msg: ::Result<(Self::RecvItem, Option<Body>)>) -> ::Result<()> {
         let (msg, body) = msg?;
-        let req = ::proto::request::from_wire(None, msg, body);
-        self.in_flight = Some(self.service.call(req));
-        Ok(())
+        for name in &self.echo_headers {
+            if let Some(value) = msg.headers.get_raw(name) {
+                self.echoed.set_raw(name.clone(), value.clone());
+            }
+        }
+        let mut req = ::proto::request::from_wire(None, msg, body);
+        if let Some(ref info) = self.info {
+            ::proto::request::conn_info(&mut req, info);
+            match info.remote_addr {
+                Some(peer) if !self.trusted_proxies.is_empty() => {
+                    let addr = client_addr(peer, req.headers(), &self.trusted_proxies);
+                    ::proto::request::addr(&mut req, addr);
+                },
+                _ => (),
+            }
+        }
+        if let Some(ref slot) = self.upgrades {
+            ::proto::request::on_upgrade(&mut req, slot.next());
+        }
+        if let Some(ref mut ids) = self.request_ids {
+            ::proto::request::request_id(&mut req, ids.next());
+        }
+        trace!("{}received {} {}", self.log_id(), req.method(), req.uri());
+        ::proto::request::cancel_token(&mut req, self.cancel.0.clone());
+        if self.informational_responses {
+            let (tx, rx) = Informational::channel();
+            ::proto::request::informational(&mut req, tx);
+            self.informational = Some(rx);
+        }
+        if self.overload.is_some() {
+            let overloaded = self.reject_all || match self.request_limit {
+                Some(ref limit) => match RequestLimit::try_acquire(limit) {
+                    Some(permit) => {
+                        self.permit = Some(permit);
+                        false
+                    },
+                    None => true,
+                },
+                None => false,
+            };
+            if overloaded {
+                debug!("{}server overloaded, sending the overload response", self.log_id());
+                self.overloaded = true;
+                return Ok(());
+            }
+            return self.call(req);
+        }
+        if self.request_limit.is_some() {
+            self.queued = Some(req);
+            return self.call_queued();
+        }
+        self.call(req)
     }
 
     fn poll_ready(&mut self) -> Poll<(), ()> {
-        if self.in_flight.is_some() {
+        // a single request is in flight at a time, which keeps the responses
+        // to pipelined requests in order without buffering them
+        if self.has_request() {
             Ok(Async::NotReady)
         } else {
             Ok(Async::Ready(()))
@@ -370,7 +859,93 @@ where
     }
 
     fn should_poll(&self) -> bool {
-        self.in_flight.is_some()
+        self.has_request()
+    }
+
+    fn on_read_closed(&mut self) {
+        // also while the body of the response is streaming, so that a
+        // long-lived response, such as server-sent events, can stop
+        trace!("{}read closed while a response is pending", self.log_id());
+        self.cancel();
+    }
+
+    fn poll_informational(&mut self) -> Option<MessageHead<StatusCode>> {
+        if let Some(ref mut rx) = self.informational {
+            if let Ok(Async::Ready(Some(head))) = rx.poll() {
+                return Some(head);
+            }
+        }
+        if !self.has_request() {
+            // the final response was taken, so later ones are refused
+            self.informational = None;
+        }
+        None
+    }
+
+    fn take_trailers(&mut self) -> Option<PendingTrailers> {
+        self.trailers.take()
+    }
+
+    fn take_overload_response(&mut self) -> Option<(Self::PollItem, Option<::Chunk>)> {
+        if !self.overloaded {
+            return None;
+        }
+        self.overloaded = false;
+        let res = self.overload.as_ref().expect("overload response");
+        let mut head = res.head.clone();
+        echo(&mut self.echoed, &mut head.headers);
+        Some((head, res.body.clone().map(::Chunk::from)))
+    }
+
+    fn tunnels_connect(&self) -> bool {
+        self.tunnel_connect || self.upgrades.as_ref().map_or(false, UpgradeSlot::is_taken)
+    }
+
+    fn log_id(&self) -> LogId {
+        match self.request_ids {
+            Some(ref ids) => ids.log_id(),
+            None => LogId(None),
+        }
+    }
+}
+
+// Moves the `echoed` request headers onto a response that doesn't have them.
+fn echo(echoed: &mut Headers, headers: &mut Headers) {
+    for field in echoed.iter() {
+        if headers.get_raw(field.name()).is_none() {
+            headers.set_raw(field.name().to_owned(), field.raw().clone());
+        }
+    }
+    echoed.clear();
+}
+
+// Removes the hop-by-hop headers a service shouldn't set itself, keeping the
+// `close` and `keep-alive` options of `Connection`, which the connection
+// acts on.
+fn strip_hop_by_hop(status: StatusCode, headers: &mut Headers) {
+    let options = headers.get::<Connection>().map(|conn| conn.0.clone()).unwrap_or_default();
+    let mut kept = Vec::new();
+    for option in options {
+        match option {
+            ConnectionOption::ConnectionHeader(name) => headers.remove_raw(&name),
+            option => kept.push(option),
+        }
+    }
+    if kept.is_empty() {
+        headers.remove::<Connection>();
+    } else {
+        headers.set(Connection(kept));
+    }
+    headers.remove_raw("Keep-Alive");
+    headers.remove_raw("Proxy-Connection");
+    headers.remove_raw("TE");
+    if status != StatusCode::SwitchingProtocols {
+        headers.remove_raw("Upgrade");
+    }
+    // the length wins, a chunked encoding besides it would be conflicting
+    // framing
+    if headers.has::<ContentLength>() {
+        headers.remove::<TransferEncoding>();
     }
 }
 
@@ -465,6 +1040,31 @@ where
     fn should_poll(&self) -> bool {
         self.callback.is_none()
     }
+
+    fn on_read_closed(&mut self) {
+        // only servers look for the read side closing mid message
+    }
+
+    fn poll_informational(&mut self) -> Option<MessageHead<StatusCode>> {
+        // requests can't have informational messages
+        None
+    }
+
+    fn take_trailers(&mut self) -> Option<PendingTrailers> {
+        None
+    }
+
+    fn take_overload_response(&mut self) -> Option<(Self::PollItem, Option<::Chunk>)> {
+        None
+    }
+
+    fn tunnels_connect(&self) -> bool {
+        false
+    }
+
+    fn log_id(&self) -> LogId {
+        LogId(None)
+    }
 }
 
 #[cfg(test)]
@@ -69,6 +69,10 @@ impl Encoder {
         }
     }
 
+    pub fn is_chunked(&self) -> bool {
+        self.kind == Kind::Chunked
+    }
+
     pub fn set_last(&mut self) {
         self.is_last = true;
     }
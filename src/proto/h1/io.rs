@@ -1,4 +1,5 @@
 use std::cell::Cell;
+use std::cmp;
 use std::collections::VecDeque;
 use std::fmt;
 use std::io;
@@ -9,17 +10,24 @@ use iovec::IoVec;
 use tokio_io::{AsyncRead, AsyncWrite};
 
 use proto::{Http1Transaction, MessageHead};
+use super::role::MAX_HEADERS;
 
 const INIT_BUFFER_SIZE: usize = 8192;
 pub const DEFAULT_MAX_BUFFER_SIZE: usize = 8192 + 4096 * 100;
 const MAX_BUF_LIST_BUFFERS: usize = 16;
 
 pub struct Buffered<T, B> {
+    bytes_read: u64,
+    bytes_written: u64,
     flush_pipeline: bool,
     io: T,
     max_buf_size: usize,
+    max_head_size: Option<usize>,
+    max_headers: usize,
+    max_uri_len: Option<usize>,
     read_blocked: bool,
     read_buf: BytesMut,
+    read_buf_init: usize,
     write_buf: WriteBuf<B>,
 }
 
@@ -42,10 +50,16 @@ where
 {
     pub fn new(io: T) -> Buffered<T, B> {
         Buffered {
+            bytes_read: 0,
+            bytes_written: 0,
             flush_pipeline: false,
             io: io,
             max_buf_size: DEFAULT_MAX_BUFFER_SIZE,
+            max_head_size: None,
+            max_headers: MAX_HEADERS,
+            max_uri_len: None,
             read_buf: BytesMut::with_capacity(0),
+            read_buf_init: INIT_BUFFER_SIZE,
             write_buf: WriteBuf::new(),
             read_blocked: false,
         }
@@ -65,6 +79,26 @@ where
         self.write_buf.max_buf_size = max;
     }
 
+    pub fn set_read_buf_init(&mut self, size: usize) {
+        self.read_buf_init = size;
+    }
+
+    pub fn set_write_buf_limit(&mut self, limit: Option<usize>) {
+        self.write_buf.limit = limit;
+    }
+
+    pub fn set_max_headers(&mut self, max: usize) {
+        self.max_headers = max;
+    }
+
+    pub fn set_max_head_size(&mut self, max: Option<usize>) {
+        self.max_head_size = max;
+    }
+
+    pub fn set_max_uri_len(&mut self, max: Option<usize>) {
+        self.max_uri_len = max;
+    }
+
     pub fn set_write_strategy_flatten(&mut self) {
         // this should always be called only at construction time,
         // so this assert is here to catch myself
@@ -76,6 +110,12 @@ where
         self.read_buf.as_ref()
     }
 
+    /// Start reading from `buf`, bytes already read from the IO.
+    pub fn set_read_buf(&mut self, buf: &[u8]) {
+        debug_assert!(self.read_buf.is_empty());
+        self.read_buf.extend_from_slice(buf);
+    }
+
     //TODO(perf): don't return a `&mut Vec<u8>`, but a wrapper
     //that protects the Vec when growing. Specifically, if this
     //Vec couldn't be reset, as it's position isn't at the end,
@@ -91,8 +131,12 @@ where
         self.write_buf.buffer(buf)
     }
 
+    pub fn is_write_empty(&self) -> bool {
+        self.write_buf.remaining() == 0
+    }
+
     pub fn can_buffer(&self) -> bool {
-        self.flush_pipeline || self.write_buf.can_buffer()
+        self.write_buf.below_limit() && (self.flush_pipeline || self.write_buf.can_buffer())
     }
 
     pub fn consume_leading_lines(&mut self) {
@@ -108,14 +152,29 @@ where
         }
     }
 
-    pub fn parse<S: Http1Transaction>(&mut self) -> Poll<MessageHead<S::Incoming>, ::Error> {
+    pub fn parse<S: Http1Transaction>(&mut self) -> Poll<(MessageHead<S::Incoming>, usize), ::Error> {
         loop {
-            match try!(S::parse(&mut self.read_buf)) {
+            if let Some(max) = self.max_uri_len {
+                // checked on the bytes received so far, before parsing
+                if uri_len(&self.read_buf, max) > max {
+                    debug!("max_uri_len ({}) exceeded, closing", max);
+                    return Err(::Error::UriTooLong);
+                }
+            }
+            match try!(S::parse(&mut self.read_buf, self.max_headers)) {
                 Some((head, len)) => {
                     debug!("parsed {} headers ({} bytes)", head.headers.len(), len);
-                    return Ok(Async::Ready(head))
+                    if self.max_head_size.map_or(false, |max| len > max) {
+                        debug!("max_head_size ({:?}) exceeded, closing", self.max_head_size);
+                        return Err(::Error::TooLarge);
+                    }
+                    return Ok(Async::Ready((head, len)))
                 },
                 None => {
+                    if self.max_head_size.map_or(false, |max| self.read_buf.len() > max) {
+                        debug!("max_head_size ({:?}) exceeded, closing", self.max_head_size);
+                        return Err(::Error::TooLarge);
+                    }
                     if self.read_buf.capacity() >= self.max_buf_size {
                         debug!("max_buf_size ({}) reached, closing", self.max_buf_size);
                         return Err(::Error::TooLarge);
@@ -135,13 +194,17 @@ where
     pub fn read_from_io(&mut self) -> Poll<usize, io::Error> {
         use bytes::BufMut;
         self.read_blocked = false;
-        if self.read_buf.remaining_mut() < INIT_BUFFER_SIZE {
-            self.read_buf.reserve(INIT_BUFFER_SIZE);
+        // never reserve more than max_buf_size, nor nothing at all, which
+        // would read as eof
+        let init = cmp::max(cmp::min(self.read_buf_init, self.max_buf_size), 1);
+        if self.read_buf.remaining_mut() < init {
+            self.read_buf.reserve(init);
         }
         self.io.read_buf(&mut self.read_buf).map(|ok| {
             match ok {
                 Async::Ready(n) => {
                     debug!("read {} bytes", n);
+                    self.bytes_read += n as u64;
                     Async::Ready(n)
                 },
                 Async::NotReady => {
@@ -156,6 +219,14 @@ where
         (self.io, self.read_buf.freeze())
     }
 
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
     pub fn io_mut(&mut self) -> &mut T {
         &mut self.io
     }
@@ -166,13 +237,22 @@ where
 
     pub fn flush(&mut self) -> Poll<(), io::Error> {
         if self.flush_pipeline && !self.read_buf.is_empty() {
-            //Ok(())
-        } else if self.write_buf.remaining() == 0 {
+            Ok(Async::Ready(()))
+        } else {
+            self.flush_all()
+        }
+    }
+
+    /// Flush the write buffer, even if `flush_pipeline` would wait on more
+    /// buffered messages.
+    pub fn flush_all(&mut self) -> Poll<(), io::Error> {
+        if self.write_buf.remaining() == 0 {
             try_nb!(self.io.flush());
         } else {
             loop {
                 let n = try_ready!(self.io.write_buf(&mut self.write_buf.auto()));
                 debug!("flushed {} bytes", n);
+                self.bytes_written += n as u64;
                 if self.write_buf.remaining() == 0 {
                     break;
                 } else if n == 0 {
@@ -186,6 +266,28 @@ where
     }
 }
 
+// The length of the URI in the request line at the start of `buf`, as far as
+// it was received, counting no further than `max + 1` bytes.
+fn uri_len(buf: &[u8], max: usize) -> usize {
+    match buf.iter().position(|&b| b == b' ') {
+        Some(start) => buf[start + 1..].iter()
+            .take(max + 1)
+            .take_while(|&&b| b != b' ' && b != b'\r' && b != b'\n')
+            .count(),
+        None => 0,
+    }
+}
+
+impl<T, B: Buf> Buffered<T, B> {
+    pub fn io(&self) -> &T {
+        &self.io
+    }
+
+    pub fn write_buf_len(&self) -> usize {
+        self.write_buf.remaining()
+    }
+}
+
 pub trait MemRead {
     fn read_mem(&mut self, len: usize) -> Poll<Bytes, io::Error>;
 }
@@ -272,6 +374,8 @@ impl<T: AsRef<[u8]>> Buf for Cursor<T> {
 struct WriteBuf<B> {
     buf: BufDeque<B>,
     max_buf_size: usize,
+    // the high-water mark of unwritten bytes, past which no more are buffered
+    limit: Option<usize>,
     strategy: Strategy,
 }
 
@@ -280,6 +384,7 @@ impl<B> WriteBuf<B> {
         WriteBuf {
             buf: BufDeque::new(),
             max_buf_size: DEFAULT_MAX_BUFFER_SIZE,
+            limit: None,
             strategy: Strategy::Auto,
         }
     }
@@ -324,6 +429,10 @@ where
         }
     }
 
+    fn below_limit(&self) -> bool {
+        self.limit.map_or(true, |limit| self.remaining() < limit)
+    }
+
     fn head_mut(&mut self) -> &mut Cursor<Vec<u8>> {
         // this dance is brought to you, The Borrow Checker!
 
@@ -592,6 +701,47 @@ mod tests {
         io_buf.flush().expect("should short-circuit flush");
     }
 
+    #[test]
+    fn read_buf_init() {
+        let mock = AsyncIo::new_buf(&b"GET / HTTP/1.1\r\n\r\n"[..], 1024);
+        let mut buffered = Buffered::<_, Cursor<Vec<u8>>>::new(mock);
+        buffered.set_read_buf_init(64);
+        buffered.read_from_io().unwrap();
+
+        assert_eq!(buffered.read_buf(), b"GET / HTTP/1.1\r\n\r\n");
+        assert!(buffered.read_buf.capacity() < INIT_BUFFER_SIZE);
+    }
+
+    #[test]
+    fn read_buf_init_clamped_to_max_buf_size() {
+        let mock = AsyncIo::new_buf(&b"GET / HTTP/1.1\r\n\r\n"[..], 1024);
+        let mut buffered = Buffered::<_, Cursor<Vec<u8>>>::new(mock);
+        buffered.set_read_buf_init(INIT_BUFFER_SIZE * 4);
+        buffered.set_max_buf_size(1024);
+        buffered.read_from_io().unwrap();
+
+        assert!(buffered.read_buf.capacity() < INIT_BUFFER_SIZE);
+    }
+
+    #[test]
+    fn parse_max_uri_len() {
+        let raw = "GET /0123456789 HTTP/1.1\r\n\r\n";
+        let mock = AsyncIo::new_buf(raw, raw.len());
+        let mut buffered = Buffered::<_, Cursor<Vec<u8>>>::new(mock);
+        buffered.set_max_uri_len(Some(11));
+        assert!(buffered.parse::<::proto::ServerTransaction>().unwrap().is_ready());
+
+        // refused before the request line is complete
+        let raw = "GET /0123456789a";
+        let mock = AsyncIo::new_buf(raw, raw.len());
+        let mut buffered = Buffered::<_, Cursor<Vec<u8>>>::new(mock);
+        buffered.set_max_uri_len(Some(11));
+        match buffered.parse::<::proto::ServerTransaction>() {
+            Err(::Error::UriTooLong) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
     #[test]
     fn parse_reads_until_blocked() {
         // missing last line ending
@@ -727,4 +877,30 @@ mod tests {
         assert_eq!(buffered.io, b"hello world, it's hyper!");
         assert_eq!(buffered.io.num_writes(), 4);
     }
+
+    #[test]
+    fn write_buf_limit() {
+        use futures::{future, Future};
+        let _: Result<(), ()> = future::lazy(|| {
+            let mock = AsyncIo::new_buf(vec![], 0);
+            let mut buffered = Buffered::<_, Cursor<Vec<u8>>>::new(mock);
+            buffered.set_write_buf_limit(Some(16));
+
+            buffered.buffer(Cursor::new(vec![b'a'; 10]));
+            assert!(buffered.can_buffer());
+            buffered.buffer(Cursor::new(vec![b'b'; 10]));
+            assert!(!buffered.can_buffer());
+
+            // below the limit once some of it is written
+            buffered.io.block_in(8);
+            assert!(buffered.flush().unwrap().is_not_ready());
+            assert!(buffered.can_buffer());
+
+            // the limit applies when flushing pipelined responses too
+            buffered.set_flush_pipeline(true);
+            buffered.buffer(Cursor::new(vec![b'c'; 10]));
+            assert!(!buffered.can_buffer());
+            Ok(())
+        }).wait();
+    }
 }
@@ -13,7 +13,7 @@ use method::Method;
 use status::StatusCode;
 use version::HttpVersion::{Http10, Http11};
 
-const MAX_HEADERS: usize = 100;
+pub const MAX_HEADERS: usize = 100;
 const AVERAGE_HEADER_SIZE: usize = 30; // totally scientific
 
 // There are 2 main roles, Client and Server.
@@ -32,7 +32,7 @@ where
     type Incoming = RequestLine;
     type Outgoing = StatusCode;
 
-    fn parse(buf: &mut BytesMut) -> ParseResult<RequestLine> {
+    fn parse(buf: &mut BytesMut, max_headers: usize) -> ParseResult<RequestLine> {
         if buf.len() == 0 {
             return Ok(None);
         }
@@ -41,10 +41,24 @@ where
         // values into it. By not zeroing out the stack memory, this saves
         // a good ~5% on pipeline benchmarks.
         let mut headers_indices: [HeaderIndices; MAX_HEADERS] = unsafe { mem::uninitialized() };
+        let mut more_indices;
+        let headers_indices = if max_headers <= MAX_HEADERS {
+            &mut headers_indices[..max_headers]
+        } else {
+            more_indices = vec![HeaderIndices::default(); max_headers];
+            &mut more_indices[..]
+        };
         let (len, method, path, version, headers_len) = {
             let mut headers: [httparse::Header; MAX_HEADERS] = unsafe { mem::uninitialized() };
+            let mut more_headers;
+            let headers = if max_headers <= MAX_HEADERS {
+                &mut headers[..max_headers]
+            } else {
+                more_headers = vec![httparse::EMPTY_HEADER; max_headers];
+                &mut more_headers[..]
+            };
             trace!("Request.parse([Header; {}], [u8; {}])", headers.len(), buf.len());
-            let mut req = httparse::Request::new(&mut headers);
+            let mut req = httparse::Request::new(headers);
             match try!(req.parse(&buf)) {
                 httparse::Status::Complete(len) => {
                     trace!("Request.parse Complete({})", len);
@@ -54,9 +68,10 @@ where
                     let path_start = path.as_ptr() as usize - bytes_ptr;
                     let path_end = path_start + path.len();
                     let path = (path_start, path_end);
+                    // httparse only accepts HTTP/1.0 and HTTP/1.1 start lines.
                     let version = if req.version.unwrap() == 1 { Http11 } else { Http10 };
 
-                    record_header_indices(buf.as_ref(), &req.headers, &mut headers_indices);
+                    record_header_indices(buf.as_ref(), &req.headers, headers_indices);
                     let headers_len = req.headers.len();
                     (len, method, path, version, headers_len)
                 }
@@ -125,14 +140,19 @@ where
     }
 
 
-    fn encode(mut head: MessageHead<Self::Outgoing>, has_body: bool, method: &mut Option<Method>, dst: &mut Vec<u8>) -> ::Result<Encoder> {
+    fn encode(mut head: MessageHead<Self::Outgoing>, has_body: bool, auto_date: bool, connect_tunnel: bool, method: &mut Option<Method>, dst: &mut Vec<u8>) -> ::Result<Encoder> {
         trace!("Server::encode has_body={}, method={:?}", has_body, method);
 
         // hyper currently doesn't support returning 1xx status codes as a Response
         // This is because Service only allows returning a single Response, and
         // so if you try to reply with a e.g. 100 Continue, you have no way of
         // replying with the latter status code response.
-        let ret = if ::StatusCode::SwitchingProtocols == head.subject {
+        //
+        // Both a 101 response, and a successful response to a CONNECT when
+        // the connection becomes a tunnel, end HTTP on this connection.
+        let is_upgrade = ::StatusCode::SwitchingProtocols == head.subject ||
+            (connect_tunnel && *method == Some(Method::Connect) && head.subject.is_success());
+        let ret = if is_upgrade {
             T::on_encode_upgrade(&mut head)
                 .map(|_| {
                     let mut enc = Server::set_length(&mut head, has_body, method.as_ref());
@@ -160,7 +180,7 @@ where
         }
         // using http::h1::date is quite a lot faster than generating a unique Date header each time
         // like req/s goes up about 10%
-        if !head.headers.has::<header::Date>() {
+        if auto_date && !head.headers.has::<header::Date>() {
             dst.reserve(date::DATE_VALUE_LENGTH + 8);
             extend(dst, b"Date: ");
             date::extend(dst);
@@ -182,6 +202,19 @@ where
             &::Error::TooLarge => {
                 StatusCode::RequestHeaderFieldsTooLarge
             }
+            &::Error::UriTooLong => {
+                StatusCode::UriTooLong
+            }
+            &::Error::BodyTooLarge => {
+                StatusCode::PayloadTooLarge
+            }
+            &::Error::Http2Preface => {
+                StatusCode::HttpVersionNotSupported
+            }
+            // only passed on with `Http::send_408_on_timeout`
+            &::Error::HeaderTimeout => {
+                StatusCode::RequestTimeout
+            }
             _ => return None,
         };
 
@@ -191,6 +224,11 @@ where
         Some(msg)
     }
 
+    fn on_error_response(head: MessageHead<StatusCode>) -> Option<MessageHead<Self::Outgoing>> {
+        debug!("sending custom error response ({})", head.subject);
+        Some(head)
+    }
+
     fn should_error_on_parse_eof() -> bool {
         false
     }
@@ -243,16 +281,30 @@ where
     type Incoming = RawStatus;
     type Outgoing = RequestLine;
 
-    fn parse(buf: &mut BytesMut) -> ParseResult<RawStatus> {
+    fn parse(buf: &mut BytesMut, max_headers: usize) -> ParseResult<RawStatus> {
         if buf.len() == 0 {
             return Ok(None);
         }
         // Unsafe: see comment in Server Http1Transaction, above.
         let mut headers_indices: [HeaderIndices; MAX_HEADERS] = unsafe { mem::uninitialized() };
+        let mut more_indices;
+        let headers_indices = if max_headers <= MAX_HEADERS {
+            &mut headers_indices[..max_headers]
+        } else {
+            more_indices = vec![HeaderIndices::default(); max_headers];
+            &mut more_indices[..]
+        };
         let (len, code, reason, version, headers_len) = {
             let mut headers: [httparse::Header; MAX_HEADERS] = unsafe { mem::uninitialized() };
+            let mut more_headers;
+            let headers = if max_headers <= MAX_HEADERS {
+                &mut headers[..max_headers]
+            } else {
+                more_headers = vec![httparse::EMPTY_HEADER; max_headers];
+                &mut more_headers[..]
+            };
             trace!("Response.parse([Header; {}], [u8; {}])", headers.len(), buf.len());
-            let mut res = httparse::Response::new(&mut headers);
+            let mut res = httparse::Response::new(headers);
             let bytes = buf.as_ref();
             match try!(res.parse(bytes)) {
                 httparse::Status::Complete(len) => {
@@ -264,7 +316,7 @@ where
                         _ => Cow::Owned(res.reason.unwrap().to_owned())
                     };
                     let version = if res.version.unwrap() == 1 { Http11 } else { Http10 };
-                    record_header_indices(bytes, &res.headers, &mut headers_indices);
+                    record_header_indices(bytes, &res.headers, headers_indices);
                     let headers_len = res.headers.len();
                     (len, code, reason, version, headers_len)
                 },
@@ -345,7 +397,7 @@ where
         }
     }
 
-    fn encode(mut head: MessageHead<Self::Outgoing>, has_body: bool, method: &mut Option<Method>, dst: &mut Vec<u8>) -> ::Result<Encoder> {
+    fn encode(mut head: MessageHead<Self::Outgoing>, has_body: bool, _auto_date: bool, _connect_tunnel: bool, method: &mut Option<Method>, dst: &mut Vec<u8>) -> ::Result<Encoder> {
         trace!("Client::encode has_body={}, method={:?}", has_body, method);
 
         *method = Some(head.subject.0.clone());
@@ -364,6 +416,10 @@ where
         None
     }
 
+    fn on_error_response(_head: MessageHead<StatusCode>) -> Option<MessageHead<Self::Outgoing>> {
+        None
+    }
+
     fn should_error_on_parse_eof() -> bool {
         true
     }
@@ -451,7 +507,7 @@ impl OnUpgrade for NoUpgrades {
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Default)]
 struct HeaderIndices {
     name: (usize, usize),
     value: (usize, usize),
@@ -515,7 +571,7 @@ mod tests {
     use bytes::BytesMut;
 
     use proto::{Decode, MessageHead};
-    use super::{Decoder, Server as S, Client as C, NoUpgrades, Http1Transaction};
+    use super::{Decoder, Server as S, Client as C, NoUpgrades, Http1Transaction, MAX_HEADERS};
     use header::{ContentLength, TransferEncoding};
 
     type Server = S<NoUpgrades>;
@@ -550,7 +606,7 @@ mod tests {
         let _ = pretty_env_logger::try_init();
         let mut raw = BytesMut::from(b"GET /echo HTTP/1.1\r\nHost: hyper.rs\r\n\r\n".to_vec());
         let expected_len = raw.len();
-        let (req, len) = Server::parse(&mut raw).unwrap().unwrap();
+        let (req, len) = Server::parse(&mut raw, MAX_HEADERS).unwrap().unwrap();
         assert_eq!(len, expected_len);
         assert_eq!(req.subject.0, ::Method::Get);
         assert_eq!(req.subject.1, "/echo");
@@ -559,6 +615,17 @@ mod tests {
         assert_eq!(req.headers.get_raw("Host").map(|raw| &raw[0]), Some(b"hyper.rs".as_ref()));
     }
 
+    #[test]
+    fn test_parse_request_version() {
+        let mut raw = BytesMut::from(b"GET / HTTP/1.0\r\n\r\n".to_vec());
+        let (req, _) = Server::parse(&mut raw, MAX_HEADERS).unwrap().unwrap();
+        assert_eq!(req.version, ::HttpVersion::Http10);
+
+        let mut raw = BytesMut::from(b"GET / HTTP/1.1\r\n\r\n".to_vec());
+        let (req, _) = Server::parse(&mut raw, MAX_HEADERS).unwrap().unwrap();
+        assert_eq!(req.version, ::HttpVersion::Http11);
+    }
+
 
     #[test]
     fn test_parse_response() {
@@ -566,7 +633,7 @@ mod tests {
         let _ = pretty_env_logger::try_init();
         let mut raw = BytesMut::from(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n".to_vec());
         let expected_len = raw.len();
-        let (req, len) = Client::parse(&mut raw).unwrap().unwrap();
+        let (req, len) = Client::parse(&mut raw, MAX_HEADERS).unwrap().unwrap();
         assert_eq!(len, expected_len);
         assert_eq!(req.subject.0, 200);
         assert_eq!(req.subject.1, "OK");
@@ -578,17 +645,36 @@ mod tests {
     #[test]
     fn test_parse_request_errors() {
         let mut raw = BytesMut::from(b"GET htt:p// HTTP/1.1\r\nHost: hyper.rs\r\n\r\n".to_vec());
-        Server::parse(&mut raw).unwrap_err();
+        Server::parse(&mut raw, MAX_HEADERS).unwrap_err();
+    }
+
+    #[test]
+    fn test_parse_request_max_headers() {
+        let mut head = b"GET / HTTP/1.1\r\n".to_vec();
+        for i in 0..200 {
+            head.extend_from_slice(format!("X-Header-{}: value\r\n", i).as_bytes());
+        }
+        head.extend_from_slice(b"\r\n");
+
+        let mut raw = BytesMut::from(head.clone());
+        match Server::parse(&mut raw, MAX_HEADERS) {
+            Err(::Error::TooLarge) => (),
+            other => panic!("unexpected parse result: {:?}", other.map(|_| ())),
+        }
+
+        let mut raw = BytesMut::from(head);
+        let (req, _) = Server::parse(&mut raw, 256).unwrap().unwrap();
+        assert_eq!(req.headers.len(), 200);
     }
 
     #[test]
     fn test_parse_raw_status() {
         let mut raw = BytesMut::from(b"HTTP/1.1 200 OK\r\n\r\n".to_vec());
-        let (res, _) = Client::parse(&mut raw).unwrap().unwrap();
+        let (res, _) = Client::parse(&mut raw, MAX_HEADERS).unwrap().unwrap();
         assert_eq!(res.subject.1, "OK");
 
         let mut raw = BytesMut::from(b"HTTP/1.1 200 Howdy\r\n\r\n".to_vec());
-        let (res, _) = Client::parse(&mut raw).unwrap().unwrap();
+        let (res, _) = Client::parse(&mut raw, MAX_HEADERS).unwrap().unwrap();
         assert_eq!(res.subject.1, "Howdy");
     }
 
@@ -644,6 +730,49 @@ mod tests {
         assert_eq!(Decoder::length(15), Server::decoder(&head, method).unwrap().normal());
     }
 
+    #[test]
+    fn test_encode_upgrade_response() {
+        use super::YesUpgrades;
+
+        let mut head = MessageHead::<::StatusCode>::default();
+        let mut dst = Vec::new();
+
+        head.subject = ::StatusCode::SwitchingProtocols;
+        let enc = S::<YesUpgrades>::encode(head.clone(), false, true, true, &mut Some(::Method::Get), &mut dst).unwrap();
+        assert!(enc.is_last());
+
+        // a successful CONNECT is a tunnel from here on
+        head.subject = ::StatusCode::Ok;
+        let enc = S::<YesUpgrades>::encode(head.clone(), false, true, true, &mut Some(::Method::Connect), &mut dst).unwrap();
+        assert!(enc.is_last());
+
+        head.subject = ::StatusCode::Forbidden;
+        let enc = S::<YesUpgrades>::encode(head.clone(), false, true, true, &mut Some(::Method::Connect), &mut dst).unwrap();
+        assert!(!enc.is_last());
+
+        // unless nothing takes the connection over
+        head.subject = ::StatusCode::Ok;
+        let enc = S::<YesUpgrades>::encode(head.clone(), false, true, false, &mut Some(::Method::Connect), &mut dst).unwrap();
+        assert!(!enc.is_last());
+
+        head.subject = ::StatusCode::Ok;
+        let enc = S::<YesUpgrades>::encode(head.clone(), false, true, true, &mut Some(::Method::Get), &mut dst).unwrap();
+        assert!(!enc.is_last());
+    }
+
+    #[test]
+    fn test_encode_auto_date() {
+        let head = MessageHead::<::StatusCode>::default();
+
+        let mut dst = Vec::new();
+        Server::encode(head.clone(), false, true, false, &mut None, &mut dst).unwrap();
+        assert!(String::from_utf8(dst).unwrap().contains("Date: "));
+
+        let mut dst = Vec::new();
+        Server::encode(head, false, false, false, &mut None, &mut dst).unwrap();
+        assert!(!String::from_utf8(dst).unwrap().contains("Date: "));
+    }
+
     #[test]
     fn test_decoder_response() {
         use super::Decoder;
@@ -741,7 +870,7 @@ mod tests {
 
         b.bytes = len as u64;
         b.iter(|| {
-            Server::parse(&mut raw).unwrap();
+            Server::parse(&mut raw, MAX_HEADERS).unwrap();
             restart(&mut raw, len);
         });
 
@@ -773,7 +902,7 @@ mod tests {
 
         b.iter(|| {
             let mut vec = Vec::new();
-            Server::encode(head.clone(), true, &mut None, &mut vec).unwrap();
+            Server::encode(head.clone(), true, true, false, &mut None, &mut vec).unwrap();
             assert_eq!(vec.len(), len);
             ::test::black_box(vec);
         })
@@ -0,0 +1,56 @@
+use std::fmt;
+
+use futures::sync::mpsc;
+
+use super::MessageHead;
+use super::response::{self, Response};
+use status::StatusCode;
+
+/// Sends informational (1xx) responses ahead of the final `Response` to a
+/// `Request`.
+///
+/// Every `Request` read by a server connection with
+/// `Http::informational_responses` enabled carries one, see
+/// `Request::informational`. A service can use it to send, for instance, a
+/// `103 Early Hints`, with the status `StatusCode::Unregistered(103)`,
+/// listing resources the client may start loading while the final response
+/// is computed. The responses are written in the order
+/// they are sent, before the final response.
+#[derive(Clone)]
+pub struct Informational {
+    tx: mpsc::UnboundedSender<MessageHead<StatusCode>>,
+}
+
+pub(crate) type InformationalRx = mpsc::UnboundedReceiver<MessageHead<StatusCode>>;
+
+impl Informational {
+    pub(crate) fn channel() -> (Informational, InformationalRx) {
+        let (tx, rx) = mpsc::unbounded();
+        (Informational { tx: tx }, rx)
+    }
+
+    /// Send an informational response.
+    ///
+    /// Only the status and headers of `res` are sent. Nothing is written to
+    /// a client speaking HTTP/1.0, which doesn't know about 1xx responses.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Status` if the status of `res` is not a 1xx, or is
+    /// `101 Switching Protocols`, which ends HTTP on the connection. Returns
+    /// `Error::Closed` once the final response was started, or when the
+    /// connection is gone.
+    pub fn send(&self, res: Response<()>) -> ::Result<()> {
+        let (head, _) = response::split(res);
+        if !head.subject.is_informational() || head.subject == StatusCode::SwitchingProtocols {
+            return Err(::Error::Status);
+        }
+        self.tx.unbounded_send(head).map_err(|_| ::Error::Closed)
+    }
+}
+
+impl fmt::Debug for Informational {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("Informational")
+    }
+}
@@ -1,6 +1,8 @@
 //! Pieces pertaining to the HTTP message protocol.
 use std::borrow::Cow;
 use std::fmt;
+use std::net::SocketAddr;
+use std::time::Instant;
 
 use bytes::BytesMut;
 
@@ -12,16 +14,29 @@ use uri::Uri;
 use version::HttpVersion;
 use version::HttpVersion::{Http10, Http11};
 
-pub use self::body::Body;
+pub use self::body::{Body, ConcatLimited};
+pub use self::cancel::{CancelToken, Cancelled};
+pub use self::forwarded::{IpNet, IpNetError};
+pub use self::informational::Informational;
+pub use self::request_id::RequestId;
+pub use self::socket::SocketControl;
+pub use self::trailers::Trailers;
 #[cfg(feature = "tokio-proto")]
 pub use self::body::TokioBody;
 pub use self::chunk::Chunk;
 pub use self::h1::{date, dispatch, Conn};
 
 mod body;
+mod cancel;
 mod chunk;
+pub(crate) mod forwarded;
 mod h1;
 //mod h2;
+mod informational;
+pub(crate) mod request_id;
+pub(crate) mod request_limit;
+mod socket;
+pub(crate) mod trailers;
 pub mod request;
 pub mod response;
 
@@ -37,6 +52,47 @@ pub struct MessageHead<S> {
     pub headers: Headers
 }
 
+/// What happened over one request and response exchange of a connection.
+///
+/// Passed to the callback set with `Http::on_exchange_complete`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExchangeInfo {
+    /// Whether the connection is kept alive for another request.
+    pub keep_alive: bool,
+    /// The bytes of the request head and of its decoded body.
+    pub bytes_read: u64,
+    /// The bytes of the response head and of its body, before any
+    /// transfer encoding.
+    pub bytes_written: u64,
+    _inner: (),
+}
+
+/// When the steps of one request and response exchange happened.
+///
+/// Passed to the callback set with `Http::on_exchange_timing`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimingInfo {
+    /// When the request head finished parsing.
+    pub head_received: Instant,
+    /// When the request was passed to the service.
+    pub service_called: Instant,
+    /// When the last byte of the response was written to the IO.
+    pub response_flushed: Instant,
+    _inner: (),
+}
+
+/// The write buffer of a connection drained to the socket.
+///
+/// Passed to the callback set with `Http::on_write_drained`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WriteDrained {
+    /// The address of the remote peer, if it is known.
+    pub remote_addr: Option<SocketAddr>,
+    /// The bytes written to the connection so far, as sent.
+    pub bytes_written: u64,
+    _inner: (),
+}
+
 /// An incoming request message.
 pub type RequestHead = MessageHead<RequestLine>;
 
@@ -144,10 +200,11 @@ pub type ClientUpgradeTransaction = h1::role::Client<h1::role::YesUpgrades>;
 pub trait Http1Transaction {
     type Incoming;
     type Outgoing: Default;
-    fn parse(bytes: &mut BytesMut) -> ParseResult<Self::Incoming>;
+    fn parse(bytes: &mut BytesMut, max_headers: usize) -> ParseResult<Self::Incoming>;
     fn decoder(head: &MessageHead<Self::Incoming>, method: &mut Option<::Method>) -> ::Result<Decode>;
-    fn encode(head: MessageHead<Self::Outgoing>, has_body: bool, method: &mut Option<Method>, dst: &mut Vec<u8>) -> ::Result<h1::Encoder>;
+    fn encode(head: MessageHead<Self::Outgoing>, has_body: bool, auto_date: bool, connect_tunnel: bool, method: &mut Option<Method>, dst: &mut Vec<u8>) -> ::Result<h1::Encoder>;
     fn on_error(err: &::Error) -> Option<MessageHead<Self::Outgoing>>;
+    fn on_error_response(head: MessageHead<StatusCode>) -> Option<MessageHead<Self::Outgoing>>;
 
     fn should_error_on_parse_eof() -> bool;
     fn should_read_first() -> bool;
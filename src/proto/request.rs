@@ -3,13 +3,15 @@ use std::fmt;
 use std::mem::replace;
 use std::net::SocketAddr;
 
+use bytes::Bytes;
 #[cfg(feature = "compat")]
 use http;
 
 use header::Headers;
-use proto::{Body, MessageHead, RequestHead, RequestLine};
+use proto::{Body, CancelToken, Informational, MessageHead, RequestHead, RequestId, RequestLine, SocketControl};
 use method::Method;
 use uri::{self, Uri};
+use server::conn::OnUpgrade;
 use version::HttpVersion;
 
 /// An HTTP Request
@@ -21,6 +23,14 @@ pub struct Request<B = Body> {
     body: Option<B>,
     is_proxy: bool,
     remote_addr: Option<SocketAddr>,
+    local_addr: Option<SocketAddr>,
+    alpn_protocol: Option<Bytes>,
+    peer_certificate: Option<Bytes>,
+    cancel_token: Option<CancelToken>,
+    informational: Option<Informational>,
+    socket_control: Option<SocketControl>,
+    on_upgrade: Option<OnUpgrade>,
+    request_id: Option<RequestId>,
 }
 
 impl<B> Request<B> {
@@ -35,6 +45,14 @@ impl<B> Request<B> {
             body: None,
             is_proxy: false,
             remote_addr: None,
+            local_addr: None,
+            alpn_protocol: None,
+            peer_certificate: None,
+            cancel_token: None,
+            informational: None,
+            socket_control: None,
+            on_upgrade: None,
+            request_id: None,
         }
     }
 
@@ -43,6 +61,9 @@ impl<B> Request<B> {
     pub fn uri(&self) -> &Uri { &self.uri }
 
     /// Read the Request Version.
+    ///
+    /// For a request read by a server, this is the version of its request
+    /// line, as parsed off the wire. It is only changed by `set_version`.
     #[inline]
     pub fn version(&self) -> HttpVersion { self.version }
 
@@ -67,6 +88,87 @@ impl<B> Request<B> {
     #[deprecated(since="0.11.12", note="This method will be gone in future versions.")]
     pub fn remote_addr(&self) -> Option<SocketAddr> { self.remote_addr }
 
+    /// The local address the connection of this Request was accepted on.
+    ///
+    /// This is only known for requests served from a hyper `Server` or
+    /// from a `Serve` stream of connections.
+    #[inline]
+    pub fn local_addr(&self) -> Option<SocketAddr> { self.local_addr }
+
+    /// The protocol negotiated with ALPN by the connection of this Request,
+    /// such as `b"http/1.1"`.
+    ///
+    /// This is only known for requests served from a `Serve` stream, whose
+    /// connections report it through `ConnectionInfo`.
+    #[inline]
+    pub fn alpn_protocol(&self) -> Option<&[u8]> {
+        self.alpn_protocol.as_ref().map(|p| p.as_ref())
+    }
+
+    /// The DER encoded certificate presented by the peer of the connection
+    /// of this Request.
+    ///
+    /// This is only known for requests served from a `Serve` stream, whose
+    /// connections report it through `ConnectionInfo`.
+    #[inline]
+    pub fn peer_certificate(&self) -> Option<&[u8]> {
+        self.peer_certificate.as_ref().map(|c| c.as_ref())
+    }
+
+    /// The token cancelled once the connection of this Request goes away.
+    ///
+    /// This is only known for requests served with `Http::serve_connection`,
+    /// or from a `Server` or `Serve` built on it.
+    #[inline]
+    pub fn cancel_token(&self) -> Option<&CancelToken> {
+        self.cancel_token.as_ref()
+    }
+
+    /// A sender of informational (1xx) responses to this Request, written
+    /// before its final response.
+    ///
+    /// This is only known for requests served with
+    /// `Http::informational_responses` enabled.
+    #[inline]
+    pub fn informational(&self) -> Option<&Informational> {
+        self.informational.as_ref()
+    }
+
+    /// A handle adjusting the options of the TCP socket this Request was
+    /// read from.
+    ///
+    /// This is only known for requests served by `Server`, or from a `Serve`
+    /// stream whose connections report it through `ConnectionInfo`, such as
+    /// those of `serve_addr`.
+    #[inline]
+    pub fn socket_control(&self) -> Option<&SocketControl> {
+        self.socket_control.as_ref()
+    }
+
+    /// The identifier of this Request, found in the log messages of hyper
+    /// about it.
+    ///
+    /// This is only known when `Http::request_ids` is enabled, so that a
+    /// service can log with the same identifier.
+    #[inline]
+    pub fn request_id(&self) -> Option<RequestId> {
+        self.request_id
+    }
+
+    /// Take the future of the connection of this Request, resolving once
+    /// the service takes it over, such as to tunnel a `CONNECT`.
+    ///
+    /// This is only known for requests served by `Server`, and is `None`
+    /// once taken. See `OnUpgrade`.
+    #[inline]
+    pub fn on_upgrade(&mut self) -> Option<OnUpgrade> {
+        let on_upgrade = self.on_upgrade.take();
+        if let Some(ref on_upgrade) = on_upgrade {
+            on_upgrade.mark_taken();
+        }
+        on_upgrade
+    }
+
     /// The target path of this Request.
     #[inline]
     pub fn path(&self) -> &str {
@@ -134,6 +236,9 @@ impl<B> fmt::Debug for Request<B> {
             .field("uri", &self.uri)
             .field("version", &self.version)
             .field("remote_addr", &self.remote_addr)
+            .field("local_addr", &self.local_addr)
+            .field("alpn_protocol", &self.alpn_protocol)
+            .field("request_id", &self.request_id)
             .field("headers", &self.headers)
             .finish()
     }
@@ -200,6 +305,14 @@ pub fn join<B>((head, body): (RequestHead, Option<B>)) -> Request<B> {
         headers: headers,
         version: version,
         remote_addr: None,
+        local_addr: None,
+        alpn_protocol: None,
+        peer_certificate: None,
+        cancel_token: None,
+        informational: None,
+        socket_control: None,
+        on_upgrade: None,
+        request_id: None,
         body: body,
         is_proxy: false,
     }
@@ -209,6 +322,44 @@ pub fn addr<B>(req: &mut Request<B>, addr: SocketAddr) {
     req.remote_addr = Some(addr);
 }
 
+pub fn local_addr<B>(req: &mut Request<B>, addr: SocketAddr) {
+    req.local_addr = Some(addr);
+}
+
+pub fn cancel_token<B>(req: &mut Request<B>, token: CancelToken) {
+    req.cancel_token = Some(token);
+}
+
+pub fn informational<B>(req: &mut Request<B>, tx: Informational) {
+    req.informational = Some(tx);
+}
+
+/// The details of a connection, injected into each `Request` read from it.
+#[derive(Clone, Debug, Default)]
+pub struct ConnInfo {
+    pub remote_addr: Option<SocketAddr>,
+    pub local_addr: Option<SocketAddr>,
+    pub alpn_protocol: Option<Bytes>,
+    pub peer_certificate: Option<Bytes>,
+    pub socket_control: Option<SocketControl>,
+}
+
+pub fn request_id<B>(req: &mut Request<B>, id: RequestId) {
+    req.request_id = Some(id);
+}
+
+pub fn on_upgrade<B>(req: &mut Request<B>, on_upgrade: OnUpgrade) {
+    req.on_upgrade = Some(on_upgrade);
+}
+
+pub fn conn_info<B>(req: &mut Request<B>, info: &ConnInfo) {
+    req.remote_addr = info.remote_addr;
+    req.local_addr = info.local_addr;
+    req.alpn_protocol = info.alpn_protocol.clone();
+    req.peer_certificate = info.peer_certificate.clone();
+    req.socket_control = info.socket_control.clone();
+}
+
 #[cfg(test)]
 mod tests {
     /*
@@ -0,0 +1,100 @@
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The identifier of a `Request`, for correlating the logs about it.
+///
+/// Given to each request when `Http::request_ids` is enabled, see
+/// `Request::request_id`. It is made of the number of its connection,
+/// unique in this process, and the number of the request on that
+/// connection, starting at 1. It displays as `3.1` for the first request of
+/// the third connection, and the log messages of hyper about that request
+/// start with `[3.1]`, while those about the connection before its first
+/// request start with `[3]`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RequestId {
+    connection: u64,
+    request: u64,
+}
+
+impl RequestId {
+    /// The number of the connection of the request.
+    pub fn connection(&self) -> u64 {
+        self.connection
+    }
+
+    /// The number of the request on its connection, starting at 1.
+    pub fn request(&self) -> u64 {
+        self.request
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.connection, self.request)
+    }
+}
+
+static NEXT_CONNECTION: AtomicUsize = AtomicUsize::new(1);
+
+// Numbers the requests read from a connection.
+#[derive(Debug)]
+pub(crate) struct RequestIds {
+    connection: u64,
+    current: Option<RequestId>,
+}
+
+impl RequestIds {
+    pub(crate) fn new() -> RequestIds {
+        RequestIds {
+            connection: NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed) as u64,
+            current: None,
+        }
+    }
+
+    pub(crate) fn next(&mut self) -> RequestId {
+        let id = RequestId {
+            connection: self.connection,
+            request: self.current.map_or(1, |id| id.request + 1),
+        };
+        self.current = Some(id);
+        id
+    }
+
+    // The prefix of the log messages about the latest request, or about the
+    // connection before its first one.
+    pub(crate) fn log_id(&self) -> LogId {
+        LogId(Some((self.connection, self.current.map(|id| id.request))))
+    }
+}
+
+// The prefix of a log message, empty unless request ids are enabled.
+#[derive(Clone, Copy)]
+pub(crate) struct LogId(pub(crate) Option<(u64, Option<u64>)>);
+
+impl fmt::Display for LogId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some((connection, Some(request))) => write!(f, "[{}.{}] ", connection, request),
+            Some((connection, None)) => write!(f, "[{}] ", connection),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RequestIds;
+
+    #[test]
+    fn test_request_ids_log_id() {
+        let mut ids = RequestIds::new();
+        let connection = ids.next().connection();
+        assert_eq!(ids.log_id().to_string(), format!("[{}.1] ", connection));
+        let id = ids.next();
+        assert_eq!(id.request(), 2);
+        assert_eq!(id.to_string(), format!("{}.2", connection));
+
+        let other = RequestIds::new();
+        assert_ne!(other.log_id().to_string(), format!("[{}] ", connection));
+    }
+}
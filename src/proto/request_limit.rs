@@ -0,0 +1,113 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use futures::task::{self, Task};
+
+// The limit of `Http::max_concurrent_requests`, shared by every connection
+// served with the same `Http`, and its clones.
+pub(crate) struct RequestLimit {
+    max: usize,
+    state: Mutex<LimitState>,
+}
+
+struct LimitState {
+    active: usize,
+    // The tasks of the connections waiting for a request to complete.
+    waiters: Vec<Task>,
+}
+
+impl RequestLimit {
+    pub(crate) fn new(max: usize) -> Arc<RequestLimit> {
+        Arc::new(RequestLimit {
+            max: max,
+            state: Mutex::new(LimitState {
+                active: 0,
+                waiters: Vec::new(),
+            }),
+        })
+    }
+
+    pub(crate) fn active(&self) -> usize {
+        self.state.lock().unwrap().active
+    }
+
+    // Takes a permit for a request, if one is free.
+    pub(crate) fn try_acquire(limit: &Arc<RequestLimit>) -> Option<Permit> {
+        let mut state = limit.state.lock().unwrap();
+        if state.active < limit.max {
+            state.active += 1;
+            Some(Permit(limit.clone()))
+        } else {
+            None
+        }
+    }
+
+    // Takes a permit for a request, or registers the current task to be
+    // notified once a permit is released.
+    pub(crate) fn poll_acquire(limit: &Arc<RequestLimit>) -> Option<Permit> {
+        let mut state = limit.state.lock().unwrap();
+        if state.active < limit.max {
+            state.active += 1;
+            return Some(Permit(limit.clone()));
+        }
+        if !state.waiters.iter().any(|task| task.will_notify_current()) {
+            state.waiters.push(task::current());
+        }
+        None
+    }
+}
+
+impl fmt::Debug for RequestLimit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RequestLimit")
+            .field("max", &self.max)
+            .field("active", &self.active())
+            .finish()
+    }
+}
+
+// Counts a request against the limit until dropped.
+pub(crate) struct Permit(Arc<RequestLimit>);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let waiters = {
+            let mut state = self.0.state.lock().unwrap();
+            state.active -= 1;
+            ::std::mem::replace(&mut state.waiters, Vec::new())
+        };
+        // every waiter tries again, those too late register again
+        for task in waiters {
+            task.notify();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::future;
+    use futures::Future;
+
+    use super::RequestLimit;
+
+    #[test]
+    fn test_request_limit_permits() {
+        future::lazy(|| {
+            let limit = RequestLimit::new(2);
+            let first = RequestLimit::poll_acquire(&limit).expect("first permit");
+            let second = RequestLimit::poll_acquire(&limit).expect("second permit");
+            assert!(RequestLimit::poll_acquire(&limit).is_none());
+            assert!(RequestLimit::poll_acquire(&limit).is_none());
+            assert_eq!(limit.state.lock().unwrap().waiters.len(), 1);
+            assert_eq!(limit.active(), 2);
+
+            drop(first);
+            assert_eq!(limit.active(), 1);
+            assert!(limit.state.lock().unwrap().waiters.is_empty());
+            let third = RequestLimit::poll_acquire(&limit).expect("released permit");
+            drop((second, third));
+            assert_eq!(limit.active(), 0);
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+}
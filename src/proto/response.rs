@@ -5,8 +5,8 @@ use std::mem::replace;
 #[cfg(feature = "compat")]
 use http;
 
-use header::{Header, Headers};
-use proto::{MessageHead, ResponseHead, Body};
+use header::{Connection, Header, Headers};
+use proto::{MessageHead, ResponseHead, Body, Trailers};
 use status::StatusCode;
 use version::HttpVersion;
 
@@ -18,6 +18,7 @@ pub struct Response<B = Body> {
     #[cfg(feature = "raw_status")]
     raw_status: ::proto::RawStatus,
     body: Option<B>,
+    trailers: Option<Trailers>,
 }
 
 impl<B> Response<B> {
@@ -84,6 +85,17 @@ impl<B> Response<B> {
         self
     }
 
+    /// Set `Connection: close` and move the Response.
+    ///
+    /// A server closes the connection once this response is written, even
+    /// with keep-alive enabled, instead of reading another request.
+    ///
+    /// Useful for the "builder-style" pattern.
+    #[inline]
+    pub fn close_connection(self) -> Self {
+        self.with_header(Connection::close())
+    }
+
     /// Set the body.
     #[inline]
     pub fn set_body<T: Into<B>>(&mut self, body: T) {
@@ -99,6 +111,16 @@ impl<B> Response<B> {
         self
     }
 
+    /// Set the trailers sent after the body, and move the Response.
+    ///
+    /// The fields must be listed by a `Trailer` header of the response, see
+    /// `Trailers`. Useful for the "builder-style" pattern.
+    #[inline]
+    pub fn with_trailers(mut self, trailers: Trailers) -> Self {
+        self.trailers = Some(trailers);
+        self
+    }
+
     /// Read the body.
     #[inline]
     pub fn body_ref(&self) -> Option<&B> { self.body.as_ref() }
@@ -122,6 +144,7 @@ impl<B> Default for Response<B> {
             headers: Default::default(),
             status: Default::default(),
             body: None,
+            trailers: None,
         }
     }
 }
@@ -135,6 +158,7 @@ impl<B> Default for Response<B> {
             status: Default::default(),
             raw_status: Default::default(),
             body: None,
+            trailers: None,
         }
     }
 }
@@ -184,6 +208,7 @@ pub fn from_wire<B>(incoming: ResponseHead, body: Option<B>) -> Response<B> {
         version: incoming.version,
         headers: incoming.headers,
         body: body,
+        trailers: None,
     }
 }
 
@@ -199,9 +224,16 @@ pub fn from_wire<B>(incoming: ResponseHead, body: Option<B>) -> Response<B> {
         headers: incoming.headers,
         raw_status: incoming.subject,
         body: body,
+        trailers: None,
     }
 }
 
+/// Takes the trailers set with `Response::with_trailers`
+#[inline]
+pub fn take_trailers<B>(res: &mut Response<B>) -> Option<Trailers> {
+    res.trailers.take()
+}
+
 /// Splits this response into a `MessageHead<StatusCode>` and its body
 #[inline]
 pub fn split<B>(res: Response<B>) -> (MessageHead<StatusCode>, Option<B>) {
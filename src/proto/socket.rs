@@ -0,0 +1,164 @@
+use std::fmt;
+use std::io;
+use std::mem::ManuallyDrop;
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd as RawSocket};
+#[cfg(windows)]
+use std::os::windows::io::{AsRawSocket, FromRawSocket, RawSocket};
+use std::sync::{Arc, Mutex};
+
+use net2::TcpStreamExt;
+
+/// A handle adjusting the options of the TCP socket of a connection.
+///
+/// Every `Request` read from an `AddrStream` carries a clone of the same
+/// handle, see `Request::socket_control`. A service can use it to tune the
+/// socket for a given client, for instance raising the send buffer before a
+/// large download.
+///
+/// # Lifetime
+///
+/// The socket is owned by the connection, not by this handle, which only
+/// borrows it for each call. Once the `AddrStream` of the connection is
+/// dropped, every method returns an error of kind `NotConnected`, so a handle
+/// kept around never touches a socket that was reused by a later
+/// connection.
+#[derive(Clone)]
+pub struct SocketControl {
+    socket: Arc<Mutex<Option<RawSocket>>>,
+}
+
+impl SocketControl {
+    #[cfg(unix)]
+    pub(crate) fn new<T: AsRawFd>(socket: &T) -> SocketControl {
+        SocketControl {
+            socket: Arc::new(Mutex::new(Some(socket.as_raw_fd()))),
+        }
+    }
+
+    #[cfg(windows)]
+    pub(crate) fn new<T: AsRawSocket>(socket: &T) -> SocketControl {
+        SocketControl {
+            socket: Arc::new(Mutex::new(Some(socket.as_raw_socket()))),
+        }
+    }
+
+    // Called before the socket closes.
+    pub(crate) fn close(&self) {
+        self.socket.lock().unwrap().take();
+    }
+
+    /// Whether the socket of the connection is still open.
+    pub fn is_open(&self) -> bool {
+        self.socket.lock().unwrap().is_some()
+    }
+
+    /// Set the size of the send buffer of the socket (`SO_SNDBUF`).
+    pub fn set_send_buffer_size(&self, size: usize) -> io::Result<()> {
+        self.with_socket(|socket| socket.set_send_buffer_size(size))
+    }
+
+    /// Get the size of the send buffer of the socket (`SO_SNDBUF`).
+    pub fn send_buffer_size(&self) -> io::Result<usize> {
+        self.with_socket(|socket| socket.send_buffer_size())
+    }
+
+    /// Set the size of the receive buffer of the socket (`SO_RCVBUF`).
+    pub fn set_recv_buffer_size(&self, size: usize) -> io::Result<()> {
+        self.with_socket(|socket| socket.set_recv_buffer_size(size))
+    }
+
+    /// Set whether `TCP_NODELAY` is enabled on the socket.
+    pub fn set_nodelay(&self, enabled: bool) -> io::Result<()> {
+        self.with_socket(|socket| socket.set_nodelay(enabled))
+    }
+
+    /// Set the time-to-live of the packets sent on the socket (`IP_TTL`).
+    pub fn set_ttl(&self, ttl: u32) -> io::Result<()> {
+        self.with_socket(|socket| socket.set_ttl(ttl))
+    }
+
+    /// Set the type-of-service field of the IPv4 packets sent on the socket
+    /// (`IP_TOS`), such as a DSCP value shifted left by 2.
+    #[cfg(unix)]
+    pub fn set_tos(&self, tos: u32) -> io::Result<()> {
+        self.with_socket(|socket| {
+            let tos = tos as ::libc::c_int;
+            let ret = unsafe {
+                ::libc::setsockopt(
+                    socket.as_raw_fd(),
+                    ::libc::IPPROTO_IP,
+                    ::libc::IP_TOS,
+                    &tos as *const _ as *const ::libc::c_void,
+                    ::std::mem::size_of::<::libc::c_int>() as ::libc::socklen_t,
+                )
+            };
+            if ret == 0 {
+                Ok(())
+            } else {
+                Err(io::Error::last_os_error())
+            }
+        })
+    }
+
+    fn with_socket<F, T>(&self, f: F) -> io::Result<T>
+    where
+        F: FnOnce(&TcpStream) -> io::Result<T>,
+    {
+        // held while using the socket, so that it can't close meanwhile
+        let socket = self.socket.lock().unwrap();
+        match *socket {
+            Some(raw) => {
+                // never closed here, the connection owns the socket
+                let socket = ManuallyDrop::new(from_raw(raw));
+                f(&socket)
+            }
+            None => Err(io::Error::new(io::ErrorKind::NotConnected, "connection is closed")),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn from_raw(raw: RawSocket) -> TcpStream {
+    unsafe { TcpStream::from_raw_fd(raw) }
+}
+
+#[cfg(windows)]
+fn from_raw(raw: RawSocket) -> TcpStream {
+    unsafe { TcpStream::from_raw_socket(raw) }
+}
+
+impl fmt::Debug for SocketControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SocketControl")
+            .field("open", &self.is_open())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::net::{TcpListener, TcpStream};
+
+    use super::SocketControl;
+
+    #[test]
+    fn socket_control_after_close() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let tcp = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let control = SocketControl::new(&tcp);
+
+        control.set_send_buffer_size(64 * 1024).unwrap();
+        assert!(control.send_buffer_size().unwrap() >= 64 * 1024);
+        control.set_nodelay(true).unwrap();
+        assert!(tcp.nodelay().unwrap());
+
+        control.clone().close();
+        assert!(!control.is_open());
+        let err = control.set_nodelay(false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+        assert!(tcp.nodelay().unwrap());
+    }
+}
@@ -0,0 +1,114 @@
+#[allow(unused, deprecated)]
+use std::ascii::AsciiExt;
+use std::fmt;
+
+use futures::{Async, Future, Poll};
+use futures::sync::oneshot;
+use unicase::Ascii;
+
+use header::{Headers, Trailer};
+
+/// The trailer section of a `Response`, sent after its body.
+///
+/// Set on a response with `Response::with_trailers`. The fields are sent
+/// once the body ends and the fields have been sent through the sender of
+/// `Trailers::pair`, so they can depend on the whole body, such as for a
+/// checksum or a gRPC status. Dropping the sender ends the body without
+/// trailer fields.
+///
+/// A trailer section is only sent with a chunked body, and only carries the
+/// fields listed by the `Trailer` header of the response. The trailers of a
+/// response with a `Content-Length`, or to an HTTP/1.0 client, are dropped.
+pub struct Trailers {
+    rx: oneshot::Receiver<Headers>,
+}
+
+impl Trailers {
+    /// Return trailers with an associated sender half.
+    pub fn pair() -> (oneshot::Sender<Headers>, Trailers) {
+        let (tx, rx) = oneshot::channel();
+        (tx, Trailers { rx: rx })
+    }
+}
+
+impl From<Headers> for Trailers {
+    fn from(fields: Headers) -> Trailers {
+        let (tx, trailers) = Trailers::pair();
+        let _ = tx.send(fields);
+        trailers
+    }
+}
+
+impl fmt::Debug for Trailers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("Trailers")
+    }
+}
+
+// The trailers of a response being written, with the fields its `Trailer`
+// header declared.
+pub(crate) struct PendingTrailers {
+    rx: oneshot::Receiver<Headers>,
+    declared: Vec<Ascii<String>>,
+}
+
+impl PendingTrailers {
+    pub(crate) fn new(trailers: Trailers, headers: &Headers) -> PendingTrailers {
+        PendingTrailers {
+            rx: trailers.rx,
+            declared: headers.get::<Trailer>().map(|t| t.0.clone()).unwrap_or_default(),
+        }
+    }
+}
+
+impl Future for PendingTrailers {
+    type Item = Headers;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Headers, ()> {
+        let fields = match self.rx.poll() {
+            Ok(Async::Ready(fields)) => fields,
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Err(_canceled) => return Ok(Async::Ready(Headers::new())),
+        };
+        let mut declared = Headers::new();
+        for field in fields.iter() {
+            if self.declared.iter().any(|name| name.eq_ignore_ascii_case(field.name())) {
+                declared.set_raw(field.name().to_owned(), field.raw().clone());
+            } else {
+                debug!("dropping trailer field not declared by Trailer: {}", field.name());
+            }
+        }
+        Ok(Async::Ready(declared))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::Future;
+    use unicase::Ascii;
+
+    use header::{ContentLength, Headers, Trailer};
+    use super::{PendingTrailers, Trailers};
+
+    #[test]
+    fn test_pending_trailers_declared() {
+        let mut headers = Headers::new();
+        headers.set(Trailer(vec![Ascii::new("Grpc-Status".to_owned())]));
+        let mut fields = Headers::new();
+        fields.set_raw("grpc-status", "0");
+        fields.set(ContentLength(4));
+
+        let fields = PendingTrailers::new(fields.into(), &headers).wait().unwrap();
+        assert_eq!(fields.len(), 1);
+        assert_eq!(fields.get_raw("grpc-status").unwrap(), "0");
+    }
+
+    #[test]
+    fn test_pending_trailers_dropped_sender() {
+        let (tx, trailers) = Trailers::pair();
+        drop(tx);
+        let fields = PendingTrailers::new(trailers, &Headers::new()).wait().unwrap();
+        assert_eq!(fields.len(), 0);
+    }
+}
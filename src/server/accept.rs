@@ -0,0 +1,675 @@
+//! Accepting the connections of a `Server` from its TCP listeners.
+
+use std::collections::VecDeque;
+use std::io;
+use std::net::{SocketAddr, TcpStream as StdTcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::task::{self, Task};
+use futures::{Async, Future, Poll, Stream};
+use net2::TcpStreamExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::reactor::{Handle, Timeout};
+
+use super::{AcceptFilter, AddrStream, OnAcceptError, OnConnection};
+use super::stats::AcceptStats;
+
+/// A handle pausing and resuming the accept loop of a `Server`, or of an
+/// `AddrIncoming`.
+///
+/// Created with `Server::accept_control` or `AddrIncoming::accept_control`.
+/// It can be cloned, and used from any thread. While paused, no connection
+/// is accepted, but the listener stays bound, so clients queue up in its
+/// backlog, and the active connections are still served as usual.
+#[derive(Clone, Debug, Default)]
+pub struct AcceptControl {
+    state: Arc<Mutex<AcceptState>>,
+}
+
+#[derive(Debug, Default)]
+struct AcceptState {
+    paused: bool,
+    // The accept loops waiting for `resume`.
+    tasks: Vec<Task>,
+}
+
+/// A stream of connections from binding to an address.
+#[must_use = "streams do nothing unless polled"]
+#[derive(Debug)]
+pub struct AddrIncoming {
+    addr: SocketAddr,
+    keep_alive_timeout: Option<Duration>,
+    nodelay: Option<bool>,
+    linger: Option<Option<Duration>>,
+    pub(super) recv_buffer_size: Option<usize>,
+    pub(super) send_buffer_size: Option<usize>,
+    listener: TcpListener,
+    handle: Handle,
+    sleep_on_errors: bool,
+    pub(super) error_sleep: Duration,
+    pub(super) on_connection: Option<OnConnection>,
+    pub(super) accept_filter: Option<AcceptFilter>,
+    pub(super) on_accept_error: Option<OnAcceptError>,
+    accept_rate: Option<AcceptRate>,
+    pub(super) accept_control: AcceptControl,
+    pub(super) stats: AcceptStats,
+    timeout: Option<Timeout>,
+    pub(super) accept_burst: usize,
+    // Connections accepted by a burst, not yet yielded.
+    pending: VecDeque<AddrStream>,
+    // An error of `accept()` after the first connection of a burst, returned
+    // once the pending connections are yielded.
+    pending_error: Option<io::Error>,
+}
+
+// A token bucket for `Http::max_accept_rate`, holding up to `capacity`
+// tokens, one of which is added every `interval`.
+#[derive(Debug)]
+struct AcceptRate {
+    capacity: u32,
+    tokens: u32,
+    interval: Duration,
+    // When the last token was added, or the bucket was last seen full.
+    refilled: Instant,
+}
+
+/// What to do after failing to accept a connection.
+///
+/// Returned by the callback set with `Http::on_accept_error`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AcceptAction {
+    /// Try to accept the next connection right away.
+    Ignore,
+    /// Wait for the given duration before accepting again.
+    RetryAfter(Duration),
+    /// Return the error from the listener, which stops a `Server`.
+    Abort,
+}
+
+// ===== impl AcceptControl =====
+
+impl AcceptControl {
+    /// Stop accepting connections, until `resume` is called.
+    pub fn pause(&self) {
+        self.state.lock().unwrap().paused = true;
+    }
+
+    /// Accept connections again, after `pause`.
+    pub fn resume(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.paused = false;
+        for task in state.tasks.drain(..) {
+            task.notify();
+        }
+    }
+
+    /// Whether accepting connections is currently paused.
+    pub fn is_paused(&self) -> bool {
+        self.state.lock().unwrap().paused
+    }
+
+    // Whether paused, in which case the current task is woken on `resume`.
+    fn poll_paused(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if !state.paused {
+            return false;
+        }
+        if !state.tasks.iter().any(Task::will_notify_current) {
+            state.tasks.push(task::current());
+        }
+        true
+    }
+}
+
+// ===== impl AddrIncoming =====
+
+impl AddrIncoming {
+    pub(super) fn new(listener: TcpListener, handle: Handle, sleep_on_errors: bool) -> io::Result<AddrIncoming> {
+         Ok(AddrIncoming {
+            addr: listener.local_addr()?,
+            keep_alive_timeout: None,
+            nodelay: None,
+            linger: None,
+            recv_buffer_size: None,
+            send_buffer_size: None,
+            listener: listener,
+            handle: handle,
+            sleep_on_errors: sleep_on_errors,
+            error_sleep: Duration::from_millis(10),
+            on_connection: None,
+            accept_filter: None,
+            on_accept_error: None,
+            accept_rate: None,
+            accept_control: AcceptControl::default(),
+            stats: AcceptStats::default(),
+            timeout: None,
+            accept_burst: 1,
+            pending: VecDeque::new(),
+            pending_error: None,
+        })
+    }
+
+    /// Get the local address bound to this listener.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Stop accepting connections, until `resume` is called.
+    ///
+    /// The listener stays bound, so new clients wait in its backlog.
+    pub fn pause(&self) {
+        self.accept_control.pause();
+    }
+
+    /// Accept connections again, after `pause`.
+    pub fn resume(&self) {
+        self.accept_control.resume();
+    }
+
+    /// Returns a handle pausing and resuming this listener, from any thread.
+    pub fn accept_control(&self) -> AcceptControl {
+        self.accept_control.clone()
+    }
+
+    /// Returns the counters of the connections accepted by this listener,
+    /// and of its errors.
+    pub fn stats(&self) -> AcceptStats {
+        self.stats.clone()
+    }
+
+    pub(super) fn set_keepalive(&mut self, dur: Option<Duration>) {
+        self.keep_alive_timeout = dur;
+    }
+
+    pub(super) fn set_nodelay(&mut self, enabled: bool) {
+        self.nodelay = Some(enabled);
+    }
+
+    pub(super) fn set_linger(&mut self, linger: Option<Duration>) {
+        self.linger = Some(linger);
+    }
+
+    pub(super) fn set_max_accept_rate(&mut self, per_second: u32) {
+        self.accept_rate = Some(AcceptRate {
+            capacity: per_second,
+            tokens: per_second,
+            interval: Duration::from_secs(1) / per_second,
+            refilled: Instant::now(),
+        });
+    }
+}
+
+impl AcceptRate {
+    // Adds the tokens due by `now`, returning how long until the next one
+    // if the bucket is still empty.
+    fn refill(&mut self, now: Instant) -> Option<Duration> {
+        fn nanos(dur: Duration) -> u64 {
+            dur.as_secs() * 1_000_000_000 + u64::from(dur.subsec_nanos())
+        }
+
+        let due = nanos(now - self.refilled) / nanos(self.interval);
+        if due >= u64::from(self.capacity - self.tokens) {
+            self.tokens = self.capacity;
+            self.refilled = now;
+        } else if due > 0 {
+            self.tokens += due as u32;
+            self.refilled += self.interval * due as u32;
+        }
+        if self.tokens > 0 {
+            None
+        } else {
+            Some(self.refilled + self.interval - now)
+        }
+    }
+}
+
+impl AddrIncoming {
+    // Accepts the next connection as a `std` socket, not yet registered with
+    // any reactor.
+    pub(super) fn poll_std(&mut self) -> Poll<Option<(StdTcpStream, SocketAddr)>, io::Error> {
+        if self.accept_control.poll_paused() {
+            trace!("accept paused");
+            return Ok(Async::NotReady);
+        }
+        // Check if a previous timeout is active that was set by IO errors.
+        if let Some(ref mut to) = self.timeout {
+            match to.poll().expect("timeout never fails") {
+                Async::Ready(_) => {}
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+        self.timeout = None;
+        if let Some(ref mut rate) = self.accept_rate {
+            if let Some(wait) = rate.refill(Instant::now()) {
+                trace!("max accept rate reached, pausing accept");
+                let mut timeout = Timeout::new(wait, &self.handle)
+                    .expect("can always set a timeout");
+                match timeout.poll().expect("timeout never fails") {
+                    Async::Ready(()) => (),
+                    Async::NotReady => {
+                        self.timeout = Some(timeout);
+                        return Ok(Async::NotReady);
+                    }
+                }
+            }
+        }
+        loop {
+            match self.listener.accept_std() {
+                Ok((socket, addr)) => {
+                    self.stats.record_accepted();
+                    if let Some(ref filter) = self.accept_filter {
+                        if !(filter.0)(&addr) {
+                            debug!("connection from {} rejected by accept filter", addr);
+                            continue;
+                        }
+                    }
+                    if let Some(ref mut rate) = self.accept_rate {
+                        rate.tokens -= 1;
+                    }
+                    if let Some(dur) = self.keep_alive_timeout {
+                        if let Err(e) = socket.set_keepalive(Some(dur)) {
+                            trace!("error trying to set TCP keepalive: {}", e);
+                        }
+                    }
+                    if let Some(enabled) = self.nodelay {
+                        if let Err(e) = socket.set_nodelay(enabled) {
+                            trace!("error trying to set TCP nodelay: {}", e);
+                        }
+                    }
+                    if let Some(linger) = self.linger {
+                        if let Err(e) = socket.set_linger(linger) {
+                            trace!("error trying to set SO_LINGER: {}", e);
+                        }
+                    }
+                    if let Some(size) = self.recv_buffer_size {
+                        if let Err(e) = socket.set_recv_buffer_size(size) {
+                            trace!("error trying to set SO_RCVBUF: {}", e);
+                        }
+                    }
+                    if let Some(size) = self.send_buffer_size {
+                        if let Err(e) = socket.set_send_buffer_size(size) {
+                            trace!("error trying to set SO_SNDBUF: {}", e);
+                        }
+                    }
+                    return Ok(Async::Ready(Some((socket, addr))));
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(Async::NotReady),
+                Err(e) => {
+                    self.stats.record_error(&e);
+                    match self.accept_action(&e) {
+                        AcceptAction::Ignore => {
+                            debug!("accept error: {}; ignoring", e);
+                            continue;
+                        },
+                        AcceptAction::RetryAfter(delay) => {
+                            debug!("accept error: {}; sleeping {:?}",
+                                e, delay);
+                            self.stats.record_sleep();
+                            let mut timeout = Timeout::new(delay, &self.handle)
+                                .expect("can always set a timeout");
+                            let result = timeout.poll()
+                                .expect("timeout never fails");
+                            match result {
+                                Async::Ready(()) => continue,
+                                Async::NotReady => {
+                                    self.timeout = Some(timeout);
+                                    return Ok(Async::NotReady);
+                                }
+                            }
+                        },
+                        AcceptAction::Abort => return Err(e),
+                    }
+                },
+            }
+        }
+    }
+
+    // Accepts the next connection, registered with the reactor.
+    fn poll_stream(&mut self) -> Poll<Option<AddrStream>, io::Error> {
+        match try_ready!(self.poll_std()) {
+            Some((socket, addr)) => {
+                let local_addr = socket.local_addr().unwrap_or(self.addr);
+                let socket = TcpStream::from_stream(socket, &self.handle)?;
+                let stream = AddrStream::new(socket, addr, local_addr);
+                if let Some(ref on_connection) = self.on_connection {
+                    (on_connection.0)(&stream);
+                }
+                Ok(Async::Ready(Some(stream)))
+            },
+            None => Ok(Async::Ready(None)),
+        }
+    }
+
+    fn accept_action(&self, e: &io::Error) -> AcceptAction {
+        accept_action(&self.on_accept_error, self.sleep_on_errors, self.error_sleep, e)
+    }
+}
+
+// What to do after an error of `accept()`, as configured with
+// `Http::on_accept_error`, `sleep_on_errors` and `accept_error_sleep`.
+pub(super) fn accept_action(on_accept_error: &Option<OnAcceptError>,
+                 sleep_on_errors: bool,
+                 error_sleep: Duration,
+                 e: &io::Error) -> AcceptAction {
+    if let Some(ref on_accept_error) = *on_accept_error {
+        (on_accept_error.0)(e)
+    } else if sleep_on_errors {
+        // Connection errors can be ignored directly, continue by
+        // accepting the next request.
+        if connection_error(e) {
+            AcceptAction::Ignore
+        } else {
+            AcceptAction::RetryAfter(error_sleep)
+        }
+    } else {
+        AcceptAction::Abort
+    }
+}
+
+impl Stream for AddrIncoming {
+    type Item = AddrStream;
+    type Error = ::std::io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if let Some(stream) = self.pending.pop_front() {
+            return Ok(Async::Ready(Some(stream)));
+        }
+        if let Some(e) = self.pending_error.take() {
+            return Err(e);
+        }
+        let stream = match try_ready!(self.poll_stream()) {
+            Some(stream) => stream,
+            None => return Ok(Async::Ready(None)),
+        };
+        while self.pending.len() + 1 < self.accept_burst {
+            match self.poll_stream() {
+                Ok(Async::Ready(Some(stream))) => self.pending.push_back(stream),
+                Ok(_) => break,
+                Err(e) => {
+                    self.pending_error = Some(e);
+                    break;
+                }
+            }
+        }
+        if !self.pending.is_empty() {
+            trace!("accepted a burst of {} connections", self.pending.len() + 1);
+        }
+        Ok(Async::Ready(Some(stream)))
+    }
+}
+
+// The accept loops of the listeners of a `Server`, polled in turn so that a
+// busy listener doesn't starve the others.
+pub(super) struct ServerIncoming {
+    pub(super) incomings: Vec<AddrIncoming>,
+    pub(super) next: usize,
+}
+
+impl ServerIncoming {
+    pub(super) fn poll_std(&mut self) -> Poll<Option<(StdTcpStream, SocketAddr)>, io::Error> {
+        self.poll_each(AddrIncoming::poll_std)
+    }
+
+    fn poll_each<T, F>(&mut self, mut poll: F) -> Poll<Option<T>, io::Error>
+        where F: FnMut(&mut AddrIncoming) -> Poll<Option<T>, io::Error>,
+    {
+        let mut polled = 0;
+        while polled < self.incomings.len() {
+            let i = (self.next + polled) % self.incomings.len();
+            match poll(&mut self.incomings[i])? {
+                Async::Ready(Some(conn)) => {
+                    self.next = (i + 1) % self.incomings.len();
+                    return Ok(Async::Ready(Some(conn)));
+                },
+                Async::Ready(None) => {
+                    drop(self.incomings.remove(i));
+                },
+                Async::NotReady => polled += 1,
+            }
+        }
+        if self.incomings.is_empty() {
+            Ok(Async::Ready(None))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+impl Stream for ServerIncoming {
+    type Item = AddrStream;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<AddrStream>, io::Error> {
+        self.poll_each(Stream::poll)
+    }
+}
+
+/// This function defines errors that are per-connection. Which basically
+/// means that if we get this error from `accept()` system call it means
+/// next connection might be ready to be accepted.
+///
+/// All other errors will incur a timeout before next `accept()` is performed.
+/// The timeout is useful to handle resource exhaustion errors like ENFILE
+/// and EMFILE. Otherwise, could enter into tight loop.
+pub(super) fn connection_error(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::ConnectionRefused ||
+    e.kind() == io::ErrorKind::ConnectionAborted ||
+    e.kind() == io::ErrorKind::ConnectionReset
+}
+
+// Whether an `accept()` error means the process or the system ran out of
+// resources, such as file descriptors or memory. These errors are usually
+// transient: accepting again after a short delay, once some connections
+// closed, is likely to work.
+pub(super) fn is_resource_exhaustion(e: &io::Error) -> bool {
+    e.raw_os_error().map(resource_exhaustion_code).unwrap_or(false)
+}
+
+#[cfg(unix)]
+fn resource_exhaustion_code(code: i32) -> bool {
+    code == ::libc::EMFILE ||
+    code == ::libc::ENFILE ||
+    code == ::libc::ENOBUFS ||
+    code == ::libc::ENOMEM
+}
+
+#[cfg(windows)]
+fn resource_exhaustion_code(code: i32) -> bool {
+    // WSAEMFILE and WSAENOBUFS
+    code == 10024 || code == 10055
+}
+
+#[cfg(not(any(unix, windows)))]
+fn resource_exhaustion_code(_code: i32) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpStream;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use futures::future;
+    use tokio::reactor::Core;
+
+    use super::super::Http;
+    use super::{AcceptRate, is_resource_exhaustion};
+
+    #[test]
+    fn test_accept_rate_refills_up_to_capacity() {
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+        let mut rate = AcceptRate {
+            capacity: 4,
+            tokens: 4,
+            interval: ms(250),
+            refilled: start,
+        };
+        // a full bucket accepts a burst
+        for _ in 0..4 {
+            assert_eq!(rate.refill(start), None);
+            rate.tokens -= 1;
+        }
+        assert_eq!(rate.refill(start + ms(100)), Some(ms(150)));
+
+        // then a token every interval
+        assert_eq!(rate.refill(start + ms(600)), None);
+        assert_eq!(rate.tokens, 2);
+        assert_eq!(rate.refilled, start + ms(500));
+
+        // never more than the capacity
+        assert_eq!(rate.refill(start + ms(60_000)), None);
+        assert_eq!(rate.tokens, 4);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn resource_exhaustion_errors() {
+        use std::io;
+        use libc;
+
+        for &code in &[libc::EMFILE, libc::ENFILE, libc::ENOBUFS, libc::ENOMEM] {
+            assert!(is_resource_exhaustion(&io::Error::from_raw_os_error(code)), "code {}", code);
+        }
+        assert!(!is_resource_exhaustion(&io::Error::from_raw_os_error(libc::ECONNABORTED)));
+        assert!(!is_resource_exhaustion(&io::Error::new(io::ErrorKind::Other, "other")));
+    }
+
+    #[test]
+    fn accept_action_defaults_and_callback() {
+        use std::io;
+        use std::sync::Arc;
+        use tokio::net::TcpListener;
+        use tokio::reactor::Core;
+        use super::{AcceptAction, AddrIncoming, OnAcceptError};
+
+        let core = Core::new().unwrap();
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+        let mut incoming = AddrIncoming::new(listener, core.handle(), false).unwrap();
+        let reset = io::Error::new(io::ErrorKind::ConnectionReset, "reset");
+        let other = io::Error::new(io::ErrorKind::Other, "other");
+
+        assert_eq!(incoming.accept_action(&reset), AcceptAction::Abort);
+
+        incoming.sleep_on_errors = true;
+        assert_eq!(incoming.accept_action(&reset), AcceptAction::Ignore);
+        assert_eq!(incoming.accept_action(&other), AcceptAction::RetryAfter(Duration::from_millis(10)));
+
+        incoming.on_accept_error = Some(OnAcceptError(Arc::new(|e: &io::Error| {
+            if e.kind() == io::ErrorKind::Other {
+                AcceptAction::Abort
+            } else {
+                AcceptAction::RetryAfter(Duration::from_secs(1))
+            }
+        })));
+        assert_eq!(incoming.accept_action(&reset), AcceptAction::RetryAfter(Duration::from_secs(1)));
+        assert_eq!(incoming.accept_action(&other), AcceptAction::Abort);
+    }
+
+    // Accepting from a listener shut down for reading fails with `EINVAL` on
+    // Linux, an error of the listener rather than of a connection.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn accept_error_sleep_delays_next_accept() {
+        use std::os::unix::io::AsRawFd;
+        use futures::{Future, Stream};
+        use libc;
+        use tokio::net::TcpListener;
+        use tokio::reactor::Timeout;
+
+        let mut core = Core::new().unwrap();
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+        assert_eq!(unsafe { libc::shutdown(listener.as_raw_fd(), libc::SHUT_RD) }, 0);
+        let mut http = Http::<::Chunk>::new();
+        http.sleep_on_errors(true).accept_error_sleep(Duration::from_millis(100));
+        let incoming = http.addr_incoming(listener, core.handle()).unwrap();
+        let stats = incoming.stats();
+
+        let timeout = Timeout::new(Duration::from_millis(350), &core.handle()).unwrap();
+        let _ = core.run(incoming.for_each(|_| Ok(())).select2(timeout));
+        // a first failed accept right away, then one every 100ms
+        assert!(stats.sleeps() >= 3 && stats.sleeps() <= 4, "{} sleeps", stats.sleeps());
+        assert_eq!(stats.other_errors(), stats.sleeps());
+    }
+
+    #[test]
+    fn tcp_linger_set_on_accepted_socket() {
+        use std::net::TcpStream;
+        use futures::future;
+        use net2::TcpStreamExt;
+        use tokio::net::TcpListener;
+
+        let mut core = Core::new().unwrap();
+        let mut accept = |linger| {
+            let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+            let mut http = Http::<::Chunk>::new();
+            http.tcp_linger(linger);
+            let mut incoming = http.addr_incoming(listener, core.handle()).unwrap();
+            let _tcp = TcpStream::connect(&incoming.local_addr()).unwrap();
+            core.run(future::poll_fn(|| incoming.poll_std())).unwrap().unwrap().0
+        };
+
+        let socket = accept(Some(Duration::from_secs(0)));
+        assert_eq!(socket.linger().unwrap(), Some(Duration::from_secs(0)));
+        let socket = accept(Some(Duration::from_secs(3)));
+        assert_eq!(socket.linger().unwrap(), Some(Duration::from_secs(3)));
+    }
+
+    #[test]
+    fn tcp_buffer_sizes_set_on_accepted_socket() {
+        use std::net::TcpStream;
+        use futures::future;
+        use net2::TcpStreamExt;
+        use tokio::net::TcpListener;
+
+        let mut core = Core::new().unwrap();
+        let mut accept = |size| {
+            let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+            let mut http = Http::<::Chunk>::new();
+            http.tcp_recv_buffer_size(size).tcp_send_buffer_size(size);
+            let mut incoming = http.addr_incoming(listener, core.handle()).unwrap();
+            let _tcp = TcpStream::connect(&incoming.local_addr()).unwrap();
+            let socket = core.run(future::poll_fn(|| incoming.poll_std())).unwrap().unwrap().0;
+            (socket.recv_buffer_size().unwrap(), socket.send_buffer_size().unwrap())
+        };
+
+        // platforms adjust the sizes, but keep them at least as requested,
+        // and growing with the request
+        let small = accept(32 * 1024);
+        let large = accept(64 * 1024);
+        assert!(small.0 >= 32 * 1024 && small.1 >= 32 * 1024, "{:?}", small);
+        assert!(large.0 > small.0 && large.1 > small.1, "{:?} then {:?}", small, large);
+    }
+
+    #[test]
+    fn accept_burst_accepts_pending_connections_in_one_poll() {
+        use futures::{Async, Stream};
+        use tokio::net::TcpListener;
+
+        let mut core = Core::new().unwrap();
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+        let mut http = Http::<::Chunk>::new();
+        http.accept_burst(2);
+        let mut incoming = http.addr_incoming(listener, core.handle()).unwrap();
+        let _tcps = (0..3)
+            .map(|_| TcpStream::connect(&incoming.local_addr()).unwrap())
+            .collect::<Vec<_>>();
+        // let the kernel complete the handshakes
+        thread::sleep(Duration::from_millis(50));
+
+        let polled = core.run(future::poll_fn(|| {
+            let first = try_ready!(incoming.poll());
+            Ok::<_, ::std::io::Error>(Async::Ready((first, incoming.pending.len())))
+        })).unwrap();
+        assert!(polled.0.is_some());
+        assert_eq!(polled.1, 1, "burst of 2 buffers 1 connection");
+
+        let second = core.run(future::poll_fn(|| incoming.poll())).unwrap();
+        assert!(second.is_some());
+        assert!(incoming.pending.is_empty());
+        let third = core.run(future::poll_fn(|| incoming.poll())).unwrap();
+        assert!(third.is_some());
+    }
+}
@@ -9,14 +9,19 @@
 //! higher-level [Server](super) API.
 
 use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
 
-use bytes::Bytes;
-use futures::{Future, Poll, Stream};
+use bytes::{Buf, Bytes};
+use futures::{Async, Future, Poll, Stream};
+use futures::sync::oneshot;
 use tokio_io::{AsyncRead, AsyncWrite};
 use std::net::SocketAddr;
 
 use proto;
-use super::{HyperService, Request, Response, Service, RemoteAddr};
+use proto::dispatch::Dispatch;
+use proto::request_id::LogId;
+use super::{AddrStream, ConnectionBytes, HyperService, OnConnectionClose, RemoteAddr, Request, Response, Service};
 
 /// A future binding a connection with a Service.
 ///
@@ -35,16 +40,64 @@ where
         <S::ResponseBody as Stream>::Item,
         proto::ServerTransaction,
     >,
-    pub(super) remote_addr: SocketAddr,
+    pub(super) remote_addr: Option<SocketAddr>,
+    pub(super) on_close: Option<OnConnectionClose>,
+    pub(super) shutting_down: bool,
 }
-impl<I,S> RemoteAddr for Connection<I, S>
+
+/// Why a `Connection` finished.
+///
+/// Created by the `WithDisconnect` future.
+#[derive(Debug)]
+pub struct Disconnect {
+    /// The terminal cause of the connection.
+    pub reason: DisconnectReason,
+    /// The number of requests read from the connection.
+    pub requests: u64,
+    _inner: (),
+}
+
+/// The terminal cause of a `Connection`, reported in a `Disconnect`.
+#[derive(Debug)]
+pub enum DisconnectReason {
+    /// HTTP finished normally, such as the client closing the connection,
+    /// or a message that disabled keep-alive.
+    Closed,
+    /// The idle connection was closed after the keep-alive timeout.
+    KeepAliveTimeout,
+    /// The connection was closed after `Connection::graceful_shutdown`.
+    Shutdown,
+    /// The connection switched away from HTTP.
+    Upgraded,
+    /// The connection failed.
+    ///
+    /// This includes errors that were already answered with a response,
+    /// such as a request that could not be parsed.
+    Error(::Error),
+}
+
+/// A future driving a `Connection`, resolving with why it finished.
+///
+/// Created with `Connection::with_disconnect`.
+#[must_use = "futures do nothing unless polled"]
+pub struct WithDisconnect<I, S>
 where
     S: HyperService,
     S::ResponseBody: Stream<Error=::Error>,
     <S::ResponseBody as Stream>::Item: AsRef<[u8]>,
+{
+    conn: Connection<I, S>,
+}
+
+impl<I, S> RemoteAddr for Connection<I, S>
+where
+    I: RemoteAddr,
+    S: HyperService,
+    S::ResponseBody: Stream<Error=::Error>,
+    <S::ResponseBody as Stream>::Item: AsRef<[u8]>,
 {
     fn remote(&self) -> SocketAddr {
-        self.remote_addr
+        self.remote_addr.unwrap_or_else(|| self.conn.io().remote())
     }
 }
 
@@ -70,6 +123,66 @@ pub struct Parts<T, S> {
     _inner: (),
 }
 
+/// A future driving a `Connection` until HTTP is done with it.
+///
+/// Created with `Connection::into_upgraded`.
+#[must_use = "futures do nothing unless polled"]
+pub struct IntoUpgraded<I, S>
+where
+    S: HyperService,
+    S::ResponseBody: Stream<Error=::Error>,
+    <S::ResponseBody as Stream>::Item: AsRef<[u8]>,
+{
+    conn: Option<Connection<I, S>>,
+    shutting_down: bool,
+}
+
+/// The IO object of a `Connection` that switched protocols.
+///
+/// Bytes sent by the client after its upgrade request may already have been
+/// read into the HTTP buffer. Reading an `Upgraded` returns those bytes
+/// first, before reading from the IO object again.
+#[derive(Debug)]
+pub struct Upgraded<T> {
+    io: T,
+    read_buf: Bytes,
+}
+
+/// A future of the IO of a connection of a `Server`, once the service
+/// takes it over, such as to tunnel a `CONNECT` request.
+///
+/// Taken from a `Request` with `Request::on_upgrade`. A service takes over
+/// the connection by responding to that request with `101 Switching
+/// Protocols`, or with a successful response to a `CONNECT`. Once that
+/// response is flushed, HTTP stops on the connection, and the future
+/// resolves with the `Upgraded` socket, including any bytes already read
+/// past the request.
+///
+/// A successful response to a `CONNECT` whose `OnUpgrade` wasn't taken
+/// leaves the connection to HTTP.
+///
+/// It resolves with `None` if the connection instead goes on to a later
+/// request, or closes.
+#[must_use = "futures do nothing unless polled"]
+pub struct OnUpgrade {
+    rx: oneshot::Receiver<Upgraded<AddrStream>>,
+    slot: UpgradeSlot,
+    request: u64,
+}
+
+// The sender of the `OnUpgrade` of the latest request of a connection.
+#[derive(Clone, Default)]
+pub(crate) struct UpgradeSlot(Arc<Mutex<Slot>>);
+
+#[derive(Default)]
+struct Slot {
+    tx: Option<oneshot::Sender<Upgraded<AddrStream>>>,
+    // The number of the latest request, and whether the service took its
+    // `OnUpgrade` out of it.
+    request: u64,
+    taken: bool,
+}
+
 // ===== impl Connection =====
 
 impl<I, B, S> Connection<I, S>
@@ -83,6 +196,45 @@ where S: Service<Request = Request, Response = Response<B>, Error = ::Error> + '
         self.conn.disable_keep_alive()
     }
 
+    /// Start a graceful shutdown of this connection.
+    ///
+    /// A response that is in flight is finished with a `Connection: close`
+    /// header, and no further requests are read before the connection closes.
+    /// An idle connection closes right away.
+    ///
+    /// The `CancelToken` of the requests read from this connection is
+    /// cancelled as well.
+    ///
+    /// This connection must still be polled afterwards, and resolves once it
+    /// is closed.
+    pub fn graceful_shutdown(&mut self) {
+        self.shutting_down = true;
+        self.conn.dispatch_mut().cancel();
+        self.conn.graceful_close()
+    }
+
+    /// The address of the remote peer, if it was known when this connection
+    /// was created.
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.remote_addr
+    }
+
+    /// The number of bytes read from the IO object so far.
+    ///
+    /// This counts the bytes as they were received, including the framing
+    /// of chunked bodies.
+    pub fn bytes_read(&self) -> u64 {
+        self.conn.bytes_read()
+    }
+
+    /// The number of bytes written to the IO object so far.
+    ///
+    /// This counts the bytes as they were sent, including the framing of
+    /// chunked bodies.
+    pub fn bytes_written(&self) -> u64 {
+        self.conn.bytes_written()
+    }
+
     /// Return the inner IO object, and additional information.
     ///
     /// This should only be called after `poll_without_shutdown` signals
@@ -109,6 +261,86 @@ where S: Service<Request = Request, Response = Response<B>, Error = ::Error> + '
         try_ready!(self.conn.poll_without_shutdown());
         Ok(().into())
     }
+
+    // Polls until HTTP is done with this connection, only shutting down
+    // the IO if it wasn't upgraded, and resolves with whether it was.
+    pub(super) fn poll_until_upgraded(&mut self) -> Poll<bool, ::Error> {
+        let ret = match self.conn.poll_without_shutdown() {
+            Ok(Async::Ready(())) if self.conn.is_upgraded() => Ok(Async::Ready(true)),
+            Ok(Async::Ready(())) => self.conn.poll_shutdown().map(|done| done.map(|()| false)),
+            ret => ret.map(|done| done.map(|()| false)),
+        };
+        match ret {
+            Ok(Async::NotReady) => (),
+            _ => self.notify_close(),
+        }
+        ret
+    }
+
+    // The prefix of the log messages about this connection.
+    pub(super) fn log_id(&self) -> LogId {
+        self.conn.dispatch().log_id()
+    }
+
+    pub(super) fn upgraded(self) -> Upgraded<I> {
+        let parts = self.into_parts();
+        Upgraded {
+            io: parts.io,
+            read_buf: parts.read_buf,
+        }
+    }
+
+    fn notify_close(&mut self) {
+        if let Some(on_close) = self.on_close.take() {
+            (on_close.0)(ConnectionBytes {
+                bytes_read: self.conn.bytes_read(),
+                bytes_written: self.conn.bytes_written(),
+                _inner: (),
+            });
+        }
+    }
+
+    /// Drive this connection, handing back its IO if the service upgrades it.
+    ///
+    /// A service upgrades the connection by responding with `101 Switching
+    /// Protocols`, or with a successful response to a `CONNECT` request.
+    /// Once that response is flushed, the returned future resolves with the
+    /// `Upgraded` IO, including any bytes already read past the request.
+    ///
+    /// If the connection instead closes without an upgrade, the IO is shut
+    /// down as with polling the `Connection`, and the future resolves with
+    /// `None`.
+    pub fn into_upgraded(mut self) -> IntoUpgraded<I, S> {
+        self.conn.dispatch_mut().set_tunnel_connect();
+        IntoUpgraded {
+            conn: Some(self),
+            shutting_down: false,
+        }
+    }
+
+    /// Drive this connection, resolving with a `Disconnect` describing why
+    /// it finished, instead of `()`.
+    ///
+    /// The returned future never fails: an error of the connection is
+    /// reported as `DisconnectReason::Error`. This allows spawning it
+    /// directly, while still telling a normal close from an abnormal one.
+    pub fn with_disconnect(self) -> WithDisconnect<I, S> {
+        WithDisconnect {
+            conn: self,
+        }
+    }
+
+    fn disconnect_reason(&self) -> DisconnectReason {
+        if self.conn.is_upgraded() {
+            DisconnectReason::Upgraded
+        } else if self.conn.is_idle_timed_out() {
+            DisconnectReason::KeepAliveTimeout
+        } else if self.shutting_down {
+            DisconnectReason::Shutdown
+        } else {
+            DisconnectReason::Closed
+        }
+    }
 }
 
 impl<I, B, S> Future for Connection<I, S>
@@ -121,7 +353,29 @@ where S: Service<Request = Request, Response = Response<B>, Error = ::Error> + '
     type Error = ::Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        self.conn.poll()
+        let ret = self.conn.poll();
+        match ret {
+            Ok(Async::NotReady) => (),
+            _ => self.notify_close(),
+        }
+        ret
+    }
+}
+
+impl<I, S> Connection<I, S>
+where
+    S: HyperService,
+    S::ResponseBody: Stream<Error=::Error>,
+    <S::ResponseBody as Stream>::Item: AsRef<[u8]>,
+{
+    /// The number of bytes of responses buffered, but not written to the IO
+    /// object yet.
+    ///
+    /// These are lost if the connection is dropped before it is done, such
+    /// as when a server's `shutdown_timeout` elapses while a client isn't
+    /// reading its response.
+    pub fn pending_write_bytes(&self) -> usize {
+        self.conn.pending_write_bytes()
     }
 }
 
@@ -137,3 +391,205 @@ where
     }
 }
 
+// ===== impl IntoUpgraded =====
+
+impl<I, B, S> Future for IntoUpgraded<I, S>
+where S: Service<Request = Request, Response = Response<B>, Error = ::Error> + 'static,
+      I: AsyncRead + AsyncWrite + 'static,
+      B: Stream<Error=::Error> + 'static,
+      B::Item: AsRef<[u8]>,
+{
+    type Item = Option<Upgraded<I>>;
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        {
+            let conn = self.conn.as_mut().expect("IntoUpgraded polled after completion");
+            if !self.shutting_down {
+                try_ready!(conn.poll_without_shutdown());
+                self.shutting_down = !conn.conn.is_upgraded();
+            }
+            if self.shutting_down {
+                try_ready!(conn.conn.poll_shutdown());
+                return Ok(Async::Ready(None));
+            }
+        }
+
+        let conn = self.conn.take().expect("checked above");
+        trace!("{}connection upgraded", conn.log_id());
+        Ok(Async::Ready(Some(conn.upgraded())))
+    }
+}
+
+impl<I, S> fmt::Debug for IntoUpgraded<I, S>
+where
+    S: HyperService,
+    S::ResponseBody: Stream<Error=::Error>,
+    <S::ResponseBody as Stream>::Item: AsRef<[u8]>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IntoUpgraded")
+            .field("conn", &self.conn)
+            .finish()
+    }
+}
+
+// ===== impl WithDisconnect =====
+
+impl<I, B, S> Future for WithDisconnect<I, S>
+where S: Service<Request = Request, Response = Response<B>, Error = ::Error> + 'static,
+      I: AsyncRead + AsyncWrite + 'static,
+      B: Stream<Error=::Error> + 'static,
+      B::Item: AsRef<[u8]>,
+{
+    type Item = Disconnect;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let reason = match self.conn.poll() {
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Ok(Async::Ready(())) => self.conn.disconnect_reason(),
+            Err(err) => DisconnectReason::Error(err),
+        };
+        Ok(Async::Ready(Disconnect {
+            reason: reason,
+            requests: self.conn.conn.messages_read(),
+            _inner: (),
+        }))
+    }
+}
+
+impl<I, S> fmt::Debug for WithDisconnect<I, S>
+where
+    S: HyperService,
+    S::ResponseBody: Stream<Error=::Error>,
+    <S::ResponseBody as Stream>::Item: AsRef<[u8]>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WithDisconnect")
+            .field("conn", &self.conn)
+            .finish()
+    }
+}
+
+// ===== impl OnUpgrade =====
+
+impl Future for OnUpgrade {
+    type Item = Option<Upgraded<AddrStream>>;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, ()> {
+        match self.rx.poll() {
+            Ok(Async::Ready(upgraded)) => Ok(Async::Ready(Some(upgraded))),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(_canceled) => Ok(Async::Ready(None)),
+        }
+    }
+}
+
+impl OnUpgrade {
+    // Marks this as taken out of its `Request`, letting a successful
+    // response to a `CONNECT` make a tunnel.
+    pub(crate) fn mark_taken(&self) {
+        let mut slot = self.slot.0.lock().unwrap();
+        if slot.request == self.request {
+            slot.taken = true;
+        }
+    }
+}
+
+impl fmt::Debug for OnUpgrade {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("OnUpgrade")
+    }
+}
+
+impl UpgradeSlot {
+    // The `OnUpgrade` of a new request, replacing that of the previous one,
+    // which resolves with `None`.
+    pub(crate) fn next(&self) -> OnUpgrade {
+        let (tx, rx) = oneshot::channel();
+        let mut slot = self.0.lock().unwrap();
+        slot.tx = Some(tx);
+        slot.request += 1;
+        slot.taken = false;
+        OnUpgrade {
+            rx: rx,
+            slot: self.clone(),
+            request: slot.request,
+        }
+    }
+
+    // Whether the service took the `OnUpgrade` of the latest request.
+    pub(crate) fn is_taken(&self) -> bool {
+        self.0.lock().unwrap().taken
+    }
+
+    pub(crate) fn send(&self, upgraded: Upgraded<AddrStream>) {
+        if let Some(tx) = self.0.lock().unwrap().tx.take() {
+            if tx.send(upgraded).is_err() {
+                debug!("upgraded connection dropped, the service didn't wait for it");
+            }
+        }
+    }
+}
+
+impl fmt::Debug for UpgradeSlot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("UpgradeSlot")
+    }
+}
+
+// ===== impl Upgraded =====
+
+impl<T> Upgraded<T> {
+    /// Return the inner IO object, and the buffered bytes that were not
+    /// read from this `Upgraded` yet.
+    pub fn into_inner(self) -> (T, Bytes) {
+        (self.io, self.read_buf)
+    }
+}
+
+impl<T: Read> Read for Upgraded<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.read_buf.is_empty() {
+            return self.io.read(buf);
+        }
+        let n = ::std::cmp::min(buf.len(), self.read_buf.len());
+        buf[..n].copy_from_slice(&self.read_buf[..n]);
+        self.read_buf.advance(n);
+        Ok(n)
+    }
+}
+
+impl<T: Write> Write for Upgraded<T> {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.io.write(buf)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl<T: AsyncRead> AsyncRead for Upgraded<T> {
+    #[inline]
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        self.io.prepare_uninitialized_buffer(buf)
+    }
+}
+
+impl<T: AsyncWrite> AsyncWrite for Upgraded<T> {
+    #[inline]
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.io.shutdown()
+    }
+
+    #[inline]
+    fn write_buf<B: Buf>(&mut self, buf: &mut B) -> Poll<usize, io::Error> {
+        self.io.write_buf(buf)
+    }
+}
+
@@ -0,0 +1,188 @@
+//! Limiting the connections served at once by a `Server`.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io;
+use std::net::IpAddr;
+use std::rc::{Rc, Weak};
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use futures::task::{self, Task};
+use futures::{Async, Future, Poll, Stream};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use super::{Connection, ConnectionCounter, Request, Response, Service};
+
+// Sends the overload response to the first request of `conn`, which must
+// come quickly, so that idle connections over `max_connections` don't hold
+// on to their file descriptors.
+pub(super) fn set_overloaded<I, S, B>(conn: &mut Connection<I, S>)
+    where I: AsyncRead + AsyncWrite,
+          S: Service<Request = Request, Response = Response<B>, Error = ::Error>,
+          B: Stream<Error=::Error>,
+          B::Item: AsRef<[u8]>,
+{
+    conn.conn.dispatch_mut().set_overloaded();
+    conn.conn.limit_head_wait(Duration::from_secs(1));
+}
+
+// ===== NotifyService =====
+
+pub(super) struct NotifyService<S> {
+    pub(super) inner: S,
+    info: Weak<RefCell<Info>>,
+    connections: Option<ConnectionCounter>,
+    ip: Option<IpAddr>,
+    overloaded: bool,
+}
+
+pub(super) struct Info {
+    pub(super) active: usize,
+    // Connections accepted over `max_connections`, getting the overload
+    // response.
+    pub(super) overloaded: usize,
+    // Active connections by remote IP, if `max_connections_per_ip` is set.
+    pub(super) per_ip: HashMap<IpAddr, usize>,
+    pub(super) blocker: Option<Task>,
+    pub(super) accept_blocker: Option<Task>,
+}
+
+impl<S> NotifyService<S> {
+    pub(super) fn new(inner: S, info: &Rc<RefCell<Info>>, connections: Option<ConnectionCounter>) -> NotifyService<S> {
+        info.borrow_mut().active += 1;
+        if let Some(ref connections) = connections {
+            connections.active.fetch_add(1, Ordering::SeqCst);
+        }
+        NotifyService {
+            inner: inner,
+            info: Rc::downgrade(info),
+            connections: connections,
+            ip: None,
+            overloaded: false,
+        }
+    }
+
+    // A service counted apart from the `active` ones, for a connection
+    // accepted over `max_connections` to send the overload response.
+    pub(super) fn overloaded(inner: S, info: &Rc<RefCell<Info>>) -> NotifyService<S> {
+        info.borrow_mut().overloaded += 1;
+        NotifyService {
+            inner: inner,
+            info: Rc::downgrade(info),
+            connections: None,
+            ip: None,
+            overloaded: true,
+        }
+    }
+
+    // Counts this service as a connection from `ip` until it is dropped.
+    pub(super) fn track_ip(mut self, ip: IpAddr) -> NotifyService<S> {
+        if let Some(info) = self.info.upgrade() {
+            *info.borrow_mut().per_ip.entry(ip).or_insert(0) += 1;
+            self.ip = Some(ip);
+        }
+        self
+    }
+}
+
+impl<S: Service> Service for NotifyService<S> {
+    type Request = S::Request;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn call(&self, message: Self::Request) -> Self::Future {
+        self.inner.call(message)
+    }
+}
+
+impl<S> Drop for NotifyService<S> {
+    fn drop(&mut self) {
+        if let Some(ref connections) = self.connections {
+            connections.active.fetch_sub(1, Ordering::SeqCst);
+        }
+        let info = match self.info.upgrade() {
+            Some(info) => info,
+            None => return,
+        };
+        let mut info = info.borrow_mut();
+        if self.overloaded {
+            info.overloaded -= 1;
+        } else {
+            info.active -= 1;
+        }
+        if let Some(ip) = self.ip {
+            let remaining = match info.per_ip.get_mut(&ip) {
+                Some(count) => {
+                    *count -= 1;
+                    *count
+                }
+                None => 0,
+            };
+            if remaining == 0 {
+                info.per_ip.remove(&ip);
+            }
+        }
+        if let Some(task) = info.accept_blocker.take() {
+            task.notify();
+        }
+        if info.active == 0 {
+            if let Some(task) = info.blocker.take() {
+                task.notify();
+            }
+        }
+    }
+}
+
+// ===== MaxConnections =====
+
+// Wraps the incoming connections of `Server::run_until`, yielding `NotReady`
+// without polling them while `max` services are alive. Dropping a
+// `NotifyService` wakes the task up again.
+pub(super) struct MaxConnections<I> {
+    pub(super) incoming: I,
+    pub(super) info: Rc<RefCell<Info>>,
+    pub(super) max: Option<usize>,
+    // Whether `max` more connections are accepted for the overload response.
+    pub(super) overload: bool,
+}
+
+impl<I: Stream> Stream for MaxConnections<I> {
+    type Item = I::Item;
+    type Error = I::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if let Some(max) = self.max {
+            let mut info = self.info.borrow_mut();
+            if info.active >= max && (!self.overload || info.overloaded >= max) {
+                trace!("max connections reached ({}), pausing accept", max);
+                info.accept_blocker = Some(task::current());
+                return Ok(Async::NotReady);
+            }
+        }
+        self.incoming.poll()
+    }
+}
+
+// ===== WaitUntilZero =====
+
+// Resolves once no service counted in `info` is alive anymore.
+pub(super) struct WaitUntilZero {
+    pub(super) info: Rc<RefCell<Info>>,
+}
+
+impl Future for WaitUntilZero {
+    type Item = ();
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<(), io::Error> {
+        let mut info = self.info.borrow_mut();
+        if info.active == 0 {
+            Ok(().into())
+        } else {
+            info.blocker = Some(task::current());
+            Ok(Async::NotReady)
+        }
+    }
+}
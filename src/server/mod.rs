@@ -3,23 +3,38 @@
 //! A `Server` is created to listen on a port, parse HTTP requests, and hand
 //! them off to a `Service`.
 
+mod accept;
 #[cfg(feature = "compat")]
 pub mod compat;
 pub mod conn;
+mod limits;
+mod proxy_protocol;
 mod service;
-
-use std::cell::RefCell;
+mod stats;
+#[cfg(feature = "sse")]
+pub mod sse;
+#[cfg(feature = "testing")]
+pub mod test;
+#[cfg(all(unix, feature = "unix"))]
+mod unix;
+
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
 use std::fmt;
 use std::io;
 use std::marker::PhantomData;
-use std::net::SocketAddr;
-use std::rc::{Rc, Weak};
-use std::sync::Arc;
+use std::net::{SocketAddr, TcpListener as StdTcpListener, TcpStream as StdTcpStream};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
+use bytes::Bytes;
 use futures::task::{self, Task};
-use futures::future::{self};
+use futures::future::{self, Either, Executor, ExecuteErrorKind, Shared};
+use futures::sync::mpsc;
+use futures::unsync::oneshot;
 use futures::{Future, Stream, Poll, Async};
 use net2;
 
@@ -27,18 +42,26 @@ use net2;
 use http;
 
 use tokio_io::{AsyncRead, AsyncWrite};
-use tokio::reactor::{Core, Handle, Interval, Timeout};
-use tokio::net::TcpListener;
+use tokio::reactor::{Core, CoreId, Handle, Interval, Timeout};
+use tokio::net::{TcpListener, TcpStream};
 pub use tokio_service::{NewService, Service};
 
 use proto;
+use proto::dispatch::OverloadResponse;
+use proto::request::ConnInfo;
+use proto::request_id::LogId;
+use proto::request_limit::RequestLimit;
 #[cfg(feature = "compat")]
 use proto::Body;
-use self::addr_stream::AddrStream;
+use self::accept::ServerIncoming;
+pub use self::addr_stream::AddrStream;
+use self::conn::{UpgradeSlot, Upgraded};
 use self::hyper_service::HyperService;
+use self::limits::{set_overloaded, Info, MaxConnections, NotifyService, WaitUntilZero};
 
 pub use proto::response::Response;
 pub use proto::request::Request;
+pub use proto::{CancelToken, Cancelled, ExchangeInfo, Informational, IpNet, IpNetError, RequestId, SocketControl, TimingInfo, Trailers, WriteDrained};
 
 feat_server_proto! {
     mod server_proto;
@@ -50,8 +73,12 @@ feat_server_proto! {
     };
 }
 
+pub use self::accept::{AcceptAction, AcceptControl, AddrIncoming};
 pub use self::conn::Connection;
-pub use self::service::{const_service, service_fn};
+pub use self::service::{blocking_service, cache_service, const_service, retry_new_service, service_fn, service_fn_ok, CacheService, MethodRouter};
+pub use self::stats::{AcceptStats, ConnectionCounter, RequestCounter};
+#[cfg(all(unix, feature = "unix"))]
+pub use self::unix::{UnixAddrStream, UnixServer};
 
 /// A configuration of the HTTP protocol.
 ///
@@ -60,9 +87,66 @@ pub use self::service::{const_service, service_fn};
 /// configured with various protocol-level options such as keepalive.
 pub struct Http<B = ::Chunk> {
     max_buf_size: Option<usize>,
+    initial_read_buf_size: Option<usize>,
+    body_write_buffer_limit: Option<usize>,
+    max_pipeline_depth: Option<usize>,
+    pipeline_fairness_limit: Option<usize>,
+    max_requests_per_connection: Option<usize>,
+    max_body_size: Option<usize>,
+    auto_drain_body: bool,
+    max_drain_bytes: Option<usize>,
+    reject_conflicting_framing: bool,
+    require_host_header: bool,
+    send_408_on_timeout: bool,
+    max_headers: usize,
+    max_header_list_size: usize,
+    max_uri_length: usize,
+    auto_date: bool,
+    date_clock: Option<DateClock>,
+    server_header: Option<::header::Server>,
+    echo_headers: Vec<Cow<'static, str>>,
+    strip_response_headers: Vec<Cow<'static, str>>,
+    strip_hop_by_hop_headers: bool,
+    trusted_proxies: Vec<IpNet>,
+    request_ids: bool,
+    informational_responses: bool,
     keep_alive: bool,
+    http10_keep_alive: bool,
     pipeline: bool,
     sleep_on_errors: bool,
+    accept_error_sleep: Duration,
+    expect_continue: bool,
+    header_read_timeout: Option<Duration>,
+    min_head_read_rate: Option<(usize, Duration)>,
+    body_read_timeout: Option<Duration>,
+    keep_alive_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    write_coalesce: Option<(Duration, usize)>,
+    max_connection_age: Option<Duration>,
+    request_timeout: Option<Duration>,
+    request_timeout_status: ::StatusCode,
+    listen_backlog: i32,
+    reuse_address: bool,
+    tcp_nodelay: Option<bool>,
+    tcp_linger: Option<Option<Duration>>,
+    tcp_recv_buffer_size: Option<usize>,
+    tcp_send_buffer_size: Option<usize>,
+    on_connection: Option<OnConnection>,
+    accept_filter: Option<AcceptFilter>,
+    on_accept_error: Option<OnAcceptError>,
+    on_exchange_complete: Option<OnExchangeComplete>,
+    on_write_drained: Option<OnWriteDrained>,
+    on_exchange_timing: Option<OnExchangeTiming>,
+    on_connection_close: Option<OnConnectionClose>,
+    on_parse_error: Option<OnParseError>,
+    internal_error_response: Option<InternalErrorResponse>,
+    max_connections: Option<usize>,
+    max_connections_per_ip: Option<usize>,
+    request_limit: Option<Arc<RequestLimit>>,
+    overload_response: Option<OverloadResponse>,
+    max_accept_rate: Option<u32>,
+    accept_burst: usize,
+    proxy_protocol: bool,
     _marker: PhantomData<fn() -> B>,
 }
 
@@ -77,8 +161,79 @@ where B: Stream<Error=::Error>,
     protocol: Http<B::Item>,
     new_service: S,
     reactor: Core,
-    listener: TcpListener,
+    // The first one is bound to `local_addr`, the others by `bind_multi`.
+    listeners: Vec<TcpListener>,
+    shutdown_timeout: Duration,
+    connections: ConnectionCounter,
+    accept_control: AcceptControl,
+    accept_stats: AcceptStats,
+    // Whether `run_threads` may bind more listeners to the same addresses.
+    reuse_port: bool,
+}
+
+/// A server created through `Http::bind_handle`, to be served on a `Core`
+/// it doesn't own.
+///
+/// This is the same as a `Server`, except that it is served by the future of
+/// `serve_until`, which the caller runs or spawns on its own `Core`, next to
+/// its other tasks.
+pub struct HandleServer<S, B>
+where B: Stream<Error=::Error>,
+      B::Item: AsRef<[u8]>,
+{
+    protocol: Http<B::Item>,
+    new_service: S,
+    handle: Handle,
+    incoming: AddrIncoming,
     shutdown_timeout: Duration,
+    connections: ConnectionCounter,
+}
+
+/// A future serving the connections of a `HandleServer`, until its shutdown
+/// signal resolves and its connections are closed.
+///
+/// Returned by `HandleServer::serve_until`.
+#[must_use = "futures do nothing unless polled"]
+pub struct ServeUntil<S, B, F>
+where B: Stream<Error=::Error>,
+      B::Item: AsRef<[u8]>,
+{
+    inner: Serving<S, B, AddrIncoming, SignalPhases<F>>,
+}
+
+/// How the graceful shutdown of a `Server` went.
+///
+/// Returned by `Server::run_until_graceful`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ShutdownOutcome {
+    /// Whether the `shutdown_timeout` elapsed before all connections closed.
+    pub forced: bool,
+    /// The number of connections still active when the server returned.
+    pub remaining: usize,
+    _inner: (),
+}
+
+/// A phase of the shutdown of a `Server`.
+///
+/// See `Server::run_until_phased`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShutdownPhase {
+    /// Stop accepting connections, still serving the active ones.
+    StopAccepting,
+    /// Close the active connections once their in-flight responses are done.
+    Drain,
+}
+
+/// The bytes transferred over a connection.
+///
+/// Passed to the callback set with `Http::on_connection_close`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConnectionBytes {
+    /// The bytes read from the connection, as received.
+    pub bytes_read: u64,
+    /// The bytes written to the connection, as sent.
+    pub bytes_written: u64,
+    _inner: (),
 }
 
 /// A stream mapping incoming IOs to new services.
@@ -90,28 +245,79 @@ pub struct Serve<I, S> {
     incoming: I,
     new_service: S,
     protocol: Http,
+    handle: Option<Handle>,
+}
+
+/// A stream mapping incoming IOs to new services, with the details of each
+/// connection provided by a function.
+///
+/// Created with `Http::serve_incoming_with`.
+#[must_use = "streams do nothing unless polled"]
+pub struct ServeWith<I, S, F> {
+    serve: Serve<I, S>,
+    info: F,
+}
+
+/// A stream mapping incoming IOs, paired with the addresses of their
+/// remote peers, to new services.
+///
+/// Created with `Http::serve_incoming_addr`.
+#[must_use = "streams do nothing unless polled"]
+#[derive(Debug)]
+pub struct ServeAddr<I, S> {
+    serve: Serve<I, S>,
 }
 
-/*
+/// A future spawning every connection of a `Serve` onto an executor.
+///
+/// Created with `Serve::spawn_all`.
 #[must_use = "futures do nothing unless polled"]
 #[derive(Debug)]
 pub struct SpawnAll<I, S, E> {
     executor: E,
     serve: Serve<I, S>,
 }
-*/
 
-/// A stream of connections from binding to an address.
-#[must_use = "streams do nothing unless polled"]
-#[derive(Debug)]
-pub struct AddrIncoming {
-    addr: SocketAddr,
-    keep_alive_timeout: Option<Duration>,
-    listener: TcpListener,
-    handle: Handle,
-    sleep_on_errors: bool,
-    timeout: Option<Timeout>,
-}
+// A clock set with `Http::date_clock`.
+#[derive(Clone)]
+struct DateClock(Arc<Fn() -> SystemTime + Send + Sync>);
+
+// A callback set with `Http::on_connection`.
+#[derive(Clone)]
+struct OnConnection(Arc<Fn(&AddrStream) + Send + Sync>);
+
+// A callback set with `Http::accept_filter`.
+#[derive(Clone)]
+struct AcceptFilter(Arc<Fn(&SocketAddr) -> bool + Send + Sync>);
+
+// A callback set with `Http::on_accept_error`.
+#[derive(Clone)]
+struct OnAcceptError(Arc<Fn(&io::Error) -> AcceptAction + Send + Sync>);
+
+// A callback set with `Http::on_exchange_complete`.
+#[derive(Clone)]
+struct OnExchangeComplete(Arc<Fn(ExchangeInfo) + Send + Sync>);
+
+// A callback set with `Http::on_write_drained`.
+#[derive(Clone)]
+struct OnWriteDrained(Arc<Fn(WriteDrained) + Send + Sync>);
+
+// A callback set with `Http::on_exchange_timing`.
+#[derive(Clone)]
+struct OnExchangeTiming(Arc<Fn(TimingInfo) + Send + Sync>);
+
+// A callback set with `Http::on_connection_close`.
+#[derive(Clone)]
+struct OnConnectionClose(Arc<Fn(ConnectionBytes) + Send + Sync>);
+
+// A callback set with `Http::on_parse_error`.
+#[derive(Clone)]
+struct OnParseError(Arc<Fn(&::Error, Option<SocketAddr>) -> Option<Response<::Chunk>> + Send + Sync>);
+
+// A callback set with `Http::internal_error_response`.
+#[derive(Clone)]
+struct InternalErrorResponse(Arc<Fn(&::Error) -> Response<::Chunk> + Send + Sync>);
+
 
 
 // ===== impl Http =====
@@ -122,9 +328,66 @@ impl<B: AsRef<[u8]> + 'static> Http<B> {
     pub fn new() -> Http<B> {
         Http {
             keep_alive: true,
+            http10_keep_alive: true,
             max_buf_size: None,
+            initial_read_buf_size: None,
+            body_write_buffer_limit: None,
+            max_pipeline_depth: None,
+            pipeline_fairness_limit: None,
+            max_requests_per_connection: None,
+            max_body_size: None,
+            auto_drain_body: true,
+            max_drain_bytes: None,
+            reject_conflicting_framing: true,
+            require_host_header: false,
+            send_408_on_timeout: false,
+            max_headers: 100,
+            max_header_list_size: 16 * 1024,
+            max_uri_length: 8 * 1024,
+            auto_date: true,
+            date_clock: None,
+            server_header: None,
+            echo_headers: Vec::new(),
+            strip_response_headers: Vec::new(),
+            strip_hop_by_hop_headers: false,
+            trusted_proxies: Vec::new(),
+            request_ids: false,
+            informational_responses: false,
             pipeline: false,
             sleep_on_errors: false,
+            accept_error_sleep: Duration::from_millis(10),
+            expect_continue: false,
+            header_read_timeout: None,
+            min_head_read_rate: None,
+            body_read_timeout: None,
+            keep_alive_timeout: None,
+            write_timeout: None,
+            write_coalesce: None,
+            max_connection_age: None,
+            request_timeout: None,
+            request_timeout_status: ::StatusCode::GatewayTimeout,
+            listen_backlog: 1024,
+            reuse_address: true,
+            tcp_nodelay: None,
+            tcp_linger: None,
+            tcp_recv_buffer_size: None,
+            tcp_send_buffer_size: None,
+            on_connection: None,
+            accept_filter: None,
+            on_accept_error: None,
+            on_exchange_complete: None,
+            on_write_drained: None,
+            on_exchange_timing: None,
+            on_connection_close: None,
+            on_parse_error: None,
+            internal_error_response: None,
+            max_connections: None,
+            max_connections_per_ip: None,
+            request_limit: None,
+            overload_response: None,
+            max_accept_rate: None,
+            accept_burst: 1,
+            proxy_protocol: false,
             _marker: PhantomData,
         }
     }
@@ -137,230 +400,1671 @@ impl<B: AsRef<[u8]> + 'static> Http<B> {
         self
     }
 
+    /// Enables or disables keep-alive for HTTP/1.0 requests.
+    ///
+    /// When enabled, an HTTP/1.0 request with a `Connection: keep-alive`
+    /// header keeps the connection open for another request. When disabled,
+    /// the connection is always closed after responding to an HTTP/1.0
+    /// request. Keep-alive is never used while `keep_alive` is disabled.
+    ///
+    /// Default is true.
+    pub fn http1_keepalive_for_10(&mut self, val: bool) -> &mut Self {
+        self.http10_keep_alive = val;
+        self
+    }
+
     /// Set the maximum buffer size for the connection.
     pub fn max_buf_size(&mut self, max: usize) -> &mut Self {
         self.max_buf_size = Some(max);
         self
     }
 
-    /// Aggregates flushes to better support pipelined responses.
+    /// Set the high-water mark of the write buffer of each connection, in
+    /// bytes.
     ///
-    /// Experimental, may be have bugs.
+    /// While this many bytes of a response are waiting to be written to the
+    /// socket, the next chunk of the response body `Stream` is not polled.
+    /// A service streaming a large body to a slow client thus only has
+    /// about this much of it held in memory per connection.
     ///
-    /// Default is false.
-    pub fn pipeline(&mut self, enabled: bool) -> &mut Self {
-        self.pipeline = enabled;
+    /// Default is to buffer up to `max_buf_size`.
+    pub fn body_write_buffer_limit(&mut self, limit: usize) -> &mut Self {
+        self.body_write_buffer_limit = Some(limit);
         self
     }
 
-    /// Swallow connection accept errors. Instead of passing up IO errors when
-    /// the server is under heavy load the errors will be ignored. Some
-    /// connection accept errors (like "connection reset") can be ignored, some
-    /// (like "too many files open") may consume 100% CPU and a timout of 10ms
-    /// is used in that case.
+    /// Set the initial capacity of the read buffer of each connection.
     ///
-    /// Default is false.
-    pub fn sleep_on_errors(&mut self, enabled: bool) -> &mut Self {
-        self.sleep_on_errors = enabled;
+    /// The buffer grows by this much at a time while a message doesn't fit,
+    /// up to `max_buf_size`, and a larger initial size is clamped to it. A
+    /// buffer is kept for as long as its connection stays open, so a smaller
+    /// size saves memory when there are many idle keep-alive connections,
+    /// at the cost of more reads and reallocations for larger requests.
+    ///
+    /// Default is 8kb.
+    pub fn initial_read_buf_size(&mut self, size: usize) -> &mut Self {
+        self.initial_read_buf_size = Some(size);
         self
     }
 
-    /// Bind the provided `addr` and return a server ready to handle
-    /// connections.
+    /// Set the maximum size of a request body, in bytes.
     ///
-    /// This method will bind the `addr` provided with a new TCP listener ready
-    /// to accept connections. Each connection will be processed with the
-    /// `new_service` object provided as well, creating a new service per
-    /// connection.
+    /// A request announcing a larger `Content-Length` is answered with
+    /// `413 Payload Too Large` without calling the service. Other bodies are
+    /// counted as they are read: once the limit is exceeded, reading stops,
+    /// the body yields an error, and a `413` is sent if the service has not
+    /// responded yet. Either way, the connection is then closed.
     ///
-    /// The returned `Server` contains one method, `run`, which is used to
-    /// actually run the server.
-    pub fn bind<S, Bd>(&self, addr: &SocketAddr, new_service: S) -> ::Result<Server<S, Bd>>
-        where S: NewService<Request = Request, Response = Response<Bd>, Error = ::Error> + 'static,
-              Bd: Stream<Item=B, Error=::Error>,
-    {
-        let core = try!(Core::new());
-        let handle = core.handle();
-        let listener = try!(thread_listener(addr, &handle));
-
-        Ok(Server {
-            new_service: new_service,
-            reactor: core,
-            listener: listener,
-            protocol: self.clone(),
-            shutdown_timeout: Duration::new(1, 0),
-        })
+    /// Default is `None`, which does not limit bodies.
+    pub fn max_body_size(&mut self, max: Option<usize>) -> &mut Self {
+        self.max_body_size = max;
+        self
     }
 
-
-    /// Bind a `NewService` using types from the `http` crate.
+    /// Set whether to drain the unread rest of a request body.
     ///
-    /// See `Http::bind`.
-    #[cfg(feature = "compat")]
-    pub fn bind_compat<S, Bd>(&self, addr: &SocketAddr, new_service: S) -> ::Result<Server<compat::NewCompatService<S>, Bd>>
-        where S: NewService<Request = http::Request<Body>, Response = http::Response<Bd>, Error = ::Error> +
-                    Send + Sync + 'static,
-              Bd: Stream<Item=B, Error=::Error>,
-    {
-        self.bind(addr, self::compat::new_service(new_service))
-    }
-
-    /// Bind the provided `addr` and return a server with a shared `Core`.
+    /// When a service drops the body of a request before its end, for
+    /// instance answering `401 Unauthorized` to an upload, the remaining
+    /// bytes still have to be read before the next request on the
+    /// connection. If enabled, they are read and discarded, so the connection
+    /// can be kept alive. If disabled, reading stops and the connection is
+    /// closed once the response is written.
     ///
-    /// This method allows the ability to share a `Core` with multiple servers.
+    /// A body whose client is still waiting on a deferred `100 Continue`
+    /// (see `expect_continue`) is never drained.
     ///
-    /// This is method will bind the `addr` provided with a new TCP listener ready
-    /// to accept connections. Each connection will be processed with the
-    /// `new_service` object provided as well, creating a new service per
-    /// connection.
-    pub fn serve_addr_handle<S, Bd>(&self, addr: &SocketAddr, handle: &Handle, new_service: S) -> ::Result<Serve<AddrIncoming, S>>
-        where S: NewService<Request = Request, Response = Response<Bd>, Error = ::Error>,
-              Bd: Stream<Item=B, Error=::Error>,
-    {
-        let listener = TcpListener::bind(addr, &handle)?;
-        let mut incoming = AddrIncoming::new(listener, handle.clone(), self.sleep_on_errors)?;
-        if self.keep_alive {
-            incoming.set_keepalive(Some(Duration::from_secs(90)));
-        }
-        Ok(self.serve_incoming(incoming, new_service))
+    /// Default is `true`.
+    pub fn auto_drain_body(&mut self, enabled: bool) -> &mut Self {
+        self.auto_drain_body = enabled;
+        self
     }
 
-    /// Bind the provided stream of incoming IO objects with a `NewService`.
+    /// Set the maximum number of bytes of a dropped request body to drain.
     ///
-    /// This method allows the ability to share a `Core` with multiple servers.
-    pub fn serve_incoming<I, S, Bd>(&self, incoming: I, new_service: S) -> Serve<I, S>
-        where I: Stream<Error=::std::io::Error>,
-              I::Item: AsyncRead + AsyncWrite,
-              S: NewService<Request = Request, Response = Response<Bd>, Error = ::Error>,
-              Bd: Stream<Item=B, Error=::Error>,
-    {
-        Serve {
-            incoming: incoming,
-            new_service: new_service,
-            protocol: Http {
-                keep_alive: self.keep_alive,
-                max_buf_size: self.max_buf_size,
-                pipeline: self.pipeline,
-                sleep_on_errors: self.sleep_on_errors,
-                _marker: PhantomData,
-            },
-        }
+    /// Once more bytes have been discarded, draining stops and the
+    /// connection is closed instead. Draining a large upload costs more than
+    /// opening a new connection.
+    ///
+    /// Default is `None`, which drains bodies of any size.
+    pub fn max_drain_bytes(&mut self, max: Option<usize>) -> &mut Self {
+        self.max_drain_bytes = max;
+        self
     }
 
-    /// Bind a connection together with a Service.
+    /// Set whether to reject requests framed by both `Transfer-Encoding`
+    /// and `Content-Length`.
     ///
-    /// This returns a Future that must be polled in order for HTTP to be
-    /// driven on the connection.
+    /// A proxy in front of the server could pick the other header to read
+    /// the body of such a request, and take its end for the start of another
+    /// request, which is how requests are smuggled past it. If enabled, such
+    /// a request is answered with `400 Bad Request`, and the connection
+    /// closed. If disabled, `Transfer-Encoding` is used, as the RFC says.
     ///
-    /// # Example
+    /// Several `Content-Length` headers with differing values are always
+    /// rejected the same way.
     ///
-    /// ```
-    /// # extern crate futures;
-    /// # extern crate hyper;
-    /// # extern crate tokio_core;
-    /// # extern crate tokio_io;
-    /// # use futures::Future;
-    /// # use hyper::server::{Http, Request, Response, Service};
-    /// # use tokio_io::{AsyncRead, AsyncWrite};
-    /// # use tokio_core::reactor::Handle;
-    /// # fn run<I, S>(some_io: I, some_service: S, some_handle: &Handle)
-    /// # where
-    /// #     I: AsyncRead + AsyncWrite + 'static,
-    /// #     S: Service<Request=Request, Response=Response, Error=hyper::Error> + 'static,
-    /// # {
-    /// let http = Http::<hyper::Chunk>::new();
-    /// let conn = http.serve_connection(some_io, some_service);
+    /// Default is `true`.
+    pub fn reject_conflicting_framing(&mut self, enabled: bool) -> &mut Self {
+        self.reject_conflicting_framing = enabled;
+        self
+    }
+
+    /// Reject HTTP/1.1 requests without exactly one `Host` header.
     ///
-    /// let fut = conn
-    ///     .map(|_| ())
-    ///     .map_err(|e| eprintln!("server connection error: {}", e));
+    /// RFC 7230 requires every HTTP/1.1 request to carry a single `Host`.
+    /// A proxy in front of the server could route or cache a request that
+    /// has none, or several, differently than the service would handle it.
+    /// If enabled, such a request is answered with `400 Bad Request`, and
+    /// the connection closed, without calling the service. HTTP/1.0
+    /// requests, which may omit `Host`, are not affected.
     ///
-    /// some_handle.spawn(fut);
-    /// # }
-    /// # fn main() {}
-    /// ```
-    pub fn serve_connection<S, I, Bd>(&self, io: I, service: S) -> Connection<I, S>
-        where S: Service<Request = Request, Response = Response<Bd>, Error = ::Error>,
-              Bd: Stream<Error=::Error>,
-              Bd::Item: AsRef<[u8]>,
-              I: AsyncRead + AsyncWrite + RemoteAddr,
-
-    {
-        let addr = io.remote();
-        let mut conn = proto::Conn::new(io);
-        if !self.keep_alive {
-            conn.disable_keep_alive();
-        }
-        conn.set_flush_pipeline(self.pipeline);
-        if let Some(max) = self.max_buf_size {
-            conn.set_max_buf_size(max);
-        }
-        Connection {
-            conn: proto::dispatch::Dispatcher::new(proto::dispatch::Server::new(service), conn),
-            remote_addr: addr,
-        }
+    /// Default is `false`, accepting such requests as before.
+    pub fn require_host_header(&mut self, enabled: bool) -> &mut Self {
+        self.require_host_header = enabled;
+        self
     }
-}
-
 
-
-impl<B> Clone for Http<B> {
-    fn clone(&self) -> Http<B> {
-        Http {
-            ..*self
-        }
+    /// Set the maximum number of headers in a request.
+    ///
+    /// A request with more headers is answered with `431 Request Header
+    /// Fields Too Large`, and the connection is closed.
+    ///
+    /// Default is 100.
+    pub fn max_headers(&mut self, max: usize) -> &mut Self {
+        self.max_headers = max;
+        self
     }
-}
 
-impl<B> fmt::Debug for Http<B> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.debug_struct("Http")
-            .field("keep_alive", &self.keep_alive)
-            .field("pipeline", &self.pipeline)
-            .finish()
+    /// Set the maximum size of a request head, in bytes.
+    ///
+    /// This counts the request line and all header fields. A larger head is
+    /// answered with `431 Request Header Fields Too Large`, and the
+    /// connection is closed.
+    ///
+    /// Default is 16KB.
+    pub fn max_header_list_size(&mut self, max: usize) -> &mut Self {
+        self.max_header_list_size = max;
+        self
     }
-}
-
-
 
-// ===== impl Server =====
+    /// Set the maximum length of a request URI, in bytes.
+    ///
+    /// A request with a longer URI is answered with `414 URI Too Long`, and
+    /// the connection is closed. The URI is checked as the request line is
+    /// received, so a long one is refused without waiting for its end.
+    ///
+    /// Default is 8KB.
+    pub fn max_uri_length(&mut self, max: usize) -> &mut Self {
+        self.max_uri_length = max;
+        self
+    }
 
-impl<S, B> Server<S, B>
-    where S: NewService<Request = Request, Response = Response<B>, Error = ::Error> + 'static,
-          B: Stream<Error=::Error> + 'static,
-          B::Item: AsRef<[u8]>,
-{
-    /// Returns the local address that this server is bound to.
-    pub fn local_addr(&self) -> ::Result<SocketAddr> {
-        Ok(try!(self.listener.local_addr()))
+    /// Enables or disables adding a `Date` header to responses.
+    ///
+    /// A response with its own `Date` header is always left as is. When
+    /// disabled, a `Server` also stops keeping its cached date up to date.
+    ///
+    /// Default is true.
+    pub fn auto_date(&mut self, enabled: bool) -> &mut Self {
+        self.auto_date = enabled;
+        self
     }
 
-    /// Returns a handle to the underlying event loop that this server will be
-    /// running on.
-    pub fn handle(&self) -> Handle {
-        self.reactor.handle()
+    /// Set the clock used for the `Date` header added to responses.
+    ///
+    /// This allows a fixed clock for reproducible tests. The date is then
+    /// read from the clock for every response, instead of from the date
+    /// cached for the current second.
+    ///
+    /// Default is to use the system clock.
+    pub fn date_clock<F>(&mut self, clock: F) -> &mut Self
+    where
+        F: Fn() -> SystemTime + Send + Sync + 'static,
+    {
+        self.date_clock = Some(DateClock(Arc::new(clock)));
+        self
     }
 
-    /// Configure the amount of time this server will wait for a "graceful
-    /// shutdown".
+    /// Set a `Server` header to add to every response.
     ///
-    /// This is the amount of time after the shutdown signal is received the
-    /// server will wait for all pending connections to finish. If the timeout
-    /// elapses then the server will be forcibly shut down.
+    /// The header is added to responses that don't already have one, so a
+    /// service can still set its own. With `None`, no `Server` header is
+    /// added, and responses only have one if the service set it.
     ///
-    /// This defaults to 1s.
-    pub fn shutdown_timeout(&mut self, timeout: Duration) -> &mut Self {
-        self.shutdown_timeout = timeout;
+    /// Default is `None`.
+    pub fn server_header(&mut self, server: Option<::header::Server>) -> &mut Self {
+        self.server_header = server;
         self
     }
 
-    #[doc(hidden)]
-    #[deprecated(since="0.11.11", note="no_proto is always enabled")]
-    pub fn no_proto(&mut self) -> &mut Self {
+    /// Copy the header `name` of every request onto its response.
+    ///
+    /// This propagates a correlation id, such as `X-Request-Id`, without
+    /// each service copying it. The values are copied when the request has
+    /// the header, and the response the service produced doesn't, so a
+    /// service can still set its own. Can be called several times, to echo
+    /// several headers.
+    pub fn echo_header<K>(&mut self, name: K) -> &mut Self
+    where
+        K: Into<Cow<'static, str>>,
+    {
+        self.echo_headers.push(name.into());
         self
     }
 
-    /// Execute this server infinitely.
+    /// Remove the headers `names` from every response of a service.
+    ///
+    /// This guarantees that headers fingerprinting the server, such as
+    /// `X-Powered-By`, never leave it, whatever a service sets. The headers
+    /// are removed after `echo_header` copied its headers, but the headers
+    /// hyper adds itself, such as with `server_header` and `auto_date`, are
+    /// still sent.
+    ///
+    /// Default is no header.
+    pub fn strip_response_headers<I, K>(&mut self, names: I) -> &mut Self
+    where
+        I: IntoIterator<Item=K>,
+        K: Into<Cow<'static, str>>,
+    {
+        self.strip_response_headers = names.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Set whether to remove the hop-by-hop headers of the responses of a
+    /// service.
+    ///
+    /// These headers are about a single connection, and managed by hyper:
+    /// `Keep-Alive`, `Proxy-Connection`, `TE`, `Upgrade` other than on a
+    /// `101 Switching Protocols`, the headers named by `Connection`, and a
+    /// `Transfer-Encoding` besides a `Content-Length`. The `close` and
+    /// `keep-alive` options of `Connection` are kept, so a service can
+    /// still close its connection.
+    ///
+    /// Default is false.
+    pub fn strip_hop_by_hop_headers(&mut self, enabled: bool) -> &mut Self {
+        self.strip_hop_by_hop_headers = enabled;
+        self
+    }
+
+    /// Set whether to give each request a `RequestId`, to correlate the log
+    /// messages about it.
+    ///
+    /// The messages logged by the dispatcher of a connection then start
+    /// with the id of the request they are about, such as `[3.1]` for the
+    /// first request of the third connection, or `[3]` about the connection
+    /// itself. A service can log with the same id, see
+    /// `Request::request_id`.
+    ///
+    /// Default is false.
+    pub fn request_ids(&mut self, enabled: bool) -> &mut Self {
+        self.request_ids = enabled;
+        self
+    }
+
+    /// Set whether services may send informational (1xx) responses, such as
+    /// a `103 Early Hints`, before the final response to a request.
+    ///
+    /// When enabled, every request carries an `Informational` sender, see
+    /// `Request::informational`.
+    ///
+    /// Default is false.
+    pub fn informational_responses(&mut self, enabled: bool) -> &mut Self {
+        self.informational_responses = enabled;
+        self
+    }
+
+    /// Aggregates flushes to better support pipelined responses.
+    ///
+    /// Experimental, may be have bugs.
+    ///
+    /// Pipelined requests are still served one at a time: the next request
+    /// is only passed to the service once the response to the previous one
+    /// has been written. Responses are thus always sent in the order of the
+    /// requests, however long the service takes for each of them.
+    ///
+    /// Default is false.
+    pub fn pipeline(&mut self, enabled: bool) -> &mut Self {
+        self.pipeline = enabled;
+        self
+    }
+
+    /// Set the maximum number of responses buffered for pipelined requests.
+    ///
+    /// With `pipeline` enabled, responses are buffered while more requests
+    /// are waiting to be read. Once `max` responses are buffered, they are
+    /// flushed without waiting, and no further requests are read until the
+    /// client has taken them.
+    ///
+    /// Default is no limit.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max` is 0.
+    pub fn max_pipeline_depth(&mut self, max: usize) -> &mut Self {
+        assert!(max > 0, "max_pipeline_depth must be at least 1");
+        self.max_pipeline_depth = Some(max);
+        self
+    }
+
+    /// Set the maximum number of pipelined requests of a connection served
+    /// before yielding to the other connections of the reactor.
+    ///
+    /// A client sending many pipelined requests at once can otherwise keep
+    /// its connection busy for all of them, delaying the requests of other
+    /// connections. After `limit` requests read without waiting for the
+    /// client, the connection is rescheduled behind the other tasks, and
+    /// goes on with its next request once they had a turn.
+    ///
+    /// Default is no limit.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `limit` is 0.
+    pub fn pipeline_fairness_limit(&mut self, limit: usize) -> &mut Self {
+        assert!(limit > 0, "pipeline_fairness_limit must be at least 1");
+        self.pipeline_fairness_limit = Some(limit);
+        self
+    }
+
+    /// Set the maximum number of requests served on a keep-alive
+    /// connection.
+    ///
+    /// The response to the `max`th request of a connection has a
+    /// `Connection: close` header, and the connection closes once it is
+    /// written. This recycles long lived connections, such as to pick up a
+    /// new configuration. Pipelined requests after it are not served.
+    ///
+    /// Default is no limit.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max` is 0.
+    pub fn max_requests_per_connection(&mut self, max: usize) -> &mut Self {
+        assert!(max > 0, "max_requests_per_connection must be at least 1");
+        self.max_requests_per_connection = Some(max);
+        self
+    }
+
+    /// Swallow connection accept errors. Instead of passing up IO errors when
+    /// the server is under heavy load the errors will be ignored. Some
+    /// connection accept errors (like "connection reset") can be ignored, some
+    /// (like "too many files open") may consume 100% CPU and a timout, see
+    /// `accept_error_sleep`, is used in that case.
+    ///
+    /// Default is false.
+    pub fn sleep_on_errors(&mut self, enabled: bool) -> &mut Self {
+        self.sleep_on_errors = enabled;
+        self
+    }
+
+    /// Set how long to wait before accepting again after an error that isn't
+    /// about a single connection, with `sleep_on_errors` enabled.
+    ///
+    /// Under sustained resource exhaustion, such as running out of file
+    /// descriptors, a longer delay spends less CPU on failing accepts, while
+    /// a shorter one picks up new connections sooner once descriptors are
+    /// freed.
+    ///
+    /// Default is 10ms.
+    pub fn accept_error_sleep(&mut self, dur: Duration) -> &mut Self {
+        self.accept_error_sleep = dur;
+        self
+    }
+
+    /// Let the service decide whether to accept request bodies sent with
+    /// `Expect: 100-continue`.
+    ///
+    /// When enabled, `100 Continue` is only sent once the service first polls
+    /// the body of such a request. By replying with a final response, such as
+    /// `417 Expectation Failed` or `413 Payload Too Large`, without touching
+    /// the body, the service rejects it before it is sent, and the connection
+    /// is closed afterwards instead of reading the body.
+    ///
+    /// Default is false, which sends `100 Continue` as soon as the request
+    /// head has been read.
+    pub fn expect_continue(&mut self, enabled: bool) -> &mut Self {
+        self.expect_continue = enabled;
+        self
+    }
+
+    /// Set a timeout for receiving a complete request head.
+    ///
+    /// The timer starts when a connection begins reading a request head, and
+    /// is canceled once the method, URI and headers have been parsed. If it
+    /// elapses first, the connection is closed with `Error::HeaderTimeout`.
+    /// This guards against clients trickling in headers to hold connections
+    /// open.
+    ///
+    /// Timeouts need a reactor to run on, so this only applies to connections
+    /// served by `Server`, `serve_addr_handle` or `serve_connection_handle`.
+    ///
+    /// Default is `None`.
+    pub fn header_read_timeout(&mut self, dur: Duration) -> &mut Self {
+        self.header_read_timeout = Some(dur);
+        self
+    }
+
+    /// Answer a request head that timed out with a `408 Request Timeout`.
+    ///
+    /// When `header_read_timeout` or `min_head_read_rate` closes a
+    /// connection, and at least the request line was received, a `408`
+    /// response is written before closing, instead of closing silently,
+    /// letting a slow client know why. As for parse errors, the response
+    /// returned by `on_parse_error` for `Error::HeaderTimeout`, if any, is
+    /// sent instead. A connection that hadn't received a full line is still
+    /// just closed.
+    ///
+    /// Default is false.
+    pub fn send_408_on_timeout(&mut self, enabled: bool) -> &mut Self {
+        self.send_408_on_timeout = enabled;
+        self
+    }
+
+    /// Set a minimum rate for receiving a request head.
+    ///
+    /// While a request head is being read, the bytes received are sampled
+    /// every `per`. If fewer than `bytes` arrived during one of these
+    /// windows, the connection is closed with `Error::HeaderTimeout`. Unlike
+    /// `header_read_timeout`, this evicts clients dribbling in a head a few
+    /// bytes at a time however long it is, while a client sending a burst,
+    /// then pausing for less than `per`, isn't penalized.
+    ///
+    /// Like `header_read_timeout`, this needs a reactor `Handle`, and idle
+    /// keep-alive connections aren't affected.
+    ///
+    /// Default is `None`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `per` is zero.
+    pub fn min_head_read_rate(&mut self, bytes: usize, per: Duration) -> &mut Self {
+        assert!(per > Duration::new(0, 0), "min_head_read_rate window must not be zero");
+        self.min_head_read_rate = Some((bytes, per));
+        self
+    }
+
+    /// Set a timeout for making progress receiving a request body.
+    ///
+    /// The timer starts when the service is waiting on more of a `Body` than
+    /// was received, and is reset every time some bytes of it arrive. If it
+    /// elapses first, the `Body` stream yields `Error::BodyTimeout`, and no
+    /// more is read from the connection. It closes once the service has
+    /// responded, such as with a `408 Request Timeout`. This evicts clients stalling in the
+    /// middle of an upload, which neither `header_read_timeout` nor
+    /// `request_timeout` cover.
+    ///
+    /// Like `header_read_timeout`, this needs a reactor `Handle`.
+    ///
+    /// Default is `None`.
+    pub fn body_read_timeout(&mut self, dur: Duration) -> &mut Self {
+        self.body_read_timeout = Some(dur);
+        self
+    }
+
+    /// Set how long an idle keep-alive connection may wait for its next
+    /// request.
+    ///
+    /// The timer starts once a response has completed and the connection is
+    /// waiting for another request head, and is reset when that request
+    /// begins. If it elapses first, the connection is closed. This is
+    /// separate from the TCP keepalive probes enabled by `keep_alive`.
+    ///
+    /// Like `header_read_timeout`, this needs a reactor `Handle`.
+    ///
+    /// Default is `None`.
+    pub fn keep_alive_timeout(&mut self, dur: Duration) -> &mut Self {
+        self.keep_alive_timeout = Some(dur);
+        self
+    }
+
+    /// Set a timeout for making progress writing a response.
+    ///
+    /// The timer starts when a response can't be flushed because the
+    /// client isn't reading it, and is reset every time some bytes are
+    /// written. If it elapses first, the connection is closed with
+    /// `Error::WriteTimeout`. This evicts clients that stop reading while
+    /// a large response is pending, holding its buffers.
+    ///
+    /// Like `header_read_timeout`, this needs a reactor `Handle`.
+    ///
+    /// Default is `None`.
+    pub fn write_timeout(&mut self, dur: Duration) -> &mut Self {
+        self.write_timeout = Some(dur);
+        self
+    }
+
+    /// Coalesce the small writes of response bodies.
+    ///
+    /// Instead of a write to the socket for every chunk produced by a
+    /// service, the bytes of a body are buffered until there are `max_bytes`
+    /// of them, or `max_delay` has elapsed since the first one was buffered,
+    /// whichever comes first. The end of a body is flushed right away. This
+    /// saves syscalls for services streaming many tiny chunks, such as
+    /// tokens or events, while bounding the latency added to each of them.
+    ///
+    /// Unlike `pipeline`, this applies within a single response. The chunks
+    /// of a `text/event-stream` response are never held, as each is an
+    /// event the client waits for.
+    ///
+    /// Like `header_read_timeout`, this needs a reactor `Handle`.
+    ///
+    /// Default is `None`, writing each chunk as soon as possible.
+    pub fn write_coalesce(&mut self, max_delay: Duration, max_bytes: usize) -> &mut Self {
+        self.write_coalesce = Some((max_delay, max_bytes));
+        self
+    }
+
+    /// Set how long a connection may stay open, however busy it is.
+    ///
+    /// The timer starts with the connection. Once it elapses, the response
+    /// in flight, if any, is finished with a `Connection: close` header, and
+    /// the connection is closed, even if the client keeps sending requests.
+    /// An idle connection is closed right away. This is a hard cap rather
+    /// than an idle timer, such as to make clients reconnect periodically.
+    ///
+    /// Like `header_read_timeout`, this needs a reactor `Handle`.
+    ///
+    /// Default is `None`.
+    pub fn max_connection_age(&mut self, dur: Duration) -> &mut Self {
+        self.max_connection_age = Some(dur);
+        self
+    }
+
+    /// Set a deadline for the service to produce each response.
+    ///
+    /// The timer starts once a request head has been read. If the future
+    /// returned by the service hasn't resolved to a response when it
+    /// elapses, the future is dropped, stopping its work, and an empty
+    /// response with the status set by `request_timeout_status` is sent
+    /// instead. The connection is then kept alive or closed as for any
+    /// other response. Once the response is produced, writing its body is
+    /// only limited by `write_timeout`.
+    ///
+    /// Like `header_read_timeout`, this needs a reactor `Handle`.
+    ///
+    /// Default is `None`.
+    pub fn request_timeout(&mut self, dur: Duration) -> &mut Self {
+        self.request_timeout = Some(dur);
+        self
+    }
+
+    /// Set the status of the response sent when `request_timeout` elapses.
+    ///
+    /// Default is `504 Gateway Timeout`.
+    pub fn request_timeout_status(&mut self, status: ::StatusCode) -> &mut Self {
+        self.request_timeout_status = status;
+        self
+    }
+
+    /// Set the `TCP_NODELAY` option on accepted connections.
+    ///
+    /// Enabling this disables Nagle's algorithm, which can reduce latency
+    /// for small responses. Failing to set the option is logged, and does
+    /// not prevent the connection from being served.
+    ///
+    /// Default is to leave the operating system default untouched.
+    pub fn tcp_nodelay(&mut self, enabled: bool) -> &mut Self {
+        self.tcp_nodelay = Some(enabled);
+        self
+    }
+
+    /// Set the `SO_LINGER` option on accepted connections.
+    ///
+    /// With `Some(timeout)`, closing a connection waits up to `timeout` for
+    /// the data still buffered in the socket to be sent. A zero timeout,
+    /// `Some(Duration::from_secs(0))`, drops that data instead, and resets
+    /// the connection with an RST rather than going through `TIME_WAIT`.
+    /// `None` turns lingering off, which closes in the background.
+    ///
+    /// The timeout is in whole seconds, and how the wait behaves depends on
+    /// the platform: on Unix the close of the socket may block for up to
+    /// `timeout`, blocking the reactor of the connection, while Windows
+    /// doesn't block a non-blocking socket. Failing to set the option is
+    /// logged, and does not prevent the connection from being served.
+    ///
+    /// Default is to leave the operating system default untouched.
+    pub fn tcp_linger(&mut self, linger: Option<Duration>) -> &mut Self {
+        self.tcp_linger = Some(linger);
+        self
+    }
+
+    /// Set the `SO_RCVBUF` option, the size of the receive buffer, on
+    /// accepted connections.
+    ///
+    /// A larger buffer lets a client send more before waiting on the server,
+    /// which helps uploads over links with a high bandwidth-delay product.
+    /// The operating system may adjust the size, such as Linux doubling it
+    /// and capping it to `net.core.rmem_max`. Failing to set the option is
+    /// logged, and does not prevent the connection from being served.
+    ///
+    /// Default is to leave the operating system default untouched.
+    pub fn tcp_recv_buffer_size(&mut self, size: usize) -> &mut Self {
+        self.tcp_recv_buffer_size = Some(size);
+        self
+    }
+
+    /// Set the `SO_SNDBUF` option, the size of the send buffer, on accepted
+    /// connections.
+    ///
+    /// Like `tcp_recv_buffer_size`, for the responses sent to clients.
+    ///
+    /// Default is to leave the operating system default untouched.
+    pub fn tcp_send_buffer_size(&mut self, size: usize) -> &mut Self {
+        self.tcp_send_buffer_size = Some(size);
+        self
+    }
+
+    /// Set a callback called with every connection accepted from a TCP
+    /// listener bound by this `Http`, before it is served.
+    ///
+    /// This can be used to observe connections, or on Unix to tune the
+    /// socket further through its raw file descriptor. The callback is called on
+    /// the thread that will serve the connection.
+    ///
+    /// Default is to not call anything.
+    pub fn on_connection<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(&AddrStream) + Send + Sync + 'static,
+    {
+        self.on_connection = Some(OnConnection(Arc::new(f)));
+        self
+    }
+
+    /// Set a callback deciding whether to serve a connection accepted from a
+    /// TCP listener bound by this `Http`, from the address of its remote
+    /// peer.
+    ///
+    /// The callback is called right after the connection is accepted. If it
+    /// returns `false`, the socket is closed at once, before any byte is
+    /// read or a service is created, and the next connection is accepted.
+    /// This is a cheap way to enforce an allowlist or a denylist of
+    /// addresses. A rejected connection isn't passed to `on_connection`.
+    ///
+    /// Default is to serve every connection.
+    pub fn accept_filter<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(&SocketAddr) -> bool + Send + Sync + 'static,
+    {
+        self.accept_filter = Some(AcceptFilter(Arc::new(f)));
+        self
+    }
+
+    /// Set a callback deciding what to do when a TCP listener bound by this
+    /// `Http` fails to accept a connection.
+    ///
+    /// After running out of resources, with errors such as "too many open
+    /// files" (`EMFILE`), accepting again is likely to work a bit later, so
+    /// `AcceptAction::RetryAfter` usually suits them.
+    ///
+    /// Takes precedence over `sleep_on_errors`. Default is to behave as
+    /// configured there.
+    pub fn on_accept_error<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(&io::Error) -> AcceptAction + Send + Sync + 'static,
+    {
+        self.on_accept_error = Some(OnAcceptError(Arc::new(f)));
+        self
+    }
+
+    /// Set a callback called each time a connection served by this `Http`
+    /// completes a request and response exchange.
+    ///
+    /// The `ExchangeInfo` reports whether the connection is kept alive for
+    /// another request, as decided from the HTTP versions, the `Connection`
+    /// headers, and `keep_alive`. The callback is called on the thread
+    /// serving the connection, once both messages are done. An exchange cut
+    /// short by an error may not be reported.
+    ///
+    /// Default is to not call anything.
+    pub fn on_exchange_complete<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(ExchangeInfo) + Send + Sync + 'static,
+    {
+        self.on_exchange_complete = Some(OnExchangeComplete(Arc::new(f)));
+        self
+    }
+
+    /// Set a callback called each time the write buffer of a connection
+    /// served by this `Http` drains to the socket.
+    ///
+    /// A connection buffers what it writes, such as a response head or the
+    /// chunks of a streaming body, and flushes it to the socket as the
+    /// socket accepts it. Once all of it is flushed, the callback is called
+    /// on the thread serving the connection, so the last chunk yielded by a
+    /// body has then been handed to the socket, though not necessarily
+    /// received by the client yet. A service pacing a download can wait for
+    /// it before yielding the next chunk.
+    ///
+    /// Flushing happens after each chunk is buffered, but a body yielding
+    /// chunks faster than the socket accepts them keeps the buffer from
+    /// draining, up to `body_write_buffer_limit`, after which the body
+    /// isn't polled until the buffer is below the limit. With a limit of a
+    /// single byte, each chunk is drained before the next one is polled.
+    ///
+    /// Default is to not call anything.
+    pub fn on_write_drained<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(WriteDrained) + Send + Sync + 'static,
+    {
+        self.on_write_drained = Some(OnWriteDrained(Arc::new(f)));
+        self
+    }
+
+    /// Set a callback called with the timing of each request and response
+    /// exchange of the connections served by this `Http`.
+    ///
+    /// The `TimingInfo` tells when the request head finished parsing, when
+    /// the request was passed to the service, and when the last byte of the
+    /// response was written. It is reported once that write completes, on
+    /// the thread serving the connection. Responses sent without calling the
+    /// service, such as for a malformed request, are not reported.
+    ///
+    /// No timestamps are taken while no callback is set. Default is to not
+    /// call anything.
+    pub fn on_exchange_timing<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(TimingInfo) + Send + Sync + 'static,
+    {
+        self.on_exchange_timing = Some(OnExchangeTiming(Arc::new(f)));
+        self
+    }
+
+    /// Set a callback called with the bytes transferred over each connection
+    /// served by this `Http`, once it completes.
+    ///
+    /// The counts are of the bytes as received and sent, including message
+    /// heads and the framing of chunked bodies. The callback is called on
+    /// the thread serving the connection, when its `Connection` resolves or
+    /// fails. A connection dropped before that is not reported, nor one
+    /// served with the deprecated `bind_connection`.
+    ///
+    /// Default is to not call anything.
+    pub fn on_connection_close<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(ConnectionBytes) + Send + Sync + 'static,
+    {
+        self.on_connection_close = Some(OnConnectionClose(Arc::new(f)));
+        self
+    }
+
+    /// Set a callback called when a request head received by a connection
+    /// can't be parsed.
+    ///
+    /// The callback gets the parse error, such as `Error::Method` or
+    /// `Error::Header`, and the address of the remote peer if it is known.
+    /// A client speaking HTTP/2 right away, which this server doesn't,
+    /// is told apart with `Error::Http2Preface`, answered by default with a
+    /// `505 HTTP Version Not Supported`.
+    /// It is called on the thread serving the connection, before the
+    /// connection closes. Since the malformed request never reaches a
+    /// `Service`, this is the place to log or count such requests.
+    ///
+    /// If the callback returns a `Response`, it is sent instead of the
+    /// automatic `400 Bad Request` (or `431`) response. Its body is sent
+    /// with a `Content-Length`. The response is ignored if part of a
+    /// response was already written on the connection.
+    ///
+    /// Default is to not call anything, sending the automatic responses.
+    pub fn on_parse_error<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(&::Error, Option<SocketAddr>) -> Option<Response<::Chunk>> + Send + Sync + 'static,
+    {
+        self.on_parse_error = Some(OnParseError(Arc::new(f)));
+        self
+    }
+
+    /// Set a callback returning the response sent when the future of a
+    /// `Service` fails.
+    ///
+    /// The callback gets the error of the service, wrapped in
+    /// `Error::User`, and is called on the thread serving the connection.
+    /// Its response, such as an error page or a JSON error, is sent with a
+    /// `Content-Length`, and the connection is then kept alive or closed as
+    /// for any other response.
+    ///
+    /// Default is to send no response, closing the connection with the
+    /// error of the service.
+    pub fn internal_error_response<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(&::Error) -> Response<::Chunk> + Send + Sync + 'static,
+    {
+        self.internal_error_response = Some(InternalErrorResponse(Arc::new(f)));
+        self
+    }
+
+    /// Set the backlog of pending connections for listeners bound by `bind`.
+    ///
+    /// This is passed to the `listen` call of every listener created for
+    /// the server, including those of each thread in `run_threads`. Binding
+    /// fails with an `io::Error` if the value is not positive.
+    ///
+    /// Default is 1024.
+    pub fn listen_backlog(&mut self, backlog: i32) -> &mut Self {
+        self.listen_backlog = backlog;
+        self
+    }
+
+    /// Enables or disables address reuse for listeners bound by `bind`.
+    ///
+    /// When enabled, `SO_REUSEADDR` is set on the listener, and on Unix also
+    /// `SO_REUSEPORT`, so that `run_threads` can bind a listener per thread.
+    /// When disabled, neither is set, and binding fails if the address is
+    /// already in use. `run_threads` then shares the single listener between
+    /// all threads, as for `bind_listener`.
+    ///
+    /// Default is true.
+    pub fn reuse_address(&mut self, enabled: bool) -> &mut Self {
+        self.reuse_address = enabled;
+        self
+    }
+
+    /// Set the maximum number of connections a `Server` serves at once.
+    ///
+    /// Once the limit is reached, no further connections are accepted from
+    /// the listener until an active one closes. Pending connections wait in
+    /// the listen backlog meanwhile. With `run_threads`, the limit applies to
+    /// each thread.
+    ///
+    /// Default is no limit.
+    pub fn max_connections(&mut self, max: usize) -> &mut Self {
+        self.max_connections = Some(max);
+        self
+    }
+
+    /// Set the maximum number of connections a `Server` serves at once from
+    /// a single remote IP address.
+    ///
+    /// Unlike `max_connections`, a connection over the limit is accepted and
+    /// immediately closed, so that other clients are still served. A slot is
+    /// freed once one of the connections of that address closes. With
+    /// `run_threads`, the limit applies to each thread.
+    ///
+    /// Default is no limit.
+    pub fn max_connections_per_ip(&mut self, max: usize) -> &mut Self {
+        self.max_connections_per_ip = Some(max);
+        self
+    }
+
+    /// Set the maximum number of requests being served at once, over all
+    /// the connections.
+    ///
+    /// Keep-alive connections each have at most one request in flight, but
+    /// many connections can still overwhelm a resource the service shares
+    /// between them, such as a pool of database connections. Once `max`
+    /// requests are in flight, a request read from any connection waits
+    /// before being passed to the service, and nothing more is read from its
+    /// connection, until one of them gets its response. A request counts
+    /// until the service responds, not while the body of the response is
+    /// written.
+    ///
+    /// The limit is shared by every `Server` and connection served with this
+    /// `Http` or its clones, including the threads of `run_threads`. The
+    /// requests in flight can be observed with `Server::request_counter`.
+    ///
+    /// Default is no limit.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max` is zero.
+    pub fn max_concurrent_requests(&mut self, max: usize) -> &mut Self {
+        assert!(max > 0, "max_concurrent_requests must be at least 1");
+        self.request_limit = Some(RequestLimit::new(max));
+        self
+    }
+
+    /// Set the response sent to requests over `max_concurrent_requests` or
+    /// `max_connections`, instead of making them wait.
+    ///
+    /// Once `max_concurrent_requests` are in flight, a request read from any
+    /// connection gets this response right away, and its connection is then
+    /// closed. Once `max_connections` are being served, new connections are
+    /// still accepted rather than left in the listen backlog, and the first
+    /// request of each gets this response before it is closed. Clients so
+    /// get a clear signal, such as a `503 Service Unavailable` with a
+    /// `Retry-After`, rather than a timeout.
+    ///
+    /// Those connections don't count toward `max_connections`, but at most
+    /// as many of them are open at once, accepting pausing beyond that as it
+    /// does without this response. They count toward
+    /// `max_connections_per_ip`. One that doesn't send its request head
+    /// within a second is closed without the response.
+    ///
+    /// The service isn't called for those requests. The response is sent
+    /// with a `Content-Length` and `Connection: close`.
+    ///
+    /// Default is `None`, waiting for the limits.
+    pub fn overload_response(&mut self, res: Option<Response<::Chunk>>) -> &mut Self {
+        self.overload_response = res.map(OverloadResponse::new);
+        self
+    }
+
+    /// Set the maximum number of connections a `Server` accepts per second.
+    ///
+    /// Accepting takes a token from a bucket holding up to `per_second`
+    /// tokens, which refills with one token every `1 / per_second` seconds.
+    /// A storm of new connections is so accepted right away up to the rate,
+    /// and then at the rate, the pending connections waiting in the listen
+    /// backlog. This composes with `max_connections`, which caps how many
+    /// are served at once. With `run_threads`, the rate applies to
+    /// each thread binding its own listener.
+    ///
+    /// Default is no limit.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `per_second` is 0.
+    pub fn max_accept_rate(&mut self, per_second: u32) -> &mut Self {
+        assert!(per_second > 0, "max_accept_rate must be at least 1");
+        self.max_accept_rate = Some(per_second);
+        self
+    }
+
+    /// Set how many connections a `Server` accepts at most each time its
+    /// listener is polled.
+    ///
+    /// With a burst over 1, `accept()` is called again until it would block
+    /// or `max` connections were accepted, and the extra connections are
+    /// yielded by the next polls, without another round-trip through the
+    /// reactor. This raises the accept throughput under very high
+    /// connection rates. Pausing the listener, or `max_accept_rate`, still
+    /// stops a burst.
+    ///
+    /// Default is 1.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max` is 0.
+    pub fn accept_burst(&mut self, max: usize) -> &mut Self {
+        assert!(max > 0, "accept_burst must be at least 1");
+        self.accept_burst = max;
+        self
+    }
+
+    /// Set whether connections accepted by a `Server` start with a PROXY
+    /// protocol header.
+    ///
+    /// Load balancers such as HAProxy or an AWS NLB can send this header,
+    /// version 1 (text) or 2 (binary), to announce the address of the client
+    /// they proxy. If enabled, the header is read before any HTTP, and the
+    /// address it announces becomes the remote address of the requests. A
+    /// header without an address, such as from a health check of the load
+    /// balancer, keeps the address of the TCP peer. A connection without a
+    /// valid header is closed. When set, `header_read_timeout` also limits
+    /// the time to receive the header.
+    ///
+    /// Limits such as `max_connections_per_ip` still apply to the TCP peer.
+    ///
+    /// Default is false.
+    pub fn proxy_protocol(&mut self, enabled: bool) -> &mut Self {
+        self.proxy_protocol = enabled;
+        self
+    }
+
+    /// Use the `Forwarded` or `X-Forwarded-For` header of requests sent by
+    /// the proxies in `trusted` as their remote address.
+    ///
+    /// When the peer of a connection is in one of the `trusted` ranges, the
+    /// addresses of the header are walked from the right, skipping those of
+    /// trusted proxies, and the first other one becomes the remote address
+    /// of the request. `Forwarded` (RFC 7239) is preferred when a request
+    /// has both. An address without a port, as in `X-Forwarded-For`, gets
+    /// the port 0. The header of an untrusted peer, which a client could
+    /// spoof, is ignored, and so is a malformed header, leaving the address
+    /// of the peer. This composes with `proxy_protocol`, whose announced
+    /// address is then the peer.
+    ///
+    /// Limits such as `max_connections_per_ip` still apply to the TCP peer.
+    ///
+    /// Default is no trusted proxy.
+    pub fn trust_forwarded_for(&mut self, trusted: Vec<IpNet>) -> &mut Self {
+        self.trusted_proxies = trusted;
+        self
+    }
+
+    /// Bind the provided `addr` and return a server ready to handle
+    /// connections.
+    ///
+    /// This method will bind the `addr` provided with a new TCP listener ready
+    /// to accept connections. Each connection will be processed with the
+    /// `new_service` object provided as well, creating a new service per
+    /// connection.
+    ///
+    /// The returned `Server` contains one method, `run`, which is used to
+    /// actually run the server.
+    pub fn bind<S, Bd>(&self, addr: &SocketAddr, new_service: S) -> ::Result<Server<S, Bd>>
+        where S: NewService<Request = Request, Response = Response<Bd>, Error = ::Error> + 'static,
+              Bd: Stream<Item=B, Error=::Error>,
+    {
+        let core = try!(Core::new());
+        let handle = core.handle();
+        let listener = try!(thread_listener(addr, &handle, self.listen_backlog, self.reuse_address));
+
+        Ok(Server {
+            new_service: new_service,
+            reactor: core,
+            listeners: vec![listener],
+            protocol: self.clone(),
+            shutdown_timeout: Duration::new(1, 0),
+            connections: ConnectionCounter::default(),
+            accept_control: AcceptControl::default(),
+            accept_stats: AcceptStats::default(),
+            reuse_port: self.reuse_address,
+        })
+    }
+
+    /// Bind each of the provided `addrs` and return a single server ready
+    /// to handle the connections of all of them.
+    ///
+    /// This is like `bind`, for several addresses, such as both an IPv4 and
+    /// an IPv6 address, or several ports. The connections of all the
+    /// listeners are served by the same `new_service`, and counted together
+    /// by `connection_counter`, `accept_control` and `accept_stats`. A
+    /// graceful shutdown stops accepting on every listener, then drains the
+    /// connections of all of them.
+    ///
+    /// With `run_threads` or `run_pool`, every thread accepts from all the
+    /// addresses.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `addrs` is empty.
+    pub fn bind_multi<S, Bd>(&self, addrs: &[SocketAddr], new_service: S) -> ::Result<Server<S, Bd>>
+        where S: NewService<Request = Request, Response = Response<Bd>, Error = ::Error> + 'static,
+              Bd: Stream<Item=B, Error=::Error>,
+    {
+        assert!(!addrs.is_empty(), "bind_multi needs at least one address");
+        let core = try!(Core::new());
+        let handle = core.handle();
+        let listeners = try!(addrs.iter()
+            .map(|addr| thread_listener(addr, &handle, self.listen_backlog, self.reuse_address))
+            .collect::<io::Result<Vec<_>>>());
+
+        Ok(Server {
+            new_service: new_service,
+            reactor: core,
+            listeners: listeners,
+            protocol: self.clone(),
+            shutdown_timeout: Duration::new(1, 0),
+            connections: ConnectionCounter::default(),
+            accept_control: AcceptControl::default(),
+            accept_stats: AcceptStats::default(),
+            reuse_port: self.reuse_address,
+        })
+    }
+
+    /// Return a server ready to handle the connections of an already bound
+    /// `listener`.
+    ///
+    /// This is like `bind`, except that the listener is used as is, without
+    /// changing any of its socket options. This allows serving a listener
+    /// inherited from the environment, such as with systemd socket activation.
+    ///
+    /// Since no other listener can be bound to the same address, `run_threads`
+    /// shares this listener between all threads.
+    pub fn bind_listener<S, Bd>(&self, listener: StdTcpListener, new_service: S) -> ::Result<Server<S, Bd>>
+        where S: NewService<Request = Request, Response = Response<Bd>, Error = ::Error> + 'static,
+              Bd: Stream<Item=B, Error=::Error>,
+    {
+        let core = try!(Core::new());
+        let addr = try!(listener.local_addr());
+        let listener = try!(TcpListener::from_listener(listener, &addr, &core.handle()));
+
+        Ok(Server {
+            new_service: new_service,
+            reactor: core,
+            listeners: vec![listener],
+            protocol: self.clone(),
+            shutdown_timeout: Duration::new(1, 0),
+            connections: ConnectionCounter::default(),
+            accept_control: AcceptControl::default(),
+            accept_stats: AcceptStats::default(),
+            reuse_port: false,
+        })
+    }
+
+
+    /// Bind a `NewService` using types from the `http` crate.
+    ///
+    /// See `Http::bind`.
+    #[cfg(feature = "compat")]
+    pub fn bind_compat<S, Bd>(&self, addr: &SocketAddr, new_service: S) -> ::Result<Server<compat::NewCompatService<S>, Bd>>
+        where S: NewService<Request = http::Request<Body>, Response = http::Response<Bd>, Error = ::Error> +
+                    Send + Sync + 'static,
+              Bd: Stream<Item=B, Error=::Error>,
+    {
+        self.bind(addr, self::compat::new_service(new_service))
+    }
+
+    /// Bind the provided `addr` and return a server with a shared `Core`.
+    ///
+    /// This method allows the ability to share a `Core` with multiple servers.
+    ///
+    /// This is method will bind the `addr` provided with a new TCP listener ready
+    /// to accept connections. Each connection will be processed with the
+    /// `new_service` object provided as well, creating a new service per
+    /// connection.
+    /// Bind the provided `addr` and return a server to serve on the `Core`
+    /// of `handle`.
+    ///
+    /// Unlike `serve_addr_handle`, the returned `HandleServer` shuts down
+    /// gracefully and counts its connections like a `Server`, but is served
+    /// by a future the caller drives, so a `Core` already running other tasks
+    /// can also serve HTTP without another thread.
+    pub fn bind_handle<S, Bd>(&self, addr: &SocketAddr, handle: &Handle, new_service: S) -> ::Result<HandleServer<S, Bd>>
+        where S: NewService<Request = Request, Response = Response<Bd>, Error = ::Error> + 'static,
+              Bd: Stream<Item=B, Error=::Error>,
+    {
+        let listener = try!(thread_listener(addr, handle, self.listen_backlog, self.reuse_address));
+        let incoming = try!(self.addr_incoming(listener, handle.clone()));
+
+        Ok(HandleServer {
+            protocol: self.clone(),
+            new_service: new_service,
+            handle: handle.clone(),
+            incoming: incoming,
+            shutdown_timeout: Duration::new(1, 0),
+            connections: ConnectionCounter::default(),
+        })
+    }
+
+    pub fn serve_addr_handle<S, Bd>(&self, addr: &SocketAddr, handle: &Handle, new_service: S) -> ::Result<Serve<AddrIncoming, S>>
+        where S: NewService<Request = Request, Response = Response<Bd>, Error = ::Error>,
+              Bd: Stream<Item=B, Error=::Error>,
+    {
+        let listener = TcpListener::bind(addr, &handle)?;
+        let incoming = self.addr_incoming(listener, handle.clone())?;
+        let mut serve = self.serve_incoming(incoming, new_service);
+        serve.handle = Some(handle.clone());
+        Ok(serve)
+    }
+
+    /// Serve the connections of an already bound `listener`, with a shared
+    /// `Core`.
+    ///
+    /// This is like `serve_addr_handle`, except that the listener is used as
+    /// is. See `Http::bind_listener`.
+    pub fn serve_listener_handle<S, Bd>(&self, listener: StdTcpListener, handle: &Handle, new_service: S) -> ::Result<Serve<AddrIncoming, S>>
+        where S: NewService<Request = Request, Response = Response<Bd>, Error = ::Error>,
+              Bd: Stream<Item=B, Error=::Error>,
+    {
+        let addr = listener.local_addr()?;
+        let listener = TcpListener::from_listener(listener, &addr, handle)?;
+        let incoming = self.addr_incoming(listener, handle.clone())?;
+        let mut serve = self.serve_incoming(incoming, new_service);
+        serve.handle = Some(handle.clone());
+        Ok(serve)
+    }
+
+    /// Bind the provided stream of incoming IO objects with a `NewService`.
+    ///
+    /// This method allows the ability to share a `Core` with multiple servers.
+    pub fn serve_incoming<I, S, Bd>(&self, incoming: I, new_service: S) -> Serve<I, S>
+        where I: Stream<Error=::std::io::Error>,
+              I::Item: AsyncRead + AsyncWrite,
+              S: NewService<Request = Request, Response = Response<Bd>, Error = ::Error>,
+              Bd: Stream<Item=B, Error=::Error>,
+    {
+        self.serve(incoming, new_service)
+    }
+
+    fn serve<I, S>(&self, incoming: I, new_service: S) -> Serve<I, S> {
+        Serve {
+            incoming: incoming,
+            new_service: new_service,
+            protocol: Http {
+                keep_alive: self.keep_alive,
+                http10_keep_alive: self.http10_keep_alive,
+                max_buf_size: self.max_buf_size,
+                initial_read_buf_size: self.initial_read_buf_size,
+                body_write_buffer_limit: self.body_write_buffer_limit,
+                max_pipeline_depth: self.max_pipeline_depth,
+                pipeline_fairness_limit: self.pipeline_fairness_limit,
+                max_requests_per_connection: self.max_requests_per_connection,
+                max_body_size: self.max_body_size,
+                auto_drain_body: self.auto_drain_body,
+                max_drain_bytes: self.max_drain_bytes,
+                reject_conflicting_framing: self.reject_conflicting_framing,
+                require_host_header: self.require_host_header,
+                send_408_on_timeout: self.send_408_on_timeout,
+                max_headers: self.max_headers,
+                max_header_list_size: self.max_header_list_size,
+                max_uri_length: self.max_uri_length,
+                auto_date: self.auto_date,
+                date_clock: self.date_clock.clone(),
+                server_header: self.server_header.clone(),
+                echo_headers: self.echo_headers.clone(),
+                strip_response_headers: self.strip_response_headers.clone(),
+                strip_hop_by_hop_headers: self.strip_hop_by_hop_headers,
+                trusted_proxies: self.trusted_proxies.clone(),
+                request_ids: self.request_ids,
+                informational_responses: self.informational_responses,
+                pipeline: self.pipeline,
+                sleep_on_errors: self.sleep_on_errors,
+                accept_error_sleep: self.accept_error_sleep,
+                expect_continue: self.expect_continue,
+                header_read_timeout: self.header_read_timeout,
+                min_head_read_rate: self.min_head_read_rate,
+                body_read_timeout: self.body_read_timeout,
+                keep_alive_timeout: self.keep_alive_timeout,
+                write_timeout: self.write_timeout,
+                write_coalesce: self.write_coalesce,
+                max_connection_age: self.max_connection_age,
+                request_timeout: self.request_timeout,
+                request_timeout_status: self.request_timeout_status,
+                listen_backlog: self.listen_backlog,
+                reuse_address: self.reuse_address,
+                tcp_nodelay: self.tcp_nodelay,
+                tcp_linger: self.tcp_linger,
+                tcp_recv_buffer_size: self.tcp_recv_buffer_size,
+                tcp_send_buffer_size: self.tcp_send_buffer_size,
+                on_connection: self.on_connection.clone(),
+                accept_filter: self.accept_filter.clone(),
+                on_accept_error: self.on_accept_error.clone(),
+                on_exchange_complete: self.on_exchange_complete.clone(),
+                on_write_drained: self.on_write_drained.clone(),
+                on_exchange_timing: self.on_exchange_timing.clone(),
+                on_connection_close: self.on_connection_close.clone(),
+                on_parse_error: self.on_parse_error.clone(),
+                internal_error_response: self.internal_error_response.clone(),
+                max_connections: self.max_connections,
+                max_connections_per_ip: self.max_connections_per_ip,
+                request_limit: self.request_limit.clone(),
+                overload_response: self.overload_response.clone(),
+                max_accept_rate: self.max_accept_rate,
+                accept_burst: self.accept_burst,
+                proxy_protocol: self.proxy_protocol,
+                _marker: PhantomData,
+            },
+            handle: None,
+        }
+    }
+
+    /// Bind the provided stream of incoming IO objects with a `NewService`,
+    /// using `info` to describe each connection.
+    ///
+    /// Unlike `serve_incoming`, the IO objects don't have to implement
+    /// `ConnectionInfo`. Instead, `info` is called with each of them before
+    /// it is served, and the details it returns are injected into the
+    /// requests read from that connection. Returning an `Option<SocketAddr>`
+    /// only sets the remote address, which makes it easy to serve IO objects
+    /// without one, like in-memory pipes.
+    pub fn serve_incoming_with<I, S, F, M, Bd>(&self, incoming: I, new_service: S, info: F) -> ServeWith<I, S, F>
+        where I: Stream<Error=::std::io::Error>,
+              I::Item: AsyncRead + AsyncWrite,
+              S: NewService<Request = Request, Response = Response<Bd>, Error = ::Error>,
+              F: Fn(&I::Item) -> M,
+              M: ConnectionInfo,
+              Bd: Stream<Item=B, Error=::Error>,
+    {
+        ServeWith {
+            serve: self.serve_incoming(incoming, new_service),
+            info: info,
+        }
+    }
+
+    /// Bind the provided stream of incoming IO objects, each paired with the
+    /// address of its remote peer, with a `NewService`.
+    ///
+    /// This suits IO objects whose remote address is known out-of-band, such
+    /// as connections handed off by a proxy announcing the address of the
+    /// original client. The address is injected into the requests read from
+    /// each connection, and given to `HasRemoteAddr::remote_addr`, without the
+    /// IO objects having to implement `ConnectionInfo`.
+    pub fn serve_incoming_addr<I, T, S, Bd>(&self, incoming: I, new_service: S) -> ServeAddr<I, S>
+        where I: Stream<Item=(T, SocketAddr), Error=::std::io::Error>,
+              T: AsyncRead + AsyncWrite,
+              S: NewService<Request = Request, Response = Response<Bd>, Error = ::Error>,
+              Bd: Stream<Item=B, Error=::Error>,
+    {
+        ServeAddr {
+            serve: self.serve(incoming, new_service),
+        }
+    }
+
+    /// Bind a connection together with a Service.
+    ///
+    /// This returns a Future that must be polled in order for HTTP to be
+    /// driven on the connection.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate hyper;
+    /// # extern crate tokio_core;
+    /// # extern crate tokio_io;
+    /// # use futures::Future;
+    /// # use hyper::server::{Http, Request, Response, Service};
+    /// # use tokio_io::{AsyncRead, AsyncWrite};
+    /// # use tokio_core::reactor::Handle;
+    /// # fn run<I, S>(some_io: I, some_service: S, some_handle: &Handle)
+    /// # where
+    /// #     I: AsyncRead + AsyncWrite + 'static,
+    /// #     S: Service<Request=Request, Response=Response, Error=hyper::Error> + 'static,
+    /// # {
+    /// let http = Http::<hyper::Chunk>::new();
+    /// let conn = http.serve_connection(some_io, some_service);
+    ///
+    /// let fut = conn
+    ///     .map(|_| ())
+    ///     .map_err(|e| eprintln!("server connection error: {}", e));
+    ///
+    /// some_handle.spawn(fut);
+    /// # }
+    /// # fn main() {}
+    /// ```
+    pub fn serve_connection<S, I, Bd>(&self, io: I, service: S) -> Connection<I, S>
+        where S: Service<Request = Request, Response = Response<Bd>, Error = ::Error>,
+              Bd: Stream<Error=::Error>,
+              Bd::Item: AsRef<[u8]>,
+              I: AsyncRead + AsyncWrite,
+    {
+        self.new_connection(io, service, None)
+    }
+
+    /// Bind a connection together with a Service, using `handle` to drive
+    /// any configured timeouts.
+    ///
+    /// This is the same as `serve_connection`, except that options such as
+    /// `header_read_timeout` will be enforced on the connection.
+    pub fn serve_connection_handle<S, I, Bd>(&self, io: I, handle: &Handle, service: S) -> Connection<I, S>
+        where S: Service<Request = Request, Response = Response<Bd>, Error = ::Error>,
+              Bd: Stream<Error=::Error>,
+              Bd::Item: AsRef<[u8]>,
+              I: AsyncRead + AsyncWrite,
+    {
+        self.new_connection(io, service, Some(handle))
+    }
+
+    fn new_connection<S, I, Bd>(&self, io: I, service: S, handle: Option<&Handle>) -> Connection<I, S>
+        where S: Service<Request = Request, Response = Response<Bd>, Error = ::Error>,
+              Bd: Stream<Error=::Error>,
+              Bd::Item: AsRef<[u8]>,
+              I: AsyncRead + AsyncWrite,
+    {
+        let mut conn = proto::Conn::new(io);
+        if !self.keep_alive {
+            conn.disable_keep_alive();
+        }
+        conn.set_http10_keep_alive(self.http10_keep_alive);
+        conn.set_flush_pipeline(self.pipeline);
+        conn.set_max_pipeline_depth(self.max_pipeline_depth);
+        conn.set_max_requests(self.max_requests_per_connection.map(|max| max as u64));
+        conn.set_defer_continue(self.expect_continue);
+        conn.set_max_body_size(self.max_body_size.map(|max| max as u64));
+        conn.set_auto_drain_body(self.auto_drain_body);
+        conn.set_max_drain_bytes(self.max_drain_bytes.map(|max| max as u64));
+        conn.set_reject_conflicting_framing(self.reject_conflicting_framing);
+        conn.set_require_host(self.require_host_header);
+        conn.set_send_408_on_timeout(self.send_408_on_timeout);
+        conn.set_max_headers(self.max_headers);
+        conn.set_max_head_size(Some(self.max_header_list_size));
+        conn.set_max_uri_len(Some(self.max_uri_length));
+        conn.set_auto_date(self.auto_date);
+        if let Some(ref clock) = self.date_clock {
+            conn.set_date_clock(clock.0.clone());
+        }
+        conn.set_server_header(self.server_header.clone());
+        if let Some(max) = self.max_buf_size {
+            conn.set_max_buf_size(max);
+        }
+        if let Some(size) = self.initial_read_buf_size {
+            conn.set_read_buf_init(size);
+        }
+        conn.set_write_buf_limit(self.body_write_buffer_limit);
+        if let Some(ref f) = self.on_exchange_complete {
+            conn.set_on_exchange_complete(f.0.clone());
+        }
+        if let Some(ref f) = self.on_write_drained {
+            conn.set_on_write_drained(f.0.clone());
+        }
+        if let Some(ref f) = self.on_exchange_timing {
+            conn.set_on_exchange_timing(f.0.clone());
+        }
+        if let Some(ref f) = self.on_parse_error {
+            conn.set_on_parse_error(f.0.clone());
+        }
+        if let Some(ref f) = self.internal_error_response {
+            conn.set_internal_error_response(f.0.clone());
+        }
+        let mut dispatch = proto::dispatch::Server::new(service);
+        dispatch.set_echo_headers(self.echo_headers.clone());
+        dispatch.set_strip_headers(self.strip_response_headers.clone());
+        dispatch.set_strip_hop_by_hop(self.strip_hop_by_hop_headers);
+        dispatch.set_trusted_proxies(self.trusted_proxies.clone());
+        if let Some(ref limit) = self.request_limit {
+            dispatch.set_request_limit(limit.clone());
+        }
+        if let Some(ref res) = self.overload_response {
+            dispatch.set_overload_response(res.clone());
+        }
+        if self.request_ids {
+            dispatch.set_request_ids();
+        }
+        if self.informational_responses {
+            dispatch.set_informational_responses();
+        }
+        if let Some(handle) = handle {
+            conn.set_timer(handle.remote().clone());
+            if let Some(dur) = self.request_timeout {
+                dispatch.set_request_timeout(dur, self.request_timeout_status, handle.remote().clone());
+            }
+            if let Some(dur) = self.header_read_timeout {
+                conn.set_header_read_timeout(dur);
+            }
+            if let Some((bytes, per)) = self.min_head_read_rate {
+                conn.set_min_head_read_rate(bytes, per);
+            }
+            if let Some(dur) = self.body_read_timeout {
+                conn.set_body_read_timeout(dur);
+            }
+            if let Some(dur) = self.keep_alive_timeout {
+                conn.set_keep_alive_timeout(dur);
+            }
+            if let Some(dur) = self.write_timeout {
+                conn.set_write_timeout(dur);
+            }
+            if let Some((max_delay, max_bytes)) = self.write_coalesce {
+                conn.set_write_coalesce(max_delay, max_bytes);
+            }
+            if let Some(dur) = self.max_connection_age {
+                conn.set_max_age(dur);
+            }
+        }
+        let mut conn = proto::dispatch::Dispatcher::new(dispatch, conn);
+        conn.set_pipeline_fairness_limit(self.pipeline_fairness_limit.map(|limit| limit as u64));
+        Connection {
+            conn: conn,
+            remote_addr: None,
+            on_close: self.on_connection_close.clone(),
+            shutting_down: false,
+        }
+    }
+}
+
+
+
+impl<B> Http<B> {
+    fn addr_incoming(&self, listener: TcpListener, handle: Handle) -> io::Result<AddrIncoming> {
+        let mut incoming = AddrIncoming::new(listener, handle, self.sleep_on_errors)?;
+        if self.keep_alive {
+            incoming.set_keepalive(Some(Duration::from_secs(90)));
+        }
+        if let Some(enabled) = self.tcp_nodelay {
+            incoming.set_nodelay(enabled);
+        }
+        if let Some(linger) = self.tcp_linger {
+            incoming.set_linger(linger);
+        }
+        incoming.recv_buffer_size = self.tcp_recv_buffer_size;
+        incoming.send_buffer_size = self.tcp_send_buffer_size;
+        incoming.error_sleep = self.accept_error_sleep;
+        if let Some(per_second) = self.max_accept_rate {
+            incoming.set_max_accept_rate(per_second);
+        }
+        incoming.accept_burst = self.accept_burst;
+        incoming.on_connection = self.on_connection.clone();
+        incoming.accept_filter = self.accept_filter.clone();
+        incoming.on_accept_error = self.on_accept_error.clone();
+        Ok(incoming)
+    }
+
+    // The accept loop of all the `listeners` of a `Server`, sharing its
+    // `accept_control` and `stats`.
+    fn server_incoming(&self,
+                       listeners: Vec<TcpListener>,
+                       handle: Handle,
+                       accept_control: AcceptControl,
+                       stats: AcceptStats) -> io::Result<ServerIncoming> {
+        let incomings = listeners.into_iter().map(|listener| {
+            let mut incoming = self.addr_incoming(listener, handle.clone())?;
+            incoming.accept_control = accept_control.clone();
+            incoming.stats = stats.clone();
+            Ok(incoming)
+        }).collect::<io::Result<Vec<_>>>()?;
+        Ok(ServerIncoming {
+            incomings: incomings,
+            next: 0,
+        })
+    }
+}
+
+impl<B> Clone for Http<B> {
+    fn clone(&self) -> Http<B> {
+        Http {
+            date_clock: self.date_clock.clone(),
+            server_header: self.server_header.clone(),
+            echo_headers: self.echo_headers.clone(),
+            strip_response_headers: self.strip_response_headers.clone(),
+            trusted_proxies: self.trusted_proxies.clone(),
+            on_connection: self.on_connection.clone(),
+            accept_filter: self.accept_filter.clone(),
+            on_accept_error: self.on_accept_error.clone(),
+            on_exchange_complete: self.on_exchange_complete.clone(),
+            on_write_drained: self.on_write_drained.clone(),
+            on_exchange_timing: self.on_exchange_timing.clone(),
+            on_connection_close: self.on_connection_close.clone(),
+            on_parse_error: self.on_parse_error.clone(),
+            internal_error_response: self.internal_error_response.clone(),
+            request_limit: self.request_limit.clone(),
+            overload_response: self.overload_response.clone(),
+            ..*self
+        }
+    }
+}
+
+impl fmt::Debug for DateClock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("DateClock")
+    }
+}
+
+impl fmt::Debug for OnConnection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("OnConnection")
+    }
+}
+
+impl fmt::Debug for AcceptFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("AcceptFilter")
+    }
+}
+
+impl fmt::Debug for OnAcceptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("OnAcceptError")
+    }
+}
+
+impl fmt::Debug for OnExchangeComplete {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("OnExchangeComplete")
+    }
+}
+
+impl fmt::Debug for OnWriteDrained {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("OnWriteDrained")
+    }
+}
+
+impl fmt::Debug for OnExchangeTiming {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("OnExchangeTiming")
+    }
+}
+
+impl fmt::Debug for OnConnectionClose {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("OnConnectionClose")
+    }
+}
+
+impl fmt::Debug for OnParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("OnParseError")
+    }
+}
+
+impl fmt::Debug for InternalErrorResponse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("InternalErrorResponse")
+    }
+}
+
+impl<B> fmt::Debug for Http<B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Http")
+            .field("keep_alive", &self.keep_alive)
+            .field("pipeline", &self.pipeline)
+            .finish()
+    }
+}
+
+
+
+// ===== impl Server =====
+
+impl<S, B> Server<S, B>
+    where S: NewService<Request = Request, Response = Response<B>, Error = ::Error> + 'static,
+          B: Stream<Error=::Error> + 'static,
+          B::Item: AsRef<[u8]>,
+{
+    /// Returns the local address that this server is bound to.
+    ///
+    /// For a server created with `Http::bind_multi`, this is the first of
+    /// its addresses, see `local_addrs`.
+    pub fn local_addr(&self) -> ::Result<SocketAddr> {
+        Ok(try!(self.listeners[0].local_addr()))
+    }
+
+    /// Returns the local addresses that this server is bound to, in the
+    /// order they were given to `Http::bind_multi`.
+    pub fn local_addrs(&self) -> ::Result<Vec<SocketAddr>> {
+        let addrs = try!(self.listeners.iter()
+            .map(|listener| listener.local_addr())
+            .collect::<io::Result<Vec<_>>>());
+        Ok(addrs)
+    }
+
+    /// Returns a handle to the underlying event loop that this server will be
+    /// running on.
+    pub fn handle(&self) -> Handle {
+        self.reactor.handle()
+    }
+
+    /// Returns a gauge of the connections this server is currently serving.
+    ///
+    /// With `run_threads`, the gauge counts the connections of all threads.
+    pub fn connection_counter(&self) -> ConnectionCounter {
+        self.connections.clone()
+    }
+
+    /// Returns a gauge of the requests in flight under
+    /// `Http::max_concurrent_requests`.
+    pub fn request_counter(&self) -> RequestCounter {
+        RequestCounter {
+            limit: self.protocol.request_limit.clone(),
+        }
+    }
+
+    /// Returns a handle pausing and resuming the acceptance of connections.
+    ///
+    /// With `run_threads`, the handle pauses the accept loops of all threads.
+    pub fn accept_control(&self) -> AcceptControl {
+        self.accept_control.clone()
+    }
+
+    /// Returns the counters of the connections accepted by this server, and
+    /// of the errors of its listener.
+    ///
+    /// With `run_threads`, the counters add up the accept loops of all
+    /// threads.
+    pub fn accept_stats(&self) -> AcceptStats {
+        self.accept_stats.clone()
+    }
+
+    /// Configure the amount of time this server will wait for a "graceful
+    /// shutdown".
+    ///
+    /// This is the amount of time after the shutdown signal is received the
+    /// server will wait for all pending connections to finish. If the timeout
+    /// elapses then the server will be forcibly shut down.
+    ///
+    /// This defaults to 1s.
+    pub fn shutdown_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    #[doc(hidden)]
+    #[deprecated(since="0.11.11", note="no_proto is always enabled")]
+    pub fn no_proto(&mut self) -> &mut Self {
+        self
+    }
+
+    /// Execute this server infinitely.
     ///
     /// This method does not currently return, but it will return an error if
     /// one occurs.
@@ -368,90 +2072,441 @@ impl<S, B> Server<S, B>
         self.run_until(future::empty())
     }
 
-    /// Execute this server until the given future, `shutdown_signal`, resolves.
+    /// Execute this server until the given future, `shutdown_signal`, resolves.
+    ///
+    /// This method, like `run` above, is used to execute this HTTP server. The
+    /// difference with `run`, however, is that this method allows for shutdown
+    /// in a graceful fashion. The future provided is interpreted as a signal to
+    /// shut down the server when it resolves.
+    ///
+    /// This method will block the current thread executing the HTTP server.
+    /// When the `shutdown_signal` has resolved then the TCP listener will be
+    /// unbound (dropped). The thread will continue to block for a maximum of
+    /// `shutdown_timeout` time waiting for active connections to shut down.
+    /// Once the `shutdown_timeout` elapses or all active connections are
+    /// cleaned out then this method will return.
+    pub fn run_until<F>(self, shutdown_signal: F) -> ::Result<()>
+        where F: Future<Item = (), Error = ()>,
+    {
+        self.run_until_graceful(shutdown_signal).map(|_| ())
+    }
+
+    /// Execute this server until the given future, `shutdown_signal`, resolves,
+    /// and report how the shutdown went.
+    ///
+    /// This is the same as `run_until`, except that the returned
+    /// `ShutdownOutcome` tells whether the `shutdown_timeout` elapsed before
+    /// all connections were closed, and how many were then still active.
+    pub fn run_until_graceful<F>(self, shutdown_signal: F) -> ::Result<ShutdownOutcome>
+        where F: Future<Item = (), Error = ()>,
+    {
+        self.run_until_phased(SignalPhases::new(shutdown_signal))
+    }
+
+    /// Execute this server until the given stream of shutdown `phases` is
+    /// done, and report how the shutdown went.
+    ///
+    /// This splits the shutdown of `run_until_graceful` in two phases. Once
+    /// `phases` yields `ShutdownPhase::StopAccepting`, the TCP listener is
+    /// dropped, but the active connections are still served as usual. Once
+    /// it yields `ShutdownPhase::Drain`, the connections are told to close
+    /// after their in-flight response, and the server waits up to
+    /// `shutdown_timeout` for them to do so.
+    ///
+    /// This gives a load balancer time to notice that the server is going
+    /// away, before its connections start to close. A `Drain` first, or the
+    /// end of `phases`, begins both phases at once.
+    pub fn run_until_phased<P>(self, phases: P) -> ::Result<ShutdownOutcome>
+        where P: Stream<Item = ShutdownPhase, Error = ()>,
+    {
+        let Server { protocol, new_service, reactor, listeners, shutdown_timeout, connections, accept_control, accept_stats, .. } = self;
+
+        let incoming = protocol.server_incoming(listeners, reactor.handle(), accept_control, accept_stats)?;
+
+        run_incoming_until(protocol, new_service, reactor, incoming, shutdown_timeout,
+            connections, phases)
+    }
+}
+
+// ===== impl HandleServer =====
+
+impl<S, B> HandleServer<S, B>
+    where S: NewService<Request = Request, Response = Response<B>, Error = ::Error> + 'static,
+          B: Stream<Error=::Error> + 'static,
+          B::Item: AsRef<[u8]>,
+{
+    /// Returns the local address that this server is bound to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.incoming.local_addr()
+    }
+
+    /// Returns the handle of the event loop this server is served on.
+    pub fn handle(&self) -> Handle {
+        self.handle.clone()
+    }
+
+    /// Returns a gauge of the connections this server is currently serving.
+    pub fn connection_counter(&self) -> ConnectionCounter {
+        self.connections.clone()
+    }
+
+    /// Returns a gauge of the requests in flight under
+    /// `Http::max_concurrent_requests`.
+    pub fn request_counter(&self) -> RequestCounter {
+        RequestCounter {
+            limit: self.protocol.request_limit.clone(),
+        }
+    }
+
+    /// Returns a handle pausing and resuming the acceptance of connections.
+    pub fn accept_control(&self) -> AcceptControl {
+        self.incoming.accept_control()
+    }
+
+    /// Returns the counters of the connections accepted by this server, and
+    /// of the errors of its listener.
+    pub fn accept_stats(&self) -> AcceptStats {
+        self.incoming.stats()
+    }
+
+    /// Configure the amount of time this server will wait for a "graceful
+    /// shutdown".
     ///
-    /// This method, like `run` above, is used to execute this HTTP server. The
-    /// difference with `run`, however, is that this method allows for shutdown
-    /// in a graceful fashion. The future provided is interpreted as a signal to
-    /// shut down the server when it resolves.
+    /// See `Server::shutdown_timeout`. This defaults to 1s.
+    pub fn shutdown_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Serve this server until the given future, `shutdown_signal`,
+    /// resolves.
     ///
-    /// This method will block the current thread executing the HTTP server.
-    /// When the `shutdown_signal` has resolved then the TCP listener will be
-    /// unbound (dropped). The thread will continue to block for a maximum of
-    /// `shutdown_timeout` time waiting for active connections to shut down.
-    /// Once the `shutdown_timeout` elapses or all active connections are
-    /// cleaned out then this method will return.
-    pub fn run_until<F>(self, shutdown_signal: F) -> ::Result<()>
+    /// The returned future does what `Server::run_until_graceful` does,
+    /// without running the event loop: once `shutdown_signal` resolves, the
+    /// TCP listener is dropped, the active connections are told to close
+    /// after their in-flight response, and the future resolves once they
+    /// closed or `shutdown_timeout` elapsed. To serve forever, pass
+    /// `future::empty()`.
+    pub fn serve_until<F>(self, shutdown_signal: F) -> ServeUntil<S, B, F>
         where F: Future<Item = (), Error = ()>,
     {
-        let Server { protocol, new_service, mut reactor, listener, shutdown_timeout } = self;
+        let HandleServer { protocol, new_service, handle, incoming, shutdown_timeout, connections } = self;
+        ServeUntil {
+            inner: Serving::new(protocol, new_service, handle, incoming, shutdown_timeout,
+                connections, SignalPhases::new(shutdown_signal)),
+        }
+    }
+}
+
+impl<S, B, F> Future for ServeUntil<S, B, F>
+    where S: NewService<Request = Request, Response = Response<B>, Error = ::Error> + 'static,
+          B: Stream<Error=::Error> + 'static,
+          B::Item: AsRef<[u8]>,
+          F: Future<Item = (), Error = ()>,
+{
+    type Item = ShutdownOutcome;
+    type Error = ::Error;
 
-        let handle = reactor.handle();
+    fn poll(&mut self) -> Poll<ShutdownOutcome, ::Error> {
+        self.inner.poll()
+    }
+}
 
-        let mut incoming = AddrIncoming::new(listener, handle.clone(), protocol.sleep_on_errors)?;
+// Serves every connection of `incoming` on `reactor`, until the `phases` of
+// the shutdown are done. This is the body of `Server::run_until`, shared with
+// the worker threads of `Server::run_threads`.
+fn run_incoming_until<S, B, I, P>(protocol: Http<B::Item>,
+                                  new_service: S,
+                                  mut reactor: Core,
+                                  incoming: I,
+                                  shutdown_timeout: Duration,
+                                  connections: ConnectionCounter,
+                                  phases: P) -> ::Result<ShutdownOutcome>
+    where S: NewService<Request = Request, Response = Response<B>, Error = ::Error> + 'static,
+          B: Stream<Error=::Error> + 'static,
+          B::Item: AsRef<[u8]>,
+          I: Stream<Error = io::Error>,
+          I::Item: Accepted,
+          P: Stream<Item = ShutdownPhase, Error = ()>,
+{
+    let handle = reactor.handle();
+    reactor.run(Serving::new(protocol, new_service, handle, incoming, shutdown_timeout,
+        connections, phases))
+}
 
-        if protocol.keep_alive {
-            incoming.set_keepalive(Some(Duration::from_secs(90)));
-        }
+// A future serving every connection of `incoming` on `handle`, until the
+// `phases` of the shutdown are done, see `run_incoming_until`.
+struct Serving<S, B, I, P>
+    where B: Stream<Error=::Error>,
+          B::Item: AsRef<[u8]>,
+{
+    // shared with the connections reading a PROXY header first
+    protocol: Rc<Http<B::Item>>,
+    new_service: S,
+    handle: Handle,
+    // Dropped once a phase comes, to stop accepting connections.
+    incoming: Option<MaxConnections<I>>,
+    phases: P,
+    info: Rc<RefCell<Info>>,
+    connections: ConnectionCounter,
+    shutdown_timeout: Duration,
+    // Resolves `draining`, telling every connection to stop accepting
+    // further requests.
+    draining_tx: Option<oneshot::Sender<()>>,
+    draining: Shared<oneshot::Receiver<()>>,
+    // Armed once the drain begins.
+    drain_timeout: Option<Timeout>,
+    _date_interval: Option<DateInterval>,
+}
 
-        date_render_interval(&handle);
+impl<S, B, I, P> Serving<S, B, I, P>
+    where S: NewService<Request = Request, Response = Response<B>, Error = ::Error> + 'static,
+          B: Stream<Error=::Error> + 'static,
+          B::Item: AsRef<[u8]>,
+          I: Stream<Error = io::Error>,
+          I::Item: Accepted,
+          P: Stream<Item = ShutdownPhase, Error = ()>,
+{
+    fn new(protocol: Http<B::Item>,
+           new_service: S,
+           handle: Handle,
+           incoming: I,
+           shutdown_timeout: Duration,
+           connections: ConnectionCounter,
+           phases: P) -> Serving<S, B, I, P> {
+        let date_interval = if protocol.auto_date {
+            Some(date_render_interval(&handle))
+        } else {
+            None
+        };
 
         // Mini future to track the number of active services
         let info = Rc::new(RefCell::new(Info {
             active: 0,
+            overloaded: 0,
+            per_ip: HashMap::new(),
             blocker: None,
+            accept_blocker: None,
         }));
 
-        // Future for our server's execution
-        let srv = incoming.for_each(|socket| {
-            let addr = socket.remote_addr;
-            debug!("accepted new connection ({})", addr);
+        // Stop pulling from `incoming` while `max_connections` are being
+        // served, and as many more get the overload response, if any.
+        let incoming = MaxConnections {
+            incoming: incoming,
+            info: info.clone(),
+            max: protocol.max_connections,
+            overload: protocol.overload_response.is_some(),
+        };
+
+        let (draining_tx, draining_rx) = oneshot::channel();
+        Serving {
+            protocol: Rc::new(protocol),
+            new_service: new_service,
+            handle: handle,
+            incoming: Some(incoming),
+            phases: phases,
+            info: info,
+            connections: connections,
+            shutdown_timeout: shutdown_timeout,
+            draining_tx: Some(draining_tx),
+            draining: draining_rx.shared(),
+            drain_timeout: None,
+            _date_interval: date_interval,
+        }
+    }
+
+    // Polls the shutdown phases, returning whether the drain begins. An
+    // ended or failed stream of phases counts as both phases.
+    fn poll_phases(&mut self) -> bool {
+        loop {
+            match self.phases.poll() {
+                Ok(Async::Ready(Some(ShutdownPhase::StopAccepting))) => {
+                    // Drop the TCP listener to stop accepting incoming
+                    // connections. The connections are served as usual until
+                    // the drain begins.
+                    if self.incoming.take().is_some() {
+                        debug!("server stopped accepting, waiting to drain");
+                    }
+                },
+                Ok(Async::NotReady) => return false,
+                Ok(Async::Ready(_)) | Err(()) => return true,
+            }
+        }
+    }
 
-            let addr_service = SocketAddrService::new(addr, new_service.new_service()?);
-            let s = NotifyService {
-                inner: addr_service,
-                info: Rc::downgrade(&info),
+    // Serves the connections accepted from `incoming`, returning whether it
+    // ended.
+    fn poll_incoming(&mut self) -> io::Result<bool> {
+        loop {
+            let polled = match self.incoming {
+                Some(ref mut incoming) => incoming.poll()?,
+                None => return Ok(false),
             };
-            info.borrow_mut().active += 1;
-            let fut = protocol.serve_connection(socket, s)
-                .map(|_| ())
-                .map_err(move |err| error!("server connection error: ({}) {}", addr, err));
-            handle.spawn(fut);
-            Ok(())
-        });
+            match polled {
+                Async::Ready(Some(socket)) => self.serve(socket)?,
+                Async::Ready(None) => return Ok(true),
+                Async::NotReady => return Ok(false),
+            }
+        }
+    }
+
+    fn serve(&self, socket: I::Item) -> io::Result<()> {
+        let protocol = &self.protocol;
+        let info = &self.info;
+        let addr = socket.remote_addr();
+        let local_addr = socket.local_addr();
+        debug!("accepted new connection ({})", Peer(addr));
+
+        if let (Some(max), Some(addr)) = (protocol.max_connections_per_ip, addr) {
+            if info.borrow().per_ip.get(&addr.ip()).map_or(false, |&n| n >= max) {
+                debug!("max connections per ip reached ({}), closing connection ({})", max, addr);
+                return Ok(());
+            }
+        }
+
+        let service = match self.new_service.new_service() {
+            Ok(service) => service,
+            Err(e) => {
+                // the server keeps serving the other connections
+                debug!("error creating service, closing connection ({}): {}", Peer(addr), e);
+                return Ok(());
+            }
+        };
+        let overloaded = protocol.overload_response.is_some() &&
+            protocol.max_connections.map_or(false, |max| info.borrow().active >= max);
+        let addr_service = SocketAddrService::new(addr, local_addr, service);
+        let s = if overloaded {
+            debug!("max connections reached, sending the overload response ({})", Peer(addr));
+            NotifyService::overloaded(addr_service, info)
+        } else {
+            NotifyService::new(addr_service, info, Some(self.connections.clone()))
+        };
+        let s = match addr {
+            Some(addr) if protocol.max_connections_per_ip.is_some() => s.track_ip(addr.ip()),
+            _ => s,
+        };
+        socket.spawn(protocol, &self.handle, s, overloaded, self.draining.clone())
+    }
+}
+
+impl<S, B, I, P> Future for Serving<S, B, I, P>
+    where S: NewService<Request = Request, Response = Response<B>, Error = ::Error> + 'static,
+          B: Stream<Error=::Error> + 'static,
+          B::Item: AsRef<[u8]>,
+          I: Stream<Error = io::Error>,
+          I::Item: Accepted,
+          P: Stream<Item = ShutdownPhase, Error = ()>,
+{
+    type Item = ShutdownOutcome;
+    type Error = ::Error;
 
-        // for now, we don't care if the shutdown signal succeeds or errors
-        // as long as it resolves, we will shutdown.
-        let shutdown_signal = shutdown_signal.then(|_| Ok(()));
+    fn poll(&mut self) -> Poll<ShutdownOutcome, ::Error> {
+        if self.drain_timeout.is_none() {
+            // We know that `incoming` will never end (it's infinite) so
+            // we're actually just waiting for an error or for a phase.
+            if !self.poll_phases() && !self.poll_incoming()? {
+                return Ok(Async::NotReady);
+            }
 
-        // Main execution of the server. Here we use `select` to wait for either
-        // `incoming` or `f` to resolve. We know that `incoming` will never
-        // resolve with a success (it's infinite) so we're actually just waiting
-        // for an error or for `f`, our shutdown signal.
-        //
-        // When we get a shutdown signal (`Ok`) then we drop the TCP listener to
-        // stop accepting incoming connections.
-        match reactor.run(shutdown_signal.select(srv)) {
-            Ok(((), _incoming)) => {}
-            Err((e, _other)) => return Err(e.into()),
+            // Ok we've stopped accepting new connections at this point. Tell
+            // the existing connections to finish their in-flight response,
+            // if any, and then close instead of reading another request.
+            self.incoming = None;
+            if let Some(tx) = self.draining_tx.take() {
+                let _ = tx.send(());
+            }
+            // We want to give existing connections a chance to clear
+            // themselves out. Wait at most `shutdown_timeout` time before we
+            // just return clearing everything out.
+            self.drain_timeout = Some(Timeout::new(self.shutdown_timeout, &self.handle)?);
         }
 
-        // Ok we've stopped accepting new connections at this point, but we want
-        // to give existing connections a chance to clear themselves out. Wait
-        // at most `shutdown_timeout` time before we just return clearing
-        // everything out.
-        //
-        // Our custom `WaitUntilZero` will resolve once all services constructed
-        // here have been destroyed.
-        let timeout = try!(Timeout::new(shutdown_timeout, &handle));
-        let wait = WaitUntilZero { info: info.clone() };
-        match reactor.run(wait.select(timeout)) {
-            Ok(_) => Ok(()),
-            Err((e, _)) => Err(e.into())
+        // Our custom `WaitUntilZero` will resolve once all services
+        // constructed here have been destroyed.
+        if let Async::Ready(()) = (WaitUntilZero { info: self.info.clone() }).poll()? {
+            return Ok(Async::Ready(ShutdownOutcome {
+                forced: false,
+                remaining: 0,
+                _inner: (),
+            }));
+        }
+        match self.drain_timeout {
+            Some(ref mut timeout) => try_ready!(timeout.poll()),
+            None => unreachable!("drain timeout is armed"),
         }
+        // the connections are dropped with the reactor, each one logging
+        // the bytes it had yet to flush
+        debug!("shutdown timeout elapsed, dropping {} connections", self.info.borrow().active);
+        Ok(Async::Ready(ShutdownOutcome {
+            forced: true,
+            remaining: self.info.borrow().active,
+            _inner: (),
+        }))
     }
 }
 
+// A connection accepted by a `Serving`, from a TCP listener or a Unix one.
+trait Accepted: AsyncRead + AsyncWrite + ConnectionInfo + Sized + 'static {
+    // Serves this connection on `handle` until the server drains.
+    fn spawn<S, B>(self,
+                   protocol: &Rc<Http<B::Item>>,
+                   handle: &Handle,
+                   service: NotifyService<SocketAddrService<S>>,
+                   overloaded: bool,
+                   draining: Shared<oneshot::Receiver<()>>) -> io::Result<()>
+        where S: Service<Request = Request, Response = Response<B>, Error = ::Error> + 'static,
+              B: Stream<Error=::Error> + 'static,
+              B::Item: AsRef<[u8]>;
+}
+
+impl Accepted for AddrStream {
+    fn spawn<S, B>(self,
+                   protocol: &Rc<Http<B::Item>>,
+                   handle: &Handle,
+                   s: NotifyService<SocketAddrService<S>>,
+                   overloaded: bool,
+                   draining: Shared<oneshot::Receiver<()>>) -> io::Result<()>
+        where S: Service<Request = Request, Response = Response<B>, Error = ::Error> + 'static,
+              B: Stream<Error=::Error> + 'static,
+              B::Item: AsRef<[u8]>,
+    {
+        let socket = self;
+        let addr = socket.remote_addr;
+        if !protocol.proxy_protocol {
+            let socket_info = conn_info(&socket);
+            spawn_connection(protocol, handle, socket, socket_info, &[], s, overloaded, draining);
+            return Ok(());
+        }
+
+        let timeout = match protocol.header_read_timeout {
+            Some(dur) => Some(Timeout::new(dur, handle)?),
+            None => None,
+        };
+        let protocol = protocol.clone();
+        let spawn_handle = handle.clone();
+        let draining_rx = draining;
+        let fut = proxy_protocol::ReadProxyHeader::new(socket, timeout).then(move |result| {
+            let (socket, proxied_addr, read_buf) = match result {
+                Ok(header) => header,
+                Err(e) => {
+                    error!("invalid PROXY header: ({}) {}", addr, e);
+                    return Ok(());
+                }
+            };
+            let mut s = s;
+            let mut socket_info = conn_info(&socket);
+            if let Some(proxied_addr) = proxied_addr {
+                debug!("PROXY header of connection ({}) announced {}", addr, proxied_addr);
+                s.inner.addr = Some(proxied_addr);
+                socket_info.remote_addr = Some(proxied_addr);
+            }
+            spawn_connection(&protocol, &spawn_handle, socket, socket_info, &read_buf, s, overloaded, draining_rx);
+            Ok(())
+        });
+        handle.spawn(fut);
+        Ok(())
+    }
+}
 
 impl<S, B> Server<S, B>
     where S: NewService<Request = Request, Response = Response<B>, Error = ::Error> + Send + Sync + 'static,
@@ -459,37 +2514,94 @@ impl<S, B> Server<S, B>
           B::Item: AsRef<[u8]>,
 {
     /// Run the server on multiple threads.
-    #[cfg(unix)]
+    ///
+    /// On Unix, each thread binds its own listener to the address of this
+    /// server with `SO_REUSEPORT`. Elsewhere, or for a server created with
+    /// `Http::bind_listener` or with `Http::reuse_address` disabled, the
+    /// listener of this server accepts every connection and hands them to
+    /// the threads in turn.
+    ///
+    /// This method does not return, and panics if a thread fails.
     pub fn run_threads(self, threads: usize) {
+        if let Err(errors) = self.run_threads_until(threads, future::empty::<(), ()>) {
+            panic!("server threads failed: {:?}", errors);
+        }
+    }
+
+    /// Run the server on multiple threads until their shutdown signals
+    /// resolve.
+    ///
+    /// Each thread calls `shutdown_signal` to build its own signal, and
+    /// shuts down gracefully as with `Server::run_until` once it resolves.
+    /// This returns after every thread is done. When threads share the
+    /// listener of this server, the signal of the calling thread also stops
+    /// accepting connections for the others.
+    ///
+    /// The errors of the threads that failed are returned together, a
+    /// panicking thread being reported as an `Error::Io`.
+    pub fn run_threads_until<F, G>(self, threads: usize, shutdown_signal: G) -> Result<(), Vec<::Error>>
+        where F: Future<Item = (), Error = ()>,
+              G: Fn() -> F + Send + Sync + 'static,
+    {
         assert!(threads > 0, "threads must be more than 0");
 
+        let shutdown_signal = Arc::new(shutdown_signal);
+        if cfg!(unix) && self.reuse_port {
+            self.run_threads_reuse_port(threads, shutdown_signal)
+        } else {
+            self.run_threads_shared(threads, shutdown_signal)
+        }
+    }
+
+    fn run_threads_reuse_port<F, G>(self, threads: usize, shutdown_signal: Arc<G>) -> Result<(), Vec<::Error>>
+        where F: Future<Item = (), Error = ()>,
+              G: Fn() -> F + Send + Sync + 'static,
+    {
         let Server {
             protocol,
             new_service,
             reactor,
-            listener,
+            listeners,
             shutdown_timeout,
+            connections,
+            accept_control,
+            accept_stats,
+            ..
         } = self;
 
         let new_service = Arc::new(new_service);
-        let addr = listener.local_addr().unwrap();
+        let addrs = try!(listeners.iter()
+            .map(|listener| listener.local_addr())
+            .collect::<io::Result<Vec<_>>>()
+            .map_err(|e| vec![e.into()]));
 
         let threads = (1..threads).map(|i| {
             let protocol = protocol.clone();
             let new_service = new_service.clone();
+            let connections = connections.clone();
+            let accept_control = accept_control.clone();
+            let accept_stats = accept_stats.clone();
+            let shutdown_signal = shutdown_signal.clone();
+            let addrs = addrs.clone();
             thread::Builder::new()
                 .name(format!("hyper-server-thread-{}", i))
                 .spawn(move || {
-                    let reactor = Core::new().unwrap();
-                    let listener = thread_listener(&addr, &reactor.handle()).unwrap();
+                    let reactor = try!(Core::new());
+                    let listeners = try!(addrs.iter()
+                        .map(|addr| thread_listener(addr, &reactor.handle(), protocol.listen_backlog, true))
+                        .collect::<io::Result<Vec<_>>>());
                     let srv = Server {
                         protocol,
                         new_service,
                         reactor,
-                        listener,
+                        listeners,
                         shutdown_timeout,
+                        connections,
+                        accept_control,
+                        accept_stats,
+                        reuse_port: true,
                     };
-                    srv.run().unwrap();
+                    srv.run_until(shutdown_signal())
                 })
                 .unwrap()
         }).collect::<Vec<_>>();
@@ -498,18 +2610,354 @@ impl<S, B> Server<S, B>
             protocol,
             new_service,
             reactor,
-            listener,
+            listeners,
+            shutdown_timeout,
+            connections,
+            accept_control,
+            accept_stats,
+            reuse_port: true,
+        };
+        join_threads(srv.run_until(shutdown_signal()), threads)
+    }
+
+    fn run_threads_shared<F, G>(self, threads: usize, shutdown_signal: Arc<G>) -> Result<(), Vec<::Error>>
+        where F: Future<Item = (), Error = ()>,
+              G: Fn() -> F + Send + Sync + 'static,
+    {
+        let Server {
+            protocol,
+            new_service,
+            reactor,
+            listeners,
+            shutdown_timeout,
+            connections,
+            accept_control,
+            accept_stats,
+            ..
+        } = self;
+
+        let new_service = Arc::new(new_service);
+        let mut senders = Vec::with_capacity(threads);
+
+        let (tx, rx) = mpsc::unbounded();
+        senders.push(tx);
+
+        let threads = (1..threads).map(|i| {
+            let (tx, rx) = mpsc::unbounded();
+            senders.push(tx);
+            let protocol = protocol.clone();
+            let new_service = new_service.clone();
+            let connections = connections.clone();
+            let shutdown_signal = shutdown_signal.clone();
+            thread::Builder::new()
+                .name(format!("hyper-server-thread-{}", i))
+                .spawn(move || {
+                    let reactor = try!(Core::new());
+                    let incoming = shared_incoming(rx, reactor.handle(), protocol.on_connection.clone());
+                    run_incoming_until(protocol, new_service, reactor, incoming,
+                        shutdown_timeout, connections, SignalPhases::new(shutdown_signal())).map(|_| ())
+                })
+                .unwrap()
+        }).collect::<Vec<_>>();
+
+        // This thread accepts the connections of all threads, including its
+        // own, handing them out round-robin. The accept loop is dropped with
+        // the reactor of this thread, which ends the incoming streams of the
+        // others.
+        let mut incoming = match protocol.server_incoming(listeners, reactor.handle(), accept_control, accept_stats) {
+            Ok(incoming) => incoming,
+            Err(e) => {
+                drop(senders);
+                return join_threads(Err(e.into()), threads);
+            }
+        };
+        let mut next = 0;
+        let accept = future::poll_fn(move || {
+            while let Some(conn) = try_ready!(incoming.poll_std()) {
+                if let Err(_) = senders[next].unbounded_send(conn) {
+                    debug!("server thread {} is gone, dropping connection", next);
+                }
+                next = (next + 1) % senders.len();
+            }
+            Ok(Async::Ready(()))
+        }).map_err(|e: io::Error| error!("server accept error: {}", e));
+        reactor.handle().spawn(accept);
+
+        let incoming = shared_incoming(rx, reactor.handle(), protocol.on_connection.clone());
+        let res = run_incoming_until(protocol, new_service, reactor, incoming,
+            shutdown_timeout, connections, SignalPhases::new(shutdown_signal())).map(|_| ());
+        join_threads(res, threads)
+    }
+
+    /// Run the server on a pool of worker threads, fed by a single accept
+    /// loop.
+    ///
+    /// The calling thread only accepts connections, and queues them for
+    /// `threads` workers, each running its own reactor. A connection is
+    /// taken from the queue by whichever worker gets to it first, so a
+    /// worker that is busy, such as with a service blocking its thread or
+    /// with `max_connections` reached, leaves new connections to the
+    /// others. With `run_threads`, connections are spread by the kernel or
+    /// in turn instead, whatever the load of each thread.
+    ///
+    /// At most `threads` connections wait in the queue. Beyond that, the
+    /// accept loop stops until a worker takes one, and clients wait in the
+    /// backlog of the listener.
+    ///
+    /// This method does not return, and panics if a thread fails.
+    pub fn run_pool(self, threads: usize) {
+        assert!(threads > 0, "threads must be more than 0");
+        if let Err(errors) = self.run_pool_until(threads, Arc::new(future::empty::<(), ()>)) {
+            panic!("server threads failed: {:?}", errors);
+        }
+    }
+
+    fn run_pool_until<F, G>(self, threads: usize, shutdown_signal: Arc<G>) -> Result<(), Vec<::Error>>
+        where F: Future<Item = (), Error = ()>,
+              G: Fn() -> F + Send + Sync + 'static,
+    {
+        let Server {
+            protocol,
+            new_service,
+            mut reactor,
+            listeners,
             shutdown_timeout,
+            connections,
+            accept_control,
+            accept_stats,
+            ..
+        } = self;
+
+        let new_service = Arc::new(new_service);
+        let queue = PoolQueue::new(threads);
+
+        let threads = (0..threads).map(|i| {
+            let queue = queue.clone();
+            let protocol = protocol.clone();
+            let new_service = new_service.clone();
+            let connections = connections.clone();
+            let shutdown_signal = shutdown_signal.clone();
+            thread::Builder::new()
+                .name(format!("hyper-server-thread-{}", i))
+                .spawn(move || {
+                    let reactor = try!(Core::new());
+                    let incoming = shared_incoming(queue, reactor.handle(), protocol.on_connection.clone());
+                    run_incoming_until(protocol, new_service, reactor, incoming,
+                        shutdown_timeout, connections, SignalPhases::new(shutdown_signal())).map(|_| ())
+                })
+                .unwrap()
+        }).collect::<Vec<_>>();
+
+        let mut incoming = match protocol.server_incoming(listeners, reactor.handle(), accept_control, accept_stats) {
+            Ok(incoming) => incoming,
+            Err(e) => {
+                queue.close();
+                return join_threads(Err(e.into()), threads);
+            }
+        };
+        let accept = future::poll_fn(|| -> Poll<(), io::Error> {
+            loop {
+                if !queue.poll_push_ready() {
+                    trace!("server pool queue is full, pausing accept");
+                    return Ok(Async::NotReady);
+                }
+                match try_ready!(incoming.poll_std()) {
+                    Some(conn) => queue.push(conn),
+                    None => return Ok(Async::Ready(())),
+                }
+            }
+        });
+        let res = match reactor.run(accept.select2(shutdown_signal())) {
+            Ok(_) | Err(Either::B(_)) => Ok(()),
+            Err(Either::A((e, _))) => Err(::Error::from(e)),
         };
-        srv.run().unwrap();
+        // ends the incoming streams of the workers, which then shut down
+        // gracefully
+        queue.close();
+        join_threads(res, threads)
+    }
+}
+
+// The connections accepted by `Server::run_pool`, waiting for a worker.
+#[derive(Clone)]
+struct PoolQueue {
+    state: Arc<Mutex<PoolState>>,
+}
+
+struct PoolState {
+    conns: VecDeque<(StdTcpStream, SocketAddr)>,
+    cap: usize,
+    closed: bool,
+    // The accept loop, waiting for room in a full queue.
+    acceptor: Option<Task>,
+    // The workers waiting for a connection.
+    workers: Vec<Task>,
+}
+
+impl PoolQueue {
+    fn new(cap: usize) -> PoolQueue {
+        PoolQueue {
+            state: Arc::new(Mutex::new(PoolState {
+                conns: VecDeque::with_capacity(cap),
+                cap: cap,
+                closed: false,
+                acceptor: None,
+                workers: Vec::new(),
+            })),
+        }
+    }
+
+    // Whether there is room for another connection, else the current task
+    // is notified once there is.
+    fn poll_push_ready(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.conns.len() < state.cap {
+            true
+        } else {
+            state.acceptor = Some(task::current());
+            false
+        }
+    }
+
+    fn push(&self, conn: (StdTcpStream, SocketAddr)) {
+        let mut state = self.state.lock().unwrap();
+        state.conns.push_back(conn);
+        // Every waiting worker is woken, the first one to poll gets the
+        // connection: a worker busy on its thread doesn't hold it up.
+        for task in state.workers.drain(..) {
+            task.notify();
+        }
+    }
+
+    fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        for task in state.workers.drain(..) {
+            task.notify();
+        }
+    }
+}
+
+impl Stream for PoolQueue {
+    type Item = (StdTcpStream, SocketAddr);
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, ()> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(conn) = state.conns.pop_front() {
+            if let Some(task) = state.acceptor.take() {
+                task.notify();
+            }
+            return Ok(Async::Ready(Some(conn)));
+        }
+        if state.closed {
+            return Ok(Async::Ready(None));
+        }
+        if !state.workers.iter().any(|task| task.will_notify_current()) {
+            state.workers.push(task::current());
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+// Waits for the other threads of `run_threads_until`, collecting the errors
+// of all of them.
+fn join_threads(res: ::Result<()>, threads: Vec<thread::JoinHandle<::Result<()>>>) -> Result<(), Vec<::Error>> {
+    let mut errors = Vec::new();
+    if let Err(e) = res {
+        errors.push(e);
+    }
+    for thread in threads {
+        match thread.join() {
+            Ok(Ok(())) => (),
+            Ok(Err(e)) => errors.push(e),
+            Err(_) => errors.push(io::Error::new(io::ErrorKind::Other, "server thread panicked").into()),
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+// Registers the connections accepted by another thread of `run_threads` or
+// `run_pool` with the reactor of the current one.
+fn shared_incoming<R>(rx: R,
+                      handle: Handle,
+                      on_connection: Option<OnConnection>)
+    -> Box<Stream<Item=AddrStream, Error=io::Error>>
+    where R: Stream<Item=(StdTcpStream, SocketAddr), Error=()> + 'static,
+{
+    let incoming = rx
+        .map_err(|()| io::Error::new(io::ErrorKind::Other, "server accept thread is gone"))
+        .filter_map(move |(socket, addr)| {
+            let local_addr = match socket.local_addr() {
+                Ok(local_addr) => local_addr,
+                Err(e) => {
+                    debug!("error getting local addr of accepted connection: {}", e);
+                    return None;
+                }
+            };
+            match TcpStream::from_stream(socket, &handle) {
+                Ok(socket) => {
+                    let stream = AddrStream::new(socket, addr, local_addr);
+                    if let Some(ref on_connection) = on_connection {
+                        (on_connection.0)(&stream);
+                    }
+                    Some(stream)
+                },
+                Err(e) => {
+                    debug!("error registering accepted connection: {}", e);
+                    None
+                }
+            }
+        });
+    Box::new(incoming)
+}
+
+// The reactors with a date interval spawned on this thread, each with the
+// number of servers currently running on it.
+thread_local!(static DATE_INTERVALS: RefCell<Vec<(CoreId, Rc<Cell<usize>>)>> = RefCell::new(Vec::new()));
 
-        for thread in threads {
-            thread.join().unwrap();
+/// Keeps the date interval of a reactor running while a server uses it.
+///
+/// The interval is stopped once the last `DateInterval` of its reactor is
+/// dropped.
+struct DateInterval {
+    id: CoreId,
+    servers: Rc<Cell<usize>>,
+}
+
+impl Drop for DateInterval {
+    fn drop(&mut self) {
+        let servers = self.servers.get() - 1;
+        self.servers.set(servers);
+        if servers == 0 {
+            date_interval_off(self.id, &self.servers);
         }
     }
 }
 
-fn date_render_interval(handle: &Handle) {
+fn date_render_interval(handle: &Handle) -> DateInterval {
+    let id = handle.id();
+    let existing = DATE_INTERVALS.with(|intervals| {
+        intervals.borrow()
+            .iter()
+            .find(|&&(core, _)| core == id)
+            .map(|&(_, ref servers)| servers.clone())
+    });
+    if let Some(servers) = existing {
+        trace!("sharing date rendering interval of {:?}", id);
+        servers.set(servers.get() + 1);
+        return DateInterval {
+            id: id,
+            servers: servers,
+        };
+    }
+
+    let servers = Rc::new(Cell::new(1));
+
     // Since we own the executor, we can spawn an interval to update the
     // thread_local rendered date, instead of checking the clock on every
     // single response.
@@ -519,15 +2967,31 @@ fn date_render_interval(handle: &Handle) {
             trace!("error spawning date rendering interval: {}", e);
             // It'd be quite weird to error, but if it does, we
             // don't actually need it, so just back out.
-            return;
+            return DateInterval {
+                id: id,
+                servers: servers,
+            };
         }
     };
 
-    let on_drop = IntervalDrop;
+    DATE_INTERVALS.with(|intervals| {
+        intervals.borrow_mut().push((id, servers.clone()));
+    });
+    proto::date::interval_on();
+
+    let running = servers.clone();
+    let on_drop = IntervalDrop {
+        id: id,
+        servers: servers.clone(),
+    };
 
     let fut =
         future::poll_fn(move || {
             try_ready!(date_interval.poll().map_err(|_| ()));
+            // the last server using this interval has stopped
+            if running.get() == 0 {
+                return Ok(Async::Ready(()));
+            }
             // If here, we were ready!
             proto::date::update_interval();
             // However, to prevent Interval from needing to clone its Task
@@ -553,24 +3017,57 @@ fn date_render_interval(handle: &Handle) {
 
     handle.spawn(fut);
 
-    struct IntervalDrop;
+    struct IntervalDrop {
+        id: CoreId,
+        servers: Rc<Cell<usize>>,
+    }
 
     impl Drop for IntervalDrop {
         fn drop(&mut self) {
-            proto::date::interval_off();
+            date_interval_off(self.id, &self.servers);
+        }
+    }
+
+    DateInterval {
+        id: id,
+        servers: servers,
+    }
+}
+
+// Unregisters the date interval of a reactor, whichever of its last server
+// stopping or the interval itself being dropped happens first.
+fn date_interval_off(id: CoreId, servers: &Rc<Cell<usize>>) {
+    let removed = DATE_INTERVALS.with(|intervals| {
+        let mut intervals = intervals.borrow_mut();
+        let pos = intervals.iter()
+            .position(|&(core, ref s)| core == id && Rc::ptr_eq(s, servers));
+        match pos {
+            Some(pos) => {
+                intervals.remove(pos);
+                true
+            },
+            None => false,
         }
+    });
+    if removed {
+        proto::date::interval_off();
     }
 }
 
-fn thread_listener(addr: &SocketAddr, handle: &Handle) -> io::Result<TcpListener> {
+fn thread_listener(addr: &SocketAddr, handle: &Handle, backlog: i32, reuse: bool) -> io::Result<TcpListener> {
+    if backlog <= 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "listen backlog must be positive"));
+    }
     let listener = match *addr {
         SocketAddr::V4(_) => net2::TcpBuilder::new_v4()?,
         SocketAddr::V6(_) => net2::TcpBuilder::new_v6()?,
     };
-    reuse_port(&listener);
-    listener.reuse_address(true)?;
+    if reuse {
+        reuse_port(&listener);
+        listener.reuse_address(true)?;
+    }
     listener.bind(addr)?;
-    listener.listen(1024).and_then(|l| {
+    listener.listen(backlog).and_then(|l| {
         TcpListener::from_listener(l, addr, handle)
     })
 }
@@ -587,13 +3084,33 @@ fn reuse_port(tcp: &net2::TcpBuilder) {
 fn reuse_port(_tcp: &net2::TcpBuilder) {
 }
 
+impl<S: fmt::Debug, B: Stream<Error=::Error>> fmt::Debug for HandleServer<S, B>
+where B::Item: AsRef<[u8]>
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HandleServer")
+         .field("incoming", &self.incoming)
+         .field("new_service", &self.new_service)
+         .field("protocol", &self.protocol)
+         .finish()
+    }
+}
+
+impl<S, B: Stream<Error=::Error>, F> fmt::Debug for ServeUntil<S, B, F>
+where B::Item: AsRef<[u8]>
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("ServeUntil")
+    }
+}
+
 impl<S: fmt::Debug, B: Stream<Error=::Error>> fmt::Debug for Server<S, B>
 where B::Item: AsRef<[u8]>
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("Server")
          .field("reactor", &"...")
-         .field("listener", &self.listener)
+         .field("listeners", &self.listeners)
          .field("new_service", &self.new_service)
          .field("protocol", &self.protocol)
          .finish()
@@ -605,20 +3122,90 @@ where B::Item: AsRef<[u8]>
 pub trait RemoteAddr {
     fn remote(&self) -> SocketAddr;
 }
+pub trait LocalAddr {
+    fn local(&self) -> SocketAddr;
+
+    #[doc(hidden)]
+    fn socket_control(&self) -> Option<SocketControl> {
+        None
+    }
+}
+
 pub trait HasRemoteAddr {
     fn remote_addr(&mut self, addr: SocketAddr);
 }
 
+/// Details about a connection, reported by its IO object.
+///
+/// The connections of a `Serve` stream inject everything reported here into
+/// each `Request` read from them. This allows a TLS stream wrapping an
+/// `AddrStream` to expose the negotiated ALPN protocol and the peer
+/// certificate, besides the addresses.
+///
+/// Any IO implementing `RemoteAddr` and `LocalAddr`, like `AddrStream`,
+/// reports its addresses through this trait.
+pub trait ConnectionInfo {
+    /// The address of the remote peer.
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+
+    /// The local address the connection was accepted on.
+    fn local_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+
+    /// The protocol negotiated with ALPN, such as `b"http/1.1"`.
+    fn alpn_protocol(&self) -> Option<&[u8]> {
+        None
+    }
+
+    /// The DER encoded certificate presented by the peer.
+    fn peer_certificate(&self) -> Option<&[u8]> {
+        None
+    }
+
+    /// A handle adjusting the options of the TCP socket of the connection.
+    ///
+    /// A stream wrapping an `AddrStream` can forward
+    /// `AddrStream::socket_control`.
+    fn socket_control(&self) -> Option<SocketControl> {
+        None
+    }
+}
+
+/// The remote address of a connection, if known.
+impl ConnectionInfo for Option<SocketAddr> {
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        *self
+    }
+}
+
+impl<T: RemoteAddr + LocalAddr> ConnectionInfo for T {
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        Some(self.remote())
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        Some(self.local())
+    }
+
+    fn socket_control(&self) -> Option<SocketControl> {
+        LocalAddr::socket_control(self)
+    }
+}
+
 impl<I, S> Serve<I, S> {
-    /*
-    /// Spawn all incoming connections onto the provide executor.
+    /// Spawn all incoming connections onto the provided executor.
+    ///
+    /// The returned future resolves once the incoming stream ends, or the
+    /// executor is shut down.
     pub fn spawn_all<E>(self, executor: E) -> SpawnAll<I, S, E> {
         SpawnAll {
             executor: executor,
             serve: self,
         }
     }
-    */
 
     /// Get a reference to the incoming stream.
     #[inline]
@@ -630,7 +3217,7 @@ impl<I, S> Serve<I, S> {
 impl<I, S, B, SI> Stream for Serve<I, S>
 where
     I: Stream<Error=io::Error>,
-    I::Item: AsyncRead + AsyncWrite + RemoteAddr,
+    I::Item: AsyncRead + AsyncWrite + ConnectionInfo,
     S: NewService<Request=Request, Response=Response<B>, Error=::Error, Instance=SI>,
     SI: HasRemoteAddr + Service<Request=Request, Response=Response<B>, Error=::Error>,
     B: Stream<Error=::Error>,
@@ -641,26 +3228,132 @@ where
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
         if let Some(io) = try_ready!(self.incoming.poll()) {
-            let mut service = self.new_service.new_service()?;
-            service.remote_addr(io.remote());
-            Ok(Async::Ready(Some(self.protocol.serve_connection(io, service))))
+            let info = conn_info(&io);
+            self.connection(io, info).map(|conn| Async::Ready(Some(conn)))
         } else {
             Ok(Async::Ready(None))
         }
     }
 }
 
-// ===== impl SpawnAll =====
+impl<I, S, B, SI> Serve<I, S>
+where
+    S: NewService<Request=Request, Response=Response<B>, Error=::Error, Instance=SI>,
+    SI: HasRemoteAddr + Service<Request=Request, Response=Response<B>, Error=::Error>,
+    B: Stream<Error=::Error>,
+    B::Item: AsRef<[u8]>,
+{
+    fn connection<T>(&mut self, io: T, info: ConnInfo) -> ::Result<Connection<T, SI>>
+    where
+        T: AsyncRead + AsyncWrite,
+    {
+        let mut service = self.new_service.new_service()?;
+        if let Some(addr) = info.remote_addr {
+            service.remote_addr(addr);
+        }
+        let mut conn = self.protocol.new_connection(io, service, self.handle.as_ref());
+        conn.remote_addr = info.remote_addr;
+        conn.conn.set_remote_addr(info.remote_addr);
+        conn.conn.dispatch_mut().set_conn_info(info);
+        Ok(conn)
+    }
+}
+
+fn conn_info<T: ConnectionInfo>(info: &T) -> ConnInfo {
+    ConnInfo {
+        remote_addr: info.remote_addr(),
+        local_addr: info.local_addr(),
+        alpn_protocol: info.alpn_protocol().map(Bytes::from),
+        peer_certificate: info.peer_certificate().map(Bytes::from),
+        socket_control: info.socket_control(),
+    }
+}
+
+// ===== impl ServeWith =====
+
+impl<I, S, F> ServeWith<I, S, F> {
+    /// Get a reference to the incoming stream.
+    #[inline]
+    pub fn incoming_ref(&self) -> &I {
+        self.serve.incoming_ref()
+    }
+}
 
-/*
-impl<I, S, E> Future for SpawnAll<I, S, E>
+impl<I, S, F, M, B, SI> Stream for ServeWith<I, S, F>
 where
     I: Stream<Error=io::Error>,
     I::Item: AsyncRead + AsyncWrite,
-    S: NewService<Request=Request, Response=Response<B>, Error=::Error>,
+    S: NewService<Request=Request, Response=Response<B>, Error=::Error, Instance=SI>,
+    SI: HasRemoteAddr + Service<Request=Request, Response=Response<B>, Error=::Error>,
+    F: Fn(&I::Item) -> M,
+    M: ConnectionInfo,
+    B: Stream<Error=::Error>,
+    B::Item: AsRef<[u8]>,
+{
+    type Item = Connection<I::Item, S::Instance>;
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if let Some(io) = try_ready!(self.serve.incoming.poll()) {
+            let info = conn_info(&(self.info)(&io));
+            self.serve.connection(io, info).map(|conn| Async::Ready(Some(conn)))
+        } else {
+            Ok(Async::Ready(None))
+        }
+    }
+}
+
+impl<I: fmt::Debug, S: fmt::Debug, F> fmt::Debug for ServeWith<I, S, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ServeWith")
+            .field("serve", &self.serve)
+            .finish()
+    }
+}
+
+// ===== impl ServeAddr =====
+
+impl<I, S> ServeAddr<I, S> {
+    /// Get a reference to the incoming stream.
+    #[inline]
+    pub fn incoming_ref(&self) -> &I {
+        self.serve.incoming_ref()
+    }
+}
+
+impl<I, S, T, B, SI> Stream for ServeAddr<I, S>
+where
+    I: Stream<Item=(T, SocketAddr), Error=io::Error>,
+    T: AsyncRead + AsyncWrite,
+    S: NewService<Request=Request, Response=Response<B>, Error=::Error, Instance=SI>,
+    SI: HasRemoteAddr + Service<Request=Request, Response=Response<B>, Error=::Error>,
     B: Stream<Error=::Error>,
     B::Item: AsRef<[u8]>,
-    //E: Executor<Connection<I::Item, S::Instance>>,
+{
+    type Item = Connection<T, S::Instance>;
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if let Some((io, addr)) = try_ready!(self.serve.incoming.poll()) {
+            let info = conn_info(&Some(addr));
+            self.serve.connection(io, info).map(|conn| Async::Ready(Some(conn)))
+        } else {
+            Ok(Async::Ready(None))
+        }
+    }
+}
+
+// ===== impl SpawnAll =====
+
+impl<I, S, E, B, SI> Future for SpawnAll<I, S, E>
+where
+    I: Stream<Error=io::Error>,
+    I::Item: AsyncRead + AsyncWrite + ConnectionInfo + 'static,
+    S: NewService<Request=Request, Response=Response<B>, Error=::Error, Instance=SI>,
+    SI: HasRemoteAddr + Service<Request=Request, Response=Response<B>, Error=::Error> + 'static,
+    B: Stream<Error=::Error> + 'static,
+    B::Item: AsRef<[u8]>,
+    E: Executor<Box<Future<Item=(), Error=()>>>,
 {
     type Item = ();
     type Error = ::Error;
@@ -671,7 +3364,7 @@ where
                 let fut = conn
                     .map(|_| ())
                     .map_err(|err| debug!("conn error: {}", err));
-                match self.executor.execute(fut) {
+                match self.executor.execute(Box::new(fut)) {
                     Ok(()) => (),
                     Err(err) => match err.kind() {
                         ExecuteErrorKind::NoCapacity => {
@@ -690,128 +3383,194 @@ where
         }
     }
 }
-*/
-
-// ===== impl AddrIncoming =====
 
-impl AddrIncoming {
-    fn new(listener: TcpListener, handle: Handle, sleep_on_errors: bool) -> io::Result<AddrIncoming> {
-         Ok(AddrIncoming {
-            addr: listener.local_addr()?,
-            keep_alive_timeout: None,
-            listener: listener,
-            handle: handle,
-            sleep_on_errors: sleep_on_errors,
-            timeout: None,
-        })
-    }
-
-    /// Get the local address bound to this listener.
-    pub fn local_addr(&self) -> SocketAddr {
-        self.addr
+// Serves `socket` on `handle`, with `read_buf` holding bytes already read
+// from it.
+fn spawn_connection<S, B>(protocol: &Http<B::Item>,
+                          handle: &Handle,
+                          socket: AddrStream,
+                          info: ConnInfo,
+                          read_buf: &[u8],
+                          service: S,
+                          overloaded: bool,
+                          draining: Shared<oneshot::Receiver<()>>)
+    where S: Service<Request = Request, Response = Response<B>, Error = ::Error> + 'static,
+          B: Stream<Error=::Error> + 'static,
+          B::Item: AsRef<[u8]>,
+{
+    let addr = info.remote_addr.unwrap_or(socket.remote_addr);
+    let mut conn = protocol.serve_connection_handle(socket, handle, service);
+    conn.remote_addr = Some(addr);
+    conn.conn.set_remote_addr(Some(addr));
+    conn.conn.set_read_buf(read_buf);
+    conn.conn.dispatch_mut().set_conn_info(info);
+    let upgrades = UpgradeSlot::default();
+    conn.conn.dispatch_mut().set_upgrades(upgrades.clone());
+    if overloaded {
+        set_overloaded(&mut conn);
     }
+    let log_id = conn.log_id();
+    let fut = Draining::new(conn, draining)
+        .upgrading()
+        .map(move |upgraded| if let Some(upgraded) = upgraded {
+            upgrades.send(upgraded);
+        })
+        .map_err(move |err| log_connection_error(log_id, Some(addr), &err));
+    handle.spawn(fut);
+}
 
-    fn set_keepalive(&mut self, dur: Option<Duration>) {
-        self.keep_alive_timeout = dur;
+// Errors caused by the client, such as a malformed request or a reset
+// connection, are logged at a lower level than those of the server itself.
+fn log_connection_error(log_id: LogId, addr: Option<SocketAddr>, err: &::Error) {
+    let by_client = match err.kind() {
+        ::ErrorKind::Parse |
+        ::ErrorKind::Canceled |
+        ::ErrorKind::Timeout => true,
+        ::ErrorKind::Io => match *err {
+            ::Error::Io(ref e) => {
+                e.kind() == io::ErrorKind::ConnectionReset ||
+                e.kind() == io::ErrorKind::ConnectionAborted ||
+                e.kind() == io::ErrorKind::BrokenPipe ||
+                e.kind() == io::ErrorKind::UnexpectedEof
+            },
+            _ => false,
+        },
+        ::ErrorKind::User => false,
+        ::ErrorKind::__Nonexhaustive(..) => unreachable!(),
+    };
+    if by_client {
+        debug!("{}server connection error: ({}) {}", log_id, Peer(addr), err);
+    } else {
+        error!("{}server connection error: ({}) {}", log_id, Peer(addr), err);
     }
 }
 
-impl Stream for AddrIncoming {
-    // currently unnameable...
-    type Item = AddrStream;
-    type Error = ::std::io::Error;
+// The peer of a connection in the logs, which has no address over a Unix
+// socket.
+struct Peer(Option<SocketAddr>);
 
-    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        // Check if a previous timeout is active that was set by IO errors.
-        if let Some(ref mut to) = self.timeout {
-            match to.poll().expect("timeout never fails") {
-                Async::Ready(_) => {}
-                Async::NotReady => return Ok(Async::NotReady),
-            }
-        }
-        self.timeout = None;
-        loop {
-            match self.listener.accept() {
-                Ok((socket, addr)) => {
-                    if let Some(dur) = self.keep_alive_timeout {
-                        if let Err(e) = socket.set_keepalive(Some(dur)) {
-                            trace!("error trying to set TCP keepalive: {}", e);
-                        }
-                    }
-                    return Ok(Async::Ready(Some(AddrStream::new(socket, addr))));
-                },
-                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(Async::NotReady),
-                Err(ref e) if self.sleep_on_errors => {
-                    // Connection errors can be ignored directly, continue by
-                    // accepting the next request.
-                    if connection_error(e) {
-                        continue;
-                    }
-                    // Sleep 10ms.
-                    let delay = ::std::time::Duration::from_millis(10);
-                    debug!("accept error: {}; sleeping {:?}",
-                        e, delay);
-                    let mut timeout = Timeout::new(delay, &self.handle)
-                        .expect("can always set a timeout");
-                    let result = timeout.poll()
-                        .expect("timeout never fails");
-                    match result {
-                        Async::Ready(()) => continue,
-                        Async::NotReady => {
-                            self.timeout = Some(timeout);
-                            return Ok(Async::NotReady);
-                        }
-                    }
-                },
-                Err(e) => return Err(e),
-            }
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(addr) => fmt::Display::fmt(&addr, f),
+            None => f.write_str("unix socket"),
         }
     }
 }
 
-/// This function defines errors that are per-connection. Which basically
-/// means that if we get this error from `accept()` system call it means
-/// next connection might be ready to be accepted.
-///
-/// All other errors will incur a timeout before next `accept()` is performed.
-/// The timeout is useful to handle resource exhaustion errors like ENFILE
-/// and EMFILE. Otherwise, could enter into tight loop.
-fn connection_error(e: &io::Error) -> bool {
-    e.kind() == io::ErrorKind::ConnectionRefused ||
-    e.kind() == io::ErrorKind::ConnectionAborted ||
-    e.kind() == io::ErrorKind::ConnectionReset
-}
-
 mod addr_stream {
     use std::io::{self, Read, Write};
     use std::net::SocketAddr;
+    #[cfg(unix)]
+    use std::os::unix::io::{AsRawFd, RawFd};
+    #[cfg(windows)]
+    use std::os::windows::io::{AsRawSocket, RawSocket};
     use bytes::{Buf, BufMut};
-    use futures::Poll;
+    use futures::{Async, Poll};
     use tokio::net::TcpStream;
     use tokio_io::{AsyncRead, AsyncWrite};
-    use super::RemoteAddr;
+    use super::{LocalAddr, RemoteAddr, SocketControl};
 
+    /// A TCP connection accepted by an `AddrIncoming`.
     #[derive(Debug)]
     pub struct AddrStream {
         inner: TcpStream,
         pub(super) remote_addr: SocketAddr,
+        pub(super) local_addr: SocketAddr,
+        socket_control: SocketControl,
     }
 
     impl AddrStream {
-        pub(super) fn new(tcp: TcpStream, addr: SocketAddr) -> AddrStream {
+        pub(super) fn new(tcp: TcpStream, addr: SocketAddr, local_addr: SocketAddr) -> AddrStream {
             AddrStream {
+                socket_control: SocketControl::new(&tcp),
                 inner: tcp,
                 remote_addr: addr,
+                local_addr: local_addr,
             }
         }
     }
 
+    impl AddrStream {
+        /// The address of the remote peer.
+        pub fn remote_addr(&self) -> SocketAddr {
+            self.remote_addr
+        }
+
+        /// The local address this connection was accepted on.
+        pub fn local_addr(&self) -> SocketAddr {
+            self.local_addr
+        }
+
+        /// A handle adjusting the options of the socket of this connection,
+        /// usable until this stream is dropped.
+        pub fn socket_control(&self) -> SocketControl {
+            self.socket_control.clone()
+        }
+
+        /// A reference to the `TcpStream` of this connection.
+        ///
+        /// This allows reading socket options, or integrating with tools
+        /// that need the socket itself. Reading from or writing to the socket
+        /// directly, including through its raw descriptor, is unsupported
+        /// while the connection is being served, and would corrupt the HTTP
+        /// messages.
+        pub fn get_ref(&self) -> &TcpStream {
+            &self.inner
+        }
+
+        /// Read the first bytes received on this connection into `buf`,
+        /// without consuming them.
+        ///
+        /// The bytes stay in the buffer of the socket (`MSG_PEEK`), so they
+        /// are read again by the next `read`, such as by the connection
+        /// serving this stream. This allows sniffing the protocol of a client
+        /// before deciding how to serve it, for instance TLS or plaintext.
+        /// Returns `NotReady` until some bytes were received, and 0 once the
+        /// peer closed the connection.
+        pub fn peek(&mut self, buf: &mut [u8]) -> Poll<usize, io::Error> {
+            Ok(Async::Ready(try_nb!(self.inner.peek(buf))))
+        }
+    }
+
+    impl Drop for AddrStream {
+        fn drop(&mut self) {
+            self.socket_control.close();
+        }
+    }
+
     impl RemoteAddr for AddrStream {
         fn remote(&self) -> SocketAddr {
             self.remote_addr
         }
     }
 
+    /// See `AddrStream::get_ref` about using the raw descriptor.
+    #[cfg(unix)]
+    impl AsRawFd for AddrStream {
+        fn as_raw_fd(&self) -> RawFd {
+            self.inner.as_raw_fd()
+        }
+    }
+
+    /// See `AddrStream::get_ref` about using the raw socket.
+    #[cfg(windows)]
+    impl AsRawSocket for AddrStream {
+        fn as_raw_socket(&self) -> RawSocket {
+            self.inner.as_raw_socket()
+        }
+    }
+
+    impl LocalAddr for AddrStream {
+        fn local(&self) -> SocketAddr {
+            self.local_addr
+        }
+
+        fn socket_control(&self) -> Option<SocketControl> {
+            Some(self.socket_control.clone())
+        }
+    }
+
     impl Read for AddrStream {
         #[inline]
         fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
@@ -858,17 +3617,19 @@ mod addr_stream {
 
 // ===== SocketAddrService
 
-// This is used from `Server::run`, which captures the remote address
-// in this service, and then injects it into each `Request`.
+// This is used from `Server::run`, which captures the remote and local
+// addresses in this service, and then injects them into each `Request`.
 struct SocketAddrService<S> {
-    addr: SocketAddr,
+    addr: Option<SocketAddr>,
+    local_addr: Option<SocketAddr>,
     inner: S,
 }
 
 impl<S> SocketAddrService<S> {
-    fn new(addr: SocketAddr, service: S) -> SocketAddrService<S> {
+    fn new(addr: Option<SocketAddr>, local_addr: Option<SocketAddr>, service: S) -> SocketAddrService<S> {
         SocketAddrService {
             addr: addr,
+            local_addr: local_addr,
             inner: service,
         }
     }
@@ -884,65 +3645,168 @@ where
     type Future = S::Future;
 
     fn call(&self, mut req: Self::Request) -> Self::Future {
-        proto::request::addr(&mut req, self.addr);
+        // an address injected by the connection wins, as it may come from
+        // the header of a trusted proxy, see `Http::trust_forwarded_for`
+        #[allow(deprecated)]
+        let injected = req.remote_addr().is_some();
+        match self.addr {
+            Some(addr) if !injected => proto::request::addr(&mut req, addr),
+            _ => (),
+        }
+        if let Some(local_addr) = self.local_addr {
+            proto::request::local_addr(&mut req, local_addr);
+        }
         self.inner.call(req)
     }
 }
 
-// ===== NotifyService =====
+// Both shutdown phases at once, when a signal resolves or fails.
+struct SignalPhases<F> {
+    signal: Option<F>,
+    drain: bool,
+}
 
-struct NotifyService<S> {
-    inner: S,
-    info: Weak<RefCell<Info>>,
+impl<F> SignalPhases<F> {
+    fn new(signal: F) -> SignalPhases<F> {
+        SignalPhases {
+            signal: Some(signal),
+            drain: false,
+        }
+    }
 }
 
-struct WaitUntilZero {
-    info: Rc<RefCell<Info>>,
+impl<F: Future> Stream for SignalPhases<F> {
+    type Item = ShutdownPhase;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<ShutdownPhase>, ()> {
+        if let Some(mut signal) = self.signal.take() {
+            match signal.poll() {
+                Ok(Async::NotReady) => {
+                    self.signal = Some(signal);
+                    return Ok(Async::NotReady);
+                },
+                Ok(Async::Ready(_)) | Err(_) => {
+                    self.drain = true;
+                    return Ok(Async::Ready(Some(ShutdownPhase::StopAccepting)));
+                },
+            }
+        }
+        if self.drain {
+            self.drain = false;
+            Ok(Async::Ready(Some(ShutdownPhase::Drain)))
+        } else {
+            Ok(Async::Ready(None))
+        }
+    }
 }
 
-struct Info {
-    active: usize,
-    blocker: Option<Task>,
+// ===== Draining =====
+
+// Wraps a connection spawned from `Server::run_until`, starting a graceful
+// shutdown of it once the server itself starts shutting down.
+//
+// Once `upgrading`, it resolves with the IO of a connection the service
+// took over, instead of shutting it down.
+struct Draining<I, S>
+where
+    S: HyperService,
+    S::ResponseBody: Stream<Error=::Error>,
+    <S::ResponseBody as Stream>::Item: AsRef<[u8]>,
+{
+    conn: Option<Connection<I, S>>,
+    signal: Option<Shared<oneshot::Receiver<()>>>,
+    upgrades: bool,
 }
 
-impl<S: Service> Service for NotifyService<S> {
-    type Request = S::Request;
-    type Response = S::Response;
-    type Error = S::Error;
-    type Future = S::Future;
+impl<I, S, B> Draining<I, S>
+where
+    S: Service<Request=Request, Response=Response<B>, Error=::Error> + 'static,
+    I: AsyncRead + AsyncWrite + 'static,
+    B: Stream<Error=::Error> + 'static,
+    B::Item: AsRef<[u8]>,
+{
+    fn new(conn: Connection<I, S>, signal: Shared<oneshot::Receiver<()>>) -> Draining<I, S> {
+        Draining {
+            conn: Some(conn),
+            signal: Some(signal),
+            upgrades: false,
+        }
+    }
 
-    fn call(&self, message: Self::Request) -> Self::Future {
-        self.inner.call(message)
+    fn upgrading(mut self) -> Draining<I, S> {
+        self.upgrades = true;
+        self
     }
 }
 
-impl<S> Drop for NotifyService<S> {
-    fn drop(&mut self) {
-        let info = match self.info.upgrade() {
-            Some(info) => info,
-            None => return,
+impl<I, S, B> Future for Draining<I, S>
+where
+    S: Service<Request=Request, Response=Response<B>, Error=::Error> + 'static,
+    I: AsyncRead + AsyncWrite + 'static,
+    B: Stream<Error=::Error> + 'static,
+    B::Item: AsRef<[u8]>,
+{
+    type Item = Option<Upgraded<I>>;
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let draining = match self.signal {
+            Some(ref mut signal) => match signal.poll() {
+                Ok(Async::NotReady) => false,
+                // a dropped sender also means the server is going away
+                Ok(Async::Ready(_)) | Err(_) => true,
+            },
+            None => false,
         };
-        let mut info = info.borrow_mut();
-        info.active -= 1;
-        if info.active == 0 {
-            if let Some(task) = info.blocker.take() {
-                task.notify();
+        let polled = {
+            let conn = self.conn.as_mut().expect("Draining polled after completion");
+            if draining {
+                trace!("{}server shutting down, draining connection", conn.log_id());
+                self.signal = None;
+                conn.graceful_shutdown();
+            }
+            if self.upgrades {
+                conn.poll_until_upgraded()
+            } else {
+                conn.poll().map(|async| async.map(|()| false))
             }
+        };
+        let upgraded = match polled {
+            Ok(Async::Ready(upgraded)) => upgraded,
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Err(e) => {
+                self.conn = None;
+                return Err(e);
+            }
+        };
+        if !upgraded {
+            self.conn = None;
+            return Ok(Async::Ready(None));
         }
+        let conn = self.conn.take().expect("checked above");
+        trace!("{}connection taken over by the service", conn.log_id());
+        Ok(Async::Ready(Some(conn.upgraded())))
     }
 }
 
-impl Future for WaitUntilZero {
-    type Item = ();
-    type Error = io::Error;
-
-    fn poll(&mut self) -> Poll<(), io::Error> {
-        let mut info = self.info.borrow_mut();
-        if info.active == 0 {
-            Ok(().into())
-        } else {
-            info.blocker = Some(task::current());
-            Ok(Async::NotReady)
+// A connection dropped before it was done, such as by a forced shutdown,
+// loses the bytes it couldn't flush yet.
+impl<I, S> Drop for Draining<I, S>
+where
+    S: HyperService,
+    S::ResponseBody: Stream<Error=::Error>,
+    <S::ResponseBody as Stream>::Item: AsRef<[u8]>,
+{
+    fn drop(&mut self) {
+        if let Some(ref conn) = self.conn {
+            let pending = conn.pending_write_bytes();
+            if pending > 0 {
+                match conn.remote_addr {
+                    Some(addr) => debug!("dropped connection ({}) with {} unflushed bytes", addr, pending),
+                    None => debug!("dropped connection with {} unflushed bytes", pending),
+                }
+            }
         }
     }
 }
@@ -998,3 +3862,148 @@ mod hyper_service {
         type Sealed = Opaque;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::sync::{mpsc, Arc};
+    use std::thread;
+    use std::time::Duration;
+
+    use futures::future;
+    use tokio::reactor::{Core, Timeout};
+
+    use proto;
+    use super::{DATE_INTERVALS, Http, Response, date_render_interval, service_fn};
+
+    #[test]
+    fn date_interval_shared_by_servers_on_one_core() {
+        fn rendered_date() -> String {
+            let mut dst = Vec::new();
+            proto::date::extend(&mut dst);
+            String::from_utf8(dst).unwrap()
+        }
+
+        let mut core = Core::new().unwrap();
+        let first = date_render_interval(&core.handle());
+        let second = date_render_interval(&core.handle());
+        let intervals = DATE_INTERVALS.with(|intervals| intervals.borrow().len());
+        assert_eq!(intervals, 1);
+
+        drop(first);
+        let intervals = DATE_INTERVALS.with(|intervals| intervals.borrow().len());
+        assert_eq!(intervals, 1);
+
+        // with an interval running, the date is only rendered by the interval
+        let before = rendered_date();
+        let wait = Timeout::new(Duration::from_millis(2100), &core.handle()).unwrap();
+        core.run(wait).unwrap();
+        assert_ne!(rendered_date(), before, "date was not updated after a server stopped");
+
+        drop(second);
+        let intervals = DATE_INTERVALS.with(|intervals| intervals.borrow().len());
+        assert_eq!(intervals, 0);
+    }
+
+    #[test]
+    fn run_threads_shared_round_robin() {
+        let (addr_tx, addr_rx) = mpsc::channel();
+        thread::spawn(move || {
+            let addr = "127.0.0.1:0".parse().unwrap();
+            let srv = Http::new().bind(&addr, || Ok(service_fn(|_req| {
+                let name = thread::current().name().unwrap_or("").to_owned();
+                future::ok::<_, ::Error>(Response::<::Body>::new().with_body(name))
+            }))).unwrap();
+            addr_tx.send(srv.local_addr().unwrap()).unwrap();
+            srv.run_threads_shared(2, Arc::new(future::empty::<(), ()>)).unwrap();
+        });
+        let addr = addr_rx.recv().unwrap();
+
+        let names = (0..4).map(|_| {
+            let mut tcp = TcpStream::connect(&addr).unwrap();
+            tcp.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+            tcp.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+            let mut res = String::new();
+            tcp.read_to_string(&mut res).unwrap();
+            assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", res);
+            let body = res.find("\r\n\r\n").unwrap() + 4;
+            res[body..].to_owned()
+        }).collect::<HashSet<_>>();
+
+        assert_eq!(names.len(), 2, "{:?}", names);
+    }
+
+    #[test]
+    fn run_pool_skips_busy_workers() {
+        use std::sync::Mutex;
+
+        let (addr_tx, addr_rx) = mpsc::channel();
+        let (blocked_tx, blocked_rx) = mpsc::channel();
+        let blocked_tx = Arc::new(Mutex::new(blocked_tx));
+        thread::spawn(move || {
+            let addr = "127.0.0.1:0".parse().unwrap();
+            let srv = Http::new().bind(&addr, move || {
+                let blocked_tx = blocked_tx.clone();
+                Ok(service_fn(move |req: ::server::Request| {
+                    let name = thread::current().name().unwrap_or("").to_owned();
+                    if req.path() == "/block" {
+                        // keeps the whole thread of this worker busy
+                        blocked_tx.lock().unwrap().send(name.clone()).unwrap();
+                        thread::sleep(Duration::from_secs(2));
+                    }
+                    future::ok::<_, ::Error>(Response::<::Body>::new().with_body(name))
+                }))
+            }).unwrap();
+            addr_tx.send(srv.local_addr().unwrap()).unwrap();
+            srv.run_pool_until(2, Arc::new(future::empty::<(), ()>)).unwrap();
+        });
+        let addr = addr_rx.recv().unwrap();
+
+        let mut block = TcpStream::connect(&addr).unwrap();
+        block.write_all(b"GET /block HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+        let blocked = blocked_rx.recv_timeout(Duration::from_secs(1)).unwrap();
+
+        // round-robin would hand every other connection to the blocked worker
+        for _ in 0..4 {
+            let mut tcp = TcpStream::connect(&addr).unwrap();
+            tcp.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+            tcp.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+            let mut res = String::new();
+            tcp.read_to_string(&mut res).unwrap();
+            assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", res);
+            let body = res.find("\r\n\r\n").unwrap() + 4;
+            assert_ne!(&res[body..], blocked);
+        }
+    }
+
+    #[test]
+    fn addr_stream_peek_leaves_bytes_to_read() {
+        use std::io;
+        use futures::{Async, Poll, Stream};
+        use tokio::net::TcpListener;
+
+        let mut core = Core::new().unwrap();
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+        let mut incoming = Http::<::Chunk>::new().addr_incoming(listener, core.handle()).unwrap();
+        let mut tcp = TcpStream::connect(&incoming.local_addr()).unwrap();
+        let mut stream = core.run(future::poll_fn(|| incoming.poll())).unwrap().unwrap();
+
+        let client = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            tcp.write_all(b"\x16\x03\x01hello").unwrap();
+            tcp
+        });
+        let mut buf = [0; 3];
+        let n = core.run(future::poll_fn(|| stream.peek(&mut buf))).unwrap();
+        assert_eq!(&buf[..n], b"\x16\x03\x01");
+        let _tcp = client.join().unwrap();
+
+        let mut buf = [0; 8];
+        let n = core.run(future::poll_fn(|| -> Poll<usize, io::Error> {
+            Ok(Async::Ready(try_nb!(stream.read(&mut buf))))
+        })).unwrap();
+        assert_eq!(&buf[..n], b"\x16\x03\x01hello");
+    }
+}
@@ -0,0 +1,258 @@
+//! Reading the PROXY protocol header a load balancer sends ahead of HTTP.
+//!
+//! See https://www.haproxy.org/download/1.8/doc/proxy-protocol.txt
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::{Async, Future, Poll};
+use tokio::reactor::Timeout;
+use tokio_io::AsyncRead;
+
+// the longest possible v1 header, CRLF included
+const V1_MAX_LEN: usize = 107;
+const V2_SIGNATURE: &'static [u8] = b"\r\n\r\n\0\r\nQUIT\n";
+const V2_HEADER_LEN: usize = 16;
+const READ_SIZE: usize = 512;
+
+/// A future reading the PROXY header of a connection.
+///
+/// Yields the IO, the address of the client announced by the header, and
+/// the bytes read after the header. The address is `None` when the header
+/// doesn't carry one, such as for health checks of the load balancer.
+pub(super) struct ReadProxyHeader<T> {
+    io: Option<T>,
+    buf: BytesMut,
+    timeout: Option<Timeout>,
+}
+
+impl<T> ReadProxyHeader<T> {
+    pub(super) fn new(io: T, timeout: Option<Timeout>) -> ReadProxyHeader<T> {
+        ReadProxyHeader {
+            io: Some(io),
+            buf: BytesMut::new(),
+            timeout: timeout,
+        }
+    }
+}
+
+impl<T: AsyncRead> Future for ReadProxyHeader<T> {
+    type Item = (T, Option<SocketAddr>, Bytes);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, io::Error> {
+        loop {
+            if let Some((addr, len)) = parse(&self.buf)? {
+                let rest = self.buf.split_off(len).freeze();
+                let io = self.io.take().expect("ReadProxyHeader polled after completion");
+                return Ok(Async::Ready((io, addr, rest)));
+            }
+            if let Some(ref mut timeout) = self.timeout {
+                if let Async::Ready(()) = timeout.poll()? {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "timed out reading PROXY header"));
+                }
+            }
+            if self.buf.remaining_mut() == 0 {
+                self.buf.reserve(READ_SIZE);
+            }
+            let io = self.io.as_mut().expect("ReadProxyHeader polled after completion");
+            if try_ready!(io.read_buf(&mut self.buf)) == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed before PROXY header"));
+            }
+        }
+    }
+}
+
+/// Parse a PROXY header at the start of `buf`.
+///
+/// Returns `None` if more bytes are needed, or else the address of the
+/// client and the length of the header.
+fn parse(buf: &[u8]) -> io::Result<Option<(Option<SocketAddr>, usize)>> {
+    if buf.starts_with(b"PROXY ") {
+        parse_v1(buf)
+    } else if buf.starts_with(V2_SIGNATURE) {
+        parse_v2(buf)
+    } else if b"PROXY ".starts_with(buf) || V2_SIGNATURE.starts_with(buf) {
+        Ok(None)
+    } else {
+        Err(invalid("missing PROXY header"))
+    }
+}
+
+fn parse_v1(buf: &[u8]) -> io::Result<Option<(Option<SocketAddr>, usize)>> {
+    let end = match buf.iter().take(V1_MAX_LEN).position(|&b| b == b'\n') {
+        Some(end) => end,
+        None if buf.len() < V1_MAX_LEN => return Ok(None),
+        None => return Err(invalid("PROXY v1 header is too long")),
+    };
+    if end == 0 || buf[end - 1] != b'\r' {
+        return Err(invalid("PROXY v1 header doesn't end with CRLF"));
+    }
+    let line = str::from_utf8(&buf[..end - 1])
+        .map_err(|_| invalid("PROXY v1 header is not ASCII"))?;
+    let mut parts = line.split(' ').skip(1);
+    let addr = match parts.next() {
+        // the rest of the line is to be ignored
+        Some("UNKNOWN") => None,
+        Some(proto @ "TCP4") | Some(proto @ "TCP6") => {
+            let parts = parts.collect::<Vec<_>>();
+            if parts.len() != 4 {
+                return Err(invalid("invalid PROXY v1 header"));
+            }
+            let ip = parts[0].parse::<IpAddr>()
+                .map_err(|_| invalid("invalid PROXY v1 source address"))?;
+            if ip.is_ipv4() != (proto == "TCP4") {
+                return Err(invalid("invalid PROXY v1 source address"));
+            }
+            let port = parts[2].parse::<u16>()
+                .map_err(|_| invalid("invalid PROXY v1 source port"))?;
+            Some(SocketAddr::new(ip, port))
+        },
+        _ => return Err(invalid("invalid PROXY v1 protocol")),
+    };
+    Ok(Some((addr, end + 1)))
+}
+
+fn parse_v2(buf: &[u8]) -> io::Result<Option<(Option<SocketAddr>, usize)>> {
+    if buf.len() < V2_HEADER_LEN {
+        return Ok(None);
+    }
+    let len = V2_HEADER_LEN + ((buf[14] as usize) << 8 | buf[15] as usize);
+    if buf.len() < len {
+        return Ok(None);
+    }
+    if buf[12] >> 4 != 2 {
+        return Err(invalid("invalid PROXY v2 version"));
+    }
+    let addrs = &buf[V2_HEADER_LEN..len];
+    let addr = match buf[12] & 0x0f {
+        // LOCAL: a connection of the load balancer itself
+        0 => None,
+        // PROXY
+        1 => match buf[13] >> 4 {
+            // AF_UNSPEC
+            0 => None,
+            // AF_INET
+            1 => {
+                if addrs.len() < 12 {
+                    return Err(invalid("invalid PROXY v2 IPv4 addresses"));
+                }
+                let mut ip = [0; 4];
+                ip.copy_from_slice(&addrs[..4]);
+                let port = (addrs[8] as u16) << 8 | addrs[9] as u16;
+                Some(SocketAddr::new(IpAddr::V4(Ipv4Addr::from(ip)), port))
+            },
+            // AF_INET6
+            2 => {
+                if addrs.len() < 36 {
+                    return Err(invalid("invalid PROXY v2 IPv6 addresses"));
+                }
+                let mut ip = [0; 16];
+                ip.copy_from_slice(&addrs[..16]);
+                let port = (addrs[32] as u16) << 8 | addrs[33] as u16;
+                Some(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(ip)), port))
+            },
+            // AF_UNIX, no useful address
+            3 => None,
+            _ => return Err(invalid("invalid PROXY v2 address family")),
+        },
+        _ => return Err(invalid("invalid PROXY v2 command")),
+    };
+    Ok(Some((addr, len)))
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use super::{parse, V2_SIGNATURE};
+
+    fn v2(cmd: u8, family: u8, addrs: &[u8]) -> Vec<u8> {
+        let mut buf = V2_SIGNATURE.to_vec();
+        buf.push(0x20 | cmd);
+        buf.push(family);
+        buf.push((addrs.len() >> 8) as u8);
+        buf.push(addrs.len() as u8);
+        buf.extend_from_slice(addrs);
+        buf
+    }
+
+    #[test]
+    fn test_parse_v1() {
+        let buf = b"PROXY TCP4 192.168.0.1 10.0.0.1 56324 443\r\nGET / HTTP/1.1\r\n";
+        assert_eq!(parse(buf).unwrap(), Some((Some("192.168.0.1:56324".parse().unwrap()), 43)));
+
+        let buf = b"PROXY TCP6 2001:db8::1 ::1 4000 80\r\n";
+        assert_eq!(parse(buf).unwrap(), Some((Some("[2001:db8::1]:4000".parse().unwrap()), buf.len())));
+
+        let buf = b"PROXY UNKNOWN ffff::1 ::1 4000 80\r\n";
+        assert_eq!(parse(buf).unwrap(), Some((None, buf.len())));
+    }
+
+    #[test]
+    fn test_parse_v1_incomplete() {
+        assert_eq!(parse(b"").unwrap(), None);
+        assert_eq!(parse(b"PRO").unwrap(), None);
+        assert_eq!(parse(b"PROXY TCP4 192.168.0.1").unwrap(), None);
+    }
+
+    #[test]
+    fn test_parse_v1_invalid() {
+        let invalid = |buf: &[u8]| parse(buf).unwrap_err().kind() == io::ErrorKind::InvalidData;
+        assert!(invalid(b"GET / HTTP/1.1\r\n"));
+        assert!(invalid(b"PROXY TCP4 192.168.0.1 10.0.0.1 56324 443\n"));
+        assert!(invalid(b"PROXY TCP4 2001:db8::1 10.0.0.1 56324 443\r\n"));
+        assert!(invalid(b"PROXY TCP4 192.168.0.1 10.0.0.1 99999 443\r\n"));
+        assert!(invalid(b"PROXY UDP4 192.168.0.1 10.0.0.1 56324 443\r\n"));
+        assert!(invalid(b"PROXY TCP4 192.168.0.1 10.0.0.1 56324\r\n"));
+        assert!(invalid(&[b'P', b'R', b'O', b'X', b'Y', b' '].iter().cloned().chain(vec![b'x'; 120]).collect::<Vec<_>>()));
+    }
+
+    #[test]
+    fn test_parse_v2() {
+        let buf = v2(1, 0x11, &[192, 168, 0, 1, 10, 0, 0, 1, 0xdc, 0x04, 0x01, 0xbb]);
+        assert_eq!(parse(&buf).unwrap(), Some((Some("192.168.0.1:56324".parse().unwrap()), 28)));
+
+        let mut addrs = vec![0; 36];
+        addrs[0] = 0x20;
+        addrs[1] = 0x01;
+        addrs[15] = 1;
+        addrs[32] = 0x0f;
+        addrs[33] = 0xa0;
+        let buf = v2(1, 0x21, &addrs);
+        assert_eq!(parse(&buf).unwrap(), Some((Some("[2001::1]:4000".parse().unwrap()), 52)));
+
+        // with TLVs after the addresses, and HTTP bytes after the header
+        let mut buf = v2(1, 0x11, &[192, 168, 0, 1, 10, 0, 0, 1, 0xdc, 0x04, 0x01, 0xbb, 0x04, 0x00, 0x01, 0xff]);
+        buf.extend_from_slice(b"GET / HTTP/1.1\r\n");
+        assert_eq!(parse(&buf).unwrap(), Some((Some("192.168.0.1:56324".parse().unwrap()), 32)));
+
+        let buf = v2(0, 0x00, &[]);
+        assert_eq!(parse(&buf).unwrap(), Some((None, 16)));
+    }
+
+    #[test]
+    fn test_parse_v2_incomplete() {
+        let buf = v2(1, 0x11, &[192, 168, 0, 1, 10, 0, 0, 1, 0xdc, 0x04, 0x01, 0xbb]);
+        assert_eq!(parse(&buf[..8]).unwrap(), None);
+        assert_eq!(parse(&buf[..15]).unwrap(), None);
+        assert_eq!(parse(&buf[..27]).unwrap(), None);
+    }
+
+    #[test]
+    fn test_parse_v2_invalid() {
+        let invalid = |buf: &[u8]| parse(buf).unwrap_err().kind() == io::ErrorKind::InvalidData;
+        let mut buf = v2(1, 0x11, &[192, 168, 0, 1, 10, 0, 0, 1, 0xdc, 0x04, 0x01, 0xbb]);
+        buf[12] = 0x11;
+        assert!(invalid(&buf));
+        assert!(invalid(&v2(2, 0x11, &[0; 12])));
+        assert!(invalid(&v2(1, 0x11, &[0; 8])));
+        assert!(invalid(&v2(1, 0x21, &[0; 12])));
+        assert!(invalid(&v2(1, 0x41, &[0; 12])));
+    }
+}
@@ -103,10 +103,37 @@ impl<T, B> ServerProto<T> for Http<B>
         if !self.keep_alive {
             conn.disable_keep_alive();
         }
+        conn.set_http10_keep_alive(self.http10_keep_alive);
         conn.set_flush_pipeline(self.pipeline);
+        conn.set_max_pipeline_depth(self.max_pipeline_depth);
+        conn.set_max_requests(self.max_requests_per_connection.map(|max| max as u64));
+        conn.set_max_body_size(self.max_body_size.map(|max| max as u64));
+        conn.set_reject_conflicting_framing(self.reject_conflicting_framing);
+        conn.set_require_host(self.require_host_header);
+        conn.set_max_headers(self.max_headers);
+        conn.set_max_head_size(Some(self.max_header_list_size));
+        conn.set_max_uri_len(Some(self.max_uri_length));
+        conn.set_auto_date(self.auto_date);
+        if let Some(ref clock) = self.date_clock {
+            conn.set_date_clock(clock.0.clone());
+        }
+        conn.set_server_header(self.server_header.clone());
         if let Some(max) = self.max_buf_size {
             conn.set_max_buf_size(max);
         }
+        if let Some(size) = self.initial_read_buf_size {
+            conn.set_read_buf_init(size);
+        }
+        conn.set_write_buf_limit(self.body_write_buffer_limit);
+        if let Some(ref f) = self.on_exchange_complete {
+            conn.set_on_exchange_complete(f.0.clone());
+        }
+        if let Some(ref f) = self.on_write_drained {
+            conn.set_on_write_drained(f.0.clone());
+        }
+        if let Some(ref f) = self.on_parse_error {
+            conn.set_on_parse_error(f.0.clone());
+        }
         __ProtoBindTransport {
             inner: future::ok(conn),
         }
@@ -1,10 +1,44 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io;
 use std::marker::PhantomData;
-use std::sync::Arc;
+use std::panic::{self, AssertUnwindSafe};
+use std::str;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use futures::IntoFuture;
+use bytes::Bytes;
+use futures::{Future, IntoFuture, Poll, Stream};
+use futures::future::{self, FutureResult};
+use futures::stream::Concat2;
+use futures_cpupool::{CpuFuture, CpuPool};
 use tokio_service::{NewService, Service};
 
+use header::{Allow, CacheControl, CacheDirective, ContentLength, Headers, Host};
+use {Body, Method, Request, Response, StatusCode};
+
 /// Create a `Service` from a function.
+///
+/// The function can return anything implementing `IntoFuture`, such as a
+/// boxed future, a `Result`, or a combinator chain, without boxing it first.
+///
+/// # Example
+///
+/// ```
+/// # extern crate futures;
+/// # extern crate hyper;
+/// use futures::{Future, Stream};
+/// use hyper::server::{Request, Response, service_fn};
+///
+/// # fn main() {
+/// let service = service_fn(|req: Request| {
+///     req.body()
+///         .concat2()
+///         .map(|body| Response::<hyper::Body>::new().with_body(body))
+/// });
+/// # drop(service);
+/// # }
+/// ```
 pub fn service_fn<F, R, S>(f: F) -> ServiceFn<F, R>
 where
     F: Fn(R) -> S,
@@ -16,6 +50,77 @@ where
     }
 }
 
+/// Create a `Service` from a function that responds synchronously.
+///
+/// The returned value is wrapped in an already completed future, so the
+/// function doesn't have to build one.
+///
+/// # Example
+///
+/// ```
+/// # extern crate hyper;
+/// use hyper::server::{Request, Response, service_fn_ok};
+///
+/// # fn main() {
+/// let service = service_fn_ok(|req: Request| {
+///     Response::<hyper::Body>::new().with_body(req.path().to_owned())
+/// });
+/// # drop(service);
+/// # }
+/// ```
+pub fn service_fn_ok<F, R, S>(f: F) -> ServiceFnOk<F, R>
+where
+    F: Fn(R) -> S,
+{
+    ServiceFnOk {
+        f: f,
+        _req: PhantomData,
+    }
+}
+
+/// Create a `Service` calling a blocking `handler` on `pool`.
+///
+/// A service shares its thread with the reactor of the connections it
+/// serves, so a handler sleeping, doing blocking I/O or heavy computation
+/// in `Service::call` stalls all of them meanwhile. With this adapter, each
+/// request is instead passed to `handler` on a thread of `pool`, and the
+/// future of the response resolves once it has returned, the reactor
+/// serving the other connections in the meantime.
+///
+/// A panic of `handler` fails the request with an `Error::Io`, without
+/// reaching the reactor.
+///
+/// # Example
+///
+/// ```
+/// # extern crate futures_cpupool;
+/// # extern crate hyper;
+/// use futures_cpupool::CpuPool;
+/// use hyper::server::{Http, Request, Response, blocking_service, const_service};
+///
+/// # fn main() {
+/// let pool = CpuPool::new(4);
+/// let service = blocking_service(pool, |req: Request| {
+///     // such as a synchronous database query
+///     let page = format!("rendered {}", req.path());
+///     Response::<hyper::Body>::new().with_body(page)
+/// });
+/// let addr = "127.0.0.1:0".parse().unwrap();
+/// let server = Http::new().bind(&addr, const_service(service)).unwrap();
+/// # drop(server);
+/// # }
+/// ```
+pub fn blocking_service<F, B>(pool: CpuPool, handler: F) -> BlockingService<F>
+where
+    F: Fn(Request) -> Response<B> + Send + Sync + 'static,
+    B: Send + 'static,
+{
+    BlockingService {
+        pool: pool,
+        handler: Arc::new(handler),
+    }
+}
+
 /// Create a `NewService` by sharing references of `service.
 pub fn const_service<S>(service: S) -> ConstService<S> {
     ConstService {
@@ -23,6 +128,65 @@ pub fn const_service<S>(service: S) -> ConstService<S> {
     }
 }
 
+/// Create a `NewService` retrying the `new_service` of `inner` when it fails.
+///
+/// A service is asked of `inner` up to `attempts` times in a row. This
+/// helps with factories touching a flaky resource, such as a connection
+/// pool that sometimes fails to hand out a connection. The last error is
+/// returned if every attempt fails, and a `Server` then closes the
+/// connection the service was for, still serving the others.
+///
+/// The attempts don't wait between them: a `Server` asks for services on
+/// the thread of its reactor, where sleeping would hold up every other
+/// connection.
+///
+/// # Panics
+///
+/// Panics if `attempts` is zero.
+pub fn retry_new_service<N>(inner: N, attempts: usize) -> RetryNewService<N> {
+    assert!(attempts > 0, "retry_new_service needs at least one attempt");
+    RetryNewService {
+        inner: inner,
+        attempts: attempts,
+    }
+}
+
+/// Wrap `inner` in a `CacheService`, keeping up to `max_bytes` of response
+/// bodies in memory.
+///
+/// # Example
+///
+/// ```
+/// # extern crate hyper;
+/// use hyper::header::{CacheControl, CacheDirective, ContentLength};
+/// use hyper::server::{Http, Request, Response, cache_service, const_service, service_fn_ok};
+///
+/// # fn main() {
+/// let assets = service_fn_ok(|_req: Request| {
+///     Response::<hyper::Body>::new()
+///         .with_header(CacheControl(vec![CacheDirective::MaxAge(60)]))
+///         .with_header(ContentLength(5))
+///         .with_body("hello")
+/// });
+/// let new_service = const_service(cache_service(assets, 16 * 1024 * 1024));
+/// let addr = "127.0.0.1:0".parse().unwrap();
+/// let server = Http::new().bind(&addr, new_service).unwrap();
+/// # drop(server);
+/// # }
+/// ```
+pub fn cache_service<S>(inner: S, max_bytes: usize) -> CacheService<S> {
+    CacheService {
+        inner: inner,
+        cache: Arc::new(Mutex::new(Cache {
+            entries: HashMap::new(),
+            lru: BTreeMap::new(),
+            tick: 0,
+            bytes: 0,
+            max_bytes: max_bytes,
+        })),
+    }
+}
+
 #[derive(Debug)]
 pub struct ServiceFn<F, R> {
     f: F,
@@ -44,6 +208,68 @@ where
     }
 }
 
+#[derive(Debug)]
+pub struct ServiceFnOk<F, R> {
+    f: F,
+    _req: PhantomData<fn() -> R>,
+}
+
+impl<F, R, S> Service for ServiceFnOk<F, R>
+where
+    F: Fn(R) -> S,
+{
+    type Request = R;
+    type Response = S;
+    type Error = ::Error;
+    type Future = FutureResult<S, ::Error>;
+
+    fn call(&self, req: Self::Request) -> Self::Future {
+        future::ok((self.f)(req))
+    }
+}
+
+pub struct BlockingService<F> {
+    pool: CpuPool,
+    handler: Arc<F>,
+}
+
+impl<F> Clone for BlockingService<F> {
+    fn clone(&self) -> BlockingService<F> {
+        BlockingService {
+            pool: self.pool.clone(),
+            handler: self.handler.clone(),
+        }
+    }
+}
+
+impl<F> fmt::Debug for BlockingService<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BlockingService")
+            .field("pool", &self.pool)
+            .finish()
+    }
+}
+
+impl<F, B> Service for BlockingService<F>
+where
+    F: Fn(Request) -> Response<B> + Send + Sync + 'static,
+    B: Send + 'static,
+{
+    type Request = Request;
+    type Response = Response<B>;
+    type Error = ::Error;
+    type Future = CpuFuture<Response<B>, ::Error>;
+
+    fn call(&self, req: Request) -> Self::Future {
+        let handler = self.handler.clone();
+        self.pool.spawn_fn(move || {
+            panic::catch_unwind(AssertUnwindSafe(|| handler(req))).map_err(|_| {
+                ::Error::Io(io::Error::new(io::ErrorKind::Other, "blocking handler panicked"))
+            })
+        })
+    }
+}
+
 #[derive(Debug)]
 pub struct ConstService<S> {
     svc: Arc<S>,
@@ -62,3 +288,593 @@ where
         Ok(self.svc.clone())
     }
 }
+
+#[derive(Debug)]
+pub struct RetryNewService<N> {
+    inner: N,
+    attempts: usize,
+}
+
+impl<N> NewService for RetryNewService<N>
+where
+    N: NewService,
+{
+    type Request = N::Request;
+    type Response = N::Response;
+    type Error = N::Error;
+    type Instance = N::Instance;
+
+    fn new_service(&self) -> io::Result<Self::Instance> {
+        let mut attempt = 1;
+        loop {
+            match self.inner.new_service() {
+                Ok(service) => return Ok(service),
+                Err(e) => {
+                    if attempt == self.attempts {
+                        debug!("new_service failed {} times, giving up: {}", attempt, e);
+                        return Err(e);
+                    }
+                    debug!("new_service failed, retrying: {}", e);
+                }
+            }
+            attempt += 1;
+        }
+    }
+}
+
+/// A `Service` passing each request to the service routed for its method
+/// and path.
+///
+/// This isn't a full router, only the gating most services start with:
+/// each route is a method and a path prefix, matching the paths equal to
+/// the prefix or continuing it with a segment, so that `/users` matches
+/// `/users` and `/users/7`, but not `/usersettings`. The longest prefix
+/// matching the path of a request decides, and then:
+///
+/// - if one of its routes has the method of the request, the request goes to
+///   the service of that route; a `HEAD` request also goes to the route
+///   for `GET` when there isn't one for `HEAD`,
+/// - otherwise, a `405 Method Not Allowed` is sent, with an `Allow` header
+///   listing the methods of these routes.
+///
+/// If no prefix matches, a `404 Not Found` is sent. Neither is given to any
+/// service.
+///
+/// # Example
+///
+/// ```
+/// # extern crate hyper;
+/// use hyper::{Method, StatusCode};
+/// use hyper::server::{Http, MethodRouter, Request, Response, const_service, service_fn_ok};
+///
+/// # fn main() {
+/// let router: MethodRouter = MethodRouter::new()
+///     .route(Method::Get, "/users", service_fn_ok(|_req: Request| {
+///         Response::new().with_body("all the users")
+///     }))
+///     .route(Method::Post, "/users", service_fn_ok(|_req: Request| {
+///         Response::new().with_status(StatusCode::Created)
+///     }));
+/// let addr = "127.0.0.1:0".parse().unwrap();
+/// let server = Http::new().bind(&addr, const_service(router)).unwrap();
+/// # drop(server);
+/// # }
+/// ```
+pub struct MethodRouter<B = Body> {
+    routes: Vec<Route<B>>,
+}
+
+struct Route<B> {
+    method: Method,
+    prefix: String,
+    service: Box<Fn(Request) -> Box<Future<Item=Response<B>, Error=::Error>>>,
+}
+
+impl<B: 'static> MethodRouter<B> {
+    /// Create a router without routes, sending a `404` to every request.
+    pub fn new() -> MethodRouter<B> {
+        MethodRouter {
+            routes: Vec::new(),
+        }
+    }
+
+    /// Route the requests with `method`, and a path under `prefix`, to
+    /// `service`.
+    ///
+    /// A trailing slash of `prefix` is ignored, and the prefix `/` matches
+    /// every path.
+    pub fn route<S>(mut self, method: Method, prefix: &str, service: S) -> MethodRouter<B>
+    where
+        S: Service<Request=Request, Response=Response<B>, Error=::Error> + 'static,
+        S::Future: 'static,
+    {
+        self.routes.push(Route {
+            method: method,
+            prefix: prefix.trim_right_matches('/').to_owned(),
+            service: Box::new(move |req| Box::new(service.call(req))),
+        });
+        self
+    }
+
+    // The routes of the longest prefix matching `path`.
+    fn matching(&self, path: &str) -> Vec<&Route<B>> {
+        let matches = |prefix: &str| {
+            path.starts_with(prefix) && path[prefix.len()..].chars().next().map_or(true, |c| c == '/')
+        };
+        let longest = self.routes.iter()
+            .filter(|route| matches(&route.prefix))
+            .map(|route| route.prefix.len())
+            .max();
+        match longest {
+            Some(len) => self.routes.iter()
+                .filter(|route| route.prefix.len() == len && matches(&route.prefix))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+impl<B> fmt::Debug for MethodRouter<B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let routes = self.routes.iter()
+            .map(|route| (&route.method, &route.prefix))
+            .collect::<Vec<_>>();
+        f.debug_struct("MethodRouter")
+            .field("routes", &routes)
+            .finish()
+    }
+}
+
+impl<B: 'static> Service for MethodRouter<B> {
+    type Request = Request;
+    type Response = Response<B>;
+    type Error = ::Error;
+    type Future = Box<Future<Item=Response<B>, Error=::Error>>;
+
+    fn call(&self, req: Request) -> Self::Future {
+        let routes = self.matching(req.path());
+        if routes.is_empty() {
+            return Box::new(future::ok(Response::new().with_status(StatusCode::NotFound)));
+        }
+        let route = routes.iter().find(|route| route.method == *req.method())
+            .or_else(|| match *req.method() {
+                Method::Head => routes.iter().find(|route| route.method == Method::Get),
+                _ => None,
+            });
+        if let Some(route) = route {
+            return (route.service)(req);
+        }
+        let mut allow = Vec::new();
+        for route in &routes {
+            if !allow.contains(&route.method) {
+                allow.push(route.method.clone());
+            }
+            if route.method == Method::Get && !allow.contains(&Method::Head) {
+                allow.push(Method::Head);
+            }
+        }
+        Box::new(future::ok(Response::new()
+            .with_status(StatusCode::MethodNotAllowed)
+            .with_header(Allow(allow))))
+    }
+}
+
+/// A `Service` answering the requests it has seen recently from memory.
+///
+/// Created with `cache_service`. A response of the inner service is kept
+/// when it answers a `GET` with a `200 OK`, a `Content-Length` and a
+/// `Cache-Control` giving a `max-age` or an `s-maxage`, and is then
+/// given back for the same URI and `Host` until that age elapses, without
+/// calling the inner service, with an `Age` header counting the seconds
+/// since the inner service gave it, added to any `Age` it had. This is only
+/// meant for public content:
+///
+/// - a response with `no-store`, `no-cache` or `private`, a `Set-Cookie`,
+///   a `Vary` or a `Trailer` isn't kept,
+/// - a request with `no-store` or `no-cache`, or an `Authorization`, goes
+///   to the inner service, and its response isn't kept.
+///
+/// The kept responses are sharing at most `max_bytes` of bodies, the least
+/// recently used ones being evicted to make room. A body larger than that
+/// is streamed as usual, while a kept one is read whole before the response
+/// is returned. Clones of a `CacheService` share the same cache.
+pub struct CacheService<S> {
+    inner: S,
+    cache: Arc<Mutex<Cache>>,
+}
+
+impl<S: Clone> Clone for CacheService<S> {
+    fn clone(&self) -> CacheService<S> {
+        CacheService {
+            inner: self.inner.clone(),
+            cache: self.cache.clone(),
+        }
+    }
+}
+
+impl<S: fmt::Debug> fmt::Debug for CacheService<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let cache = self.cache.lock().unwrap();
+        f.debug_struct("CacheService")
+            .field("inner", &self.inner)
+            .field("entries", &cache.entries.len())
+            .field("bytes", &cache.bytes)
+            .field("max_bytes", &cache.max_bytes)
+            .finish()
+    }
+}
+
+impl<S> Service for CacheService<S>
+where
+    S: Service<Request=Request, Response=Response, Error=::Error>,
+{
+    type Request = Request;
+    type Response = Response;
+    type Error = ::Error;
+    type Future = CacheFuture<S::Future>;
+
+    fn call(&self, req: Request) -> Self::Future {
+        let key = cache_key(&req);
+        if let Some(ref key) = key {
+            if let Some(res) = self.cache.lock().unwrap().get(key) {
+                trace!("cache hit for {}", key.1);
+                return CacheFuture {
+                    key: None,
+                    cache: self.cache.clone(),
+                    state: CacheState::Hit(Some(res)),
+                };
+            }
+        }
+        CacheFuture {
+            key: key,
+            cache: self.cache.clone(),
+            state: CacheState::Call(self.inner.call(req)),
+        }
+    }
+}
+
+// The `Host` and the URI of a request.
+type CacheKey = (String, String);
+
+struct Cache {
+    entries: HashMap<CacheKey, CacheEntry>,
+    // The keys of the entries, by their last use.
+    lru: BTreeMap<u64, CacheKey>,
+    tick: u64,
+    bytes: usize,
+    max_bytes: usize,
+}
+
+struct CacheEntry {
+    headers: Headers,
+    body: Bytes,
+    // The `Age` of the response when it was kept.
+    age: u64,
+    stored: Instant,
+    expires: Instant,
+    used: u64,
+}
+
+impl Cache {
+    fn get(&mut self, key: &CacheKey) -> Option<Response> {
+        let expired = match self.entries.get(key) {
+            Some(entry) => entry.expires <= Instant::now(),
+            None => return None,
+        };
+        if expired {
+            self.remove(key);
+            return None;
+        }
+        self.tick += 1;
+        let entry = self.entries.get_mut(key).expect("entry just found");
+        self.lru.remove(&entry.used);
+        entry.used = self.tick;
+        self.lru.insert(self.tick, key.clone());
+        let mut headers = entry.headers.clone();
+        let age = entry.age + entry.stored.elapsed().as_secs();
+        headers.set_raw("Age", age.to_string());
+        Some(Response::new()
+            .with_headers(headers)
+            .with_body(Body::from(entry.body.clone())))
+    }
+
+    fn insert(&mut self, key: CacheKey, headers: Headers, body: Bytes, expires: Instant) {
+        if body.len() > self.max_bytes {
+            return;
+        }
+        self.remove(&key);
+        while self.bytes + body.len() > self.max_bytes {
+            let oldest = match self.lru.keys().next() {
+                Some(&used) => used,
+                None => break,
+            };
+            let key = self.lru[&oldest].clone();
+            trace!("cache evicting {}", key.1);
+            self.remove(&key);
+        }
+        self.tick += 1;
+        self.bytes += body.len();
+        self.lru.insert(self.tick, key.clone());
+        let age = headers.get_raw("Age")
+            .and_then(|raw| raw.one())
+            .and_then(|line| str::from_utf8(line).ok())
+            .and_then(|age| age.trim().parse().ok())
+            .unwrap_or(0);
+        self.entries.insert(key, CacheEntry {
+            headers: headers,
+            body: body,
+            age: age,
+            stored: Instant::now(),
+            expires: expires,
+            used: self.tick,
+        });
+    }
+
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.lru.remove(&entry.used);
+            self.bytes -= entry.body.len();
+        }
+    }
+}
+
+fn has_directive(headers: &Headers, directives: &[CacheDirective]) -> bool {
+    headers.get::<CacheControl>()
+        .map_or(false, |cc| cc.iter().any(|d| directives.contains(d)))
+}
+
+// The key of a request whose response may come from, and go to, the cache.
+fn cache_key(req: &Request) -> Option<CacheKey> {
+    if *req.method() != Method::Get
+        || req.headers().get_raw("Authorization").is_some()
+        || has_directive(req.headers(), &[CacheDirective::NoStore, CacheDirective::NoCache]) {
+        return None;
+    }
+    let host = req.headers().get::<Host>().map_or(String::new(), |host| host.to_string());
+    Some((host, req.uri().to_string()))
+}
+
+// How long a response may be kept, if at all.
+fn freshness(res: &Response, max_bytes: usize) -> Option<Duration> {
+    let headers = res.headers();
+    if res.status() != StatusCode::Ok
+        || headers.get_raw("Set-Cookie").is_some()
+        || headers.get_raw("Vary").is_some()
+        || headers.get_raw("Trailer").is_some()
+        || has_directive(headers, &[CacheDirective::NoStore, CacheDirective::NoCache, CacheDirective::Private]) {
+        return None;
+    }
+    match headers.get::<ContentLength>() {
+        Some(&ContentLength(len)) if len <= max_bytes as u64 => (),
+        _ => return None,
+    }
+    let cc = match headers.get::<CacheControl>() {
+        Some(cc) => cc,
+        None => return None,
+    };
+    let shared = cc.iter().filter_map(|d| match *d {
+        CacheDirective::SMaxAge(secs) => Some(secs),
+        _ => None,
+    }).next();
+    let max_age = cc.iter().filter_map(|d| match *d {
+        CacheDirective::MaxAge(secs) => Some(secs),
+        _ => None,
+    }).next();
+    match shared.or(max_age) {
+        Some(secs) if secs > 0 => Some(Duration::from_secs(secs as u64)),
+        _ => None,
+    }
+}
+
+/// The `Future` of a `CacheService`.
+pub struct CacheFuture<F> {
+    key: Option<CacheKey>,
+    cache: Arc<Mutex<Cache>>,
+    state: CacheState<F>,
+}
+
+enum CacheState<F> {
+    Hit(Option<Response>),
+    Call(F),
+    Buffer {
+        headers: Option<Headers>,
+        expires: Instant,
+        body: Concat2<Body>,
+    },
+}
+
+impl<F> fmt::Debug for CacheFuture<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CacheFuture")
+            .field("key", &self.key)
+            .finish()
+    }
+}
+
+impl<F> Future for CacheFuture<F>
+where
+    F: Future<Item=Response, Error=::Error>,
+{
+    type Item = Response;
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Response, ::Error> {
+        loop {
+            let next = match self.state {
+                CacheState::Hit(ref mut res) => {
+                    return Ok(res.take().expect("polled CacheFuture after completion").into());
+                },
+                CacheState::Call(ref mut f) => {
+                    let mut res = try_ready!(f.poll());
+                    let max_bytes = self.cache.lock().unwrap().max_bytes;
+                    let fresh = match self.key {
+                        Some(_) => freshness(&res, max_bytes),
+                        None => None,
+                    };
+                    match fresh {
+                        Some(fresh) => CacheState::Buffer {
+                            headers: Some(::std::mem::replace(res.headers_mut(), Headers::new())),
+                            expires: Instant::now() + fresh,
+                            body: res.body().concat2(),
+                        },
+                        None => return Ok(res.into()),
+                    }
+                },
+                CacheState::Buffer { ref mut headers, expires, ref mut body } => {
+                    let body = Bytes::from(try_ready!(body.poll()));
+                    let headers = headers.take().expect("polled CacheFuture after completion");
+                    if let Some(key) = self.key.take() {
+                        self.cache.lock().unwrap().insert(key, headers.clone(), body.clone(), expires);
+                    }
+                    return Ok(Response::new()
+                        .with_headers(headers)
+                        .with_body(Body::from(body))
+                        .into());
+                },
+            };
+            self.state = next;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use futures::{Future, Stream};
+    use futures::future::FutureResult;
+    use tokio_service::Service;
+
+    use header::{Allow, CacheControl, CacheDirective, ContentLength};
+    use {Method, Request, Response, StatusCode};
+    use super::{cache_service, service_fn_ok, MethodRouter};
+
+    // Serves the path as the body, with the `Cache-Control` of `directives`,
+    // counting the calls.
+    fn origin(directives: Vec<CacheDirective>, calls: Arc<AtomicUsize>) -> Box<Service<Request=Request, Response=Response, Error=::Error, Future=FutureResult<Response, ::Error>>> {
+        Box::new(service_fn_ok(move |req: Request| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            let body = req.path().to_owned();
+            Response::new()
+                .with_header(CacheControl(directives.clone()))
+                .with_header(ContentLength(body.len() as u64))
+                .with_body(body)
+        }))
+    }
+
+    fn get<S>(service: &S, path: &str) -> String
+    where
+        S: Service<Request=Request, Response=Response, Error=::Error>,
+    {
+        let req = Request::new(Method::Get, path.parse().unwrap());
+        let res = service.call(req).wait().unwrap();
+        let body = res.body().concat2().wait().unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_cache_service_hit() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let service = cache_service(origin(vec![CacheDirective::MaxAge(60)], calls.clone()), 1024);
+        let res = service.call(Request::new(Method::Get, "/a".parse().unwrap())).wait().unwrap();
+        assert_eq!(res.headers().get_raw("Age"), None);
+        assert_eq!(get(&service, "/a"), "/a");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        let res = service.call(Request::new(Method::Get, "/a".parse().unwrap())).wait().unwrap();
+        assert_eq!(res.headers().get_raw("Age").unwrap(), "0");
+
+        service.call(Request::new(Method::Head, "/a".parse().unwrap())).wait().unwrap();
+        assert_eq!(get(&service, "/b"), "/b");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_cache_service_no_store_bypass() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let service = cache_service(origin(vec![CacheDirective::NoStore, CacheDirective::MaxAge(60)], calls.clone()), 1024);
+        assert_eq!(get(&service, "/a"), "/a");
+        assert_eq!(get(&service, "/a"), "/a");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let service = cache_service(origin(vec![CacheDirective::MaxAge(60)], calls.clone()), 1024);
+        let mut req = Request::new(Method::Get, "/a".parse().unwrap());
+        req.headers_mut().set(CacheControl(vec![CacheDirective::NoStore]));
+        service.call(req).wait().unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        // the response to a no-store request wasn't kept
+        assert_eq!(get(&service, "/a"), "/a");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_cache_service_evicts_least_recently_used() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        // room for two bodies of 4 bytes
+        let service = cache_service(origin(vec![CacheDirective::MaxAge(60)], calls.clone()), 10);
+        get(&service, "/one");
+        get(&service, "/two");
+        get(&service, "/one");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        get(&service, "/six");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        get(&service, "/one");
+        get(&service, "/six");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        get(&service, "/two");
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+
+        // larger than the whole budget, streamed without being kept
+        assert_eq!(get(&service, "/larger-than-ten"), "/larger-than-ten");
+        assert_eq!(get(&service, "/larger-than-ten"), "/larger-than-ten");
+        assert_eq!(calls.load(Ordering::SeqCst), 6);
+    }
+
+    fn router() -> MethodRouter {
+        let named = |name: &'static str| service_fn_ok(move |_req: Request| Response::new().with_body(name));
+        MethodRouter::new()
+            .route(Method::Get, "/users", named("list users"))
+            .route(Method::Post, "/users/", named("create user"))
+            .route(Method::Delete, "/users/admin", named("delete admin"))
+    }
+
+    fn call(router: &MethodRouter, method: Method, path: &str) -> Response {
+        router.call(Request::new(method, path.parse().unwrap())).wait().unwrap()
+    }
+
+    #[test]
+    fn test_method_router_routes_by_method_and_prefix() {
+        let router = router();
+        assert_eq!(get(&router, "/users"), "list users");
+        assert_eq!(get(&router, "/users/7"), "list users");
+        let res = call(&router, Method::Post, "/users/7/friends");
+        assert_eq!(res.body().concat2().wait().unwrap().as_ref(), b"create user");
+        // falls back to GET
+        assert_eq!(call(&router, Method::Head, "/users").status(), StatusCode::Ok);
+    }
+
+    #[test]
+    fn test_method_router_405_with_allow() {
+        let router = router();
+        let res = call(&router, Method::Put, "/users/7");
+        assert_eq!(res.status(), StatusCode::MethodNotAllowed);
+        assert_eq!(res.headers().get::<Allow>(), Some(&Allow(vec![Method::Get, Method::Head, Method::Post])));
+
+        // the longest prefix decides
+        let res = call(&router, Method::Get, "/users/admin");
+        assert_eq!(res.status(), StatusCode::MethodNotAllowed);
+        assert_eq!(res.headers().get::<Allow>(), Some(&Allow(vec![Method::Delete])));
+    }
+
+    #[test]
+    fn test_method_router_404_unknown_path() {
+        let router = router();
+        for path in &["/", "/usersettings", "/other/users"] {
+            let res = call(&router, Method::Get, path);
+            assert_eq!(res.status(), StatusCode::NotFound, "{}", path);
+            assert!(res.headers().get::<Allow>().is_none());
+        }
+    }
+}
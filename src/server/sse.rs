@@ -0,0 +1,195 @@
+//! Server-Sent Events, the `text/event-stream` responses of the EventSource
+//! API.
+//!
+//! A service streams events by answering with the response of an
+//! `EventStream`:
+//!
+//! ```
+//! # extern crate futures;
+//! # extern crate hyper;
+//! use futures::stream;
+//! use hyper::server::{Request, service_fn_ok};
+//! use hyper::server::sse::{EventStream, SseEvent};
+//!
+//! # fn main() {
+//! let service = service_fn_ok(|_req: Request| {
+//!     let events = stream::iter_ok(vec![
+//!         SseEvent::data("hello"),
+//!         SseEvent::data("world").with_event("greeting").with_id("2"),
+//!     ]);
+//!     EventStream::new(events).into_response()
+//! });
+//! # drop(service);
+//! # }
+//! ```
+
+use std::time::Duration;
+
+use futures::{Poll, Stream};
+
+use header::{CacheControl, CacheDirective, ContentType};
+use mime;
+use proto::Chunk;
+use proto::response::Response;
+
+/// An event of an `EventStream`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SseEvent {
+    /// The type of the event, `message` when `None`.
+    pub event: Option<String>,
+    /// The data of the event, sent as one `data` field per line.
+    pub data: String,
+    /// The id the client sends back in `Last-Event-ID` when reconnecting.
+    pub id: Option<String>,
+    /// How long the client waits before reconnecting.
+    pub retry: Option<Duration>,
+}
+
+impl SseEvent {
+    /// Create a `message` event with `data`.
+    pub fn data<S: Into<String>>(data: S) -> SseEvent {
+        SseEvent {
+            data: data.into(),
+            ..SseEvent::default()
+        }
+    }
+
+    /// Set the type of the event.
+    pub fn with_event<S: Into<String>>(mut self, event: S) -> SseEvent {
+        self.event = Some(event.into());
+        self
+    }
+
+    /// Set the id of the event.
+    pub fn with_id<S: Into<String>>(mut self, id: S) -> SseEvent {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// Set the reconnection delay of the client.
+    pub fn with_retry(mut self, retry: Duration) -> SseEvent {
+        self.retry = Some(retry);
+        self
+    }
+
+    // The wire format of the event, ended by an empty line. A line break
+    // would end the `event` and `id` fields early, so they are left out of
+    // them, while the lines of `data` each get their field.
+    fn encode(&self) -> Vec<u8> {
+        let mut dst = Vec::with_capacity(self.data.len() + 16);
+        if let Some(ref event) = self.event {
+            field(&mut dst, "event", event);
+        }
+        if let Some(ref id) = self.id {
+            field(&mut dst, "id", id);
+        }
+        if let Some(retry) = self.retry {
+            let millis = retry.as_secs() * 1000 + (retry.subsec_nanos() / 1_000_000) as u64;
+            field(&mut dst, "retry", &millis.to_string());
+        }
+        let data = self.data.replace("\r\n", "\n").replace('\r', "\n");
+        for line in data.split('\n') {
+            dst.extend_from_slice(b"data: ");
+            dst.extend_from_slice(line.as_bytes());
+            dst.push(b'\n');
+        }
+        dst.push(b'\n');
+        dst
+    }
+}
+
+fn field(dst: &mut Vec<u8>, name: &str, value: &str) {
+    dst.extend_from_slice(name.as_bytes());
+    dst.extend_from_slice(b": ");
+    dst.extend(value.bytes().filter(|&b| b != b'\r' && b != b'\n'));
+    dst.push(b'\n');
+}
+
+/// The body of a `text/event-stream` response, sending the events of a
+/// `Stream` as they come.
+///
+/// Each event is a chunk of the body. A connection flushes every chunk of a
+/// `text/event-stream` response as soon as it is written, whatever
+/// `Http::pipeline` or `Http::write_coalesce` would hold back, so that
+/// each event reaches the client right away.
+#[derive(Debug)]
+#[must_use = "streams do nothing unless polled"]
+pub struct EventStream<S> {
+    events: S,
+}
+
+impl<S> EventStream<S>
+where
+    S: Stream<Item=SseEvent, Error=::Error>,
+{
+    /// Create the body sending `events`.
+    pub fn new(events: S) -> EventStream<S> {
+        EventStream {
+            events: events,
+        }
+    }
+
+    /// Create a response with this body, its `Content-Type` being
+    /// `text/event-stream`, and its `Cache-Control` being `no-cache`.
+    pub fn into_response(self) -> Response<EventStream<S>> {
+        Response::new()
+            .with_header(ContentType(mime::TEXT_EVENT_STREAM))
+            .with_header(CacheControl(vec![CacheDirective::NoCache]))
+            .with_body(self)
+    }
+}
+
+impl<S> Stream for EventStream<S>
+where
+    S: Stream<Item=SseEvent, Error=::Error>,
+{
+    type Item = Chunk;
+    type Error = ::Error;
+
+    fn poll(&mut self) -> Poll<Option<Chunk>, ::Error> {
+        let event = try_ready!(self.events.poll());
+        Ok(event.map(|event| Chunk::from(event.encode())).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::{stream, Future, Stream};
+
+    use header::{CacheControl, ContentType};
+    use mime;
+    use super::{EventStream, SseEvent};
+
+    #[test]
+    fn test_sse_event_encode() {
+        assert_eq!(SseEvent::data("hello").encode(), b"data: hello\n\n".to_vec());
+        assert_eq!(SseEvent::default().encode(), b"data: \n\n".to_vec());
+
+        let event = SseEvent::data("one\ntwo\r\nthree\rfour")
+            .with_event("update")
+            .with_id("7")
+            .with_retry(Duration::from_millis(1500));
+        assert_eq!(
+            ::std::str::from_utf8(&event.encode()).unwrap(),
+            "event: update\nid: 7\nretry: 1500\ndata: one\ndata: two\ndata: three\ndata: four\n\n"
+        );
+
+        let event = SseEvent::data("x").with_event("spoof\ndata: injected");
+        assert_eq!(event.encode(), b"event: spoofdata: injected\ndata: x\n\n".to_vec());
+    }
+
+    #[test]
+    fn test_event_stream_response() {
+        let events = stream::iter_ok(vec![SseEvent::data("a"), SseEvent::data("b").with_id("2")]);
+        let res = EventStream::new(events).into_response();
+        assert_eq!(res.headers().get::<ContentType>(), Some(&ContentType(mime::TEXT_EVENT_STREAM)));
+        assert_eq!(res.headers().get::<CacheControl>().unwrap().to_string(), "no-cache");
+
+        let (_, body) = ::proto::response::split(res);
+        let chunks = body.unwrap().collect().wait().unwrap();
+        let chunks = chunks.iter().map(|chunk| chunk.to_vec()).collect::<Vec<_>>();
+        assert_eq!(chunks, vec![b"data: a\n\n".to_vec(), b"id: 2\ndata: b\n\n".to_vec()]);
+    }
+}
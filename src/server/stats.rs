@@ -0,0 +1,148 @@
+//! Counters of the connections and requests of a `Server`.
+
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use proto::request_limit::RequestLimit;
+use super::accept::{connection_error, is_resource_exhaustion};
+
+/// A gauge of the connections currently served by a `Server`.
+///
+/// Created with `Server::connection_counter`. It can be cloned, and read from
+/// any thread.
+#[derive(Clone, Debug, Default)]
+pub struct ConnectionCounter {
+    pub(super) active: Arc<AtomicUsize>,
+}
+
+/// A gauge of the requests in flight under `Http::max_concurrent_requests`.
+///
+/// Created with `Server::request_counter`. It can be cloned, and read from
+/// any thread.
+#[derive(Clone, Debug)]
+pub struct RequestCounter {
+    pub(super) limit: Option<Arc<RequestLimit>>,
+}
+
+/// Counters of the connections accepted by a `Server`, or by an
+/// `AddrIncoming`, and of the errors of its listener.
+///
+/// Created with `Server::accept_stats` or `AddrIncoming::stats`. It can be
+/// cloned, and read from any thread. The counters only ever increase, from
+/// when the accept loop starts.
+#[derive(Clone, Debug, Default)]
+pub struct AcceptStats {
+    counters: Arc<AcceptCounters>,
+}
+
+#[derive(Debug, Default)]
+struct AcceptCounters {
+    accepted: AtomicUsize,
+    connection_errors: AtomicUsize,
+    resource_exhaustion_errors: AtomicUsize,
+    other_errors: AtomicUsize,
+    sleeps: AtomicUsize,
+}
+
+// ===== impl ConnectionCounter =====
+
+impl ConnectionCounter {
+    /// The number of connections currently being served.
+    pub fn get(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+}
+
+// ===== impl RequestCounter =====
+
+impl RequestCounter {
+    /// The number of requests passed to a service that hasn't responded yet.
+    ///
+    /// Always 0 without `Http::max_concurrent_requests`, which is what
+    /// enables the counting.
+    pub fn get(&self) -> usize {
+        self.limit.as_ref().map_or(0, |limit| limit.active())
+    }
+}
+
+// ===== impl AcceptStats =====
+
+impl AcceptStats {
+    /// The number of connections accepted, including those closed right
+    /// away by `Http::accept_filter`.
+    pub fn accepted(&self) -> usize {
+        self.counters.accepted.load(Ordering::SeqCst)
+    }
+
+    /// The number of errors about a single connection, such as one reset by
+    /// its client before it was accepted.
+    pub fn connection_errors(&self) -> usize {
+        self.counters.connection_errors.load(Ordering::SeqCst)
+    }
+
+    /// The number of errors for running out of resources, such as file
+    /// descriptors or buffers, such as `EMFILE` or `ENOBUFS`.
+    pub fn resource_exhaustion_errors(&self) -> usize {
+        self.counters.resource_exhaustion_errors.load(Ordering::SeqCst)
+    }
+
+    /// The number of errors of any other kind.
+    pub fn other_errors(&self) -> usize {
+        self.counters.other_errors.load(Ordering::SeqCst)
+    }
+
+    /// The number of times accepting was paused for a while after an
+    /// error, see `Http::sleep_on_errors` and `AcceptAction::RetryAfter`.
+    pub fn sleeps(&self) -> usize {
+        self.counters.sleeps.load(Ordering::SeqCst)
+    }
+
+    pub(super) fn record_accepted(&self) {
+        self.counters.accepted.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub(super) fn record_error(&self, e: &io::Error) {
+        let counter = if connection_error(e) {
+            &self.counters.connection_errors
+        } else if is_resource_exhaustion(e) {
+            &self.counters.resource_exhaustion_errors
+        } else {
+            &self.counters.other_errors
+        };
+        counter.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub(super) fn record_sleep(&self) {
+        self.counters.sleeps.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::AcceptStats;
+
+    #[test]
+    fn accept_stats_count_errors_by_kind() {
+        let stats = AcceptStats::default();
+        let shared = stats.clone();
+        stats.record_error(&io::Error::new(io::ErrorKind::ConnectionReset, "reset"));
+        stats.record_error(&io::Error::new(io::ErrorKind::ConnectionAborted, "aborted"));
+        stats.record_error(&io::Error::new(io::ErrorKind::Other, "other"));
+        stats.record_sleep();
+        assert_eq!(shared.connection_errors(), 2);
+        assert_eq!(shared.resource_exhaustion_errors(), 0);
+        assert_eq!(shared.other_errors(), 1);
+        assert_eq!(shared.sleeps(), 1);
+        assert_eq!(shared.accepted(), 0);
+
+        #[cfg(unix)]
+        {
+            use libc;
+            stats.record_error(&io::Error::from_raw_os_error(libc::EMFILE));
+            assert_eq!(shared.resource_exhaustion_errors(), 1);
+        }
+    }
+}
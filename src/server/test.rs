@@ -0,0 +1,173 @@
+//! Serving a `Service` over in-memory IO, for testing.
+//!
+//! Requires the `testing` feature. The raw bytes of a request go through the
+//! same parsing and framing as on a TCP connection, so a test can check the
+//! exact bytes a service puts on the wire, without binding a port.
+
+use std::cell::RefCell;
+use std::io::{self, Cursor, Read, Write};
+use std::mem;
+use std::rc::Rc;
+
+use futures::{Async, Future, Poll, Stream};
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_service::Service;
+
+use super::{Http, Request, Response};
+
+/// Serve the raw bytes of `request` to `service`, and return the raw bytes
+/// of the response.
+///
+/// Once all of `request` is read, the client side of the connection is
+/// closed, so several pipelined requests get all of their responses, in
+/// order. No `Date` header is added, so that the response can be compared
+/// exactly.
+///
+/// # Example
+///
+/// ```
+/// # extern crate hyper;
+/// use hyper::header::ContentLength;
+/// use hyper::server::{Request, Response, service_fn_ok};
+/// use hyper::server::test::serve_one;
+///
+/// # fn main() {
+/// let echo = service_fn_ok(|req: Request| -> Response {
+///     let path = req.path().to_owned();
+///     Response::new()
+///         .with_header(ContentLength(path.len() as u64))
+///         .with_body(path)
+/// });
+/// let res = serve_one(echo, b"GET /echo HTTP/1.1\r\nHost: example.domain\r\n\r\n");
+/// assert_eq!(res, &b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n/echo"[..]);
+/// # }
+/// ```
+pub fn serve_one<S, Bd>(service: S, request: &[u8]) -> Vec<u8>
+    where S: Service<Request = Request, Response = Response<Bd>, Error = ::Error> + 'static,
+          Bd: Stream<Error=::Error> + 'static,
+          Bd::Item: AsRef<[u8]>,
+{
+    let mut http = Http::<::Chunk>::new();
+    http.auto_date(false);
+    serve_one_with(&http, service, request)
+}
+
+/// Serve the raw bytes of `request` to `service` with the options of
+/// `http`, and return the raw bytes of the response.
+///
+/// Unlike `serve_one`, a `Date` header is added unless `http` disables it,
+/// see `Http::auto_date` and `Http::date_clock`.
+///
+/// The connection is driven on the current thread, without a reactor, so
+/// a timeout of `http`, or a service waiting on a timer of a `Core` that
+/// isn't running, doesn't complete. An error of the connection, such as
+/// for a request that doesn't parse, is not returned: the bytes written
+/// before it, such as a `400 Bad Request`, are.
+pub fn serve_one_with<B, S, Bd>(http: &Http<B>, service: S, request: &[u8]) -> Vec<u8>
+    where B: AsRef<[u8]> + 'static,
+          S: Service<Request = Request, Response = Response<Bd>, Error = ::Error> + 'static,
+          Bd: Stream<Error=::Error> + 'static,
+          Bd::Item: AsRef<[u8]>,
+{
+    let written = Rc::new(RefCell::new(Vec::new()));
+    let io = MemIo {
+        read: Cursor::new(request.to_vec()),
+        written: written.clone(),
+    };
+    if let Err(e) = http.serve_connection(io, service).wait() {
+        debug!("test connection error: {}", e);
+    }
+    let mut written = written.borrow_mut();
+    mem::replace(&mut *written, Vec::new())
+}
+
+// The request is read from `read`, and the response collected in `written`,
+// which outlives the connection.
+struct MemIo {
+    read: Cursor<Vec<u8>>,
+    written: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Read for MemIo {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.read.read(buf)
+    }
+}
+
+impl Write for MemIo {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.written.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsyncRead for MemIo {}
+
+impl AsyncWrite for MemIo {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        Ok(Async::Ready(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{Future, Stream};
+
+    use header::ContentLength;
+    use server::{Http, Request, Response, service_fn};
+    use super::{serve_one, serve_one_with};
+
+    fn echo(req: Request) -> Box<Future<Item=Response, Error=::Error>> {
+        Box::new(req.body().concat2().map(|body| {
+            Response::new()
+                .with_header(ContentLength(body.len() as u64))
+                .with_body(body)
+        }))
+    }
+
+    #[test]
+    fn serve_one_echo() {
+        let res = serve_one(service_fn(echo), b"\
+            POST /echo HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            Content-Length: 5\r\n\
+            \r\n\
+            hello\
+        ");
+        assert_eq!(s(&res), "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello");
+    }
+
+    #[test]
+    fn serve_one_pipelined() {
+        let res = serve_one(service_fn(echo), b"\
+            POST / HTTP/1.1\r\n\
+            Content-Length: 3\r\n\
+            \r\n\
+            one\
+            POST / HTTP/1.1\r\n\
+            Content-Length: 3\r\n\
+            \r\n\
+            two\
+        ");
+        assert_eq!(s(&res), "\
+            HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\none\
+            HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\ntwo\
+        ");
+    }
+
+    #[test]
+    fn serve_one_with_bad_request() {
+        let mut http = Http::<::Chunk>::new();
+        http.auto_date(false);
+        let res = serve_one_with(&http, service_fn(echo), b"GET / HTTP/1.1\r\nHost\r\n\r\n");
+        assert!(s(&res).starts_with("HTTP/1.1 400 Bad Request\r\n"), "{:?}", s(&res));
+    }
+
+    fn s(buf: &[u8]) -> &str {
+        ::std::str::from_utf8(buf).unwrap()
+    }
+}
@@ -0,0 +1,378 @@
+//! Serving HTTP over Unix domain sockets.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::os::unix::net as std_unix;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
+
+use bytes::{Buf, BufMut};
+use futures::future::{self, Shared};
+use futures::unsync::oneshot;
+use futures::{Async, Future, Poll, Stream};
+use tokio::reactor::{Core, Handle, Timeout};
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_uds::{UCred, UnixListener, UnixStream};
+
+use super::{conn_info, log_connection_error, run_incoming_until, set_overloaded, AcceptAction,
+    Accepted, ConnectionCounter, ConnectionInfo, Draining, Http, NewService, NotifyService,
+    OnAcceptError, Request, RequestCounter, Response, Service, ShutdownOutcome, ShutdownPhase,
+    SignalPhases, SocketAddrService};
+use super::accept::accept_action;
+
+/// An instance of a server created through `Http::bind_unix`.
+///
+/// This is the Unix domain socket counterpart of `Server`. Since the peers
+/// of a Unix socket have no `SocketAddr`, `Request::remote_addr` and
+/// `Request::local_addr` are `None` for every request it serves.
+pub struct UnixServer<S, B>
+where B: Stream<Error=::Error>,
+      B::Item: AsRef<[u8]>,
+{
+    protocol: Http<B::Item>,
+    new_service: S,
+    reactor: Core,
+    listener: UnixListener,
+    path: PathBuf,
+    shutdown_timeout: Duration,
+    connections: ConnectionCounter,
+}
+
+/// A stream accepted by a `UnixServer`.
+#[derive(Debug)]
+pub struct UnixAddrStream {
+    inner: UnixStream,
+    peer_cred: Option<UCred>,
+}
+
+// The connections of the listener of a `UnixServer`. Like `AddrIncoming`,
+// it pauses accepting a while after an error such as running out of file
+// descriptors, rather than returning it.
+struct UnixIncoming {
+    listener: UnixListener,
+    handle: Handle,
+    sleep_on_errors: bool,
+    error_sleep: Duration,
+    on_accept_error: Option<OnAcceptError>,
+    timeout: Option<Timeout>,
+}
+
+impl<B: AsRef<[u8]> + 'static> Http<B> {
+    /// Bind the provided Unix socket `path` and return a server ready to
+    /// handle connections.
+    ///
+    /// This works like `Http::bind`, except that connections are accepted
+    /// from a Unix domain socket. Binding fails if `path` already exists.
+    pub fn bind_unix<P, S, Bd>(&self, path: P, new_service: S) -> ::Result<UnixServer<S, Bd>>
+        where P: AsRef<Path>,
+              S: NewService<Request = Request, Response = Response<Bd>, Error = ::Error> + 'static,
+              Bd: Stream<Item=B, Error=::Error>,
+    {
+        let core = try!(Core::new());
+        let std_listener = try!(std_unix::UnixListener::bind(path.as_ref()));
+        let listener = try!(UnixListener::from_std(std_listener, core.handle().new_tokio_handle()));
+
+        Ok(UnixServer {
+            new_service: new_service,
+            reactor: core,
+            listener: listener,
+            path: path.as_ref().to_path_buf(),
+            protocol: self.clone(),
+            shutdown_timeout: Duration::new(1, 0),
+            connections: ConnectionCounter::default(),
+        })
+    }
+}
+
+// ===== impl UnixServer =====
+
+impl<S, B> UnixServer<S, B>
+    where S: NewService<Request = Request, Response = Response<B>, Error = ::Error> + 'static,
+          B: Stream<Error=::Error> + 'static,
+          B::Item: AsRef<[u8]>,
+{
+    /// Returns the path of the Unix socket this server is bound to.
+    pub fn local_path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns a handle to the underlying event loop that this server will be
+    /// running on.
+    pub fn handle(&self) -> Handle {
+        self.reactor.handle()
+    }
+
+    /// Returns a gauge of the connections this server is currently serving.
+    pub fn connection_counter(&self) -> ConnectionCounter {
+        self.connections.clone()
+    }
+
+    /// Returns a gauge of the requests in flight under
+    /// `Http::max_concurrent_requests`.
+    pub fn request_counter(&self) -> RequestCounter {
+        RequestCounter {
+            limit: self.protocol.request_limit.clone(),
+        }
+    }
+
+    /// Configure the amount of time this server will wait for a "graceful
+    /// shutdown".
+    ///
+    /// See `Server::shutdown_timeout`.
+    pub fn shutdown_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Execute this server infinitely.
+    ///
+    /// This method does not currently return, but it will return an error if
+    /// one occurs.
+    pub fn run(self) -> ::Result<()> {
+        self.run_until(future::empty())
+    }
+
+    /// Execute this server until the given future, `shutdown_signal`, resolves.
+    ///
+    /// See `Server::run_until`. The socket file is not removed when this
+    /// method returns.
+    pub fn run_until<F>(self, shutdown_signal: F) -> ::Result<()>
+        where F: Future<Item = (), Error = ()>,
+    {
+        self.run_until_graceful(shutdown_signal).map(|_| ())
+    }
+
+    /// Execute this server until the given future, `shutdown_signal`,
+    /// resolves, and report how the shutdown went.
+    ///
+    /// See `Server::run_until_graceful`.
+    pub fn run_until_graceful<F>(self, shutdown_signal: F) -> ::Result<ShutdownOutcome>
+        where F: Future<Item = (), Error = ()>,
+    {
+        self.run_until_phased(SignalPhases::new(shutdown_signal))
+    }
+
+    /// Execute this server until the given stream of shutdown `phases` is
+    /// done, and report how the shutdown went.
+    ///
+    /// See `Server::run_until_phased`.
+    pub fn run_until_phased<P>(self, phases: P) -> ::Result<ShutdownOutcome>
+        where P: Stream<Item = ShutdownPhase, Error = ()>,
+    {
+        let UnixServer { protocol, new_service, reactor, listener, shutdown_timeout, connections, .. } = self;
+        let incoming = UnixIncoming {
+            listener: listener,
+            handle: reactor.handle(),
+            sleep_on_errors: protocol.sleep_on_errors,
+            error_sleep: protocol.accept_error_sleep,
+            on_accept_error: protocol.on_accept_error.clone(),
+            timeout: None,
+        };
+        run_incoming_until(protocol, new_service, reactor, incoming, shutdown_timeout,
+            connections, phases)
+    }
+}
+
+impl<S: fmt::Debug, B: Stream<Error=::Error>> fmt::Debug for UnixServer<S, B>
+where B::Item: AsRef<[u8]>
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("UnixServer")
+         .field("reactor", &"...")
+         .field("path", &self.path)
+         .field("new_service", &self.new_service)
+         .field("protocol", &self.protocol)
+         .finish()
+    }
+}
+
+// ===== impl UnixIncoming =====
+
+impl Stream for UnixIncoming {
+    type Item = UnixAddrStream;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<UnixAddrStream>, io::Error> {
+        // Check if a previous timeout is active that was set by IO errors.
+        if let Some(ref mut to) = self.timeout {
+            match to.poll().expect("timeout never fails") {
+                Async::Ready(_) => {}
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+        self.timeout = None;
+        loop {
+            let e = match self.listener.poll_accept() {
+                Ok(Async::Ready((stream, _))) => return Ok(Async::Ready(Some(UnixAddrStream::new(stream)))),
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(e) => e,
+            };
+            match accept_action(&self.on_accept_error, self.sleep_on_errors, self.error_sleep, &e) {
+                AcceptAction::Ignore => {
+                    debug!("unix accept error: {}; ignoring", e);
+                },
+                AcceptAction::RetryAfter(delay) => {
+                    debug!("unix accept error: {}; sleeping {:?}", e, delay);
+                    let mut timeout = Timeout::new(delay, &self.handle)
+                        .expect("can always set a timeout");
+                    if let Async::NotReady = timeout.poll().expect("timeout never fails") {
+                        self.timeout = Some(timeout);
+                        return Ok(Async::NotReady);
+                    }
+                },
+                AcceptAction::Abort => return Err(e),
+            }
+        }
+    }
+}
+
+// ===== impl UnixAddrStream =====
+
+impl UnixAddrStream {
+    fn new(stream: UnixStream) -> UnixAddrStream {
+        let peer_cred = match stream.peer_cred() {
+            Ok(cred) => Some(cred),
+            Err(e) => {
+                trace!("error trying to get unix peer credentials: {}", e);
+                None
+            }
+        };
+        UnixAddrStream {
+            inner: stream,
+            peer_cred: peer_cred,
+        }
+    }
+
+    /// The credentials of the process on the other end of the socket, if
+    /// they could be read when the connection was accepted.
+    pub fn peer_cred(&self) -> Option<&UCred> {
+        self.peer_cred.as_ref()
+    }
+}
+
+/// Unix sockets have no `SocketAddr`, so no details are reported.
+impl ConnectionInfo for UnixAddrStream {}
+
+impl Accepted for UnixAddrStream {
+    fn spawn<S, B>(self,
+                   protocol: &Rc<Http<B::Item>>,
+                   handle: &Handle,
+                   service: NotifyService<SocketAddrService<S>>,
+                   overloaded: bool,
+                   draining: Shared<oneshot::Receiver<()>>) -> io::Result<()>
+        where S: Service<Request = Request, Response = Response<B>, Error = ::Error> + 'static,
+              B: Stream<Error=::Error> + 'static,
+              B::Item: AsRef<[u8]>,
+    {
+        let info = conn_info(&self);
+        let mut conn = protocol.serve_connection_handle(self, handle, service);
+        conn.conn.dispatch_mut().set_conn_info(info);
+        if overloaded {
+            set_overloaded(&mut conn);
+        }
+        let log_id = conn.log_id();
+        let fut = Draining::new(conn, draining)
+            .map(|_| ())
+            .map_err(move |err| log_connection_error(log_id, None, &err));
+        handle.spawn(fut);
+        Ok(())
+    }
+}
+
+impl Read for UnixAddrStream {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl Write for UnixAddrStream {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    #[inline]
+    fn flush(&mut self ) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl AsyncRead for UnixAddrStream {
+    #[inline]
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        self.inner.prepare_uninitialized_buffer(buf)
+    }
+
+    #[inline]
+    fn read_buf<B: BufMut>(&mut self, buf: &mut B) -> Poll<usize, io::Error> {
+        self.inner.read_buf(buf)
+    }
+}
+
+impl AsyncWrite for UnixAddrStream {
+    #[inline]
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        AsyncWrite::shutdown(&mut self.inner)
+    }
+
+    #[inline]
+    fn write_buf<B: Buf>(&mut self, buf: &mut B) -> Poll<usize, io::Error> {
+        self.inner.write_buf(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use futures::{Future, Stream};
+    use futures::future::Either;
+    use tokio::reactor::{Core, Timeout};
+
+    use super::UnixIncoming;
+    use server::{AcceptAction, OnAcceptError};
+
+    // Accepting from a socket that isn't listening fails with `EINVAL` on
+    // Linux, an error of the listener rather than of a connection.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_unix_incoming_retries_after_accept_error() {
+        use std::os::unix::io::FromRawFd;
+        use std::os::unix::net as std_unix;
+        use libc;
+        use tokio_uds::UnixListener;
+
+        let fd = unsafe { libc::socket(libc::AF_UNIX, libc::SOCK_STREAM, 0) };
+        assert!(fd >= 0);
+        let std_listener = unsafe { std_unix::UnixListener::from_raw_fd(fd) };
+        let mut core = Core::new().unwrap();
+        let listener = UnixListener::from_std(std_listener, core.handle().new_tokio_handle()).unwrap();
+
+        let errors = Arc::new(AtomicUsize::new(0));
+        let counted = errors.clone();
+        let incoming = UnixIncoming {
+            listener: listener,
+            handle: core.handle(),
+            sleep_on_errors: false,
+            error_sleep: Duration::from_millis(10),
+            on_accept_error: Some(OnAcceptError(Arc::new(move |_: &::std::io::Error| {
+                counted.fetch_add(1, Ordering::SeqCst);
+                AcceptAction::RetryAfter(Duration::from_millis(100))
+            }))),
+            timeout: None,
+        };
+
+        let timeout = Timeout::new(Duration::from_millis(350), &core.handle()).unwrap();
+        match core.run(incoming.for_each(|_| Ok(())).select2(timeout)) {
+            Ok(Either::B(_)) => (),
+            _ => panic!("incoming ended before the timeout"),
+        }
+        // a first failed accept right away, then one every 100ms
+        let errors = errors.load(Ordering::SeqCst);
+        assert!(errors >= 3 && errors <= 4, "{} errors", errors);
+    }
+}
@@ -0,0 +1,107 @@
+#![deny(warnings)]
+extern crate futures;
+extern crate hyper;
+#[macro_use]
+extern crate log;
+
+use futures::Future;
+use futures::sync::oneshot;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::Mutex;
+use std::sync::mpsc;
+use std::thread;
+
+use log::{Log, Metadata, Record};
+
+use hyper::header::ContentLength;
+use hyper::server::{Http, Request, Response, service_fn_ok};
+
+// Collects the log messages of the whole process, which is why this test
+// has its own binary.
+struct Capture {
+    lines: Mutex<Vec<String>>,
+}
+
+static CAPTURE: Capture = Capture {
+    lines: Mutex::new(Vec::new()),
+};
+
+impl Log for Capture {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        self.lines.lock().unwrap().push(format!("{}", record.args()));
+    }
+
+    fn flush(&self) {}
+}
+
+#[test]
+fn request_ids_prefix_logs_of_each_request() {
+    log::set_logger(&CAPTURE).unwrap();
+    log::set_max_level(log::LevelFilter::Trace);
+
+    let (addr_tx, addr_rx) = mpsc::channel();
+    let (ids_tx, ids_rx) = mpsc::channel();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let ids_tx = Mutex::new(ids_tx);
+    let server = thread::spawn(move || {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let srv = Http::new()
+            .request_ids(true)
+            .bind(&addr, move || {
+                let ids_tx = ids_tx.lock().unwrap().clone();
+                Ok(service_fn_ok(move |req: Request| {
+                    let id = req.request_id().expect("request_id");
+                    info!("[{}] service handling {}", id, req.path());
+                    ids_tx.send(id).unwrap();
+                    Response::<hyper::Body>::new()
+                        .with_header(ContentLength(2))
+                        .with_body("ok")
+                }))
+            })
+            .unwrap();
+        addr_tx.send(srv.local_addr().unwrap()).unwrap();
+        srv.run_until(shutdown_rx.then(|_| Ok(()))).unwrap();
+    });
+    let addr = addr_rx.recv().unwrap();
+
+    let mut tcp = TcpStream::connect(addr).unwrap();
+    for path in &["/one", "/two"] {
+        write!(tcp, "GET {} HTTP/1.1\r\nHost: example.domain\r\n\r\n", path).unwrap();
+        let mut res = Vec::new();
+        let mut buf = [0; 256];
+        while !res.ends_with(b"\r\n\r\nok") {
+            let n = tcp.read(&mut buf).unwrap();
+            assert!(n > 0, "closed before a complete response");
+            res.extend_from_slice(&buf[..n]);
+        }
+    }
+    drop(tcp);
+    drop(shutdown_tx);
+    server.join().unwrap();
+
+    let first = ids_rx.recv().unwrap();
+    let second = ids_rx.recv().unwrap();
+    assert_eq!(first.connection(), second.connection());
+    assert_eq!((first.request(), second.request()), (1, 2));
+
+    let lines = CAPTURE.lines.lock().unwrap();
+    let about = |id: hyper::server::RequestId| {
+        let prefix = format!("[{}] ", id);
+        lines.iter().filter(|line| line.starts_with(&prefix)).cloned().collect::<Vec<_>>()
+    };
+    let first_lines = about(first);
+    assert!(first_lines.contains(&format!("[{}] received GET /one", first)), "{:?}", first_lines);
+    assert!(first_lines.contains(&format!("[{}] service handling /one", first)), "{:?}", first_lines);
+    assert!(first_lines.contains(&format!("[{}] service responded with 200 OK", first)), "{:?}", first_lines);
+    assert!(first_lines.iter().all(|line| !line.contains("/two")), "{:?}", first_lines);
+
+    let second_lines = about(second);
+    assert!(second_lines.contains(&format!("[{}] received GET /two", second)), "{:?}", second_lines);
+    assert!(second_lines.contains(&format!("[{}] service handling /two", second)), "{:?}", second_lines);
+}
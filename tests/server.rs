@@ -2,10 +2,13 @@
 extern crate hyper;
 #[macro_use]
 extern crate futures;
+extern crate futures_cpupool;
+extern crate net2;
 extern crate spmc;
 extern crate pretty_env_logger;
 extern crate tokio_core;
 extern crate tokio_io;
+extern crate unicase;
 
 use futures::{Future, Stream};
 use futures::future::{self, FutureResult, Either};
@@ -14,18 +17,20 @@ use futures::sync::oneshot;
 use tokio_core::net::TcpListener;
 use tokio_core::reactor::{Core, Timeout};
 use tokio_io::{AsyncRead, AsyncWrite};
+use unicase::Ascii;
 
 use std::net::{TcpStream, Shutdown, SocketAddr};
 use std::io::{self, Read, Write};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use hyper::StatusCode;
-use hyper::header::ContentLength;
-use hyper::server::{Http, Request, Response, Service, NewService, service_fn};
+use hyper::header::{ContentLength, Server, Trailer};
+use hyper::server::conn::{Disconnect, DisconnectReason};
+use hyper::server::{Http, ConnectionInfo, HasRemoteAddr, Request, Response, Service, NewService, ShutdownPhase, service_fn, service_fn_ok};
 
 
 #[test]
@@ -629,528 +634,5075 @@ fn expect_continue() {
 }
 
 #[test]
-fn pipeline_disabled() {
-    let server = serve();
-    let mut req = connect(server.addr());
-    server.reply()
-        .status(hyper::Ok)
-        .header(ContentLength(12))
-        .body("Hello World!");
-    server.reply()
-        .status(hyper::Ok)
-        .header(ContentLength(12))
-        .body("Hello World!");
-
-    req.write_all(b"\
-        GET / HTTP/1.1\r\n\
-        Host: example.domain\r\n\
-        \r\n\
-        GET / HTTP/1.1\r\n\
-        Host: example.domain\r\n\
-        \r\n\
-    ").expect("write 1");
+fn max_uri_length_rejects_long_uri() {
+    let _ = pretty_env_logger::try_init();
+    let (addr_tx, addr_rx) = mpsc::channel();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let thread = thread::spawn(move || {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let srv = Http::new()
+            .max_uri_length(64)
+            .bind(&addr, || Ok(HelloWorld))
+            .unwrap();
+        addr_tx.send(srv.local_addr().unwrap()).unwrap();
+        srv.run_until(shutdown_rx.then(|_| Ok(()))).unwrap();
+    });
+    let addr = addr_rx.recv().unwrap();
 
-    let mut buf = vec![0; 4096];
-    let n = req.read(&mut buf).expect("read 1");
-    assert_ne!(n, 0);
-    // Woah there. What?
-    //
-    // This test is wishy-washy because of race conditions in access of the
-    // socket. The test is still useful, since it allows for the responses
-    // to be received in 2 reads. But it might sometimes come in 1 read.
-    //
-    // TODO: add in a delay to the `ServeReply` interface, to allow this
-    // delay to prevent the 2 writes from happening before this test thread
-    // can read from the socket.
-    match req.read(&mut buf) {
-        Ok(n) => {
-            // won't be 0, because we didn't say to close, and so socket
-            // will be open until `server` drops
-            assert_ne!(n, 0);
-        }
-        Err(_) => (),
-    }
+    let request = |uri: &str| {
+        let mut tcp = connect(&addr);
+        write!(tcp, "GET {} HTTP/1.1\r\nConnection: close\r\n\r\n", uri).unwrap();
+        let mut resp = String::new();
+        tcp.read_to_string(&mut resp).expect("read");
+        resp
+    };
+
+    let uri = format!("/{}", "a".repeat(63));
+    let resp = request(&uri);
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", resp);
+
+    let uri = format!("/{}", "a".repeat(64));
+    let resp = request(&uri);
+    // and then the connection is closed
+    assert!(resp.starts_with("HTTP/1.1 414 URI Too Long\r\n"), "{:?}", resp);
+
+    drop(shutdown_tx);
+    thread.join().unwrap();
 }
 
 #[test]
-fn pipeline_enabled() {
-    let server = serve_with_options(ServeOptions {
-        pipeline: true,
-        .. Default::default()
-    });
-    let mut req = connect(server.addr());
-    server.reply()
-        .status(hyper::Ok)
-        .header(ContentLength(12))
-        .body("Hello World\n");
-    server.reply()
-        .status(hyper::Ok)
-        .header(ContentLength(12))
-        .body("Hello World\n");
-
-    req.write_all(b"\
-        GET / HTTP/1.1\r\n\
-        Host: example.domain\r\n\
-        \r\n\
-        GET / HTTP/1.1\r\n\
-        Host: example.domain\r\n\
-        Connection: close\r\n\
-        \r\n\
-    ").expect("write 1");
+fn malformed_chunked_body_errors_the_body_stream() {
+    use hyper::BodyError;
 
-    let mut buf = vec![0; 4096];
-    let n = req.read(&mut buf).expect("read 1");
-    assert_ne!(n, 0);
-
-    {
-        let mut lines = buf.split(|&b| b == b'\n');
-        assert_eq!(s(lines.next().unwrap()), "HTTP/1.1 200 OK\r");
-        assert_eq!(s(lines.next().unwrap()), "Content-Length: 12\r");
-        lines.next().unwrap(); // Date
-        assert_eq!(s(lines.next().unwrap()), "\r");
-        assert_eq!(s(lines.next().unwrap()), "Hello World");
+    let _ = pretty_env_logger::try_init();
+    let cases: &[(&[u8], BodyError)] = &[
+        (b"3\r\nfoo\r\nzz\r\n", BodyError::InvalidChunkSize),
+        (b"9\r\nfoo", BodyError::IncompleteBody),
+        (b"3\r\nfoo\r\n0\r\nGrpc-Status: 0\r\n\r\n", BodyError::InvalidTrailer),
+        (b"3\r\nfoo\r\n1", BodyError::UnexpectedEof),
+    ];
+
+    for &(body, expected) in cases {
+        let mut core = Core::new().unwrap();
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let body = body.to_vec();
+        let client = thread::spawn(move || {
+            let mut tcp = connect(&addr);
+            tcp.write_all(b"\
+                POST / HTTP/1.1\r\n\
+                Transfer-Encoding: chunked\r\n\
+                \r\n\
+            ").unwrap();
+            tcp.write_all(&body).unwrap();
+            tcp.shutdown(Shutdown::Write).unwrap();
+            let mut buf = Vec::new();
+            let _ = tcp.read_to_end(&mut buf);
+        });
 
-        assert_eq!(s(lines.next().unwrap()), "HTTP/1.1 200 OK\r");
-        assert_eq!(s(lines.next().unwrap()), "Content-Length: 12\r");
-        lines.next().unwrap(); // Date
-        assert_eq!(s(lines.next().unwrap()), "\r");
-        assert_eq!(s(lines.next().unwrap()), "Hello World");
+        let (err_tx, err_rx) = mpsc::channel();
+        let err_tx = Mutex::new(err_tx);
+        let fut = listener.incoming()
+            .into_future()
+            .map_err(|_| unreachable!())
+            .and_then(move |(item, _incoming)| {
+                let (socket, _) = item.unwrap();
+                Http::<hyper::Chunk>::new()
+                    .serve_connection(socket, service_fn(move |req: Request| {
+                        let err_tx = err_tx.lock().unwrap().clone();
+                        req.body().concat2().then(move |res| {
+                            err_tx.send(res.map(|_| ())).unwrap();
+                            Ok::<_, hyper::Error>(Response::<hyper::Body>::new())
+                        })
+                    }))
+                    .then(|_| Ok::<_, ()>(()))
+            });
+
+        core.run(fut).unwrap();
+        client.join().unwrap();
+        match err_rx.recv().unwrap() {
+            Err(hyper::Error::Body(kind)) => assert_eq!(kind, expected),
+            other => panic!("expected {:?}, found {:?}", expected, other),
+        }
     }
-
-
-    // with pipeline enabled, both responses should have been in the first read
-    // so a second read should be EOF
-    let n = req.read(&mut buf).expect("read 2");
-    assert_eq!(n, 0);
 }
 
 #[test]
-fn http_10_request_receives_http_10_response() {
-    let server = serve();
-
-    let mut req = connect(server.addr());
-    req.write_all(b"\
-        GET / HTTP/1.0\r\n\
-        \r\n\
-    ").unwrap();
-
-    let expected = "HTTP/1.0 200 OK\r\nContent-Length: 0\r\n";
-    let mut buf = [0; 256];
-    let n = req.read(&mut buf).unwrap();
-    assert!(n >= expected.len(), "read: {:?} >= {:?}", n, expected.len());
-    assert_eq!(s(&buf[..expected.len()]), expected);
-}
+fn body_from_reader_serves_a_file() {
+    use std::fs::{self, File};
 
-#[test]
-fn disable_keep_alive_mid_request() {
-    let mut core = Core::new().unwrap();
-    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
-    let addr = listener.local_addr().unwrap();
+    // a blocking file, which is always ready to be read
+    struct FileReader(File);
 
-    let (tx1, rx1) = oneshot::channel();
-    let (tx2, rx2) = oneshot::channel();
+    impl Read for FileReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
 
-    let child = thread::spawn(move || {
-        let mut req = connect(&addr);
-        req.write_all(b"GET / HTTP/1.1\r\n").unwrap();
-        tx1.send(()).unwrap();
-        rx2.wait().unwrap();
-        req.write_all(b"Host: localhost\r\n\r\n").unwrap();
-        let mut buf = vec![];
-        req.read_to_end(&mut buf).unwrap();
-    });
+    impl AsyncRead for FileReader {}
 
-    let fut = listener.incoming()
-        .into_future()
-        .map_err(|_| unreachable!())
-        .and_then(|(item, _incoming)| {
-            let (socket, _) = item.unwrap();
-            Http::<hyper::Chunk>::new().serve_connection(socket, HelloWorld)
-                .select2(rx1)
-                .then(|r| {
-                    match r {
-                        Ok(Either::A(_)) => panic!("expected rx first"),
-                        Ok(Either::B(((), mut conn))) => {
-                            conn.disable_keep_alive();
-                            tx2.send(()).unwrap();
-                            conn
-                        }
-                        Err(Either::A((e, _))) => panic!("unexpected error {}", e),
-                        Err(Either::B((e, _))) => panic!("unexpected error {}", e),
-                    }
-                })
-        });
+    let contents = (0..100_000).map(|i| b'a' + (i % 26) as u8).collect::<Vec<u8>>();
+    let path = ::std::env::temp_dir().join(format!("hyper-body-from-reader-{}", ::std::process::id()));
+    File::create(&path).unwrap().write_all(&contents).unwrap();
 
-    core.run(fut).unwrap();
-    child.join().unwrap();
+    let file_path = path.clone();
+    let resp = serve_one_connection_with(Http::new(), b"\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ", service_fn(move |_req| {
+        let file = File::open(&file_path).unwrap();
+        let len = file.metadata().unwrap().len();
+        future::ok(Response::new()
+            .with_header(ContentLength(len))
+            .with_body(hyper::Body::from_reader(FileReader(file), 4096)))
+    }));
+    fs::remove_file(&path).unwrap();
+
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", &resp[..100]);
+    let body = resp.find("\r\n\r\n").expect("end of head") + 4;
+    assert_eq!(resp[body..].len(), contents.len());
+    assert!(resp[body..].as_bytes() == &contents[..], "body differs from the file");
 }
 
 #[test]
-fn disable_keep_alive_post_request() {
+fn on_write_drained_called_after_each_streamed_chunk() {
+    use futures::Sink;
+
+    let _ = pretty_env_logger::try_init();
     let mut core = Core::new().unwrap();
     let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
     let addr = listener.local_addr().unwrap();
 
-    let (tx1, rx1) = oneshot::channel();
-
-    let child = thread::spawn(move || {
-        let mut req = connect(&addr);
-        req.write_all(b"\
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
             GET / HTTP/1.1\r\n\
-            Host: localhost\r\n\
+            Host: example.domain\r\n\
+            Connection: close\r\n\
             \r\n\
         ").unwrap();
+        let mut resp = String::new();
+        tcp.read_to_string(&mut resp).expect("read");
+        resp
+    });
 
-        let mut buf = [0; 1024 * 8];
-        loop {
-            let n = req.read(&mut buf).expect("reading 1");
-            if n < buf.len() {
-                if &buf[n - HELLO.len()..n] == HELLO.as_bytes() {
-                    break;
-                }
-            }
+    let (drained_tx, drained_rx) = mpsc::channel();
+    let drained_tx = Mutex::new(drained_tx);
+    let (body_tx, body_rx) = mpsc::channel();
+    let body_tx = Mutex::new(body_tx);
+
+    // paces the body, yielding the next chunk once the last one drained
+    let pacer = thread::spawn(move || {
+        let tx: futures::sync::mpsc::Sender<Result<hyper::Chunk, hyper::Error>> = body_rx.recv().unwrap();
+        let head = drained_rx.recv_timeout(Duration::from_secs(1)).expect("head drained");
+        let mut tx = Some(tx);
+        let mut drained = vec![];
+        for &chunk in &["one", "two", "three"] {
+            tx = Some(tx.take().unwrap().send(Ok(chunk.into())).wait().unwrap());
+            let event: hyper::server::WriteDrained = drained_rx.recv_timeout(Duration::from_secs(1)).expect("chunk drained");
+            drained.push(event.bytes_written - head.bytes_written);
         }
-
-        tx1.send(()).unwrap();
-
-        let nread = req.read(&mut buf).expect("keep-alive reading");
-        assert_eq!(nread, 0);
+        drop(tx);
+        drained
     });
 
-    let dropped = Dropped::new();
-    let dropped2 = dropped.clone();
     let fut = listener.incoming()
         .into_future()
         .map_err(|_| unreachable!())
-        .and_then(|(item, _incoming)| {
-            let (socket, _) = item.expect("accepted socket");
-            let transport = DebugStream {
-                stream: socket,
-                _debug: dropped2,
-            };
-            Http::<hyper::Chunk>::new().serve_connection(transport, HelloWorld)
-                .select2(rx1)
-                .then(|r| {
-                    match r {
-                        Ok(Either::A(_)) => panic!("expected rx first"),
-                        Ok(Either::B(((), mut conn))) => {
-                            conn.disable_keep_alive();
-                            conn
-                        }
-                        Err(Either::A((e, _))) => panic!("unexpected error {}", e),
-                        Err(Either::B((e, _))) => panic!("unexpected error {}", e),
-                    }
+        .and_then(move |(item, _incoming)| {
+            let (socket, _) = item.unwrap();
+            Http::<hyper::Chunk>::new()
+                .on_write_drained(move |event| {
+                    // the end of the body drains once the pacer is done
+                    let _ = drained_tx.lock().unwrap().send(event);
                 })
+                .serve_connection(socket, service_fn(move |_req| {
+                    let (tx, body) = hyper::Body::pair();
+                    body_tx.lock().unwrap().send(tx).unwrap();
+                    future::ok::<_, hyper::Error>(Response::<hyper::Body>::new().with_body(body))
+                }))
+                .map(|_| ())
         });
 
-    assert!(!dropped.load());
     core.run(fut).unwrap();
-    // we must poll the Core one more time in order for Windows to drop
-    // the read-blocked socket.
-    //
-    // See https://github.com/carllerche/mio/issues/776
-    let timeout = Timeout::new(Duration::from_millis(10), &core.handle()).unwrap();
-    core.run(timeout).unwrap();
-    assert!(dropped.load());
-    child.join().unwrap();
+    let resp = client.join().unwrap();
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", resp);
+    // each chunk is drained with its chunked framing
+    assert_eq!(pacer.join().unwrap(), vec![8, 8 + 8, 8 + 8 + 10]);
 }
 
 #[test]
-fn empty_parse_eof_does_not_return_error() {
-    let mut core = Core::new().unwrap();
-    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
-    let addr = listener.local_addr().unwrap();
-
-    thread::spawn(move || {
-        let _tcp = connect(&addr);
+fn max_body_size_rejects_content_length() {
+    let server = serve_with_options(ServeOptions {
+        max_body_size: Some(8),
+        .. ServeOptions::default()
     });
+    let mut req = connect(server.addr());
+    req.write_all(b"\
+        POST / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Content-Length: 11\r\n\
+        \r\n\
+        hello world\
+    ").expect("write");
 
-    let fut = listener.incoming()
-        .into_future()
-        .map_err(|_| unreachable!())
-        .and_then(|(item, _incoming)| {
-            let (socket, _) = item.unwrap();
-            Http::<hyper::Chunk>::new().serve_connection(socket, HelloWorld)
-        });
-
-    core.run(fut).unwrap();
+    let mut resp = String::new();
+    req.read_to_string(&mut resp).expect("read");
+    assert!(resp.starts_with("HTTP/1.1 413 Payload Too Large\r\n"), "{:?}", resp);
+    assert!(has_header(&resp, "Connection: close\r\n"), "{:?}", resp);
 }
 
 #[test]
-fn nonempty_parse_eof_returns_error() {
-    let mut core = Core::new().unwrap();
-    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
-    let addr = listener.local_addr().unwrap();
-
-    thread::spawn(move || {
-        let mut tcp = connect(&addr);
-        tcp.write_all(b"GET / HTTP/1.1").unwrap();
+fn max_body_size_rejects_chunked_body_as_it_arrives() {
+    let server = serve_with_options(ServeOptions {
+        max_body_size: Some(8),
+        .. ServeOptions::default()
     });
+    let mut req = connect(server.addr());
+    req.write_all(b"\
+        POST / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Transfer-Encoding: chunked\r\n\
+        \r\n\
+        5\r\n\
+        hello\r\n\
+    ").expect("write 1");
+    thread::sleep(Duration::from_millis(50));
+    req.write_all(b"\
+        6\r\n\
+        \x20world\r\n\
+        0\r\n\
+        \r\n\
+    ").expect("write 2");
+
+    let mut resp = String::new();
+    req.read_to_string(&mut resp).expect("read");
+    assert!(resp.starts_with("HTTP/1.1 413 Payload Too Large\r\n"), "{:?}", resp);
+    assert!(has_header(&resp, "Connection: close\r\n"), "{:?}", resp);
+}
+
+#[test]
+fn expect_continue_sent_when_service_reads_body() {
+    let server = serve_with_options(ServeOptions {
+        expect_continue: true,
+        .. ServeOptions::default()
+    });
+    let mut req = connect(server.addr());
+    server.reply().status(hyper::Ok);
+
+    req.write_all(b"\
+        POST /foo HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Expect: 100-continue\r\n\
+        Content-Length: 5\r\n\
+        Connection: Close\r\n\
+        \r\n\
+    ").expect("write 1");
+
+    let msg = b"HTTP/1.1 100 Continue\r\n\r\n";
+    let mut buf = vec![0; msg.len()];
+    req.read_exact(&mut buf).expect("read 1");
+    assert_eq!(buf, msg);
+
+    let msg = b"hello";
+    req.write_all(msg).expect("write 2");
+
+    let mut body = String::new();
+    req.read_to_string(&mut body).expect("read 2");
+    assert!(body.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", body);
+
+    let body = server.body();
+    assert_eq!(body, msg);
+}
+
+#[test]
+fn expect_continue_rejected_by_service() {
+    struct MaxLength(Arc<AtomicBool>);
+
+    impl Service for MaxLength {
+        type Request = Request;
+        type Response = Response;
+        type Error = hyper::Error;
+        type Future = Box<Future<Item=Response, Error=hyper::Error>>;
+
+        fn call(&self, req: Request) -> Self::Future {
+            let too_long = match req.headers().get::<ContentLength>() {
+                Some(&ContentLength(len)) => len > 10,
+                None => false,
+            };
+            if too_long {
+                return Box::new(future::ok(Response::new()
+                    .with_status(StatusCode::PayloadTooLarge)));
+            }
+            let polled = self.0.clone();
+            Box::new(req.body().concat2().map(move |_| {
+                polled.store(true, Ordering::SeqCst);
+                Response::new()
+            }))
+        }
+    }
+
+    let _ = pretty_env_logger::try_init();
+    let body_read = Arc::new(AtomicBool::new(false));
+    let (addr_tx, addr_rx) = mpsc::channel();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let srv_body_read = body_read.clone();
+    let thread = thread::spawn(move || {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let srv = Http::new()
+            .expect_continue(true)
+            .bind(&addr, move || Ok(MaxLength(srv_body_read.clone())))
+            .unwrap();
+        addr_tx.send(srv.local_addr().unwrap()).unwrap();
+        srv.run_until(shutdown_rx.then(|_| Ok(()))).unwrap();
+    });
+    let addr = addr_rx.recv().unwrap();
+
+    let mut req = connect(&addr);
+    req.write_all(b"\
+        POST /foo HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Expect: 100-continue\r\n\
+        Content-Length: 100\r\n\
+        \r\n\
+    ").expect("write");
+
+    // no 100 Continue, just the final response, and then the connection is
+    // closed without the body having been read
+    let mut res = String::new();
+    req.read_to_string(&mut res).expect("read");
+    assert!(res.starts_with("HTTP/1.1 413 Payload Too Large\r\n"), "{:?}", res);
+    assert!(!res.contains("100 Continue"));
+    assert!(!body_read.load(Ordering::SeqCst));
+
+    drop(shutdown_tx);
+    thread.join().unwrap();
+}
+
+fn serve_unauthorized<F>(configure: F) -> (SocketAddr, oneshot::Sender<()>, thread::JoinHandle<()>)
+where
+    F: FnOnce(&mut Http) + Send + 'static,
+{
+    let _ = pretty_env_logger::try_init();
+    let (addr_tx, addr_rx) = mpsc::channel();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let thread = thread::spawn(move || {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let mut http = Http::new();
+        configure(&mut http);
+        // answers without ever reading the body
+        let srv = http
+            .bind(&addr, || Ok(service_fn_ok(|_req| {
+                Response::<hyper::Body>::new().with_status(StatusCode::Unauthorized)
+            })))
+            .unwrap();
+        addr_tx.send(srv.local_addr().unwrap()).unwrap();
+        srv.run_until(shutdown_rx.then(|_| Ok(()))).unwrap();
+    });
+    (addr_rx.recv().unwrap(), shutdown_tx, thread)
+}
+
+fn write_upload_then_get(req: &mut TcpStream, len: usize) {
+    let mut msg = format!("\
+        POST /upload HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Content-Length: {}\r\n\
+        \r\n\
+    ", len).into_bytes();
+    msg.extend(vec![b'x'; len]);
+    msg.extend_from_slice(b"\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ");
+    req.write_all(&msg).expect("write");
+}
+
+// Reads until the server closes the connection, ignoring a reset caused by
+// the unread body.
+fn read_until_closed(req: &mut TcpStream) -> String {
+    let mut res = Vec::new();
+    let mut buf = [0; 1024];
+    loop {
+        match req.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => res.extend_from_slice(&buf[..n]),
+        }
+    }
+    String::from_utf8(res).unwrap()
+}
+
+#[test]
+fn trailers_written_after_chunked_body() {
+    let res = serve_one_connection_with(Http::new(), b"\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ", service_fn_ok(|_req| {
+        let mut fields = hyper::Headers::new();
+        fields.set_raw("Grpc-Status", "0");
+        fields.set_raw("Undeclared", "dropped");
+        Response::new()
+            .with_header(Trailer(vec![Ascii::new("grpc-status".to_owned())]))
+            .with_body("hello")
+            .with_trailers(fields.into())
+    }));
+    assert!(res.contains("Transfer-Encoding: chunked\r\n"), "{:?}", res);
+    assert!(res.contains("Trailer: grpc-status\r\n"), "{:?}", res);
+    assert!(res.ends_with("\r\n\r\n5\r\nhello\r\n0\r\nGrpc-Status: 0\r\n\r\n"), "{:?}", res);
+}
+
+#[test]
+fn trailers_dropped_with_content_length() {
+    let res = serve_one_connection_with(Http::new(), b"\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ", service_fn_ok(|_req| {
+        let mut fields = hyper::Headers::new();
+        fields.set_raw("Grpc-Status", "0");
+        Response::new()
+            .with_header(ContentLength(5))
+            .with_header(Trailer(vec![Ascii::new("grpc-status".to_owned())]))
+            .with_body("hello")
+            .with_trailers(fields.into())
+    }));
+    assert!(res.ends_with("\r\n\r\nhello"), "{:?}", res);
+}
+
+#[test]
+fn close_connection_response_closes_keep_alive_connection() {
+    let res = serve_one_connection_with(Http::new(), b"\
+        GET /a HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        \r\n\
+        GET /b HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        \r\n\
+    ", service_fn_ok(|_req| {
+        Response::new()
+            .with_header(ContentLength(0))
+            .close_connection()
+    }));
+    assert_eq!(res.matches("HTTP/1.1 200 OK\r\n").count(), 1, "{:?}", res);
+    assert!(res.contains("Connection: close\r\n"), "{:?}", res);
+}
+
+#[test]
+fn auto_drain_body_keeps_connection_alive() {
+    let (addr, shutdown_tx, thread) = serve_unauthorized(|_| ());
+
+    let mut req = connect(&addr);
+    write_upload_then_get(&mut req, 16 * 1024);
+
+    let res = read_until_closed(&mut req);
+    assert_eq!(res.matches("HTTP/1.1 401 Unauthorized\r\n").count(), 2, "{:?}", res);
+
+    drop(shutdown_tx);
+    thread.join().unwrap();
+}
+
+#[test]
+fn auto_drain_body_disabled_closes_connection() {
+    let (addr, shutdown_tx, thread) = serve_unauthorized(|http| {
+        http.auto_drain_body(false);
+    });
+
+    let mut req = connect(&addr);
+    write_upload_then_get(&mut req, 16 * 1024);
+
+    let res = read_until_closed(&mut req);
+    assert_eq!(res.matches("HTTP/1.1 401 Unauthorized\r\n").count(), 1, "{:?}", res);
+
+    drop(shutdown_tx);
+    thread.join().unwrap();
+}
+
+#[test]
+fn max_drain_bytes_closes_connection_past_limit() {
+    let (addr, shutdown_tx, thread) = serve_unauthorized(|http| {
+        http.max_drain_bytes(Some(1024));
+    });
+
+    let mut req = connect(&addr);
+    write_upload_then_get(&mut req, 16 * 1024);
+
+    let res = read_until_closed(&mut req);
+    assert_eq!(res.matches("HTTP/1.1 401 Unauthorized\r\n").count(), 1, "{:?}", res);
+
+    // a body within the limit is still drained
+    let mut req = connect(&addr);
+    write_upload_then_get(&mut req, 512);
+
+    let res = read_until_closed(&mut req);
+    assert_eq!(res.matches("HTTP/1.1 401 Unauthorized\r\n").count(), 2, "{:?}", res);
+
+    drop(shutdown_tx);
+    thread.join().unwrap();
+}
+
+#[test]
+fn server_header_added_to_responses() {
+    let server = serve_with_options(ServeOptions {
+        server_header: Some(Server::new("hyper-test")),
+        .. ServeOptions::default()
+    });
+    let mut req = connect(server.addr());
+    server.reply().status(hyper::Ok);
+    server.reply()
+        .status(hyper::Ok)
+        .header(Server::new("custom"));
+
+    req.write_all(b"\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        \r\n\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ").expect("write");
+
+    let mut res = String::new();
+    req.read_to_string(&mut res).expect("read");
+    let mut responses = res.split("HTTP/1.1 200 OK\r\n").skip(1);
+    let first = responses.next().expect("first response");
+    assert!(has_header(first, "Server: hyper-test\r\n"), "{:?}", res);
+    // the header of the service is kept
+    let second = responses.next().expect("second response");
+    assert!(has_header(second, "Server: custom\r\n"), "{:?}", res);
+    assert!(!second.contains("hyper-test"), "{:?}", res);
+}
+
+#[test]
+fn echo_header_copies_request_header_unless_service_set_it() {
+    let service = service_fn_ok(|req: Request| {
+        let mut res = Response::new();
+        if req.path() == "/custom" {
+            res.headers_mut().set_raw("X-Request-Id", "from-service");
+        }
+        res
+    });
+    let mut http = Http::new();
+    http.echo_header("X-Request-Id").echo_header("X-Trace");
+    let resp = serve_one_connection_with(http, b"\
+        GET / HTTP/1.1\r\n\
+        X-Request-Id: abc\r\n\
+        x-trace: t1\r\n\
+        \r\n\
+        GET /custom HTTP/1.1\r\n\
+        X-Request-Id: def\r\n\
+        \r\n\
+        GET / HTTP/1.1\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ", service);
+    let mut responses = resp.split("HTTP/1.1 200 OK\r\n").skip(1);
+    let first = responses.next().expect("first response");
+    assert!(has_header(first, "X-Request-Id: abc\r\n"), "{:?}", resp);
+    assert!(has_header(first, "X-Trace: t1\r\n"), "{:?}", resp);
+    // the header of the service is kept
+    let second = responses.next().expect("second response");
+    assert!(has_header(second, "X-Request-Id: from-service\r\n"), "{:?}", resp);
+    assert!(!second.contains("def"), "{:?}", resp);
+    assert!(!second.contains("X-Trace"), "{:?}", resp);
+    let third = responses.next().expect("third response");
+    assert!(!third.contains("X-Request-Id"), "{:?}", resp);
+}
+
+#[test]
+fn strip_response_headers_removes_them_from_the_wire() {
+    let service = service_fn_ok(|_req: Request| {
+        let mut res = Response::new()
+            .with_header(ContentLength(2))
+            .with_body("ok");
+        res.headers_mut().set_raw("X-Powered-By", "php");
+        res.headers_mut().set_raw("server", "custom");
+        res.headers_mut().set_raw("X-Kept", "yes");
+        res.headers_mut().set_raw("Connection", "x-hop");
+        res.headers_mut().set_raw("X-Hop", "1");
+        res.headers_mut().set_raw("Keep-Alive", "timeout=5");
+        res.headers_mut().set_raw("Transfer-Encoding", "chunked");
+        res
+    });
+    let mut http = Http::new();
+    http.strip_response_headers(vec!["X-Powered-By", "Server"])
+        .strip_hop_by_hop_headers(true);
+    let resp = serve_one_connection_with(http, b"\
+        GET / HTTP/1.1\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ", service);
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", resp);
+    assert!(has_header(&resp, "X-Kept: yes\r\n"), "{:?}", resp);
+    assert!(has_header(&resp, "Content-Length: 2\r\n"), "{:?}", resp);
+    for name in &["X-Powered-By", "Server", "X-Hop", "Keep-Alive", "Transfer-Encoding", "x-hop"] {
+        assert!(!resp.to_lowercase().contains(&name.to_lowercase()), "{} in {:?}", name, resp);
+    }
+    assert!(resp.ends_with("\r\n\r\nok"), "{:?}", resp);
+}
+
+#[test]
+fn strip_hop_by_hop_headers_keeps_connection_close() {
+    let service = service_fn_ok(|_req: Request| {
+        let mut res = Response::new().with_header(ContentLength(0));
+        res.headers_mut().set_raw("Connection", "close, x-hop");
+        res.headers_mut().set_raw("X-Hop", "1");
+        res
+    });
+    let mut http = Http::new();
+    http.strip_hop_by_hop_headers(true);
+    let resp = serve_one_connection_with(http, b"\
+        GET / HTTP/1.1\r\n\
+        \r\n\
+        GET / HTTP/1.1\r\n\
+        \r\n\
+    ", service);
+    assert_eq!(resp.matches("HTTP/1.1 200 OK\r\n").count(), 1, "{:?}", resp);
+    assert!(has_header(&resp, "Connection: close\r\n"), "{:?}", resp);
+    assert!(!resp.contains("X-Hop"), "{:?}", resp);
+}
+
+#[test]
+fn pipeline_disabled() {
+    let server = serve();
+    let mut req = connect(server.addr());
+    server.reply()
+        .status(hyper::Ok)
+        .header(ContentLength(12))
+        .body("Hello World!");
+    server.reply()
+        .status(hyper::Ok)
+        .header(ContentLength(12))
+        .body("Hello World!");
+
+    req.write_all(b"\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        \r\n\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        \r\n\
+    ").expect("write 1");
+
+    let mut buf = vec![0; 4096];
+    let n = req.read(&mut buf).expect("read 1");
+    assert_ne!(n, 0);
+    // Woah there. What?
+    //
+    // This test is wishy-washy because of race conditions in access of the
+    // socket. The test is still useful, since it allows for the responses
+    // to be received in 2 reads. But it might sometimes come in 1 read.
+    //
+    // TODO: add in a delay to the `ServeReply` interface, to allow this
+    // delay to prevent the 2 writes from happening before this test thread
+    // can read from the socket.
+    match req.read(&mut buf) {
+        Ok(n) => {
+            // won't be 0, because we didn't say to close, and so socket
+            // will be open until `server` drops
+            assert_ne!(n, 0);
+        }
+        Err(_) => (),
+    }
+}
+
+#[test]
+fn pipeline_enabled() {
+    let server = serve_with_options(ServeOptions {
+        pipeline: true,
+        .. Default::default()
+    });
+    let mut req = connect(server.addr());
+    server.reply()
+        .status(hyper::Ok)
+        .header(ContentLength(12))
+        .body("Hello World\n");
+    server.reply()
+        .status(hyper::Ok)
+        .header(ContentLength(12))
+        .body("Hello World\n");
+
+    req.write_all(b"\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        \r\n\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ").expect("write 1");
+
+    let mut buf = vec![0; 4096];
+    let n = req.read(&mut buf).expect("read 1");
+    assert_ne!(n, 0);
+
+    {
+        let mut lines = buf.split(|&b| b == b'\n');
+        assert_eq!(s(lines.next().unwrap()), "HTTP/1.1 200 OK\r");
+        assert_eq!(s(lines.next().unwrap()), "Content-Length: 12\r");
+        lines.next().unwrap(); // Date
+        assert_eq!(s(lines.next().unwrap()), "\r");
+        assert_eq!(s(lines.next().unwrap()), "Hello World");
+
+        assert_eq!(s(lines.next().unwrap()), "HTTP/1.1 200 OK\r");
+        assert_eq!(s(lines.next().unwrap()), "Content-Length: 12\r");
+        lines.next().unwrap(); // Date
+        assert_eq!(s(lines.next().unwrap()), "\r");
+        assert_eq!(s(lines.next().unwrap()), "Hello World");
+    }
+
+
+    // with pipeline enabled, both responses should have been in the first read
+    // so a second read should be EOF
+    let n = req.read(&mut buf).expect("read 2");
+    assert_eq!(n, 0);
+}
+
+#[test]
+fn pipeline_responses_in_request_order() {
+    // the first request takes longer than the second one
+    struct Delayed;
+
+    impl Service for Delayed {
+        type Request = Request;
+        type Response = Response;
+        type Error = hyper::Error;
+        type Future = Box<Future<Item=Response, Error=hyper::Error>>;
+
+        fn call(&self, req: Request) -> Self::Future {
+            let (delay, body) = match req.path() {
+                "/slow" => (100, "slow"),
+                _ => (0, "fast"),
+            };
+            let (tx, rx) = oneshot::channel();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(delay));
+                let _ = tx.send(());
+            });
+            Box::new(rx.then(move |_| Ok(Response::new()
+                .with_header(ContentLength(body.len() as u64))
+                .with_body(body))))
+        }
+    }
+
+    let _ = pretty_env_logger::try_init();
+    let (addr_tx, addr_rx) = mpsc::channel();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let thread = thread::spawn(move || {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let srv = Http::new()
+            .pipeline(true)
+            .bind(&addr, || Ok(Delayed))
+            .unwrap();
+        addr_tx.send(srv.local_addr().unwrap()).unwrap();
+        srv.run_until(shutdown_rx.then(|_| Ok(()))).unwrap();
+    });
+    let addr = addr_rx.recv().unwrap();
+
+    let mut req = connect(&addr);
+    req.write_all(b"\
+        GET /slow HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        \r\n\
+        GET /fast HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        \r\n\
+        GET /slow HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ").expect("write");
+
+    let mut res = String::new();
+    req.read_to_string(&mut res).expect("read");
+    let bodies = res.split("\r\n\r\n").skip(1)
+        .map(|part| &part[..4])
+        .collect::<Vec<_>>();
+    assert_eq!(bodies, ["slow", "fast", "slow"], "{:?}", res);
+
+    drop(shutdown_tx);
+    thread.join().unwrap();
+}
+
+#[test]
+fn http_10_request_receives_http_10_response() {
+    let server = serve();
+
+    let mut req = connect(server.addr());
+    req.write_all(b"\
+        GET / HTTP/1.0\r\n\
+        \r\n\
+    ").unwrap();
+
+    let expected = "HTTP/1.0 200 OK\r\nContent-Length: 0\r\n";
+    let mut buf = [0; 256];
+    let n = req.read(&mut buf).unwrap();
+    assert!(n >= expected.len(), "read: {:?} >= {:?}", n, expected.len());
+    assert_eq!(s(&buf[..expected.len()]), expected);
+}
+
+#[test]
+fn disable_keep_alive_mid_request() {
+    let mut core = Core::new().unwrap();
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (tx1, rx1) = oneshot::channel();
+    let (tx2, rx2) = oneshot::channel();
+
+    let child = thread::spawn(move || {
+        let mut req = connect(&addr);
+        req.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+        tx1.send(()).unwrap();
+        rx2.wait().unwrap();
+        req.write_all(b"Host: localhost\r\n\r\n").unwrap();
+        let mut buf = vec![];
+        req.read_to_end(&mut buf).unwrap();
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let (socket, _) = item.unwrap();
+            Http::<hyper::Chunk>::new().serve_connection(socket, HelloWorld)
+                .select2(rx1)
+                .then(|r| {
+                    match r {
+                        Ok(Either::A(_)) => panic!("expected rx first"),
+                        Ok(Either::B(((), mut conn))) => {
+                            conn.disable_keep_alive();
+                            tx2.send(()).unwrap();
+                            conn
+                        }
+                        Err(Either::A((e, _))) => panic!("unexpected error {}", e),
+                        Err(Either::B((e, _))) => panic!("unexpected error {}", e),
+                    }
+                })
+        });
+
+    core.run(fut).unwrap();
+    child.join().unwrap();
+}
+
+#[test]
+fn disable_keep_alive_post_request() {
+    let mut core = Core::new().unwrap();
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (tx1, rx1) = oneshot::channel();
+
+    let child = thread::spawn(move || {
+        let mut req = connect(&addr);
+        req.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            Host: localhost\r\n\
+            \r\n\
+        ").unwrap();
+
+        let mut buf = [0; 1024 * 8];
+        loop {
+            let n = req.read(&mut buf).expect("reading 1");
+            if n < buf.len() {
+                if &buf[n - HELLO.len()..n] == HELLO.as_bytes() {
+                    break;
+                }
+            }
+        }
+
+        tx1.send(()).unwrap();
+
+        let nread = req.read(&mut buf).expect("keep-alive reading");
+        assert_eq!(nread, 0);
+    });
+
+    let dropped = Dropped::new();
+    let dropped2 = dropped.clone();
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let (socket, _) = item.expect("accepted socket");
+            let transport = DebugStream {
+                stream: socket,
+                _debug: dropped2,
+            };
+            Http::<hyper::Chunk>::new().serve_connection(transport, HelloWorld)
+                .select2(rx1)
+                .then(|r| {
+                    match r {
+                        Ok(Either::A(_)) => panic!("expected rx first"),
+                        Ok(Either::B(((), mut conn))) => {
+                            conn.disable_keep_alive();
+                            conn
+                        }
+                        Err(Either::A((e, _))) => panic!("unexpected error {}", e),
+                        Err(Either::B((e, _))) => panic!("unexpected error {}", e),
+                    }
+                })
+        });
+
+    assert!(!dropped.load());
+    core.run(fut).unwrap();
+    // we must poll the Core one more time in order for Windows to drop
+    // the read-blocked socket.
+    //
+    // See https://github.com/carllerche/mio/issues/776
+    let timeout = Timeout::new(Duration::from_millis(10), &core.handle()).unwrap();
+    core.run(timeout).unwrap();
+    assert!(dropped.load());
+    child.join().unwrap();
+}
+
+#[test]
+fn empty_parse_eof_does_not_return_error() {
+    let mut core = Core::new().unwrap();
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let _tcp = connect(&addr);
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let (socket, _) = item.unwrap();
+            Http::<hyper::Chunk>::new().serve_connection(socket, HelloWorld)
+        });
+
+    core.run(fut).unwrap();
+}
+
+#[test]
+fn nonempty_parse_eof_returns_error() {
+    let mut core = Core::new().unwrap();
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"GET / HTTP/1.1").unwrap();
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let (socket, _) = item.unwrap();
+            Http::<hyper::Chunk>::new().serve_connection(socket, HelloWorld)
+                .map(|_| ())
+        });
+
+    core.run(fut).unwrap_err();
+}
+
+#[test]
+fn returning_1xx_response_is_error() {
+    let mut core = Core::new().unwrap();
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut buf = [0; 256];
+        tcp.read(&mut buf).unwrap();
+
+        let expected = "HTTP/1.1 500 ";
+        assert_eq!(s(&buf[..expected.len()]), expected);
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let (socket, _) = item.unwrap();
+            Http::<hyper::Chunk>::new()
+                .serve_connection(socket, service_fn(|_| {
+                    Ok(Response::<hyper::Body>::new()
+                        .with_status(StatusCode::Continue))
+                }))
+                .map(|_| ())
+        });
+
+    core.run(fut).unwrap_err();
+}
+
+#[test]
+fn upgrades() {
+    use tokio_io::io::{read_to_end, write_all};
+    let _ = pretty_env_logger::try_init();
+    let mut core = Core::new().unwrap();
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (tx, rx) = oneshot::channel();
+
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            Upgrade: foobar\r\n\
+            Connection: upgrade\r\n\
+            \r\n\
+            eagerly optimistic\
+        ").expect("write 1");
+        let mut buf = [0; 256];
+        tcp.read(&mut buf).expect("read 1");
+
+        let expected = "HTTP/1.1 101 Switching Protocols\r\n";
+        assert_eq!(s(&buf[..expected.len()]), expected);
+        let _ = tx.send(());
+
+        let n = tcp.read(&mut buf).expect("read 2");
+        assert_eq!(s(&buf[..n]), "foo=bar");
+        tcp.write_all(b"bar=foo").expect("write 2");
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| -> hyper::Error { unreachable!() })
+        .and_then(|(item, _incoming)| {
+            let (socket, _) = item.unwrap();
+            let conn = Http::<hyper::Chunk>::new()
+                .serve_connection(socket, service_fn(|_| {
+                    let mut res = Response::<hyper::Body>::new()
+                        .with_status(StatusCode::SwitchingProtocols);
+                    res.headers_mut().set_raw("Upgrade", "foobar");
+                    Ok(res)
+                }));
+
+            let mut conn_opt = Some(conn);
+            future::poll_fn(move || {
+                try_ready!(conn_opt.as_mut().unwrap().poll_without_shutdown());
+                // conn is done with HTTP now
+                Ok(conn_opt.take().unwrap().into())
+            })
+        });
+
+    let conn = core.run(fut).unwrap();
+
+    // wait so that we don't write until other side saw 101 response
+    core.run(rx).unwrap();
+
+    let parts = conn.into_parts();
+    let io = parts.io;
+    assert_eq!(parts.read_buf, "eagerly optimistic");
+
+    let io = core.run(write_all(io, b"foo=bar")).unwrap().0;
+    let vec = core.run(read_to_end(io, vec![])).unwrap().1;
+    assert_eq!(vec, b"bar=foo");
+}
+
+#[test]
+fn into_upgraded_hands_back_io_and_buffered_bytes() {
+    use tokio_io::io::{read_exact, read_to_end, write_all};
+    let _ = pretty_env_logger::try_init();
+    let mut core = Core::new().unwrap();
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            Upgrade: foobar\r\n\
+            Connection: upgrade\r\n\
+            \r\n\
+            eagerly optimistic\
+        ").expect("write 1");
+        let mut buf = [0; 256];
+        tcp.read(&mut buf).expect("read 1");
+
+        let expected = "HTTP/1.1 101 Switching Protocols\r\n";
+        assert_eq!(s(&buf[..expected.len()]), expected);
+
+        tcp.write_all(b" foo=bar").expect("write 2");
+        let n = tcp.read(&mut buf).expect("read 2");
+        assert_eq!(s(&buf[..n]), "bar=foo");
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| -> hyper::Error { unreachable!() })
+        .and_then(|(item, _incoming)| {
+            let (socket, _) = item.unwrap();
+            Http::<hyper::Chunk>::new()
+                .serve_connection(socket, service_fn(|_| {
+                    let mut res = Response::<hyper::Body>::new()
+                        .with_status(StatusCode::SwitchingProtocols);
+                    res.headers_mut().set_raw("Upgrade", "foobar");
+                    Ok(res)
+                }))
+                .into_upgraded()
+        });
+
+    let upgraded = core.run(fut).unwrap().expect("upgraded");
+
+    let expected = b"eagerly optimistic foo=bar";
+    let (upgraded, buf) = core.run(read_exact(upgraded, vec![0; expected.len()])).unwrap();
+    assert_eq!(buf, &expected[..]);
+
+    let io = core.run(write_all(upgraded, b"bar=foo")).unwrap().0;
+    let vec = core.run(read_to_end(io, vec![])).unwrap().1;
+    assert!(vec.is_empty());
+}
+
+#[test]
+fn into_upgraded_without_upgrade_resolves_none() {
+    let _ = pretty_env_logger::try_init();
+    let mut core = Core::new().unwrap();
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            Connection: close\r\n\
+            \r\n\
+        ").expect("write");
+        let mut body = String::new();
+        tcp.read_to_string(&mut body).expect("read");
+        assert!(body.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", body);
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| -> hyper::Error { unreachable!() })
+        .and_then(|(item, _incoming)| {
+            let (socket, _) = item.unwrap();
+            Http::<hyper::Chunk>::new()
+                .serve_connection(socket, HelloWorld)
+                .into_upgraded()
+        });
+
+    assert!(core.run(fut).unwrap().is_none());
+}
+
+#[test]
+fn connect_tunnel_hands_socket_to_service() {
+    use tokio_core::net::TcpStream as TokioTcpStream;
+    use tokio_io::io::copy;
+    let _ = pretty_env_logger::try_init();
+
+    // the target of the tunnel, echoing what it reads
+    let target = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let target_addr = target.local_addr().unwrap();
+    thread::spawn(move || {
+        let (mut sock, _) = target.accept().unwrap();
+        let mut buf = [0; 256];
+        loop {
+            let n = sock.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            sock.write_all(&buf[..n]).unwrap();
+        }
+    });
+
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let service_handle = handle.clone();
+    let srv = Http::new().bind_handle(&addr, &handle, move || {
+        let handle = service_handle.clone();
+        Ok(service_fn_ok(move |mut req: Request| {
+            assert_eq!(*req.method(), hyper::Method::Connect);
+            let target_addr = req.uri().authority().unwrap().parse().unwrap();
+            let on_upgrade = req.on_upgrade().expect("on_upgrade");
+            let connect = TokioTcpStream::connect(&target_addr, &handle);
+            handle.spawn(on_upgrade
+                .and_then(|upgraded| {
+                    let upgraded = upgraded.expect("tunnel");
+                    connect.map_err(|e| panic!("connect target: {}", e))
+                        .map(|target| (upgraded, target))
+                })
+                .and_then(|(upgraded, target)| {
+                    let (client_r, client_w) = upgraded.split();
+                    let (target_r, target_w) = target.split();
+                    copy(client_r, target_w).join(copy(target_r, client_w))
+                        .map(|_| ())
+                        .map_err(|_| ())
+                }));
+            Response::<hyper::Body>::new()
+        }))
+    }).unwrap();
+    let addr = srv.local_addr();
+
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        // the first bytes of the tunnel are sent along with the request
+        write!(tcp, "CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n\r\nearly bytes, ", target_addr).unwrap();
+        let mut res = Vec::new();
+        let mut buf = [0; 256];
+        while !res.windows(4).any(|w| w == b"\r\n\r\n") {
+            let n = tcp.read(&mut buf).expect("read response");
+            assert!(n > 0, "closed before the response: {:?}", s(&res));
+            res.extend_from_slice(&buf[..n]);
+        }
+        assert!(s(&res).starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", s(&res));
+        assert!(!s(&res).contains("Content-Length"), "{:?}", s(&res));
+        let end = res.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        let mut echoed = res[end..].to_vec();
+
+        // not HTTP, such as the handshake of TLS
+        tcp.write_all(b"\x16\x03\x01 GET / HTTP/1.1\r\n\r\n").unwrap();
+        let expected = b"early bytes, \x16\x03\x01 GET / HTTP/1.1\r\n\r\n";
+        while echoed.len() < expected.len() {
+            let n = tcp.read(&mut buf).expect("read tunnel");
+            assert!(n > 0, "tunnel closed: {:?}", s(&echoed));
+            echoed.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(s(&echoed), s(&expected[..]));
+        shutdown_tx.send(()).unwrap();
+    });
+
+    let outcome = core.run(srv.serve_until(shutdown_rx.then(|_| Ok(())))).unwrap();
+    // the tunnel no longer counts as a connection of the server
+    assert_eq!(outcome.remaining, 0);
+    client.join().unwrap();
+}
+
+#[test]
+fn connect_without_on_upgrade_keeps_http() {
+    let _ = pretty_env_logger::try_init();
+    let (addr_tx, addr_rx) = mpsc::channel();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let thread = thread::spawn(move || {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        // answers every request, CONNECT included, without taking the
+        // connection over
+        let srv = Http::new().bind(&addr, || Ok(HelloWorld)).unwrap();
+        addr_tx.send(srv.local_addr().unwrap()).unwrap();
+        srv.run_until(shutdown_rx.then(|_| Ok(()))).unwrap();
+    });
+    let addr = addr_rx.recv().unwrap();
+
+    let mut tcp = connect(&addr);
+    tcp.write_all(b"\
+        CONNECT example.domain:443 HTTP/1.1\r\n\
+        Host: example.domain:443\r\n\
+        \r\n\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ").unwrap();
+    let mut res = String::new();
+    tcp.read_to_string(&mut res).expect("read until closed");
+    assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", res);
+    assert_eq!(res.matches("HTTP/1.1 200 OK\r\n").count(), 2, "{:?}", res);
+    assert!(res.ends_with(HELLO), "{:?}", res);
+
+    drop(shutdown_tx);
+    thread.join().unwrap();
+}
+
+#[test]
+fn parse_errors_send_4xx_response() {
+    let mut core = Core::new().unwrap();
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"GE T / HTTP/1.1\r\n\r\n").unwrap();
+        let mut buf = [0; 256];
+        tcp.read(&mut buf).unwrap();
+
+        let expected = "HTTP/1.1 400 ";
+        assert_eq!(s(&buf[..expected.len()]), expected);
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let (socket, _) = item.unwrap();
+            Http::<hyper::Chunk>::new()
+                .serve_connection(socket, HelloWorld)
+                .map(|_| ())
+        });
+
+    core.run(fut).unwrap_err();
+}
+
+#[test]
+fn h2_preface_gets_505_response() {
+    let _ = pretty_env_logger::try_init();
+    let mut core = Core::new().unwrap();
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n").unwrap();
+        let mut resp = String::new();
+        tcp.read_to_string(&mut resp).unwrap();
+        resp
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let (socket, _) = item.unwrap();
+            Http::<hyper::Chunk>::new()
+                .serve_connection(socket, HelloWorld)
+                .map(|_| ())
+        });
+
+    let err = core.run(fut).unwrap_err();
+    match err {
+        hyper::Error::Http2Preface => (),
+        other => panic!("expected Http2Preface, found {:?}", other),
+    }
+    assert_eq!(err.kind(), hyper::ErrorKind::Parse);
+    let resp = client.join().unwrap();
+    assert!(resp.starts_with("HTTP/1.1 505 HTTP Version Not Supported\r\n"), "{:?}", resp);
+}
+
+#[test]
+fn illegal_request_length_returns_400_response() {
+    let mut core = Core::new().unwrap();
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"POST / HTTP/1.1\r\nContent-Length: foo\r\n\r\n").unwrap();
+        let mut buf = [0; 256];
+        tcp.read(&mut buf).unwrap();
+
+        let expected = "HTTP/1.1 400 ";
+        assert_eq!(s(&buf[..expected.len()]), expected);
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let (socket, _) = item.unwrap();
+            Http::<hyper::Chunk>::new()
+                .serve_connection(socket, HelloWorld)
+                .map(|_| ())
+        });
+
+    core.run(fut).unwrap_err();
+}
+
+#[test]
+fn max_buf_size() {
+    let _ = pretty_env_logger::try_init();
+    let mut core = Core::new().unwrap();
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    const MAX: usize = 16_000;
+
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"POST /").expect("write 1");
+        tcp.write_all(&vec![b'a'; MAX]).expect("write 2");
+        let mut buf = [0; 256];
+        tcp.read(&mut buf).expect("read 1");
+
+        let expected = "HTTP/1.1 431 ";
+        assert_eq!(s(&buf[..expected.len()]), expected);
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let (socket, _) = item.unwrap();
+            Http::<hyper::Chunk>::new()
+                .max_buf_size(MAX)
+                .serve_connection(socket, HelloWorld)
+                .map(|_| ())
+        });
+
+    core.run(fut).unwrap_err();
+}
+
+#[test]
+fn on_exchange_complete_reports_keep_alive() {
+    let _ = pretty_env_logger::try_init();
+    let mut core = Core::new().unwrap();
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let first = b"GET / HTTP/1.1\r\nHost: example.domain\r\n\r\n";
+    let second = b"POST / HTTP/1.1\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello";
+
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(first).expect("write 1");
+        tcp.write_all(second).expect("write 2");
+        let mut resp = String::new();
+        tcp.read_to_string(&mut resp).expect("read");
+        resp
+    });
+
+    let exchanges = Arc::new(Mutex::new(Vec::new()));
+    let exchanges2 = exchanges.clone();
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(move |(item, _incoming)| {
+            let (socket, _) = item.unwrap();
+            Http::<hyper::Chunk>::new()
+                .on_exchange_complete(move |info| exchanges2.lock().unwrap().push(info))
+                .serve_connection(socket, service_fn(|req: Request| {
+                    req.body().concat2().map(|body| {
+                        Response::<hyper::Body>::new()
+                            .with_header(ContentLength(body.len() as u64))
+                            .with_body(body)
+                    })
+                }))
+                .map(|_| ())
+        });
+
+    core.run(fut).unwrap();
+    let resp = client.join().unwrap();
+
+    let exchanges = exchanges.lock().unwrap();
+    assert_eq!(exchanges.len(), 2);
+    assert!(exchanges[0].keep_alive);
+    assert!(!exchanges[1].keep_alive);
+    assert_eq!(exchanges[0].bytes_read, first.len() as u64);
+    assert_eq!(exchanges[1].bytes_read, second.len() as u64);
+    assert_eq!(exchanges[0].bytes_written + exchanges[1].bytes_written, resp.len() as u64);
+}
+
+#[test]
+fn on_exchange_timing_reports_each_exchange() {
+    let _ = pretty_env_logger::try_init();
+    let mut core = Core::new().unwrap();
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            \r\n\
+            GET / HTTP/1.1\r\n\
+            Connection: close\r\n\
+            \r\n\
+        ").expect("write");
+        let mut resp = String::new();
+        tcp.read_to_string(&mut resp).expect("read");
+    });
+
+    let timings = Arc::new(Mutex::new(Vec::new()));
+    let timings2 = timings.clone();
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(move |(item, _incoming)| {
+            let (socket, _) = item.unwrap();
+            Http::<hyper::Chunk>::new()
+                .on_exchange_timing(move |timing| timings2.lock().unwrap().push(timing))
+                .serve_connection(socket, service_fn(|_req| {
+                    thread::sleep(Duration::from_millis(20));
+                    future::ok::<_, hyper::Error>(Response::<hyper::Body>::new())
+                }))
+                .map(|_| ())
+        });
+
+    core.run(fut).unwrap();
+    client.join().unwrap();
+
+    let timings = timings.lock().unwrap();
+    assert_eq!(timings.len(), 2);
+    for timing in timings.iter() {
+        assert!(timing.head_received <= timing.service_called);
+        assert!(timing.response_flushed - timing.service_called >= Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn on_connection_close_reports_wire_bytes() {
+    let _ = pretty_env_logger::try_init();
+    let mut core = Core::new().unwrap();
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let req = b"\
+        POST / HTTP/1.1\r\n\
+        Transfer-Encoding: chunked\r\n\
+        Connection: close\r\n\
+        \r\n\
+        5\r\n\
+        hello\r\n\
+        0\r\n\
+        \r\n\
+    ";
+
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(req).expect("write");
+        let mut resp = String::new();
+        tcp.read_to_string(&mut resp).expect("read");
+        resp
+    });
+
+    let (bytes_tx, bytes_rx) = mpsc::channel();
+    let bytes_tx = Mutex::new(bytes_tx);
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(move |(item, _incoming)| {
+            let (socket, _) = item.unwrap();
+            Http::<hyper::Chunk>::new()
+                .on_connection_close(move |bytes| bytes_tx.lock().unwrap().send(bytes).unwrap())
+                .serve_connection(socket, service_fn(|req: Request| {
+                    future::ok::<_, hyper::Error>(Response::<hyper::Body>::new().with_body(req.body()))
+                }))
+                .map(|_| ())
+        });
+
+    core.run(fut).unwrap();
+    let resp = client.join().unwrap();
+    assert!(resp.ends_with("\r\n\r\n5\r\nhello\r\n0\r\n\r\n"), "{:?}", resp);
+
+    let bytes = bytes_rx.recv().unwrap();
+    assert_eq!(bytes.bytes_read, req.len() as u64);
+    assert_eq!(bytes.bytes_written, resp.len() as u64);
+}
+
+#[test]
+fn auto_date_disabled_omits_date_header() {
+    let mut http = Http::<hyper::Chunk>::new();
+    http.auto_date(false);
+    let resp = serve_one_connection(http, b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n");
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", resp);
+    assert!(!resp.contains("Date: "), "{:?}", resp);
+}
+
+#[test]
+fn date_clock_sets_date_header() {
+    let mut http = Http::<hyper::Chunk>::new();
+    http.date_clock(|| ::std::time::UNIX_EPOCH + Duration::from_secs(784_111_777));
+    let resp = serve_one_connection(http, b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n");
+    assert!(has_header(&resp, "Date: Sun, 06 Nov 1994 08:49:37 GMT"), "{:?}", resp);
+}
+
+#[test]
+fn http1_keepalive_for_10() {
+    let reqs = b"\
+        GET / HTTP/1.0\r\n\
+        Connection: keep-alive\r\n\
+        \r\n\
+        GET / HTTP/1.0\r\n\
+        \r\n\
+    ";
+
+    let resp = serve_one_connection(Http::new(), reqs);
+    assert_eq!(resp.matches("HTTP/1.0 200 OK\r\n").count(), 2, "{:?}", resp);
+
+    let mut http = Http::new();
+    http.http1_keepalive_for_10(false);
+    let resp = serve_one_connection(http, reqs);
+    assert_eq!(resp.matches("HTTP/1.0 200 OK\r\n").count(), 1, "{:?}", resp);
+    assert!(!has_header(&resp, "Connection: keep-alive"), "{:?}", resp);
+}
+
+#[test]
+fn request_version_is_parsed_from_request_line() {
+    let service = service_fn_ok(|req: Request| {
+        let version = req.version().to_string();
+        Response::new()
+            .with_header(ContentLength(version.len() as u64))
+            .with_body(version)
+    });
+    let resp = serve_one_connection_with(Http::new(), b"\
+        GET / HTTP/1.0\r\n\
+        Connection: keep-alive\r\n\
+        \r\n\
+        GET / HTTP/1.1\r\n\
+        \r\n\
+        GET / HTTP/1.0\r\n\
+        \r\n\
+    ", service);
+    let bodies = resp.split("\r\n\r\n").skip(1)
+        .map(|rest| &rest[..8])
+        .collect::<Vec<_>>();
+    assert_eq!(bodies, ["HTTP/1.0", "HTTP/1.1", "HTTP/1.0"], "{:?}", resp);
+}
+
+#[test]
+fn max_pipeline_depth_serves_every_request() {
+    let mut http = Http::new();
+    http.pipeline(true).max_pipeline_depth(1);
+    let resp = serve_one_connection(http, b"\
+        GET /a HTTP/1.1\r\n\
+        \r\n\
+        GET /b HTTP/1.1\r\n\
+        \r\n\
+        GET /c HTTP/1.1\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ");
+    assert_eq!(resp.matches("HTTP/1.1 200 OK\r\n").count(), 3, "{:?}", resp);
+}
+
+fn serve_greedy_and_other(limit: usize) -> Vec<String> {
+    let _ = pretty_env_logger::try_init();
+    let served = Arc::new(Mutex::new(Vec::new()));
+    let served2 = served.clone();
+    let (addr_tx, addr_rx) = mpsc::channel();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let thread = thread::spawn(move || {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let srv = Http::new()
+            .pipeline(true)
+            .pipeline_fairness_limit(limit)
+            .bind(&addr, move || {
+                let served = served2.clone();
+                Ok(service_fn_ok(move |req: Request| {
+                    let first = {
+                        let mut served = served.lock().unwrap();
+                        served.push(req.path().to_owned());
+                        served.len() == 1
+                    };
+                    if first {
+                        // block the reactor while the other connection sends
+                        // its request, so both are ready at once
+                        thread::sleep(Duration::from_millis(200));
+                    }
+                    Response::<hyper::Body>::new()
+                        .with_header(ContentLength(2))
+                        .with_body("ok")
+                }))
+            })
+            .unwrap();
+        addr_tx.send(srv.local_addr().unwrap()).unwrap();
+        srv.run_until(shutdown_rx.then(|_| Ok(()))).unwrap();
+    });
+    let addr = addr_rx.recv().unwrap();
+
+    let mut other = connect(&addr);
+    let mut greedy = connect(&addr);
+    // both connections are accepted before the greedy one sends
+    thread::sleep(Duration::from_millis(50));
+    let mut flood = String::new();
+    for i in 0..100 {
+        flood.push_str(&format!("GET /greedy/{} HTTP/1.1\r\nHost: example.domain\r\n\r\n", i));
+    }
+    greedy.write_all(flood.as_bytes()).unwrap();
+    thread::sleep(Duration::from_millis(50));
+    other.write_all(b"GET /other HTTP/1.1\r\nHost: example.domain\r\nConnection: close\r\n\r\n").unwrap();
+
+    let mut resp = String::new();
+    other.read_to_string(&mut resp).unwrap();
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", resp);
+    let mut resp = Vec::new();
+    let mut buf = [0; 8192];
+    while resp.windows(2).filter(|w| w == b"ok").count() < 100 {
+        let n = greedy.read(&mut buf).unwrap();
+        assert!(n > 0, "greedy connection closed early");
+        resp.extend_from_slice(&buf[..n]);
+    }
+
+    drop(shutdown_tx);
+    thread.join().unwrap();
+    let served = served.lock().unwrap();
+    assert_eq!(served.len(), 101, "{:?}", *served);
+    served.clone()
+}
+
+#[test]
+fn pipeline_fairness_limit_lets_other_connections_progress() {
+    let served = serve_greedy_and_other(4);
+    let other = served.iter().position(|path| path == "/other").unwrap();
+    // served after a few turns of the greedy connection, not after all of
+    // its pipelined requests
+    assert!(other < 50, "other served at {} of {:?}", other, served);
+}
+
+#[test]
+fn max_requests_per_connection_closes_after_last_response() {
+    let mut http = Http::new();
+    http.max_requests_per_connection(2);
+    // no request closes the connection itself
+    let resp = serve_one_connection(http, b"\
+        GET /a HTTP/1.1\r\n\
+        \r\n\
+        GET /b HTTP/1.1\r\n\
+        \r\n\
+        GET /c HTTP/1.1\r\n\
+        \r\n\
+    ");
+    let responses = resp.split("HTTP/1.1 200 OK\r\n").skip(1).collect::<Vec<_>>();
+    assert_eq!(responses.len(), 2, "{:?}", resp);
+    assert!(!responses[0].contains("Connection: close"), "{:?}", resp);
+    assert!(has_header(responses[1], "Connection: close"), "{:?}", resp);
+}
+
+#[test]
+fn write_timeout_closes_connection_of_stalled_reader() {
+    let _ = pretty_env_logger::try_init();
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &handle).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (done_tx, done_rx) = mpsc::channel::<()>();
+
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            \r\n\
+        ").expect("write");
+        // never read the response, until the server gives up
+        let _ = done_rx.recv();
+    });
+
+    let mut http = Http::<hyper::Chunk>::new();
+    http.write_timeout(Duration::from_millis(100));
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(move |(item, _incoming)| {
+            let (socket, _) = item.unwrap();
+            let service = service_fn_ok(|_req: Request| {
+                Response::<hyper::Body>::new().with_body(vec![b'x'; 16 * 1024 * 1024])
+            });
+            http.serve_connection_handle(socket, &handle, service)
+        });
+
+    match core.run(fut) {
+        Err(hyper::Error::WriteTimeout) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    done_tx.send(()).unwrap();
+    client.join().unwrap();
+}
+
+#[test]
+fn body_read_timeout_errors_stalled_upload() {
+    let _ = pretty_env_logger::try_init();
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &handle).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            POST /upload HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            Transfer-Encoding: chunked\r\n\
+            \r\n\
+            5\r\n\
+            hello\r\n\
+        ").expect("write");
+        // stall in the middle of the body, until the server gives up
+        let mut resp = String::new();
+        tcp.read_to_string(&mut resp).expect("read");
+        resp
+    });
+
+    let (body_tx, body_rx) = mpsc::channel();
+    let mut http = Http::<hyper::Chunk>::new();
+    http.header_read_timeout(Duration::from_secs(5))
+        .request_timeout(Duration::from_secs(5))
+        .body_read_timeout(Duration::from_millis(100));
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(move |(item, _incoming)| {
+            let (socket, _) = item.unwrap();
+            let service = service_fn(move |req: Request| {
+                let body_tx = body_tx.clone();
+                req.body().fold(0, |n, chunk| Ok::<_, hyper::Error>(n + chunk.len())).then(move |result| {
+                    let status = match result {
+                        Err(hyper::Error::BodyTimeout) => StatusCode::RequestTimeout,
+                        _ => StatusCode::Ok,
+                    };
+                    body_tx.send(result).unwrap();
+                    Ok(Response::<hyper::Body>::new().with_status(status))
+                })
+            });
+            http.serve_connection_handle(socket, &handle, service)
+        });
+
+    core.run(fut).unwrap();
+    match body_rx.try_recv().expect("body result") {
+        Err(hyper::Error::BodyTimeout) => (),
+        other => panic!("unexpected body result: {:?}", other),
+    }
+    let resp = client.join().unwrap();
+    assert!(resp.starts_with("HTTP/1.1 408 Request Timeout\r\n"), "{:?}", resp);
+}
+
+#[test]
+fn body_read_timeout_resets_on_each_chunk() {
+    let _ = pretty_env_logger::try_init();
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &handle).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            POST /upload HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            Transfer-Encoding: chunked\r\n\
+            Connection: close\r\n\
+            \r\n\
+        ").expect("write");
+        // slower overall than the timeout, but never stalling that long
+        for _ in 0..4 {
+            thread::sleep(Duration::from_millis(50));
+            tcp.write_all(b"5\r\nhello\r\n").expect("write chunk");
+        }
+        tcp.write_all(b"0\r\n\r\n").expect("write end");
+        let mut resp = String::new();
+        tcp.read_to_string(&mut resp).expect("read");
+        resp
+    });
+
+    let mut http = Http::<hyper::Chunk>::new();
+    http.body_read_timeout(Duration::from_millis(100));
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(move |(item, _incoming)| {
+            let (socket, _) = item.unwrap();
+            let service = service_fn(|req: Request| {
+                req.body().concat2().map(|body| {
+                    Response::<hyper::Body>::new().with_body(body.to_vec())
+                })
+            });
+            http.serve_connection_handle(socket, &handle, service)
+        });
+
+    core.run(fut).unwrap();
+    let resp = client.join().unwrap();
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", resp);
+    assert!(resp.contains("\r\nhellohellohellohello\r\n"), "{:?}", resp);
+}
+
+#[test]
+fn pending_write_bytes_counts_unflushed_response() {
+    let _ = pretty_env_logger::try_init();
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &handle).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (done_tx, done_rx) = mpsc::channel::<()>();
+
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            \r\n\
+        ").expect("write");
+        // never read the response
+        let _ = done_rx.recv();
+    });
+
+    let (socket, _) = core.run(listener.incoming().into_future()).map_err(|_| ()).unwrap().0.unwrap();
+    let service = service_fn_ok(|_req: Request| {
+        Response::<hyper::Body>::new().with_body(vec![b'x'; 16 * 1024 * 1024])
+    });
+    let conn = Http::<hyper::Chunk>::new().serve_connection_handle(socket, &handle, service);
+    assert_eq!(conn.pending_write_bytes(), 0);
+
+    // run the connection until the socket buffers are full
+    let timeout = Timeout::new(Duration::from_millis(200), &handle).unwrap();
+    let conn = match core.run(timeout.select2(conn)) {
+        Ok(Either::A((_, conn))) => conn,
+        Ok(Either::B(_)) => panic!("connection finished"),
+        Err(_) => panic!("error running the connection"),
+    };
+    assert!(conn.bytes_written() > 0);
+    assert!(conn.pending_write_bytes() > 0);
+    drop(conn);
+
+    done_tx.send(()).unwrap();
+    client.join().unwrap();
+}
+
+// Serves a response streaming `chunks` 1-byte chunks, one every `interval`,
+// returning the raw response, the number of reads it took, and the longest
+// wait between two reads.
+fn serve_dribbled_chunks(mut http: Http<hyper::Chunk>, chunks: usize, interval: Duration) -> (String, usize, Duration) {
+    let _ = pretty_env_logger::try_init();
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &handle).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            Connection: close\r\n\
+            \r\n\
+        ").expect("write");
+        let mut resp = Vec::new();
+        let mut buf = [0; 4096];
+        let mut reads = 0;
+        let mut longest_wait = Duration::from_millis(0);
+        let mut last = Instant::now();
+        loop {
+            let n = tcp.read(&mut buf).expect("read");
+            if n == 0 {
+                break;
+            }
+            let now = Instant::now();
+            if reads > 0 {
+                longest_wait = longest_wait.max(now - last);
+            }
+            last = now;
+            reads += 1;
+            resp.extend_from_slice(&buf[..n]);
+        }
+        (String::from_utf8(resp).unwrap(), reads, longest_wait)
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(move |(item, _incoming)| {
+            let (socket, _) = item.unwrap();
+            let service = service_fn_ok(move |_req: Request| {
+                let (body_tx, body) = hyper::Body::pair();
+                thread::spawn(move || {
+                    let mut body_tx = body_tx;
+                    for _ in 0..chunks {
+                        thread::sleep(interval);
+                        body_tx = futures::Sink::send(body_tx, Ok("x".into())).wait().expect("send chunk");
+                    }
+                });
+                Response::<hyper::Body>::new().with_body(body)
+            });
+            http.serve_connection_handle(socket, &handle, service)
+        });
+
+    core.run(fut).unwrap();
+    client.join().unwrap()
+}
+
+#[test]
+fn write_coalesce_batches_tiny_chunks_within_delay() {
+    let mut http = Http::new();
+    http.write_coalesce(Duration::from_millis(50), 64 * 1024);
+    let (resp, reads, longest_wait) = serve_dribbled_chunks(http, 100, Duration::from_millis(2));
+
+    assert_eq!(resp.matches("1\r\nx\r\n").count(), 100, "{:?}", resp);
+    assert!(resp.ends_with("0\r\n\r\n"), "{:?}", resp);
+    // about one write every 50ms, instead of one per chunk
+    assert!(reads <= 20, "{} reads", reads);
+    assert!(longest_wait < Duration::from_millis(200), "{:?} between reads", longest_wait);
+}
+
+#[cfg(feature = "sse")]
+#[test]
+fn sse_event_stream_flushes_each_event() {
+    use futures::sync::mpsc as futures_mpsc;
+    use hyper::server::sse::{EventStream, SseEvent};
+    let _ = pretty_env_logger::try_init();
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &handle).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (events_tx, events_rx) = mpsc::channel();
+
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET /events HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            \r\n\
+        ").expect("write");
+        let events: futures_mpsc::UnboundedSender<SseEvent> = events_rx.recv().unwrap();
+        let mut read_until = |end: &str| {
+            let mut resp = String::new();
+            let mut buf = [0; 4096];
+            while !resp.ends_with(end) {
+                let n = tcp.read(&mut buf).expect("read");
+                assert!(n > 0, "closed after {:?}", resp);
+                resp.push_str(::std::str::from_utf8(&buf[..n]).unwrap());
+            }
+            resp
+        };
+
+        events.unbounded_send(SseEvent::data("first")).unwrap();
+        let head = read_until("\r\n\r\nD\r\ndata: first\n\n\r\n");
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", head);
+        assert!(head.contains("Content-Type: text/event-stream\r\n"), "{:?}", head);
+        assert!(head.contains("Cache-Control: no-cache\r\n"), "{:?}", head);
+        assert!(head.contains("Transfer-Encoding: chunked\r\n"), "{:?}", head);
+
+        events.unbounded_send(SseEvent::data("two\nlines").with_event("update").with_id("2")).unwrap();
+        assert_eq!(read_until("\r\n"), "2B\r\nevent: update\nid: 2\ndata: two\ndata: lines\n\n\r\n");
+        drop(events);
+        assert_eq!(read_until("0\r\n\r\n"), "0\r\n\r\n");
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(move |(item, _incoming)| {
+            let (socket, _) = item.unwrap();
+            let service = service_fn_ok(move |_req: Request| {
+                let (tx, rx) = futures_mpsc::unbounded();
+                events_tx.send(tx).unwrap();
+                EventStream::new(rx.map_err(|()| unreachable!())).into_response()
+            });
+            // without the flush of each event, coalescing would hold them
+            let mut http = Http::<hyper::Chunk>::new();
+            http.write_coalesce(Duration::from_secs(10), 64 * 1024);
+            http.serve_connection_handle(socket, &handle, service)
+        });
+
+    core.run(fut.select2(Timeout::new(Duration::from_secs(5), &core.handle()).unwrap()))
+        .map_err(|_| "server errored")
+        .unwrap();
+    client.join().unwrap();
+}
+
+#[test]
+fn write_coalesce_flushes_at_max_bytes() {
+    let mut http = Http::new();
+    http.write_coalesce(Duration::from_secs(10), 64);
+    let start = Instant::now();
+    let (resp, reads, _) = serve_dribbled_chunks(http, 100, Duration::from_millis(1));
+
+    assert_eq!(resp.matches("1\r\nx\r\n").count(), 100, "{:?}", resp);
+    // never waiting on the delay
+    assert!(start.elapsed() < Duration::from_secs(5), "took {:?}", start.elapsed());
+    assert!(reads > 1, "{} reads", reads);
+}
+
+// Serves `reqs` over a single connection, with a service sleeping a second
+// for `/slow`, returning the raw responses and whether the future of the
+// slow service was dropped without completing.
+fn serve_one_connection_with_slow_service(mut http: Http<hyper::Chunk>, reqs: &'static [u8]) -> (String, bool) {
+    struct DropFlag(Arc<AtomicBool>);
+    impl Drop for DropFlag {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    let _ = pretty_env_logger::try_init();
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &handle).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(reqs).expect("write");
+        let mut resp = String::new();
+        tcp.read_to_string(&mut resp).expect("read");
+        resp
+    });
+
+    http.request_timeout(Duration::from_millis(100));
+    let dropped = Arc::new(AtomicBool::new(false));
+    let completed = Arc::new(AtomicBool::new(false));
+    let (dropped2, completed2) = (dropped.clone(), completed.clone());
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(move |(item, _incoming)| {
+            let (socket, _) = item.unwrap();
+            let timer_handle = handle.clone();
+            let service = service_fn(move |req: Request| -> Box<Future<Item=Response, Error=hyper::Error>> {
+                if req.path() != "/slow" {
+                    return Box::new(future::ok(Response::new()));
+                }
+                let guard = DropFlag(dropped2.clone());
+                let completed = completed2.clone();
+                let sleep = Timeout::new(Duration::from_secs(1), &timer_handle).unwrap();
+                Box::new(sleep.map_err(hyper::Error::from).map(move |()| {
+                    drop(guard);
+                    completed.store(true, Ordering::SeqCst);
+                    Response::new()
+                }))
+            });
+            http.serve_connection_handle(socket, &handle, service)
+        });
+
+    core.run(fut).unwrap();
+    let canceled = dropped.load(Ordering::SeqCst) && !completed.load(Ordering::SeqCst);
+    (client.join().unwrap(), canceled)
+}
+
+#[test]
+fn request_timeout_responds_504_and_keeps_connection_alive() {
+    let (resp, canceled) = serve_one_connection_with_slow_service(Http::new(), b"\
+        GET /slow HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        \r\n\
+        GET /fast HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ");
+    assert!(canceled, "slow service future wasn't dropped");
+    assert!(resp.starts_with("HTTP/1.1 504 Gateway Timeout\r\n"), "{:?}", resp);
+    assert!(has_header(&resp, "Content-Length: 0"), "{:?}", resp);
+    assert_eq!(resp.matches("HTTP/1.1 200 OK\r\n").count(), 1, "{:?}", resp);
+}
+
+#[test]
+fn request_timeout_status_is_configurable() {
+    let mut http = Http::new();
+    http.request_timeout_status(StatusCode::ServiceUnavailable);
+    let (resp, canceled) = serve_one_connection_with_slow_service(http, b"\
+        GET /slow HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ");
+    assert!(canceled, "slow service future wasn't dropped");
+    assert!(resp.starts_with("HTTP/1.1 503 Service Unavailable\r\n"), "{:?}", resp);
+}
+
+#[test]
+fn initial_read_buf_size_grows_for_larger_heads() {
+    let mut http = Http::new();
+    http.initial_read_buf_size(16);
+    let resp = serve_one_connection(http, b"\
+        GET /a/path/longer/than/the/initial/buffer HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ");
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", resp);
+}
+
+// Serves the requests written in `reqs` over a single connection with
+// `HelloWorld`, returning the raw responses.
+fn serve_one_connection(http: Http<hyper::Chunk>, reqs: &'static [u8]) -> String {
+    serve_one_connection_with(http, reqs, HelloWorld)
+}
+
+fn serve_one_connection_with<S>(http: Http<hyper::Chunk>, reqs: &'static [u8], service: S) -> String
+where
+    S: Service<Request=Request, Response=Response, Error=hyper::Error> + 'static,
+{
+    let _ = pretty_env_logger::try_init();
+    let mut core = Core::new().unwrap();
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(reqs).expect("write");
+        let mut resp = String::new();
+        tcp.read_to_string(&mut resp).expect("read");
+        resp
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(move |(item, _incoming)| {
+            let (socket, _) = item.unwrap();
+            http.serve_connection(socket, service)
+                .map(|_| ())
+        });
+
+    core.run(fut).unwrap();
+    client.join().unwrap()
+}
+
+// Serves the bytes written in `reqs` over a single connection expected to
+// fail, returning what the client read until the connection closed.
+fn serve_one_connection_rejected(http: Http<hyper::Chunk>, reqs: &'static [u8]) -> String {
+    let _ = pretty_env_logger::try_init();
+    let mut core = Core::new().unwrap();
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(reqs).expect("write");
+        let mut resp = String::new();
+        tcp.read_to_string(&mut resp).expect("read");
+        resp
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(move |(item, _incoming)| {
+            let (socket, _) = item.unwrap();
+            http.serve_connection(socket, HelloWorld)
+        });
+
+    core.run(fut).map(|_| ()).expect_err("connection error");
+    client.join().unwrap()
+}
+
+#[test]
+fn reject_content_length_with_transfer_encoding() {
+    let resp = serve_one_connection_rejected(Http::new(), b"\
+        POST / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Content-Length: 4\r\n\
+        Transfer-Encoding: chunked\r\n\
+        \r\n\
+        0\r\n\
+        \r\n\
+        GET /smuggled HTTP/1.1\r\n\
+        \r\n\
+    ");
+    assert!(resp.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{:?}", resp);
+    assert_eq!(resp.matches("HTTP/1.1").count(), 1, "{:?}", resp);
+}
+
+#[test]
+fn reject_differing_content_lengths() {
+    let resp = serve_one_connection_rejected(Http::new(), b"\
+        POST / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Content-Length: 0\r\n\
+        Content-Length: 25\r\n\
+        \r\n\
+        GET /smuggled HTTP/1.1\r\n\
+        \r\n\
+    ");
+    assert!(resp.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{:?}", resp);
+    assert_eq!(resp.matches("HTTP/1.1").count(), 1, "{:?}", resp);
+}
+
+#[test]
+fn accept_repeated_equal_content_lengths() {
+    let resp = serve_one_connection(Http::new(), b"\
+        POST / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Content-Length: 4\r\n\
+        Content-Length: 4\r\n\
+        Connection: close\r\n\
+        \r\n\
+        body\
+    ");
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", resp);
+}
+
+#[test]
+fn reject_conflicting_framing_disabled_uses_transfer_encoding() {
+    let mut http = Http::new();
+    http.reject_conflicting_framing(false);
+    let resp = serve_one_connection(http, b"\
+        POST / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Content-Length: 100\r\n\
+        Transfer-Encoding: chunked\r\n\
+        Connection: close\r\n\
+        \r\n\
+        4\r\n\
+        body\r\n\
+        0\r\n\
+        \r\n\
+    ");
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", resp);
+}
+
+#[test]
+fn require_host_header_rejects_missing_host() {
+    let mut http = Http::new();
+    http.require_host_header(true);
+    let resp = serve_one_connection_rejected(http, b"\
+        GET / HTTP/1.1\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ");
+    assert!(resp.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{:?}", resp);
+}
+
+#[test]
+fn require_host_header_rejects_several_hosts() {
+    let mut http = Http::new();
+    http.require_host_header(true);
+    let resp = serve_one_connection_rejected(http, b"\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Host: smuggled.domain\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ");
+    assert!(resp.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{:?}", resp);
+}
+
+#[test]
+fn require_host_header_accepts_single_host_and_http10() {
+    let mut http = Http::new();
+    http.require_host_header(true);
+    let resp = serve_one_connection(http.clone(), b"\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ");
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", resp);
+
+    let resp = serve_one_connection(http, b"\
+        GET / HTTP/1.0\r\n\
+        \r\n\
+    ");
+    assert!(resp.starts_with("HTTP/1.0 200 OK\r\n"), "{:?}", resp);
+}
+
+// Serves the bytes written in `reqs` over a single connection, returning
+// the error the connection failed with. With `reset`, the client then resets
+// the connection instead of waiting for a response.
+fn serve_one_connection_error<S>(reqs: &'static [u8], reset: bool, service: S) -> hyper::Error
+where
+    S: Service<Request=Request, Response=Response, Error=hyper::Error> + 'static,
+{
+    let _ = pretty_env_logger::try_init();
+    let mut core = Core::new().unwrap();
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let client = thread::spawn(move || {
+        use net2::TcpStreamExt;
+
+        let mut tcp = connect(&addr);
+        tcp.write_all(reqs).expect("write");
+        if reset {
+            thread::sleep(Duration::from_millis(50));
+            tcp.set_linger(Some(Duration::from_secs(0))).expect("set_linger");
+        } else {
+            let _ = tcp.read_to_end(&mut Vec::new());
+        }
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(move |(item, _incoming)| {
+            let (socket, _) = item.unwrap();
+            Http::<hyper::Chunk>::new().serve_connection(socket, service)
+        });
+
+    let err = core.run(fut).map(|_| ()).expect_err("connection error");
+    client.join().unwrap();
+    err
+}
+
+#[test]
+fn connection_error_kind_parse() {
+    let err = serve_one_connection_error(b"GE,T / HTTP/1.1\r\n\r\n", false, HelloWorld);
+    assert_eq!(err.kind(), hyper::ErrorKind::Parse, "{:?}", err);
+}
+
+#[test]
+fn connection_error_kind_io() {
+    let err = serve_one_connection_error(b"GET / HTTP/1.1\r\nHost: ", true, HelloWorld);
+    assert_eq!(err.kind(), hyper::ErrorKind::Io, "{:?}", err);
+}
+
+#[test]
+fn internal_error_response_replaces_failed_response() {
+    let service = service_fn(|req: Request| {
+        if req.path() == "/fail" {
+            future::err(hyper::Error::Io(io::Error::new(io::ErrorKind::Other, "service failed")))
+        } else {
+            future::ok(Response::new())
+        }
+    });
+    let mut http = Http::new();
+    http.internal_error_response(|err| {
+        assert_eq!(err.kind(), hyper::ErrorKind::User, "{:?}", err);
+        Response::new()
+            .with_status(StatusCode::InternalServerError)
+            .with_body(format!("{{\"error\":\"{}\"}}", err))
+    });
+    let resp = serve_one_connection_with(http, b"\
+        GET /fail HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        \r\n\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ", service);
+    let body = "{\"error\":\"service failed\"}";
+    assert!(resp.starts_with("HTTP/1.1 500 Internal Server Error\r\n"), "{:?}", resp);
+    assert!(has_header(&resp, &format!("Content-Length: {}", body.len())), "{:?}", resp);
+    assert!(resp.contains(&format!("\r\n\r\n{}HTTP/1.1 200 OK\r\n", body)), "{:?}", resp);
+}
+
+#[test]
+fn connection_error_kind_user() {
+    let service = service_fn(|_req: Request| {
+        future::err::<Response, _>(hyper::Error::Io(io::Error::new(io::ErrorKind::Other, "service failed")))
+    });
+    let err = serve_one_connection_error(b"GET / HTTP/1.1\r\n\r\n", false, service);
+    assert_eq!(err.kind(), hyper::ErrorKind::User, "{:?}", err);
+    assert_eq!(err.to_string(), "service failed");
+}
+
+// Serves the requests written in `reqs` over a single connection with
+// `HelloWorld`, returning why the connection finished.
+fn serve_one_connection_disconnect(http: Http<hyper::Chunk>, reqs: &'static [u8]) -> Disconnect {
+    let _ = pretty_env_logger::try_init();
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &handle).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(reqs).expect("write");
+        let _ = tcp.read_to_end(&mut Vec::new());
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(move |(item, _incoming)| {
+            let (socket, _) = item.unwrap();
+            http.serve_connection_handle(socket, &handle, HelloWorld)
+                .with_disconnect()
+        });
+
+    let disconnect = core.run(fut).unwrap();
+    client.join().unwrap();
+    disconnect
+}
+
+#[test]
+fn with_disconnect_reports_close() {
+    let disconnect = serve_one_connection_disconnect(Http::new(), b"\
+        GET / HTTP/1.1\r\n\
+        \r\n\
+        GET / HTTP/1.1\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ");
+    match disconnect.reason {
+        DisconnectReason::Closed => (),
+        other => panic!("unexpected reason: {:?}", other),
+    }
+    assert_eq!(disconnect.requests, 2);
+}
+
+#[test]
+fn with_disconnect_reports_keep_alive_timeout() {
+    let mut http = Http::new();
+    http.keep_alive_timeout(Duration::from_millis(100));
+    let disconnect = serve_one_connection_disconnect(http, b"\
+        GET / HTTP/1.1\r\n\
+        \r\n\
+    ");
+    match disconnect.reason {
+        DisconnectReason::KeepAliveTimeout => (),
+        other => panic!("unexpected reason: {:?}", other),
+    }
+    assert_eq!(disconnect.requests, 1);
+}
+
+#[test]
+fn max_connection_age_closes_busy_keep_alive_connection() {
+    let _ = pretty_env_logger::try_init();
+    let (addr_tx, addr_rx) = mpsc::channel();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let thread = thread::spawn(move || {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let srv = Http::new()
+            .max_connection_age(Duration::from_millis(300))
+            .bind(&addr, || Ok(service_fn(|req: Request| {
+                // `/slow` is still in flight when the age elapses
+                let delay = if req.path() == "/slow" { 500 } else { 0 };
+                let (tx, rx) = oneshot::channel();
+                thread::spawn(move || {
+                    thread::sleep(Duration::from_millis(delay));
+                    let _ = tx.send(Response::<hyper::Body>::new()
+                        .with_header(ContentLength(2))
+                        .with_body("ok"));
+                });
+                rx.map_err(|_| hyper::Error::Incomplete)
+            })))
+            .unwrap();
+        addr_tx.send(srv.local_addr().unwrap()).unwrap();
+        srv.run_until(shutdown_rx.then(|_| Ok(()))).unwrap();
+    });
+    let addr = addr_rx.recv().unwrap();
+
+    let mut tcp = connect(&addr);
+    let mut get = |path: &str| {
+        write!(tcp, "GET {} HTTP/1.1\r\nHost: example.domain\r\n\r\n", path).unwrap();
+        let mut res = Vec::new();
+        let mut buf = [0; 1024];
+        while !res.ends_with(b"\r\n\r\nok") {
+            let n = tcp.read(&mut buf).expect("read");
+            assert!(n > 0, "closed before a complete response: {:?}", String::from_utf8_lossy(&res));
+            res.extend_from_slice(&buf[..n]);
+        }
+        String::from_utf8(res).unwrap()
+    };
+    for _ in 0..2 {
+        let res = get("/");
+        assert!(!res.contains("Connection: close"), "{:?}", res);
+    }
+    let res = get("/slow");
+    assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", res);
+    assert!(res.contains("Connection: close\r\n"), "{:?}", res);
+
+    // closed after the exchange that announced it, without serving more
+    let _ = tcp.write_all(b"GET / HTTP/1.1\r\nHost: example.domain\r\n\r\n");
+    let mut rest = Vec::new();
+    let _ = tcp.read_to_end(&mut rest);
+    assert!(rest.is_empty(), "{:?}", String::from_utf8_lossy(&rest));
+
+    drop(shutdown_tx);
+    thread.join().unwrap();
+}
+
+#[test]
+fn with_disconnect_reports_error() {
+    let disconnect = serve_one_connection_disconnect(Http::new(), b"\
+        GET / HTTP/1.1\r\n\
+        \r\n\
+        NOT HTTP\r\n\
+        \r\n\
+    ");
+    match disconnect.reason {
+        DisconnectReason::Error(_) => (),
+        other => panic!("unexpected reason: {:?}", other),
+    }
+    assert_eq!(disconnect.requests, 1);
+}
+
+#[test]
+fn cancel_token_fires_when_client_disconnects() {
+    let _ = pretty_env_logger::try_init();
+    let mut core = Core::new().unwrap();
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            \r\n\
+        ").expect("write");
+        tcp.shutdown(Shutdown::Write).expect("shutdown write");
+        let _ = tcp.read(&mut [0; 256]);
+    });
+
+    let (tx, rx) = mpsc::channel();
+    let service = service_fn(move |req: Request| {
+        let token = req.cancel_token().expect("cancel_token").clone();
+        assert!(!token.is_cancelled());
+        let tx = tx.clone();
+        // never responds until the client goes away
+        token.cancelled().then(move |_| {
+            tx.send(()).unwrap();
+            Ok::<_, hyper::Error>(Response::<hyper::Body>::new())
+        })
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(move |(item, _incoming)| {
+            let (socket, _) = item.unwrap();
+            Http::<hyper::Chunk>::new().serve_connection(socket, service)
+                .map(|_| ())
+        });
+
+    core.run(fut).unwrap();
+    rx.try_recv().expect("cancel token fired");
+}
+
+#[test]
+fn cancel_token_fires_when_client_disconnects_during_streaming_body() {
+    use futures::Sink;
+    let _ = pretty_env_logger::try_init();
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &handle).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET /events HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            \r\n\
+        ").expect("write");
+        let mut buf = Vec::new();
+        while !String::from_utf8_lossy(&buf).contains("data: 1") {
+            let mut chunk = [0; 256];
+            let n = tcp.read(&mut chunk).expect("read");
+            assert!(n > 0, "closed before first event");
+            buf.extend_from_slice(&chunk[..n]);
+        }
+        tcp.shutdown(Shutdown::Both).expect("shutdown");
+    });
+
+    let (tx, rx) = mpsc::channel();
+    let service = service_fn(move |req: Request| {
+        let token = req.cancel_token().expect("cancel_token").clone();
+        let (body_tx, body) = hyper::Body::pair();
+        let tx = tx.clone();
+        // the body never ends, until the client goes away
+        handle.spawn(body_tx.send(Ok("data: 1\n\n".into()))
+            .map_err(|_| ())
+            .and_then(move |body_tx| token.cancelled().map(move |()| {
+                tx.send(()).unwrap();
+                drop(body_tx);
+            })));
+        Ok::<_, hyper::Error>(Response::<hyper::Body>::new().with_body(body))
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(move |(item, _incoming)| {
+            let (socket, _) = item.unwrap();
+            Http::<hyper::Chunk>::new().serve_connection(socket, service)
+                .then(|_| Ok::<(), ()>(()))
+        });
+
+    core.run(fut).unwrap();
+    rx.try_recv().expect("cancel token fired");
+}
+
+#[test]
+fn service_fn_ok_sync_handler() {
+    let service = service_fn_ok(|req: Request| {
+        Response::new().with_body(format!("sync {}", req.path()))
+    });
+    let resp = serve_one_connection_with(Http::new(), b"\
+        GET /hello HTTP/1.1\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ", service);
+
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", resp);
+    assert!(resp.contains("\r\nsync /hello\r\n"), "{:?}", resp);
+}
+
+#[test]
+fn informational_responses_written_before_final_response() {
+    let service = service_fn_ok(|req: Request| {
+        let informational = req.informational().expect("informational sender");
+        let mut hints = Response::new().with_status(StatusCode::Unregistered(103));
+        hints.headers_mut().set_raw("Link", "</style.css>; rel=preload; as=style");
+        informational.send(hints).expect("send 103");
+        match informational.send(Response::new()) {
+            Err(hyper::Error::Status) => (),
+            other => panic!("200 sent as informational: {:?}", other),
+        }
+        Response::new().with_body("final")
+    });
+    let mut http = Http::new();
+    http.informational_responses(true);
+    let resp = serve_one_connection_with(http, b"\
+        GET / HTTP/1.1\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ", service);
+
+    let expected_hints = "HTTP/1.1 103 Early Hints\r\nLink: </style.css>; rel=preload; as=style\r\n\r\n";
+    assert!(resp.starts_with(expected_hints), "{:?}", resp);
+    assert!(resp[expected_hints.len()..].starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", resp);
+    assert!(resp.contains("\r\nfinal\r\n"), "{:?}", resp);
+}
+
+#[test]
+fn informational_responses_sent_while_final_response_pending() {
+    let service = service_fn(|req: Request| {
+        let informational = req.informational().expect("informational sender").clone();
+        let (done_tx, done_rx) = oneshot::channel();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            let hints = Response::new().with_status(StatusCode::Unregistered(103));
+            informational.send(hints).expect("send 103");
+            thread::sleep(Duration::from_millis(20));
+            done_tx.send(()).unwrap();
+        });
+        done_rx
+            .map(|()| Response::new().with_body("final"))
+            .map_err(|_| unreachable!())
+    });
+    let mut http = Http::new();
+    http.informational_responses(true);
+    let resp = serve_one_connection_with(http, b"\
+        GET / HTTP/1.1\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ", service);
+
+    assert!(resp.starts_with("HTTP/1.1 103 Early Hints\r\n\r\nHTTP/1.1 200 OK\r\n"), "{:?}", resp);
+}
+
+#[test]
+fn informational_responses_disabled_by_default() {
+    let service = service_fn_ok(|req: Request| {
+        assert!(req.informational().is_none());
+        Response::new().with_body("final")
+    });
+    let resp = serve_one_connection_with(Http::new(), b"\
+        GET / HTTP/1.1\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ", service);
+
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", resp);
+}
+
+#[test]
+fn informational_responses_not_sent_to_http10() {
+    let service = service_fn_ok(|req: Request| {
+        let hints = Response::new().with_status(StatusCode::Unregistered(103));
+        req.informational().expect("informational sender").send(hints).expect("send 103");
+        Response::new().with_body("final")
+    });
+    let mut http = Http::new();
+    http.informational_responses(true);
+    let resp = serve_one_connection_with(http, b"\
+        GET / HTTP/1.0\r\n\
+        \r\n\
+    ", service);
+
+    assert!(resp.starts_with("HTTP/1.0 200 OK\r\n"), "{:?}", resp);
+    assert!(!resp.contains("103"), "{:?}", resp);
+}
+
+#[test]
+fn service_fn_async_handler() {
+    let service = service_fn(|req: Request| {
+        req.body()
+            .concat2()
+            .map(|body| Response::new().with_body(body))
+    });
+    let resp = serve_one_connection_with(Http::new(), b"\
+        POST / HTTP/1.1\r\n\
+        Content-Length: 5\r\n\
+        Connection: close\r\n\
+        \r\n\
+        async\
+    ", service);
+
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", resp);
+    assert!(resp.contains("\r\nasync\r\n"), "{:?}", resp);
+}
+
+#[test]
+fn max_headers() {
+    let _ = pretty_env_logger::try_init();
+    let mut core = Core::new().unwrap();
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        let mut head = b"GET / HTTP/1.1\r\nHost: example.domain\r\n".to_vec();
+        for i in 0..200 {
+            head.extend_from_slice(format!("X-Header-{}: value\r\n", i).as_bytes());
+        }
+        head.extend_from_slice(b"\r\n");
+        tcp.write_all(&head).expect("write");
+        let mut resp = String::new();
+        tcp.read_to_string(&mut resp).expect("read");
+
+        let expected = "HTTP/1.1 431 Request Header Fields Too Large\r\n";
+        assert!(resp.starts_with(expected), "{:?}", resp);
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let (socket, _) = item.unwrap();
+            Http::<hyper::Chunk>::new()
+                .max_headers(100)
+                .serve_connection(socket, HelloWorld)
+                .map(|_| ())
+        });
+
+    core.run(fut).unwrap_err();
+}
+
+#[test]
+fn max_header_list_size() {
+    let _ = pretty_env_logger::try_init();
+    let mut core = Core::new().unwrap();
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"GET / HTTP/1.1\r\nX-Big: ").expect("write 1");
+        tcp.write_all(&vec![b'a'; 4096]).expect("write 2");
+        tcp.write_all(b"\r\n\r\n").expect("write 3");
+        let mut resp = String::new();
+        tcp.read_to_string(&mut resp).expect("read");
+
+        let expected = "HTTP/1.1 431 Request Header Fields Too Large\r\n";
+        assert!(resp.starts_with(expected), "{:?}", resp);
+    });
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let (socket, _) = item.unwrap();
+            Http::<hyper::Chunk>::new()
+                .max_header_list_size(1024)
+                .serve_connection(socket, HelloWorld)
+                .map(|_| ())
+        });
+
+    core.run(fut).unwrap_err();
+}
+
+#[test]
+fn streaming_body() {
+    let _ = pretty_env_logger::try_init();
+    let mut core = Core::new().unwrap();
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (tx, rx) = oneshot::channel();
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut buf = [0; 8192];
+        let mut sum = tcp.read(&mut buf).expect("read 1");
+
+        let expected = "HTTP/1.1 200 ";
+        assert_eq!(s(&buf[..expected.len()]), expected);
+
+        loop {
+            let n = tcp.read(&mut buf).expect("read loop");
+            sum += n;
+            if n == 0 {
+                break;
+            }
+        }
+        assert_eq!(sum, 100_789);
+        let _ = tx.send(());
+    });
+
+    let rx = rx.map_err(|_| panic!("thread panicked"));
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(|(item, _incoming)| {
+            let (socket, _) = item.unwrap();
+            Http::<& &'static [u8]>::new()
+                .keep_alive(false)
+                .serve_connection(socket, service_fn(|_| {
+                    static S: &'static [&'static [u8]] = &[&[b'x'; 1_000] as &[u8]; 1_00] as _;
+                    let b = ::futures::stream::iter_ok(S.iter());
+                    Ok(Response::<futures::stream::IterOk<::std::slice::Iter<&'static [u8]>, ::hyper::Error>>::new()
+                        .with_body(b))
+                }))
+                .map(|_| ())
+        });
+
+    core.run(fut.join(rx)).unwrap();
+}
+
+#[test]
+fn body_write_buffer_limit_stops_polling_body_for_slow_reader() {
+    let _ = pretty_env_logger::try_init();
+    let mut core = Core::new().unwrap();
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (tx, rx) = oneshot::channel();
+    thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"GET / HTTP/1.1\r\nHost: example.domain\r\n\r\n").unwrap();
+        // never read the response
+        thread::sleep(Duration::from_millis(500));
+        let _ = tx.send(tcp);
+    });
+
+    const CHUNK: usize = 16 * 1024;
+    const CHUNKS: usize = 4096;
+    let polled = Arc::new(AtomicUsize::new(0));
+    let polled2 = polled.clone();
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| unreachable!())
+        .and_then(move |(item, _incoming)| {
+            let (socket, _) = item.unwrap();
+            Http::<hyper::Chunk>::new()
+                .body_write_buffer_limit(64 * 1024)
+                .serve_connection(socket, service_fn(move |_| {
+                    let polled = polled2.clone();
+                    let body = futures::stream::repeat(())
+                        .take(CHUNKS as u64)
+                        .map(move |()| {
+                            polled.fetch_add(1, Ordering::SeqCst);
+                            hyper::Chunk::from(vec![b'x'; CHUNK])
+                        });
+                    let body: Box<Stream<Item=hyper::Chunk, Error=hyper::Error>> = Box::new(body);
+                    Ok(Response::<Box<Stream<Item=_, Error=_>>>::new().with_body(body))
+                }))
+                .map(|_| ())
+        });
+
+    let rx = rx.map_err(|_| panic!("thread panicked"));
+    let _tcp = match core.run(fut.select2(rx)) {
+        Ok(Either::B((tcp, _))) => tcp,
+        _ => panic!("connection should be waiting on the client"),
+    };
+
+    // only the limit and what the sockets hold were pulled from the body
+    let polled = polled.load(Ordering::SeqCst);
+    assert!(polled > 0);
+    assert!(polled * CHUNK < 16 * 1024 * 1024, "polled {} chunks", polled);
+}
+
+#[test]
+fn remote_addr() {
+    let server = serve();
+
+    let mut req = connect(server.addr());
+    req.write_all(b"\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        \r\n\
+    ").unwrap();
+    req.read(&mut [0; 256]).unwrap();
+
+    let client_addr = req.local_addr().unwrap();
+    assert_eq!(server.remote_addr(), client_addr);
+}
+
+#[test]
+fn local_addr() {
+    let server = serve();
+
+    let mut req = connect(server.addr());
+    req.write_all(b"\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        \r\n\
+    ").unwrap();
+    req.read(&mut [0; 256]).unwrap();
+
+    let server_addr = req.peer_addr().unwrap();
+    assert_eq!(server.local_addr(), server_addr);
+}
+
+#[test]
+fn header_read_timeout_closes_slow_head() {
+    let server = serve_with_options(ServeOptions {
+        header_read_timeout: Some(Duration::from_millis(100)),
+        .. ServeOptions::default()
+    });
+
+    let mut req = connect(server.addr());
+    req.write_all(b"\
+        GET / HTTP/1.1\r\n\
+        Host: exam\
+    ").unwrap();
+
+    // the connection is closed long before the client's 1s read timeout
+    let n = req.read(&mut [0; 256]).expect("read");
+    assert_eq!(n, 0);
+}
+
+#[test]
+fn header_read_timeout_ignores_idle_keep_alive() {
+    let server = serve_with_options(ServeOptions {
+        header_read_timeout: Some(Duration::from_millis(100)),
+        .. ServeOptions::default()
+    });
+
+    let mut req = connect(server.addr());
+    for _ in 0..2 {
+        req.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            \r\n\
+        ").unwrap();
+        let n = req.read(&mut [0; 256]).expect("read");
+        assert!(n > 0);
+        thread::sleep(Duration::from_millis(200));
+    }
+}
+
+#[test]
+fn send_408_on_timeout_answers_partial_head() {
+    let server = serve_with_options(ServeOptions {
+        header_read_timeout: Some(Duration::from_millis(100)),
+        send_408_on_timeout: true,
+        .. ServeOptions::default()
+    });
+
+    let mut req = connect(server.addr());
+    req.write_all(b"\
+        GET / HTTP/1.1\r\n\
+        Host: exam\
+    ").unwrap();
+
+    let mut resp = String::new();
+    req.read_to_string(&mut resp).expect("read");
+    // followed by the connection closing
+    assert!(resp.starts_with("HTTP/1.1 408 Request Timeout\r\n"), "{:?}", resp);
+}
+
+#[test]
+fn send_408_on_timeout_closes_without_request_line() {
+    let server = serve_with_options(ServeOptions {
+        header_read_timeout: Some(Duration::from_millis(100)),
+        send_408_on_timeout: true,
+        .. ServeOptions::default()
+    });
+
+    let mut req = connect(server.addr());
+    req.write_all(b"GET / HT").unwrap();
+
+    let n = req.read(&mut [0; 256]).expect("read");
+    assert_eq!(n, 0);
+}
+
+#[test]
+fn min_head_read_rate_closes_dribbling_client() {
+    let server = serve_with_options(ServeOptions {
+        min_head_read_rate: Some((16, Duration::from_millis(100))),
+        .. ServeOptions::default()
+    });
+
+    let mut req = connect(server.addr());
+    req.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+    // a few bytes every window, well within a plain header timeout
+    let mut closed = false;
+    for &b in b"Host: example.domain\r\n\r\n" {
+        thread::sleep(Duration::from_millis(40));
+        if req.write_all(&[b]).is_err() {
+            closed = true;
+            break;
+        }
+    }
+    if !closed {
+        let n = req.read(&mut [0; 256]).unwrap_or(0);
+        assert_eq!(n, 0, "dribbled request was served");
+    }
+}
+
+#[test]
+fn min_head_read_rate_allows_burst_then_pause() {
+    let server = serve_with_options(ServeOptions {
+        min_head_read_rate: Some((16, Duration::from_millis(100))),
+        .. ServeOptions::default()
+    });
+    server.reply().status(hyper::Ok);
+
+    let mut req = connect(server.addr());
+    req.write_all(b"GET / HTTP/1.1\r\nHost: exam").unwrap();
+    // the burst covers the first window, and the rest comes in the second
+    thread::sleep(Duration::from_millis(150));
+    req.write_all(b"ple.domain\r\n\r\n").unwrap();
+
+    let mut buf = [0; 256];
+    let n = req.read(&mut buf).expect("read");
+    assert!(s(&buf[..n]).starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", s(&buf[..n]));
+}
+
+#[test]
+fn keep_alive_timeout_closes_idle_connection() {
+    let server = serve_with_options(ServeOptions {
+        keep_alive_timeout: Some(Duration::from_millis(100)),
+        .. ServeOptions::default()
+    });
+
+    let mut req = connect(server.addr());
+    req.write_all(b"\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        \r\n\
+    ").unwrap();
+    let n = req.read(&mut [0; 256]).expect("read response");
+    assert!(n > 0);
+
+    // no second request is sent, so the idle connection is closed
+    let n = req.read(&mut [0; 256]).expect("read eof");
+    assert_eq!(n, 0);
+}
+
+#[test]
+fn listen_backlog_must_be_positive() {
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let new_service = || Ok(HelloWorld);
+
+    match Http::new().listen_backlog(0).bind(&addr, new_service) {
+        Err(hyper::Error::Io(ref e)) if e.kind() == io::ErrorKind::InvalidInput => (),
+        other => panic!("expected InvalidInput, found: {:?}", other.map(|_| ())),
+    }
+    assert!(Http::new().listen_backlog(-1).bind(&addr, new_service).is_err());
+
+    let srv = Http::new().listen_backlog(4096).bind(&addr, new_service).unwrap();
+    assert!(srv.local_addr().is_ok());
+}
+
+#[test]
+fn graceful_shutdown_closes_idle_keep_alive_connections() {
+    let mut server = serve();
+    let mut req = connect(server.addr());
+    req.write_all(b"\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        \r\n\
+    ").unwrap();
+    let mut buf = [0; 256];
+    let n = req.read(&mut buf).expect("read response");
+    assert!(!has_header(s(&buf[..n]), "Connection:"));
+
+    server.shutdown();
+    thread::sleep(Duration::from_millis(100));
+
+    // the connection was idle, so the next request is not read
+    let _ = req.write_all(b"\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        \r\n\
+    ");
+    let n = req.read(&mut buf).unwrap_or(0);
+    assert_eq!(n, 0, "unexpected response: {:?}", s(&buf[..n]));
+}
+
+#[test]
+fn graceful_shutdown_finishes_in_flight_request_with_connection_close() {
+    let mut server = serve();
+    let mut req = connect(server.addr());
+    req.write_all(b"\
+        POST / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Content-Length: 10\r\n\
+        \r\n\
+        hello\
+    ").unwrap();
+    thread::sleep(Duration::from_millis(50));
+
+    server.shutdown();
+    thread::sleep(Duration::from_millis(50));
+
+    req.write_all(b"world").unwrap();
+    let mut body = String::new();
+    req.read_to_string(&mut body).expect("read response");
+    assert!(body.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(has_header(&body, "Connection: close\r\n"), "{:?}", body);
+}
+
+#[test]
+fn connection_graceful_shutdown_completes_in_flight_response() {
+    let _ = pretty_env_logger::try_init();
+    let mut core = Core::new().unwrap();
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let client = thread::spawn(move || {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            \r\n\
+        ").unwrap();
+        let mut resp = String::new();
+        tcp.read_to_string(&mut resp).expect("read response");
+        resp
+    });
+
+    let (started_tx, started_rx) = oneshot::channel::<()>();
+    let (release_tx, release_rx) = oneshot::channel::<()>();
+    let started_tx = Mutex::new(Some(started_tx));
+    let release_rx = Mutex::new(Some(release_rx));
+
+    let fut = listener.incoming()
+        .into_future()
+        .map_err(|_| -> hyper::Error { unreachable!() })
+        .and_then(move |(item, _incoming)| {
+            let (socket, _) = item.unwrap();
+            let mut conn = Http::<hyper::Chunk>::new()
+                .serve_connection(socket, service_fn(move |_req| {
+                    let _ = started_tx.lock().unwrap().take().unwrap().send(());
+                    let release = release_rx.lock().unwrap().take().unwrap();
+                    release
+                        .map_err(|_| -> hyper::Error { unreachable!() })
+                        .map(|()| Response::<hyper::Body>::new()
+                            .with_header(ContentLength(HELLO.len() as u64))
+                            .with_body(HELLO))
+                }));
+
+            let mut started_rx = Some(started_rx);
+            let mut release_tx = Some(release_tx);
+            future::poll_fn(move || {
+                let started = match started_rx {
+                    Some(ref mut rx) => rx.poll().expect("started").is_ready(),
+                    None => false,
+                };
+                if started {
+                    // the request is in flight now
+                    started_rx = None;
+                    conn.graceful_shutdown();
+                    let _ = release_tx.take().unwrap().send(());
+                }
+                conn.poll()
+            })
+        });
+
+    core.run(fut).unwrap();
+
+    let resp = client.join().unwrap();
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", resp);
+    assert!(has_header(&resp, "Connection: close\r\n"), "{:?}", resp);
+    assert!(resp.ends_with(HELLO), "{:?}", resp);
+}
+
+#[cfg(all(unix, feature = "unix"))]
+#[test]
+fn bind_unix_serves_requests() {
+    use std::os::unix::net::UnixStream;
+
+    struct RemoteAddrs;
+
+    impl Service for RemoteAddrs {
+        type Request = Request;
+        type Response = Response;
+        type Error = hyper::Error;
+        type Future = FutureResult<Response, hyper::Error>;
+
+        fn call(&self, req: Request) -> Self::Future {
+            #[allow(deprecated)]
+            let status = match (req.remote_addr(), req.local_addr()) {
+                (None, None) => StatusCode::Ok,
+                _ => StatusCode::InternalServerError,
+            };
+            future::ok(Response::new().with_status(status))
+        }
+    }
+
+    let _ = pretty_env_logger::try_init();
+    let path = ::std::env::temp_dir().join(format!("hyper-test-{}.sock", ::std::process::id()));
+    let _ = ::std::fs::remove_file(&path);
+
+    let (addr_tx, addr_rx) = mpsc::channel();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let srv_path = path.clone();
+    let thread = thread::spawn(move || {
+        let srv = Http::new().bind_unix(&srv_path, || Ok(RemoteAddrs)).unwrap();
+        addr_tx.send(srv.local_path().to_path_buf()).unwrap();
+        srv.run_until(shutdown_rx.then(|_| Ok(()))).unwrap();
+    });
+    assert_eq!(addr_rx.recv().unwrap(), path);
+
+    let mut sock = UnixStream::connect(&path).unwrap();
+    sock.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+    sock.write_all(b"\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ").unwrap();
+    let mut body = String::new();
+    sock.read_to_string(&mut body).unwrap();
+    assert!(body.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", body);
+
+    drop(shutdown_tx);
+    thread.join().unwrap();
+    ::std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn run_threads_serves_requests() {
+    let _ = pretty_env_logger::try_init();
+    let (addr_tx, addr_rx) = mpsc::channel();
+    thread::spawn(move || {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let srv = Http::new().bind(&addr, || Ok(HelloWorld)).unwrap();
+        addr_tx.send(srv.local_addr().unwrap()).unwrap();
+        srv.run_threads(2);
+    });
+    let addr = addr_rx.recv().unwrap();
+
+    for _ in 0..6 {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            Connection: close\r\n\
+            \r\n\
+        ").unwrap();
+        let mut res = String::new();
+        tcp.read_to_string(&mut res).expect("read response");
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", res);
+    }
+}
+
+#[test]
+fn run_threads_until_stops_every_thread() {
+    let _ = pretty_env_logger::try_init();
+    let (addr_tx, addr_rx) = mpsc::channel();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let shutdown_rx = shutdown_rx.shared();
+    let thread = thread::spawn(move || {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let srv = Http::new().bind(&addr, || Ok(HelloWorld)).unwrap();
+        addr_tx.send(srv.local_addr().unwrap()).unwrap();
+        srv.run_threads_until(2, move || shutdown_rx.clone().then(|_| Ok(())))
+    });
+    let addr = addr_rx.recv().unwrap();
+
+    for _ in 0..4 {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            Connection: close\r\n\
+            \r\n\
+        ").unwrap();
+        let mut res = String::new();
+        tcp.read_to_string(&mut res).expect("read response");
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", res);
+    }
+
+    drop(shutdown_tx);
+    thread.join().unwrap().unwrap();
+}
+
+#[test]
+fn accept_filter_closes_rejected_connections_without_a_service() {
+    use net2::TcpBuilder;
+
+    let _ = pretty_env_logger::try_init();
+    let (addr_tx, addr_rx) = mpsc::channel();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let services = Arc::new(AtomicUsize::new(0));
+    let services2 = services.clone();
+    let thread = thread::spawn(move || {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let srv = Http::new()
+            .accept_filter(|addr| addr.ip() != "127.0.0.2".parse::<::std::net::IpAddr>().unwrap())
+            .bind(&addr, move || {
+                services2.fetch_add(1, Ordering::SeqCst);
+                Ok(HelloWorld)
+            })
+            .unwrap();
+        addr_tx.send(srv.local_addr().unwrap()).unwrap();
+        srv.run_until(shutdown_rx.then(|_| Ok(()))).unwrap();
+    });
+    let addr = addr_rx.recv().unwrap();
+
+    let mut rejected = TcpBuilder::new_v4().unwrap()
+        .bind("127.0.0.2:0").unwrap()
+        .connect(&addr).unwrap();
+    rejected.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+    let _ = rejected.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n");
+    let mut res = Vec::new();
+    match rejected.read_to_end(&mut res) {
+        Ok(_) => assert!(res.is_empty(), "{:?}", res),
+        Err(e) => assert_eq!(e.kind(), io::ErrorKind::ConnectionReset),
+    }
+    assert_eq!(services.load(Ordering::SeqCst), 0);
+
+    let mut tcp = connect(&addr);
+    tcp.write_all(b"\
+        GET / HTTP/1.1\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ").unwrap();
+    let mut res = String::new();
+    tcp.read_to_string(&mut res).expect("read response");
+    assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", res);
+    assert_eq!(services.load(Ordering::SeqCst), 1);
+
+    drop(shutdown_tx);
+    thread.join().unwrap();
+}
+
+#[test]
+fn accept_stats_count_accepted_connections() {
+    let _ = pretty_env_logger::try_init();
+    let (addr_tx, addr_rx) = mpsc::channel();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let thread = thread::spawn(move || {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let srv = Http::new()
+            .bind(&addr, || Ok(HelloWorld))
+            .unwrap();
+        addr_tx.send((srv.local_addr().unwrap(), srv.accept_stats())).unwrap();
+        srv.run_until(shutdown_rx.then(|_| Ok(()))).unwrap();
+    });
+    let (addr, stats) = addr_rx.recv().unwrap();
+    assert_eq!(stats.accepted(), 0);
+
+    for _ in 0..2 {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            Connection: close\r\n\
+            \r\n\
+        ").unwrap();
+        let mut res = String::new();
+        tcp.read_to_string(&mut res).expect("read response");
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", res);
+    }
+    assert_eq!(stats.accepted(), 2);
+    assert_eq!(stats.connection_errors() + stats.resource_exhaustion_errors() + stats.other_errors(), 0);
+    assert_eq!(stats.sleeps(), 0);
+
+    drop(shutdown_tx);
+    thread.join().unwrap();
+}
+
+#[test]
+fn on_connection_called_for_each_accepted_connection() {
+    let _ = pretty_env_logger::try_init();
+    let (addr_tx, addr_rx) = mpsc::channel();
+    let (conn_tx, conn_rx) = mpsc::channel();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let thread = thread::spawn(move || {
+        let conn_tx = Mutex::new(conn_tx);
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let srv = Http::new()
+            .on_connection(move |stream| {
+                conn_tx.lock().unwrap().send(stream.remote_addr()).unwrap();
+            })
+            .bind(&addr, || Ok(HelloWorld))
+            .unwrap();
+        addr_tx.send(srv.local_addr().unwrap()).unwrap();
+        srv.run_until(shutdown_rx.then(|_| Ok(()))).unwrap();
+    });
+    let addr = addr_rx.recv().unwrap();
+
+    for _ in 0..2 {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            Connection: close\r\n\
+            \r\n\
+        ").unwrap();
+        let mut res = String::new();
+        tcp.read_to_string(&mut res).expect("read response");
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", res);
+        let remote_addr = conn_rx.recv_timeout(Duration::from_secs(1)).expect("on_connection");
+        assert_eq!(remote_addr, tcp.local_addr().unwrap());
+    }
+
+    drop(shutdown_tx);
+    thread.join().unwrap();
+    assert!(conn_rx.try_recv().is_err());
+}
+
+#[test]
+fn addr_stream_exposes_tcp_stream() {
+    let _ = pretty_env_logger::try_init();
+    let (addr_tx, addr_rx) = mpsc::channel();
+    let (conn_tx, conn_rx) = mpsc::channel();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let thread = thread::spawn(move || {
+        let conn_tx = Mutex::new(conn_tx);
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let srv = Http::new()
+            .on_connection(move |stream| {
+                let tcp = stream.get_ref();
+                #[cfg(unix)]
+                {
+                    use std::os::unix::io::AsRawFd;
+                    assert_eq!(stream.as_raw_fd(), tcp.as_raw_fd());
+                }
+                let addrs = (tcp.peer_addr().unwrap(), tcp.local_addr().unwrap());
+                conn_tx.lock().unwrap().send(addrs).unwrap();
+            })
+            .bind(&addr, || Ok(HelloWorld))
+            .unwrap();
+        addr_tx.send(srv.local_addr().unwrap()).unwrap();
+        srv.run_until(shutdown_rx.then(|_| Ok(()))).unwrap();
+    });
+    let addr = addr_rx.recv().unwrap();
+
+    let mut tcp = connect(&addr);
+    tcp.write_all(b"\
+        GET / HTTP/1.1\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ").unwrap();
+    let mut res = String::new();
+    tcp.read_to_string(&mut res).expect("read response");
+    assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", res);
+    let (peer_addr, local_addr) = conn_rx.recv_timeout(Duration::from_secs(1)).expect("on_connection");
+    assert_eq!(peer_addr, tcp.local_addr().unwrap());
+    assert_eq!(local_addr, addr);
+
+    drop(shutdown_tx);
+    thread.join().unwrap();
+}
+
+// Serves with `proxy_protocol` enabled, responding with the remote address
+// of each request.
+fn serve_proxy_protocol() -> (SocketAddr, oneshot::Sender<()>, thread::JoinHandle<()>) {
+    let _ = pretty_env_logger::try_init();
+    let (addr_tx, addr_rx) = mpsc::channel();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let thread = thread::spawn(move || {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let srv = Http::new()
+            .proxy_protocol(true)
+            .bind(&addr, || Ok(service_fn_ok(|req: Request| {
+                #[allow(deprecated)]
+                let body = format!("{:?}", req.remote_addr());
+                Response::<hyper::Body>::new()
+                    .with_header(ContentLength(body.len() as u64))
+                    .with_body(body)
+            })))
+            .unwrap();
+        addr_tx.send(srv.local_addr().unwrap()).unwrap();
+        srv.run_until(shutdown_rx.then(|_| Ok(()))).unwrap();
+    });
+    (addr_rx.recv().unwrap(), shutdown_tx, thread)
+}
+
+#[test]
+fn proxy_protocol_v1_sets_remote_addr() {
+    let (addr, shutdown_tx, thread) = serve_proxy_protocol();
+
+    let mut tcp = connect(&addr);
+    tcp.write_all(b"\
+        PROXY TCP4 192.168.0.1 10.0.0.1 56324 443\r\n\
+        GET / HTTP/1.1\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ").unwrap();
+    let mut res = String::new();
+    tcp.read_to_string(&mut res).expect("read");
+    assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", res);
+    assert!(res.ends_with("\r\n\r\nSome(192.168.0.1:56324)"), "{:?}", res);
+
+    // without an address, the TCP peer is the remote address
+    let mut tcp = connect(&addr);
+    tcp.write_all(b"\
+        PROXY UNKNOWN\r\n\
+        GET / HTTP/1.1\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ").unwrap();
+    let mut res = String::new();
+    tcp.read_to_string(&mut res).expect("read");
+    assert!(res.ends_with(&format!("\r\n\r\nSome({})", tcp.local_addr().unwrap())), "{:?}", res);
+
+    drop(shutdown_tx);
+    thread.join().unwrap();
+}
+
+#[test]
+fn proxy_protocol_v2_sets_remote_addr() {
+    let (addr, shutdown_tx, thread) = serve_proxy_protocol();
+
+    let mut header = b"\r\n\r\n\0\r\nQUIT\n\x21\x21\x00\x24".to_vec();
+    // 2001:db8::1 port 4000 to ::1 port 80
+    header.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+    header.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+    header.extend_from_slice(&[0x0f, 0xa0, 0x00, 0x50]);
+
+    let mut tcp = connect(&addr);
+    // the header arrives in pieces, followed by two requests
+    tcp.write_all(&header[..10]).unwrap();
+    thread::sleep(Duration::from_millis(50));
+    let mut rest = header[10..].to_vec();
+    rest.extend_from_slice(b"\
+        GET /a HTTP/1.1\r\n\
+        \r\n\
+        GET /b HTTP/1.1\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ");
+    tcp.write_all(&rest).unwrap();
+    let mut res = String::new();
+    tcp.read_to_string(&mut res).expect("read");
+    assert_eq!(res.matches("\r\n\r\nSome([2001:db8::1]:4000)").count(), 2, "{:?}", res);
+
+    drop(shutdown_tx);
+    thread.join().unwrap();
+}
+
+#[test]
+fn proxy_protocol_invalid_header_closes_connection() {
+    let (addr, shutdown_tx, thread) = serve_proxy_protocol();
+
+    let mut tcp = connect(&addr);
+    tcp.write_all(b"\
+        GET / HTTP/1.1\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ").unwrap();
+    let mut res = Vec::new();
+    let _ = tcp.read_to_end(&mut res);
+    assert!(res.is_empty(), "{:?}", String::from_utf8_lossy(&res));
+
+    drop(shutdown_tx);
+    thread.join().unwrap();
+}
+
+// Serves with `trust_forwarded_for(trusted)`, responding with the remote
+// address of each request.
+fn serve_forwarded_for(trusted: &'static str) -> (SocketAddr, oneshot::Sender<()>, thread::JoinHandle<()>) {
+    let _ = pretty_env_logger::try_init();
+    let (addr_tx, addr_rx) = mpsc::channel();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let thread = thread::spawn(move || {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let srv = Http::new()
+            .trust_forwarded_for(vec![trusted.parse().unwrap()])
+            .bind(&addr, || Ok(service_fn_ok(|req: Request| {
+                #[allow(deprecated)]
+                let body = format!("{:?}", req.remote_addr());
+                Response::<hyper::Body>::new()
+                    .with_header(ContentLength(body.len() as u64))
+                    .with_body(body)
+            })))
+            .unwrap();
+        addr_tx.send(srv.local_addr().unwrap()).unwrap();
+        srv.run_until(shutdown_rx.then(|_| Ok(()))).unwrap();
+    });
+    (addr_rx.recv().unwrap(), shutdown_tx, thread)
+}
+
+fn get_remote_addr(addr: &SocketAddr, forwarded: &str) -> (String, SocketAddr) {
+    let mut tcp = connect(addr);
+    write!(tcp, "GET / HTTP/1.1\r\n{}\r\nConnection: close\r\n\r\n", forwarded).unwrap();
+    let mut res = String::new();
+    tcp.read_to_string(&mut res).expect("read");
+    let body = res.split("\r\n\r\n").nth(1).unwrap().to_owned();
+    (body, tcp.local_addr().unwrap())
+}
+
+#[test]
+fn trust_forwarded_for_trusted_peer_sets_remote_addr() {
+    let (addr, shutdown_tx, thread) = serve_forwarded_for("127.0.0.0/8");
+
+    let (body, _) = get_remote_addr(&addr, "X-Forwarded-For: 198.51.100.1, 203.0.113.7, 127.0.0.2");
+    assert_eq!(body, "Some(203.0.113.7:0)");
+    let (body, _) = get_remote_addr(&addr, "Forwarded: for=\"[2001:db8::1]:4711\";proto=https");
+    assert_eq!(body, "Some([2001:db8::1]:4711)");
+
+    drop(shutdown_tx);
+    thread.join().unwrap();
+}
+
+#[test]
+fn trust_forwarded_for_untrusted_peer_ignores_header() {
+    let (addr, shutdown_tx, thread) = serve_forwarded_for("10.0.0.0/8");
+
+    let (body, client) = get_remote_addr(&addr, "X-Forwarded-For: 203.0.113.7");
+    assert_eq!(body, format!("Some({})", client));
+
+    drop(shutdown_tx);
+    thread.join().unwrap();
+}
+
+#[test]
+fn trust_forwarded_for_malformed_header_keeps_peer() {
+    let (addr, shutdown_tx, thread) = serve_forwarded_for("127.0.0.0/8");
+
+    let (body, client) = get_remote_addr(&addr, "X-Forwarded-For: 203.0.113.7, not-an-ip");
+    assert_eq!(body, format!("Some({})", client));
+    let (body, client) = get_remote_addr(&addr, "Forwarded: for=unknown");
+    assert_eq!(body, format!("Some({})", client));
+
+    drop(shutdown_tx);
+    thread.join().unwrap();
+}
+
+#[test]
+fn connection_counter_tracks_active_connections() {
+    fn wait_for(counter: &hyper::server::ConnectionCounter, expected: usize) {
+        for _ in 0..100 {
+            if counter.get() == expected {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("expected {} connections, found {}", expected, counter.get());
+    }
+
+    let _ = pretty_env_logger::try_init();
+    let (addr_tx, addr_rx) = mpsc::channel();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let thread = thread::spawn(move || {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let srv = Http::new().bind(&addr, || Ok(HelloWorld)).unwrap();
+        addr_tx.send((srv.local_addr().unwrap(), srv.connection_counter())).unwrap();
+        srv.run_until(shutdown_rx.then(|_| Ok(()))).unwrap();
+    });
+    let (addr, counter) = addr_rx.recv().unwrap();
+    assert_eq!(counter.get(), 0);
+
+    let request = |tcp: &mut TcpStream| {
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            \r\n\
+        ").unwrap();
+        let n = tcp.read(&mut [0; 256]).expect("read response");
+        assert!(n > 0);
+    };
+
+    let mut first = connect(&addr);
+    request(&mut first);
+    wait_for(&counter, 1);
+
+    let mut second = connect(&addr);
+    request(&mut second);
+    wait_for(&counter, 2);
+
+    drop(first);
+    wait_for(&counter, 1);
+    drop(second);
+    wait_for(&counter, 0);
+
+    drop(shutdown_tx);
+    thread.join().unwrap();
+}
+
+#[test]
+fn max_concurrent_requests_holds_excess_requests() {
+    let _ = pretty_env_logger::try_init();
+    let (addr_tx, addr_rx) = mpsc::channel();
+    let (calls_tx, calls_rx) = mpsc::channel();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let calls_tx = Mutex::new(calls_tx);
+    let thread = thread::spawn(move || {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let srv = Http::new()
+            .max_concurrent_requests(2)
+            .bind(&addr, move || {
+                let calls_tx = calls_tx.lock().unwrap().clone();
+                // each response waits to be released by the test
+                Ok(service_fn(move |req: Request| {
+                    let (release_tx, release_rx) = oneshot::channel::<()>();
+                    calls_tx.send((req.path().to_owned(), release_tx)).unwrap();
+                    release_rx.then(|_| {
+                        Ok::<_, hyper::Error>(Response::<hyper::Body>::new()
+                            .with_header(ContentLength(2))
+                            .with_body("ok"))
+                    })
+                }))
+            })
+            .unwrap();
+        addr_tx.send((srv.local_addr().unwrap(), srv.request_counter())).unwrap();
+        srv.run_until(shutdown_rx.then(|_| Ok(()))).unwrap();
+    });
+    let (addr, counter) = addr_rx.recv().unwrap();
+
+    let clients = ["/one", "/two", "/three"].iter().map(|path| {
+        let mut tcp = connect(&addr);
+        write!(tcp, "GET {} HTTP/1.1\r\nHost: example.domain\r\n\r\n", path).unwrap();
+        tcp
+    }).collect::<Vec<_>>();
+
+    let first = calls_rx.recv_timeout(Duration::from_secs(5)).expect("first call");
+    let second = calls_rx.recv_timeout(Duration::from_secs(5)).expect("second call");
+    // the third request waits for one of the others to complete
+    assert!(calls_rx.recv_timeout(Duration::from_millis(200)).is_err());
+    assert_eq!(counter.get(), 2);
+
+    first.1.send(()).unwrap();
+    let third = calls_rx.recv_timeout(Duration::from_secs(5)).expect("third call");
+    let mut paths = vec![first.0, second.0, third.0.clone()];
+    paths.sort();
+    assert_eq!(paths, ["/one", "/three", "/two"]);
+    assert_eq!(counter.get(), 2);
+
+    second.1.send(()).unwrap();
+    third.1.send(()).unwrap();
+    for mut tcp in clients {
+        let mut res = String::new();
+        let mut buf = [0; 256];
+        while !res.ends_with("\r\n\r\nok") {
+            let n = tcp.read(&mut buf).expect("read response");
+            assert!(n > 0, "closed before a complete response");
+            res.push_str(::std::str::from_utf8(&buf[..n]).unwrap());
+        }
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", res);
+    }
+    assert_eq!(counter.get(), 0);
+
+    drop(shutdown_tx);
+    thread.join().unwrap();
+}
+
+#[test]
+fn overload_response_answers_requests_over_the_limits() {
+    use hyper::header::RetryAfter;
+    let _ = pretty_env_logger::try_init();
+    let (addr_tx, addr_rx) = mpsc::channel();
+    let (calls_tx, calls_rx) = mpsc::channel();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let calls_tx = Mutex::new(calls_tx);
+    let thread = thread::spawn(move || {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let overloaded = Response::new()
+            .with_status(StatusCode::ServiceUnavailable)
+            .with_header(RetryAfter::Delay(Duration::from_secs(5)))
+            .with_body("busy");
+        let srv = Http::new()
+            .max_concurrent_requests(1)
+            .max_connections(2)
+            .overload_response(Some(overloaded))
+            .bind(&addr, move || {
+                let calls_tx = calls_tx.lock().unwrap().clone();
+                // each response waits to be released by the test
+                Ok(service_fn(move |req: Request| {
+                    let (release_tx, release_rx) = oneshot::channel::<()>();
+                    calls_tx.send((req.path().to_owned(), release_tx)).unwrap();
+                    release_rx.then(|_| {
+                        Ok::<_, hyper::Error>(Response::<hyper::Body>::new()
+                            .with_header(ContentLength(2))
+                            .with_body("ok"))
+                    })
+                }))
+            })
+            .unwrap();
+        addr_tx.send((srv.local_addr().unwrap(), srv.request_counter())).unwrap();
+        srv.run_until(shutdown_rx.then(|_| Ok(()))).unwrap();
+    });
+    let (addr, counter) = addr_rx.recv().unwrap();
+    let overloaded = |mut tcp: TcpStream| {
+        let mut res = String::new();
+        tcp.read_to_string(&mut res).expect("read until closed");
+        assert!(res.starts_with("HTTP/1.1 503 Service Unavailable\r\n"), "{:?}", res);
+        assert!(res.contains("\r\nRetry-After: 5\r\n"), "{:?}", res);
+        assert!(res.contains("\r\nConnection: close\r\n"), "{:?}", res);
+        assert!(res.ends_with("\r\n\r\nbusy"), "{:?}", res);
+    };
+
+    let mut held = connect(&addr);
+    held.write_all(b"GET /held HTTP/1.1\r\nHost: example.domain\r\n\r\n").unwrap();
+    let first = calls_rx.recv_timeout(Duration::from_secs(5)).expect("first call");
+    assert_eq!(first.0, "/held");
+
+    // with `max_connections` reached, a new connection is still answered
+    let mut idle = connect(&addr);
+    let mut over_connections = connect(&addr);
+    over_connections.write_all(b"GET /a HTTP/1.1\r\nHost: example.domain\r\n\r\n").unwrap();
+    overloaded(over_connections);
+
+    // with `max_concurrent_requests` reached, the request doesn't wait
+    idle.write_all(b"GET /b HTTP/1.1\r\nHost: example.domain\r\n\r\n").unwrap();
+    overloaded(idle);
+    assert!(calls_rx.recv_timeout(Duration::from_millis(100)).is_err());
+    assert_eq!(counter.get(), 1);
+
+    first.1.send(()).unwrap();
+    let mut res = String::new();
+    let mut buf = [0; 256];
+    while !res.ends_with("\r\n\r\nok") {
+        let n = held.read(&mut buf).expect("read response");
+        assert!(n > 0, "closed before a complete response");
+        res.push_str(::std::str::from_utf8(&buf[..n]).unwrap());
+    }
+    assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", res);
+    assert_eq!(counter.get(), 0);
+
+    drop(shutdown_tx);
+    thread.join().unwrap();
+}
+
+#[test]
+fn overload_response_closes_idle_connections_over_the_limit() {
+    let _ = pretty_env_logger::try_init();
+    let (addr_tx, addr_rx) = mpsc::channel();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let thread = thread::spawn(move || {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let overloaded = Response::new()
+            .with_status(StatusCode::ServiceUnavailable)
+            .with_body("busy");
+        let srv = Http::new()
+            .max_connections(1)
+            .overload_response(Some(overloaded))
+            .bind(&addr, || Ok(HelloWorld))
+            .unwrap();
+        addr_tx.send((srv.local_addr().unwrap(), srv.connection_counter())).unwrap();
+        srv.run_until(shutdown_rx.then(|_| Ok(()))).unwrap();
+    });
+    let (addr, connections) = addr_rx.recv().unwrap();
+
+    let held = connect(&addr);
+    for _ in 0..100 {
+        if connections.get() == 1 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(connections.get(), 1);
+
+    let start = Instant::now();
+    let mut idle = connect(&addr);
+    idle.set_read_timeout(Some(Duration::from_secs(3))).unwrap();
+    // at most `max_connections` more are accepted for the overload response,
+    // so this one waits in the backlog until the idle one is closed
+    let mut waiting = connect(&addr);
+    waiting.set_read_timeout(Some(Duration::from_secs(3))).unwrap();
+    waiting.write_all(b"GET / HTTP/1.1\r\nHost: example.domain\r\n\r\n").unwrap();
+
+    let mut res = String::new();
+    idle.read_to_string(&mut res).expect("read until closed");
+    assert_eq!(res, "");
+    assert!(start.elapsed() >= Duration::from_millis(900), "closed after {:?}", start.elapsed());
+
+    let mut res = String::new();
+    waiting.read_to_string(&mut res).expect("read until closed");
+    assert!(res.starts_with("HTTP/1.1 503 Service Unavailable\r\n"), "{:?}", res);
+    assert!(start.elapsed() >= Duration::from_millis(900), "answered after {:?}", start.elapsed());
+
+    drop(held);
+    drop(shutdown_tx);
+    thread.join().unwrap();
+}
+
+#[test]
+fn blocking_service_keeps_reactor_responsive() {
+    use futures_cpupool::CpuPool;
+    use hyper::server::{blocking_service, const_service};
+    let _ = pretty_env_logger::try_init();
+    let (addr_tx, addr_rx) = mpsc::channel();
+    let (release_tx, release_rx) = mpsc::channel::<()>();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let release_rx = Mutex::new(release_rx);
+    let thread = thread::spawn(move || {
+        let service = blocking_service(CpuPool::new(2), move |req: Request| {
+            if req.path() == "/slow" {
+                release_rx.lock().unwrap().recv().unwrap();
+            }
+            let body = req.path().to_owned();
+            Response::<hyper::Body>::new()
+                .with_header(ContentLength(body.len() as u64))
+                .with_body(body)
+        });
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let srv = Http::new().bind(&addr, const_service(service)).unwrap();
+        addr_tx.send(srv.local_addr().unwrap()).unwrap();
+        srv.run_until(shutdown_rx.then(|_| Ok(()))).unwrap();
+    });
+    let addr = addr_rx.recv().unwrap();
+    let get = |tcp: &mut TcpStream, path: &str| {
+        write!(tcp, "GET {} HTTP/1.1\r\nHost: example.domain\r\n\r\n", path).unwrap();
+    };
+    let read_body = |tcp: &mut TcpStream, body: &str| {
+        let mut res = String::new();
+        let mut buf = [0; 256];
+        while !res.ends_with(body) {
+            let n = tcp.read(&mut buf).expect("read response");
+            assert!(n > 0, "closed before a complete response");
+            res.push_str(::std::str::from_utf8(&buf[..n]).unwrap());
+        }
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", res);
+    };
+
+    let mut slow = connect(&addr);
+    get(&mut slow, "/slow");
+    thread::sleep(Duration::from_millis(50));
+    // served by the reactor while the handler of /slow blocks its thread
+    let mut fast = connect(&addr);
+    get(&mut fast, "/fast");
+    read_body(&mut fast, "\r\n\r\n/fast");
+    get(&mut fast, "/again");
+    read_body(&mut fast, "\r\n\r\n/again");
+
+    release_tx.send(()).unwrap();
+    read_body(&mut slow, "\r\n\r\n/slow");
+
+    drop(shutdown_tx);
+    thread.join().unwrap();
+}
+
+#[test]
+fn bind_multi_serves_every_address() {
+    let _ = pretty_env_logger::try_init();
+    let (addrs_tx, addrs_rx) = mpsc::channel();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let thread = thread::spawn(move || {
+        let addrs = ["127.0.0.1:0".parse().unwrap(), "127.0.0.1:0".parse().unwrap()];
+        let srv = Http::new().bind_multi(&addrs, || Ok(HelloWorld)).unwrap();
+        assert_eq!(srv.local_addr().unwrap(), srv.local_addrs().unwrap()[0]);
+        addrs_tx.send((srv.local_addrs().unwrap(), srv.connection_counter())).unwrap();
+        srv.run_until(shutdown_rx.then(|_| Ok(()))).unwrap();
+    });
+    let (addrs, counter) = addrs_rx.recv().unwrap();
+    assert_eq!(addrs.len(), 2);
+    assert_ne!(addrs[0], addrs[1]);
+
+    let mut conns = addrs.iter().map(|addr| {
+        let mut tcp = connect(addr);
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            \r\n\
+        ").unwrap();
+        let mut buf = [0; 256];
+        let n = tcp.read(&mut buf).expect("read response");
+        assert!(s(&buf[..n]).starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", s(&buf[..n]));
+        tcp
+    }).collect::<Vec<_>>();
+    for _ in 0..100 {
+        if counter.get() == 2 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(counter.get(), 2);
+
+    conns.clear();
+    drop(shutdown_tx);
+    thread.join().unwrap();
+    for addr in &addrs {
+        assert!(TcpStream::connect(addr).is_err(), "{} still listening", addr);
+    }
+}
+
+// A factory failing its first `failures` services.
+struct FlakyNewService {
+    calls: Arc<AtomicUsize>,
+    failures: usize,
+}
+
+impl NewService for FlakyNewService {
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Instance = HelloWorld;
+
+    fn new_service(&self) -> io::Result<HelloWorld> {
+        if self.calls.fetch_add(1, Ordering::SeqCst) < self.failures {
+            Err(io::Error::new(io::ErrorKind::Other, "pool exhausted"))
+        } else {
+            Ok(HelloWorld)
+        }
+    }
+}
+
+// Serves with `retry_new_service(FlakyNewService { failures }, attempts)`,
+// returning the address and the count of calls of the factory.
+fn serve_flaky_new_service(failures: usize, attempts: usize) -> (SocketAddr, Arc<AtomicUsize>, oneshot::Sender<()>) {
+    let _ = pretty_env_logger::try_init();
+    let (addr_tx, addr_rx) = mpsc::channel();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let calls = Arc::new(AtomicUsize::new(0));
+    let factory = FlakyNewService {
+        calls: calls.clone(),
+        failures: failures,
+    };
+    thread::spawn(move || {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let new_service = hyper::server::retry_new_service(factory, attempts);
+        let srv = Http::new().bind(&addr, new_service).unwrap();
+        addr_tx.send(srv.local_addr().unwrap()).unwrap();
+        srv.run_until(shutdown_rx.then(|_| Ok(()))).unwrap();
+    });
+    (addr_rx.recv().unwrap(), calls, shutdown_tx)
+}
+
+fn get_hello(addr: &SocketAddr) -> io::Result<String> {
+    let mut tcp = connect(addr);
+    tcp.write_all(b"\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ")?;
+    let mut resp = String::new();
+    tcp.read_to_string(&mut resp)?;
+    Ok(resp)
+}
+
+#[test]
+fn retry_new_service_retries_failing_factory() {
+    let (addr, calls, _shutdown_tx) = serve_flaky_new_service(2, 3);
+
+    let resp = get_hello(&addr).expect("get");
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", resp);
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn retry_new_service_gives_up_on_one_connection() {
+    let (addr, calls, _shutdown_tx) = serve_flaky_new_service(3, 2);
+
+    // both attempts fail, closing only this connection, maybe with a reset
+    match get_hello(&addr) {
+        Ok(resp) => assert_eq!(resp, ""),
+        Err(e) => assert_eq!(e.kind(), io::ErrorKind::ConnectionReset),
+    }
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+    let resp = get_hello(&addr).expect("get");
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", resp);
+    assert_eq!(calls.load(Ordering::SeqCst), 4);
+}
+
+#[test]
+fn accept_control_pauses_and_resumes_accepting() {
+    let _ = pretty_env_logger::try_init();
+    let (addr_tx, addr_rx) = mpsc::channel();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let thread = thread::spawn(move || {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let srv = Http::new().bind(&addr, || Ok(HelloWorld)).unwrap();
+        addr_tx.send((srv.local_addr().unwrap(), srv.accept_control())).unwrap();
+        srv.run_until(shutdown_rx.then(|_| Ok(()))).unwrap();
+    });
+    let (addr, control) = addr_rx.recv().unwrap();
 
-    let fut = listener.incoming()
-        .into_future()
-        .map_err(|_| unreachable!())
-        .and_then(|(item, _incoming)| {
-            let (socket, _) = item.unwrap();
-            Http::<hyper::Chunk>::new().serve_connection(socket, HelloWorld)
-                .map(|_| ())
-        });
-
-    core.run(fut).unwrap_err();
+    let request = |tcp: &mut TcpStream| {
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            \r\n\
+        ").unwrap();
+    };
+    let read_response = |tcp: &mut TcpStream| {
+        let mut buf = [0; 256];
+        let n = tcp.read(&mut buf).expect("read response");
+        let resp = String::from_utf8_lossy(&buf[..n]).into_owned();
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", resp);
+    };
+
+    let mut active = connect(&addr);
+    request(&mut active);
+    read_response(&mut active);
+
+    control.pause();
+    assert!(control.is_paused());
+
+    // the connect completes in the backlog, but nothing serves it
+    let mut waiting = connect(&addr);
+    waiting.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+    request(&mut waiting);
+    let err = waiting.read(&mut [0; 256]).expect_err("served while paused");
+    assert!(err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::TimedOut, "{:?}", err);
+
+    // connections accepted before the pause are still served
+    request(&mut active);
+    read_response(&mut active);
+
+    control.resume();
+    assert!(!control.is_paused());
+    waiting.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+    read_response(&mut waiting);
+
+    drop(shutdown_tx);
+    thread.join().unwrap();
 }
 
 #[test]
-fn returning_1xx_response_is_error() {
+fn bind_listener_serves_inherited_listener() {
+    let _ = pretty_env_logger::try_init();
+    let std_listener = ::std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = std_listener.local_addr().unwrap();
+    let (addr_tx, addr_rx) = mpsc::channel();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let thread = thread::spawn(move || {
+        let srv = Http::new().bind_listener(std_listener, || Ok(HelloWorld)).unwrap();
+        addr_tx.send(srv.local_addr().unwrap()).unwrap();
+        srv.run_until(shutdown_rx.then(|_| Ok(()))).unwrap();
+    });
+    assert_eq!(addr_rx.recv().unwrap(), addr);
+
+    let mut req = connect(&addr);
+    req.write_all(b"\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ").unwrap();
+    let mut resp = String::new();
+    req.read_to_string(&mut resp).expect("read response");
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", resp);
+    assert!(resp.ends_with(HELLO), "{:?}", resp);
+
+    drop(shutdown_tx);
+    thread.join().unwrap();
+}
+
+#[test]
+fn reuse_address_disabled_rejects_second_bind() {
+    let _ = pretty_env_logger::try_init();
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let first = Http::new()
+        .reuse_address(false)
+        .bind(&addr, || Ok(HelloWorld))
+        .unwrap();
+    let addr = first.local_addr().unwrap();
+
+    let second = Http::new()
+        .reuse_address(false)
+        .bind(&addr, || Ok(HelloWorld));
+    assert!(second.is_err());
+}
+
+#[test]
+fn run_until_graceful_reports_clean_shutdown() {
+    let _ = pretty_env_logger::try_init();
+    let (addr_tx, addr_rx) = mpsc::channel();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let thread = thread::spawn(move || {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let srv = Http::new().bind(&addr, || Ok(HelloWorld)).unwrap();
+        addr_tx.send(srv.local_addr().unwrap()).unwrap();
+        srv.run_until_graceful(shutdown_rx.then(|_| Ok(()))).unwrap()
+    });
+    let addr = addr_rx.recv().unwrap();
+
+    let mut req = connect(&addr);
+    req.write_all(b"\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        \r\n\
+    ").unwrap();
+    let n = req.read(&mut [0; 256]).expect("read response");
+    assert!(n > 0);
+
+    drop(shutdown_tx);
+    let outcome = thread.join().unwrap();
+    assert!(!outcome.forced);
+    assert_eq!(outcome.remaining, 0);
+}
+
+#[test]
+fn bind_handle_shares_a_core_with_other_tasks() {
+    let _ = pretty_env_logger::try_init();
     let mut core = Core::new().unwrap();
-    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
-    let addr = listener.local_addr().unwrap();
+    let handle = core.handle();
+    let addr = "127.0.0.1:0".parse().unwrap();
+    let srv = Http::new().bind_handle(&addr, &handle, || Ok(HelloWorld)).unwrap();
+    let addr = srv.local_addr();
+    let counter = srv.connection_counter();
 
-    thread::spawn(move || {
+    let (done_tx, done_rx) = oneshot::channel::<()>();
+    let client = thread::spawn(move || {
+        let mut req = connect(&addr);
+        req.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            \r\n\
+        ").unwrap();
+        let n = req.read(&mut [0; 256]).expect("read response");
+        assert!(n > 0);
+        done_tx.send(()).unwrap();
+        // the idle connection is closed by the shutdown
+        let mut rest = Vec::new();
+        req.read_to_end(&mut rest).expect("read to end");
+    });
+
+    // another task of the core, seeing the connection while it is served,
+    // then shutting the server down
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let active = Arc::new(AtomicUsize::new(0));
+    let active2 = active.clone();
+    let gauge = counter.clone();
+    handle.spawn(done_rx.map(move |()| {
+        active2.store(gauge.get(), Ordering::SeqCst);
+        let _ = shutdown_tx.send(());
+    }).map_err(|_| ()));
+
+    let outcome = core.run(srv.serve_until(shutdown_rx.then(|_| Ok(())))).unwrap();
+    assert!(!outcome.forced);
+    assert_eq!(outcome.remaining, 0);
+    assert_eq!(active.load(Ordering::SeqCst), 1);
+    assert_eq!(counter.get(), 0);
+    client.join().unwrap();
+
+    // the core is still usable by other tasks
+    assert_eq!(core.run(future::ok::<_, ()>(7)).unwrap(), 7);
+}
+
+#[test]
+fn run_until_graceful_closes_idle_connections_without_waiting_for_timeout() {
+    let _ = pretty_env_logger::try_init();
+    let (addr_tx, addr_rx) = mpsc::channel();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let thread = thread::spawn(move || {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let mut srv = Http::new().bind(&addr, || Ok(HelloWorld)).unwrap();
+        srv.shutdown_timeout(Duration::from_secs(10));
+        addr_tx.send(srv.local_addr().unwrap()).unwrap();
+        srv.run_until_graceful(shutdown_rx.then(|_| Ok(()))).unwrap()
+    });
+    let addr = addr_rx.recv().unwrap();
+
+    // idle between requests
+    let mut keep_alive = connect(&addr);
+    keep_alive.write_all(b"\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        \r\n\
+    ").unwrap();
+    let n = keep_alive.read(&mut [0; 256]).expect("read response");
+    assert!(n > 0);
+    // idle before its first request
+    let _never_used = connect(&addr);
+    thread::sleep(Duration::from_millis(50));
+
+    let start = ::std::time::Instant::now();
+    drop(shutdown_tx);
+    let outcome = thread.join().unwrap();
+    assert!(start.elapsed() < Duration::from_secs(1), "shutdown took {:?}", start.elapsed());
+    assert!(!outcome.forced);
+    assert_eq!(outcome.remaining, 0);
+    assert_eq!(keep_alive.read(&mut [0; 256]).expect("read eof"), 0);
+}
+
+#[test]
+fn run_until_graceful_reports_forced_shutdown() {
+    let _ = pretty_env_logger::try_init();
+    let (addr_tx, addr_rx) = mpsc::channel();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let thread = thread::spawn(move || {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        // the response never finishes, so the connection cannot drain
+        let mut srv = Http::new().bind(&addr, || Ok(service_fn(|_req| {
+            future::empty::<Response, hyper::Error>()
+        }))).unwrap();
+        srv.shutdown_timeout(Duration::from_millis(100));
+        addr_tx.send(srv.local_addr().unwrap()).unwrap();
+        srv.run_until_graceful(shutdown_rx.then(|_| Ok(()))).unwrap()
+    });
+    let addr = addr_rx.recv().unwrap();
+
+    let mut req = connect(&addr);
+    req.write_all(b"\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        \r\n\
+    ").unwrap();
+    thread::sleep(Duration::from_millis(50));
+
+    drop(shutdown_tx);
+    let outcome = thread.join().unwrap();
+    assert!(outcome.forced);
+    assert_eq!(outcome.remaining, 1);
+}
+
+// Serves `reqs` with a large response, shutting the server down as soon as
+// the first response is ready. Returns what the client read.
+fn serve_large_then_shut_down(pipeline: bool, reqs: &'static [u8], len: usize) -> Vec<u8> {
+    let _ = pretty_env_logger::try_init();
+    let (addr_tx, addr_rx) = mpsc::channel();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let shutdown_tx = Arc::new(Mutex::new(Some(shutdown_tx)));
+    let thread = thread::spawn(move || {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let srv = Http::new().pipeline(pipeline).bind(&addr, move || {
+            let shutdown_tx = shutdown_tx.clone();
+            Ok(service_fn_ok(move |_req| {
+                if let Some(tx) = shutdown_tx.lock().unwrap().take() {
+                    let _ = tx.send(());
+                }
+                Response::<hyper::Body>::new()
+                    .with_header(ContentLength(len as u64))
+                    .with_body(vec![b'x'; len])
+            }))
+        }).unwrap();
+        addr_tx.send(srv.local_addr().unwrap()).unwrap();
+        srv.run_until_graceful(shutdown_rx.then(|_| Ok(()))).unwrap()
+    });
+    let addr = addr_rx.recv().unwrap();
+
+    let mut req = connect(&addr);
+    req.write_all(reqs).unwrap();
+    req.shutdown(Shutdown::Write).unwrap();
+    // let the server buffer more than the sockets take
+    thread::sleep(Duration::from_millis(200));
+    let mut res = Vec::new();
+    req.read_to_end(&mut res).unwrap();
+
+    let outcome = thread.join().unwrap();
+    assert!(!outcome.forced);
+    res
+}
+
+fn body_len(res: &[u8]) -> usize {
+    let body = res.windows(4).position(|w| w == b"\r\n\r\n").expect("end of head") + 4;
+    res.len() - body
+}
+
+#[test]
+fn graceful_shutdown_writes_all_of_large_response() {
+    const LEN: usize = 16 * 1024 * 1024;
+    let res = serve_large_then_shut_down(false, b"\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        \r\n\
+    ", LEN);
+    assert_eq!(body_len(&res), LEN);
+}
+
+#[test]
+fn graceful_shutdown_writes_all_of_pipelined_responses() {
+    const LEN: usize = 16 * 1024 * 1024;
+    let res = serve_large_then_shut_down(true, b"\
+        GET /a HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        \r\n\
+        GET /b HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        \r\n\
+    ", LEN);
+    // the second request may or may not be served before the drain
+    let len = body_len(&res);
+    assert!(len == LEN || (len > LEN && res.len() > 2 * LEN), "read {} bytes", res.len());
+}
+
+#[test]
+fn run_until_phased_serves_connections_until_drain() {
+    let _ = pretty_env_logger::try_init();
+    let (addr_tx, addr_rx) = mpsc::channel();
+    let (phase_tx, phase_rx) = futures::sync::mpsc::unbounded();
+    let thread = thread::spawn(move || {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let srv = Http::new().bind(&addr, || Ok(HelloWorld)).unwrap();
+        addr_tx.send(srv.local_addr().unwrap()).unwrap();
+        srv.run_until_phased(phase_rx.map_err(|_| ())).unwrap()
+    });
+    let addr = addr_rx.recv().unwrap();
+
+    let mut req = connect(&addr);
+    let get = b"\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        \r\n\
+    ";
+    req.write_all(get).unwrap();
+    let n = req.read(&mut [0; 256]).expect("read response");
+    assert!(n > 0);
+
+    phase_tx.unbounded_send(ShutdownPhase::StopAccepting).unwrap();
+    thread::sleep(Duration::from_millis(50));
+    assert!(TcpStream::connect(&addr).is_err());
+
+    // the connection accepted before is still kept alive
+    req.write_all(get).unwrap();
+    let n = req.read(&mut [0; 256]).expect("read response");
+    assert!(n > 0);
+
+    phase_tx.unbounded_send(ShutdownPhase::Drain).unwrap();
+    let outcome = thread.join().unwrap();
+    assert!(!outcome.forced);
+    assert_eq!(outcome.remaining, 0);
+}
+
+#[test]
+fn max_connections_pauses_accept_until_one_closes() {
+    let _ = pretty_env_logger::try_init();
+    let (addr_tx, addr_rx) = mpsc::channel();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let thread = thread::spawn(move || {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let srv = Http::new()
+            .max_connections(2)
+            .bind(&addr, || Ok(HelloWorld))
+            .unwrap();
+        addr_tx.send(srv.local_addr().unwrap()).unwrap();
+        srv.run_until(shutdown_rx.then(|_| Ok(()))).unwrap();
+    });
+    let addr = addr_rx.recv().unwrap();
+
+    let write_request = |tcp: &mut TcpStream| {
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            \r\n\
+        ").unwrap();
+    };
+
+    let mut first = connect(&addr);
+    write_request(&mut first);
+    assert!(first.read(&mut [0; 256]).expect("read first response") > 0);
+
+    let mut second = connect(&addr);
+    write_request(&mut second);
+    assert!(second.read(&mut [0; 256]).expect("read second response") > 0);
+
+    let mut third = connect(&addr);
+    third.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+    write_request(&mut third);
+    let err = third.read(&mut [0; 256]).expect_err("third is not served yet");
+    assert!(
+        err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::TimedOut,
+        "unexpected error: {}",
+        err
+    );
+
+    drop(first);
+    third.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+    assert!(third.read(&mut [0; 256]).expect("read third response") > 0);
+
+    drop(shutdown_tx);
+    thread.join().unwrap();
+}
+
+#[test]
+fn on_parse_error_reports_and_replaces_response() {
+    let _ = pretty_env_logger::try_init();
+    let (addr_tx, addr_rx) = mpsc::channel();
+    let (err_tx, err_rx) = mpsc::channel();
+    let err_tx = Mutex::new(err_tx);
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let calls = AtomicUsize::new(0);
+    let thread = thread::spawn(move || {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let srv = Http::new()
+            .on_parse_error(move |err, remote_addr| {
+                err_tx.lock().unwrap().send((err.to_string(), remote_addr)).unwrap();
+                // the first connection gets the automatic response
+                if calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                    None
+                } else {
+                    Some(Response::new()
+                        .with_status(StatusCode::BadRequest)
+                        .with_body("branded bad request"))
+                }
+            })
+            .bind(&addr, || Ok(HelloWorld))
+            .unwrap();
+        addr_tx.send(srv.local_addr().unwrap()).unwrap();
+        srv.run_until(shutdown_rx.then(|_| Ok(()))).unwrap();
+    });
+    let addr = addr_rx.recv().unwrap();
+
+    let request = || {
         let mut tcp = connect(&addr);
-        tcp.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
-        let mut buf = [0; 256];
-        tcp.read(&mut buf).unwrap();
+        tcp.write_all(b"GE,T / HTTP/1.1\r\n\r\n").unwrap();
+        let mut resp = String::new();
+        tcp.read_to_string(&mut resp).expect("read");
+        (resp, tcp.local_addr().unwrap())
+    };
+
+    let (resp, client_addr) = request();
+    assert!(resp.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{:?}", resp);
+    assert!(has_header(&resp, "Content-Length: 0"), "{:?}", resp);
+    let (err, remote_addr) = err_rx.recv().unwrap();
+    assert_eq!(err, hyper::Error::Header.to_string());
+    assert_eq!(remote_addr, Some(client_addr));
+
+    let (resp, client_addr) = request();
+    assert!(resp.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{:?}", resp);
+    assert!(has_header(&resp, "Content-Length: 19"), "{:?}", resp);
+    assert!(resp.ends_with("\r\n\r\nbranded bad request"), "{:?}", resp);
+    assert_eq!(err_rx.recv().unwrap().1, Some(client_addr));
+
+    drop(shutdown_tx);
+    thread.join().unwrap();
+}
 
-        let expected = "HTTP/1.1 500 ";
-        assert_eq!(s(&buf[..expected.len()]), expected);
+#[test]
+fn max_accept_rate_absorbs_burst_then_spreads_out_accepts() {
+    let _ = pretty_env_logger::try_init();
+    let (addr_tx, addr_rx) = mpsc::channel();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let thread = thread::spawn(move || {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let srv = Http::new()
+            .max_accept_rate(10)
+            .max_connections(20)
+            .bind(&addr, || Ok(HelloWorld))
+            .unwrap();
+        addr_tx.send(srv.local_addr().unwrap()).unwrap();
+        srv.run_until(shutdown_rx.then(|_| Ok(()))).unwrap();
     });
+    let addr = addr_rx.recv().unwrap();
 
-    let fut = listener.incoming()
-        .into_future()
-        .map_err(|_| unreachable!())
-        .and_then(|(item, _incoming)| {
-            let (socket, _) = item.unwrap();
-            Http::<hyper::Chunk>::new()
-                .serve_connection(socket, service_fn(|_| {
-                    Ok(Response::<hyper::Body>::new()
-                        .with_status(StatusCode::Continue))
+    // a burst of connections, waiting in the listen backlog
+    let start = ::std::time::Instant::now();
+    let mut conns = (0..15).map(|_| {
+        let mut tcp = connect(&addr);
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            \r\n\
+        ").unwrap();
+        tcp
+    }).collect::<Vec<_>>();
+    for (i, tcp) in conns.iter_mut().enumerate() {
+        tcp.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        assert!(tcp.read(&mut [0; 256]).expect("read response") > 0);
+        if i == 9 {
+            // the first 10 are accepted with the tokens of a full bucket
+            let elapsed = start.elapsed();
+            assert!(elapsed < Duration::from_millis(300), "burst accepted too slow: {:?}", elapsed);
+        }
+    }
+
+    // then one accept every 100ms
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(400), "accepted too fast: {:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(2), "accepted too slow: {:?}", elapsed);
+
+    drop(shutdown_tx);
+    thread.join().unwrap();
+}
+
+#[test]
+fn socket_control_adjusts_socket_of_request() {
+    let _ = pretty_env_logger::try_init();
+    let (addr_tx, addr_rx) = mpsc::channel();
+    let (control_tx, control_rx) = mpsc::channel();
+    let control_tx = Mutex::new(control_tx);
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let thread = thread::spawn(move || {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let srv = Http::new()
+            .bind(&addr, move || {
+                let control_tx = control_tx.lock().unwrap().clone();
+                Ok(service_fn_ok(move |req: Request| {
+                    let control = req.socket_control().expect("socket control").clone();
+                    control.set_send_buffer_size(128 * 1024).expect("set_send_buffer_size");
+                    #[cfg(unix)]
+                    control.set_tos(0x20).expect("set_tos");
+                    let size = control.send_buffer_size().expect("send_buffer_size");
+                    control_tx.send(control).unwrap();
+                    let body = size.to_string();
+                    Response::<hyper::Body>::new()
+                        .with_header(ContentLength(body.len() as u64))
+                        .with_body(body)
                 }))
-                .map(|_| ())
-        });
+            })
+            .unwrap();
+        addr_tx.send(srv.local_addr().unwrap()).unwrap();
+        srv.run_until(shutdown_rx.then(|_| Ok(()))).unwrap();
+    });
+    let addr = addr_rx.recv().unwrap();
+
+    let mut tcp = connect(&addr);
+    tcp.write_all(b"\
+        GET / HTTP/1.1\r\n\
+        Host: example.domain\r\n\
+        Connection: close\r\n\
+        \r\n\
+    ").unwrap();
+    let mut resp = String::new();
+    tcp.read_to_string(&mut resp).expect("read");
+    let size = resp.rsplit("\r\n\r\n").next().unwrap().parse::<usize>().expect("size");
+    assert!(size >= 128 * 1024, "send buffer of {}", size);
+
+    // the handle outlives the connection, without touching its socket
+    let control = control_rx.recv().unwrap();
+    drop(shutdown_tx);
+    thread.join().unwrap();
+    assert!(!control.is_open());
+    assert_eq!(control.set_nodelay(true).unwrap_err().kind(), io::ErrorKind::NotConnected);
+}
+
+#[test]
+fn max_connections_per_ip_closes_connections_over_limit() {
+    let _ = pretty_env_logger::try_init();
+    let (addr_tx, addr_rx) = mpsc::channel();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let thread = thread::spawn(move || {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let srv = Http::new()
+            .max_connections_per_ip(2)
+            .bind(&addr, || Ok(HelloWorld))
+            .unwrap();
+        addr_tx.send(srv.local_addr().unwrap()).unwrap();
+        srv.run_until(shutdown_rx.then(|_| Ok(()))).unwrap();
+    });
+    let addr = addr_rx.recv().unwrap();
+
+    // every connection comes from 127.0.0.1
+    let request = |tcp: &mut TcpStream| {
+        tcp.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            \r\n\
+        ").unwrap();
+        tcp.read(&mut [0; 256])
+    };
+
+    let mut first = connect(&addr);
+    assert!(request(&mut first).expect("read first response") > 0);
+    let mut second = connect(&addr);
+    assert!(request(&mut second).expect("read second response") > 0);
+
+    let mut third = connect(&addr);
+    match request(&mut third) {
+        Ok(0) => (),
+        Err(ref e) if e.kind() == io::ErrorKind::ConnectionReset => (),
+        other => panic!("third should be closed: {:?}", other),
+    }
+
+    // closing one frees a slot for that address
+    drop(first);
+    let mut fourth = connect(&addr);
+    let mut served = false;
+    for _ in 0..10 {
+        if let Ok(n) = request(&mut fourth) {
+            if n > 0 {
+                served = true;
+                break;
+            }
+        }
+        // the server may not have noticed `first` closing yet
+        thread::sleep(Duration::from_millis(50));
+        fourth = connect(&addr);
+    }
+    assert!(served, "fourth should be served");
+    assert!(request(&mut second).expect("second still served") > 0);
+
+    drop(shutdown_tx);
+    thread.join().unwrap();
+}
+
+#[test]
+fn serve_injects_connection_info() {
+    // stands in for a TLS stream, which knows more about its connection
+    struct Tls(tokio_core::net::TcpStream);
+
+    impl Read for Tls {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl Write for Tls {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.0.flush()
+        }
+    }
+
+    impl AsyncRead for Tls {}
+
+    impl AsyncWrite for Tls {
+        fn shutdown(&mut self) -> futures::Poll<(), io::Error> {
+            AsyncWrite::shutdown(&mut self.0)
+        }
+    }
+
+    impl ConnectionInfo for Tls {
+        fn remote_addr(&self) -> Option<SocketAddr> {
+            self.0.peer_addr().ok()
+        }
+
+        fn alpn_protocol(&self) -> Option<&[u8]> {
+            Some(b"http/1.1")
+        }
+
+        fn peer_certificate(&self) -> Option<&[u8]> {
+            Some(b"not really DER")
+        }
+    }
+
+    struct Echo;
+
+    impl Service for Echo {
+        type Request = Request;
+        type Response = Response;
+        type Error = hyper::Error;
+        type Future = FutureResult<Response, hyper::Error>;
 
-    core.run(fut).unwrap_err();
-}
+        fn call(&self, req: Request) -> Self::Future {
+            #[allow(deprecated)]
+            let body = format!(
+                "{} {} {} {}",
+                req.remote_addr().is_some(),
+                req.local_addr().is_some(),
+                s(req.alpn_protocol().unwrap_or(b"none")),
+                s(req.peer_certificate().unwrap_or(b"none")),
+            );
+            future::ok(Response::new()
+                .with_header(ContentLength(body.len() as u64))
+                .with_body(body))
+        }
+    }
+
+    impl HasRemoteAddr for Echo {
+        fn remote_addr(&mut self, _addr: SocketAddr) {}
+    }
 
-#[test]
-fn upgrades() {
-    use tokio_io::io::{read_to_end, write_all};
     let _ = pretty_env_logger::try_init();
     let mut core = Core::new().unwrap();
     let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
     let addr = listener.local_addr().unwrap();
-    let (tx, rx) = oneshot::channel();
 
-    thread::spawn(move || {
+    let client = thread::spawn(move || {
         let mut tcp = connect(&addr);
         tcp.write_all(b"\
             GET / HTTP/1.1\r\n\
-            Upgrade: foobar\r\n\
-            Connection: upgrade\r\n\
+            Connection: close\r\n\
             \r\n\
-            eagerly optimistic\
-        ").expect("write 1");
-        let mut buf = [0; 256];
-        tcp.read(&mut buf).expect("read 1");
-
-        let expected = "HTTP/1.1 101 Switching Protocols\r\n";
-        assert_eq!(s(&buf[..expected.len()]), expected);
-        let _ = tx.send(());
-
-        let n = tcp.read(&mut buf).expect("read 2");
-        assert_eq!(s(&buf[..n]), "foo=bar");
-        tcp.write_all(b"bar=foo").expect("write 2");
+        ").unwrap();
+        let mut resp = String::new();
+        tcp.read_to_string(&mut resp).expect("read response");
+        resp
     });
 
-    let fut = listener.incoming()
+    let incoming = listener.incoming().map(|(tcp, _)| Tls(tcp));
+    let fut = Http::<hyper::Chunk>::new()
+        .serve_incoming(incoming, || Ok(Echo))
         .into_future()
-        .map_err(|_| -> hyper::Error { unreachable!() })
-        .and_then(|(item, _incoming)| {
-            let (socket, _) = item.unwrap();
-            let conn = Http::<hyper::Chunk>::new()
-                .serve_connection(socket, service_fn(|_| {
-                    let mut res = Response::<hyper::Body>::new()
-                        .with_status(StatusCode::SwitchingProtocols);
-                    res.headers_mut().set_raw("Upgrade", "foobar");
-                    Ok(res)
-                }));
+        .map_err(|(e, _)| e)
+        .and_then(|(conn, _serve)| conn.expect("connection"));
 
-            let mut conn_opt = Some(conn);
-            future::poll_fn(move || {
-                try_ready!(conn_opt.as_mut().unwrap().poll_without_shutdown());
-                // conn is done with HTTP now
-                Ok(conn_opt.take().unwrap().into())
-            })
-        });
+    core.run(fut).unwrap();
+    let resp = client.join().unwrap();
+    assert!(resp.ends_with("\r\n\r\ntrue false http/1.1 not really DER"), "{:?}", resp);
+}
 
-    let conn = core.run(fut).unwrap();
+#[test]
+fn serve_incoming_with_describes_connections() {
+    // an IO object that knows nothing about its connection
+    struct Pipe(tokio_core::net::TcpStream);
 
-    // wait so that we don't write until other side saw 101 response
-    core.run(rx).unwrap();
+    impl Read for Pipe {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
 
-    let parts = conn.into_parts();
-    let io = parts.io;
-    assert_eq!(parts.read_buf, "eagerly optimistic");
+    impl Write for Pipe {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.write(buf)
+        }
 
-    let io = core.run(write_all(io, b"foo=bar")).unwrap().0;
-    let vec = core.run(read_to_end(io, vec![])).unwrap().1;
-    assert_eq!(vec, b"bar=foo");
-}
+        fn flush(&mut self) -> io::Result<()> {
+            self.0.flush()
+        }
+    }
 
-#[test]
-fn parse_errors_send_4xx_response() {
-    let mut core = Core::new().unwrap();
-    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
-    let addr = listener.local_addr().unwrap();
+    impl AsyncRead for Pipe {}
 
-    thread::spawn(move || {
-        let mut tcp = connect(&addr);
-        tcp.write_all(b"GE T / HTTP/1.1\r\n\r\n").unwrap();
-        let mut buf = [0; 256];
-        tcp.read(&mut buf).unwrap();
+    impl AsyncWrite for Pipe {
+        fn shutdown(&mut self) -> futures::Poll<(), io::Error> {
+            AsyncWrite::shutdown(&mut self.0)
+        }
+    }
 
-        let expected = "HTTP/1.1 400 ";
-        assert_eq!(s(&buf[..expected.len()]), expected);
-    });
+    struct Echo;
 
-    let fut = listener.incoming()
-        .into_future()
-        .map_err(|_| unreachable!())
-        .and_then(|(item, _incoming)| {
-            let (socket, _) = item.unwrap();
-            Http::<hyper::Chunk>::new()
-                .serve_connection(socket, HelloWorld)
-                .map(|_| ())
-        });
+    impl Service for Echo {
+        type Request = Request;
+        type Response = Response;
+        type Error = hyper::Error;
+        type Future = FutureResult<Response, hyper::Error>;
 
-    core.run(fut).unwrap_err();
-}
+        fn call(&self, req: Request) -> Self::Future {
+            #[allow(deprecated)]
+            let body = format!("{:?}", req.remote_addr());
+            future::ok(Response::new()
+                .with_header(ContentLength(body.len() as u64))
+                .with_body(body))
+        }
+    }
 
-#[test]
-fn illegal_request_length_returns_400_response() {
+    impl HasRemoteAddr for Echo {
+        fn remote_addr(&mut self, _addr: SocketAddr) {}
+    }
+
+    let _ = pretty_env_logger::try_init();
     let mut core = Core::new().unwrap();
     let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
     let addr = listener.local_addr().unwrap();
 
-    thread::spawn(move || {
+    let client = thread::spawn(move || {
         let mut tcp = connect(&addr);
-        tcp.write_all(b"POST / HTTP/1.1\r\nContent-Length: foo\r\n\r\n").unwrap();
-        let mut buf = [0; 256];
-        tcp.read(&mut buf).unwrap();
-
-        let expected = "HTTP/1.1 400 ";
-        assert_eq!(s(&buf[..expected.len()]), expected);
+        tcp.write_all(b"\
+            GET / HTTP/1.1\r\n\
+            Connection: close\r\n\
+            \r\n\
+        ").unwrap();
+        let mut resp = String::new();
+        tcp.read_to_string(&mut resp).expect("read response");
+        resp
     });
 
-    let fut = listener.incoming()
+    let remote: SocketAddr = "10.0.0.1:8080".parse().unwrap();
+    let incoming = listener.incoming().map(|(tcp, _)| Pipe(tcp));
+    let fut = Http::<hyper::Chunk>::new()
+        .serve_incoming_with(incoming, || Ok(Echo), move |_pipe: &Pipe| Some(remote))
         .into_future()
-        .map_err(|_| unreachable!())
-        .and_then(|(item, _incoming)| {
-            let (socket, _) = item.unwrap();
-            Http::<hyper::Chunk>::new()
-                .serve_connection(socket, HelloWorld)
-                .map(|_| ())
-        });
+        .map_err(|(e, _)| e)
+        .and_then(|(conn, _serve)| conn.expect("connection"));
 
-    core.run(fut).unwrap_err();
+    core.run(fut).unwrap();
+    let resp = client.join().unwrap();
+    assert!(resp.ends_with("\r\n\r\nSome(10.0.0.1:8080)"), "{:?}", resp);
 }
 
 #[test]
-fn max_buf_size() {
-    let _ = pretty_env_logger::try_init();
-    let mut core = Core::new().unwrap();
-    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
-    let addr = listener.local_addr().unwrap();
+fn serve_incoming_addr_injects_remote_addr() {
+    // a fabricated connection, reading a request and recording the response
+    struct Fake {
+        read: io::Cursor<&'static [u8]>,
+        written: Arc<Mutex<Vec<u8>>>,
+    }
 
-    const MAX: usize = 16_000;
+    impl Read for Fake {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.read.read(buf)
+        }
+    }
 
-    thread::spawn(move || {
-        let mut tcp = connect(&addr);
-        tcp.write_all(b"POST /").expect("write 1");
-        tcp.write_all(&vec![b'a'; MAX]).expect("write 2");
-        let mut buf = [0; 256];
-        tcp.read(&mut buf).expect("read 1");
+    impl Write for Fake {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written.lock().unwrap().write(buf)
+        }
 
-        let expected = "HTTP/1.1 431 ";
-        assert_eq!(s(&buf[..expected.len()]), expected);
-    });
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
 
-    let fut = listener.incoming()
-        .into_future()
-        .map_err(|_| unreachable!())
-        .and_then(|(item, _incoming)| {
-            let (socket, _) = item.unwrap();
-            Http::<hyper::Chunk>::new()
-                .max_buf_size(MAX)
-                .serve_connection(socket, HelloWorld)
-                .map(|_| ())
-        });
+    impl AsyncRead for Fake {}
 
-    core.run(fut).unwrap_err();
-}
+    impl AsyncWrite for Fake {
+        fn shutdown(&mut self) -> futures::Poll<(), io::Error> {
+            Ok(().into())
+        }
+    }
+
+    struct Echo(Arc<Mutex<Vec<SocketAddr>>>);
+
+    impl Service for Echo {
+        type Request = Request;
+        type Response = Response;
+        type Error = hyper::Error;
+        type Future = FutureResult<Response, hyper::Error>;
+
+        fn call(&self, req: Request) -> Self::Future {
+            #[allow(deprecated)]
+            let body = format!("{:?}", req.remote_addr());
+            future::ok(Response::new()
+                .with_header(ContentLength(body.len() as u64))
+                .with_body(body))
+        }
+    }
+
+    impl HasRemoteAddr for Echo {
+        fn remote_addr(&mut self, addr: SocketAddr) {
+            self.0.lock().unwrap().push(addr);
+        }
+    }
 
-#[test]
-fn streaming_body() {
     let _ = pretty_env_logger::try_init();
     let mut core = Core::new().unwrap();
-    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &core.handle()).unwrap();
-    let addr = listener.local_addr().unwrap();
+    let addrs: Vec<SocketAddr> = vec!["10.0.0.1:8080".parse().unwrap(), "[::1]:9090".parse().unwrap()];
+    let outputs = addrs.iter().map(|_| Arc::new(Mutex::new(Vec::new()))).collect::<Vec<_>>();
+    let incoming = futures::stream::iter_ok::<_, io::Error>(outputs.iter().zip(addrs.iter()).map(|(written, &addr)| {
+        let io = Fake {
+            read: io::Cursor::new(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n"),
+            written: written.clone(),
+        };
+        (io, addr)
+    }).collect::<Vec<_>>());
+
+    let services = Arc::new(Mutex::new(Vec::new()));
+    let services2 = services.clone();
+    let fut = Http::<hyper::Chunk>::new()
+        .serve_incoming_addr(incoming, move || Ok(Echo(services2.clone())))
+        .for_each(|conn| conn.map(|_| ()));
+    core.run(fut).unwrap();
 
-    let (tx, rx) = oneshot::channel();
-    thread::spawn(move || {
-        let mut tcp = connect(&addr);
-        tcp.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
-        let mut buf = [0; 8192];
-        let mut sum = tcp.read(&mut buf).expect("read 1");
+    for (written, addr) in outputs.iter().zip(addrs.iter()) {
+        let resp = String::from_utf8(written.lock().unwrap().clone()).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", resp);
+        assert!(resp.ends_with(&format!("\r\n\r\nSome({})", addr)), "{:?}", resp);
+    }
+    assert_eq!(*services.lock().unwrap(), addrs);
+}
 
-        let expected = "HTTP/1.1 200 ";
-        assert_eq!(s(&buf[..expected.len()]), expected);
+#[test]
+fn serve_spawn_all_on_executor() {
+    struct Counted(Arc<AtomicUsize>);
 
-        loop {
-            let n = tcp.read(&mut buf).expect("read loop");
-            sum += n;
-            if n == 0 {
-                break;
+    impl Service for Counted {
+        type Request = Request;
+        type Response = Response;
+        type Error = hyper::Error;
+        type Future = FutureResult<Response, hyper::Error>;
+
+        fn call(&self, req: Request) -> Self::Future {
+            let mut res = Response::new();
+            if req.local_addr().is_none() {
+                res.set_status(StatusCode::InternalServerError);
             }
+            future::ok(res)
         }
-        assert_eq!(sum, 100_789);
-        let _ = tx.send(());
-    });
-
-    let rx = rx.map_err(|_| panic!("thread panicked"));
+    }
 
-    let fut = listener.incoming()
-        .into_future()
-        .map_err(|_| unreachable!())
-        .and_then(|(item, _incoming)| {
-            let (socket, _) = item.unwrap();
-            Http::<& &'static [u8]>::new()
-                .keep_alive(false)
-                .serve_connection(socket, service_fn(|_| {
-                    static S: &'static [&'static [u8]] = &[&[b'x'; 1_000] as &[u8]; 1_00] as _;
-                    let b = ::futures::stream::iter_ok(S.iter());
-                    Ok(Response::<futures::stream::IterOk<::std::slice::Iter<&'static [u8]>, ::hyper::Error>>::new()
-                        .with_body(b))
-                }))
-                .map(|_| ())
-        });
+    impl HasRemoteAddr for Counted {
+        fn remote_addr(&mut self, _addr: SocketAddr) {}
+    }
 
-    core.run(fut.join(rx)).unwrap();
-}
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
 
-#[test]
-fn remote_addr() {
-    let server = serve();
+    let _ = pretty_env_logger::try_init();
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let dropped = Arc::new(AtomicUsize::new(0));
+
+    let counter = dropped.clone();
+    let serve = Http::<hyper::Chunk>::new()
+        .serve_addr_handle(&"127.0.0.1:0".parse().unwrap(), &handle, move || {
+            Ok(Counted(counter.clone()))
+        })
+        .unwrap();
+    let addr = serve.incoming_ref().local_addr();
+    handle.spawn(serve.spawn_all(handle.clone()).map_err(|e| panic!("spawn_all error: {}", e)));
 
-    let mut req = connect(server.addr());
-    req.write_all(b"\
-        GET / HTTP/1.1\r\n\
-        Host: example.domain\r\n\
-        \r\n\
-    ").unwrap();
-    req.read(&mut [0; 256]).unwrap();
+    let (tx, rx) = oneshot::channel();
+    thread::spawn(move || {
+        let clients = (0..4).map(|_| thread::spawn(move || {
+            let mut tcp = connect(&addr);
+            tcp.write_all(b"\
+                GET / HTTP/1.1\r\n\
+                Connection: close\r\n\
+                \r\n\
+            ").unwrap();
+            let mut buf = Vec::new();
+            tcp.read_to_end(&mut buf).expect("read response");
+            assert!(buf.starts_with(b"HTTP/1.1 200 OK\r\n"));
+        })).collect::<Vec<_>>();
+        for client in clients {
+            client.join().expect("client thread");
+        }
+        tx.send(()).unwrap();
+    });
 
-    let client_addr = req.local_addr().unwrap();
-    assert_eq!(server.remote_addr(), client_addr);
+    core.run(rx).unwrap();
+    // let each finished connection future be dropped by the executor
+    while dropped.load(Ordering::SeqCst) < 4 {
+        core.turn(Some(Duration::from_millis(10)));
+    }
+    assert_eq!(dropped.load(Ordering::SeqCst), 4);
 }
 
 // -------------------------------------------------
@@ -1170,6 +5722,10 @@ impl Serve {
         &self.addr
     }
 
+    fn shutdown(&mut self) {
+        drop(self.shutdown_signal.take());
+    }
+
     pub fn remote_addr(&self) -> SocketAddr {
         match self.msg_rx.recv() {
             Ok(Msg::Addr(addr)) => addr,
@@ -1177,6 +5733,16 @@ impl Serve {
         }
     }
 
+    pub fn local_addr(&self) -> SocketAddr {
+        loop {
+            match self.msg_rx.recv() {
+                Ok(Msg::Addr(_)) => {},
+                Ok(Msg::LocalAddr(addr)) => return addr,
+                other => panic!("expected local addr, found: {:?}", other),
+            }
+        }
+    }
+
     fn body(&self) -> Vec<u8> {
         self.try_body().expect("body")
     }
@@ -1193,6 +5759,7 @@ impl Serve {
                     buf.extend(&msg);
                 },
                 Ok(Msg::Addr(_)) => {},
+                Ok(Msg::LocalAddr(_)) => {},
                 Ok(Msg::Error(e)) => return Err(e),
                 Ok(Msg::End) => break,
                 Err(e) => panic!("expected body, found: {:?}", e),
@@ -1262,6 +5829,7 @@ enum Reply {
 enum Msg {
     //Head(Request),
     Addr(SocketAddr),
+    LocalAddr(SocketAddr),
     Chunk(Vec<u8>),
     Error(hyper::Error),
     End,
@@ -1291,6 +5859,8 @@ impl Service for TestService {
         #[allow(deprecated)]
         let remote_addr = req.remote_addr().expect("remote_addr");
         tx1.lock().unwrap().send(Msg::Addr(remote_addr)).unwrap();
+        let local_addr = req.local_addr().expect("local_addr");
+        tx1.lock().unwrap().send(Msg::LocalAddr(local_addr)).unwrap();
 
         let replies = self.reply.clone();
         Box::new(req.body().for_each(move |chunk| {
@@ -1359,6 +5929,13 @@ struct ServeOptions {
     keep_alive_disabled: bool,
     pipeline: bool,
     timeout: Option<Duration>,
+    header_read_timeout: Option<Duration>,
+    min_head_read_rate: Option<(usize, Duration)>,
+    send_408_on_timeout: bool,
+    keep_alive_timeout: Option<Duration>,
+    expect_continue: bool,
+    max_body_size: Option<usize>,
+    server_header: Option<Server>,
 }
 
 impl Default for ServeOptions {
@@ -1367,6 +5944,13 @@ impl Default for ServeOptions {
             keep_alive_disabled: false,
             pipeline: false,
             timeout: None,
+            header_read_timeout: None,
+            min_head_read_rate: None,
+            send_408_on_timeout: false,
+            keep_alive_timeout: None,
+            expect_continue: false,
+            max_body_size: None,
+            server_header: None,
         }
     }
 }
@@ -1384,12 +5968,33 @@ fn serve_with_options(options: ServeOptions) -> Serve {
     let keep_alive = !options.keep_alive_disabled;
     let pipeline = options.pipeline;
     let dur = options.timeout;
+    let header_read_timeout = options.header_read_timeout;
+    let min_head_read_rate = options.min_head_read_rate;
+    let send_408_on_timeout = options.send_408_on_timeout;
+    let keep_alive_timeout = options.keep_alive_timeout;
+    let expect_continue = options.expect_continue;
+    let max_body_size = options.max_body_size;
+    let server_header = options.server_header;
 
     let thread_name = format!("test-server-{:?}", dur);
     let thread = thread::Builder::new().name(thread_name).spawn(move || {
-        let srv = Http::new()
-            .keep_alive(keep_alive)
+        let mut http = Http::new();
+        http.keep_alive(keep_alive)
             .pipeline(pipeline)
+            .expect_continue(expect_continue)
+            .max_body_size(max_body_size)
+            .server_header(server_header)
+            .send_408_on_timeout(send_408_on_timeout);
+        if let Some(dur) = header_read_timeout {
+            http.header_read_timeout(dur);
+        }
+        if let Some((bytes, per)) = min_head_read_rate {
+            http.min_head_read_rate(bytes, per);
+        }
+        if let Some(dur) = keep_alive_timeout {
+            http.keep_alive_timeout(dur);
+        }
+        let srv = http
             .bind(&addr, TestService {
                 tx: Arc::new(Mutex::new(msg_tx.clone())),
                 _timeout: dur,